@@ -0,0 +1,69 @@
+//! Scalar vs. vectorized per-chunk DSP. Run with `cargo bench --bench dsp`.
+
+#[allow(dead_code)]
+#[path = "../src/simd.rs"]
+mod simd;
+
+use std::hint::black_box;
+use std::time::Instant;
+
+/// One 40 ms stereo chunk at 48 kHz, the stream's unit of work.
+const CHUNK_SAMPLES: usize = 48_000 / 25 * 2;
+const ITERATIONS: u32 = 20_000;
+
+fn bench(name: &str, mut run: impl FnMut()) {
+    for _ in 0..ITERATIONS / 10 {
+        run();
+    }
+    let started = Instant::now();
+    for _ in 0..ITERATIONS {
+        run();
+    }
+    let per_chunk = started.elapsed() / ITERATIONS;
+    println!(
+        "{:<28} {:>8.2} us/chunk",
+        name,
+        per_chunk.as_secs_f64() * 1e6
+    );
+}
+
+fn main() {
+    let samples = (0..CHUNK_SAMPLES)
+        .map(|idx| (idx as f32 * 0.013).sin() * 0.8)
+        .collect::<Vec<_>>();
+    let mut bytes = Vec::with_capacity(CHUNK_SAMPLES * 2);
+    for sample in &samples {
+        bytes.extend_from_slice(&((sample * i16::MAX as f32) as i16).to_le_bytes());
+    }
+    let mut floats = Vec::with_capacity(CHUNK_SAMPLES);
+    let mut ints = Vec::with_capacity(CHUNK_SAMPLES);
+
+    bench("i16 le -> f32 scalar", || {
+        floats.clear();
+        simd::i16_le_to_f32_scalar(black_box(&bytes), &mut floats);
+    });
+    bench("i16 le -> f32 simd", || {
+        floats.clear();
+        simd::i16_le_to_f32(black_box(&bytes), &mut floats);
+    });
+    bench("f32 -> i16 scalar", || {
+        ints.clear();
+        simd::f32_to_i16_scalar(black_box(&samples), &mut ints);
+    });
+    bench("f32 -> i16 simd", || {
+        ints.clear();
+        simd::f32_to_i16(black_box(&samples), &mut ints);
+    });
+    bench("sum squares/peak scalar", || {
+        black_box(simd::sum_squares_peak_scalar(black_box(&samples)));
+    });
+    bench("sum squares/peak simd", || {
+        black_box(simd::sum_squares_peak(black_box(&samples)));
+    });
+
+    let mut reference = Vec::new();
+    simd::f32_to_i16_scalar(&samples, &mut reference);
+    ints.clear();
+    simd::f32_to_i16(&samples, &mut ints);
+    assert_eq!(reference, ints, "simd conversion differs from scalar");
+}
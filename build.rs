@@ -1,5 +1,10 @@
 fn main() {
     println!("cargo:rustc-check-cfg=cfg(alsa_silence)");
+    // `update` picks the release archive built for the same target.
+    println!(
+        "cargo:rustc-env=BUILD_TARGET={}",
+        std::env::var("TARGET").unwrap_or_default()
+    );
     #[cfg(target_os = "linux")]
     {
         println!("cargo:rerun-if-changed=src/alsa_silence.c");
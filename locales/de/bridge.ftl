@@ -0,0 +1,135 @@
+# Benutzertexte von CLI, Installer und Einrichtungsassistent.
+# Log-Meldungen bleiben englisch.
+
+examples-heading = Beispiele:
+run-options-without-run = { $option } gilt nur für `run`; hinter `run` angeben oder den Unterbefehl weglassen
+
+install-config = Konfiguration: { $path }
+install-unit-written = systemd-Unit geschrieben: { $path }
+install-token-saved = Server-Token in der Konfiguration gespeichert.
+install-audio-group-missing = Warnung: { $user } ist nicht in der Gruppe { $group } und kann das Aufnahmegerät eventuell nicht öffnen. Hinzufügen mit: usermod -aG { $group } { $user }
+install-no-start = Unit geschrieben, nicht gestartet. Starten mit: systemctl { $scope }daemon-reload && systemctl { $scope }enable --now lox-linein-bridge
+install-init-written = Init-Skript geschrieben: { $path }
+install-init-no-start = Init-Skript geschrieben, nicht gestartet. Starten mit: { $enable } && { $restart }
+install-user-linger = Damit die Bridge ohne Anmeldung beim Booten startet: loginctl enable-linger $USER
+install-server-mac-saved = Bevorzugt wird der Server mit der MAC { $mac }.
+install-config-owner = Konfigurationsverzeichnis an { $user }:{ $group } übergeben.
+install-servers-found = { $count } Server gefunden; welchen soll die Bridge verwenden?
+
+update-current = lox-linein-bridge { $version } ist aktuell.
+update-available = Version { $version } ist verfügbar (installiert: { $current }). Installieren mit `lox-linein-bridge update`.
+update-downloading = Lade { $version } herunter...
+update-installed = { $version } nach { $path } installiert.
+update-restarted = Dienst neu gestartet.
+update-no-config = Keine Konfigurationsdatei gefunden; zuerst die Bridge einrichten.
+
+devices-none = Keine Aufnahmegeräte gefunden.
+devices-default = (Standard)
+devices-channels = Kanäle
+devices-rates = Abtastraten
+devices-formats = Formate
+devices-probe-failed = unterstützte Formate nicht lesbar: { $error }
+
+test-capture-opened = Nehme { $device } auf: { $rate } Hz, { $channels } Kan., { $format } für { $seconds } s...
+test-capture-peaks = Höchster Pegel: { $peaks }
+test-capture-clipping = Der Eingang übersteuert; Verstärkung verringern.
+test-capture-silent = Kein Signal über -60 dBFS; Verkabelung und Quelle prüfen.
+
+record-started = Nehme { $device } mit { $rate } Hz ({ $resampler }) für { $seconds } s nach { $path } auf...
+record-capped = Eine WAV-Datei fasst bei dieser Rate höchstens { $seconds } s; die Aufnahme endet dort.
+record-written = { $seconds } s nach { $path } geschrieben.
+
+status-not-running = Keine Statusdatei unter { $path }; die Bridge läuft nicht oder der Datei-Reporter ist aus.
+status-stale = Die Statusdatei wurde vor { $seconds } s zuletzt geschrieben; die Bridge läuft vermutlich nicht.
+status-bridges = { $streaming } von { $total } Bridges streamen
+status-state = Zustand
+status-device = Gerät
+status-input = Eingang
+status-ingest = Ingest
+status-rate = Abtastrate
+status-rate-observed = { $rate } Hz (Gerät liefert { $observed } Hz)
+status-throughput = Durchsatz
+status-throughput-value = { $rate }/s, { $total } gesendet
+status-connection = Verbindung
+status-connection-up = seit { $uptime } verbunden, { $reconnects } Neuverbindungen
+status-connection-down = getrennt, { $reconnects } Neuverbindungen
+status-dropped = Verworfen
+status-dropped-value = { $chunks } Chunks, { $ms } ms
+status-last-error = Letzter Fehler
+
+diagnose-step = Prüfe { $step }...
+diagnose-written = Diagnosepaket nach { $path } geschrieben. Vor dem Anhängen an einen Bericht bitte durchsehen.
+
+discover-browsing = Suche { $seconds } s nach Servern...
+discover-none = Keine _loxaudio._tcp-Dienste gefunden.
+discover-host = Host
+discover-addresses = Adressen
+discover-url = URL
+discover-dns-sd-failed = DNS-SD-Abfrage fehlgeschlagen, nur mDNS-Ergebnisse: { $error }
+
+validate-checking = Prüfe { $path }
+validate-missing = Keine Konfigurationsdatei unter { $path }.
+validate-error = Fehler
+validate-warning = Warnung
+validate-ok = Keine Probleme gefunden.
+validate-summary = { $errors } Fehler, { $warnings } Warnungen
+validate-unknown-key = unbekannter Schlüssel; die Bridge ignoriert ihn
+validate-empty = darf nicht leer sein
+validate-zero = darf nicht 0 sein
+validate-bad-url = keine gültige URL: { $error }
+validate-not-http = keine http://- oder https://-URL
+validate-bad-mac = keine MAC-Adresse wie aa:bb:cc:dd:ee:ff
+validate-bad-value = { $error }
+validate-bad-choice = { $value } ist keiner von: { $choices }
+validate-out-of-range = { $value } liegt außerhalb von { $min } bis { $max }; die Bridge begrenzt den Wert
+validate-no-file = Datei nicht gefunden: { $path }
+validate-no-interface = derzeit keine Netzwerkschnittstelle { $name }
+validate-no-device = derzeit kein Aufnahmegerät { $device }; `lox-linein-bridge devices` listet sie auf
+validate-no-source = kein Eingang namens { $name } in sources.inputs
+validate-fft-drift = fft hat ein festes Verhältnis und folgt keiner Taktdrift; über Stunden läuft der Ingest-Puffer leer oder über. Besser sinc oder sinc-fast
+secrets-no-config = Keine Konfigurationsdatei gefunden; die Bridge einmal starten oder --file angeben.
+secrets-key-created = Geheimer Schlüssel erstellt: { $path }
+secrets-encrypted = Verschlüsselte Geheimnisse in { $path }: { $count }
+
+confirm-hint-yes = [J/n]
+confirm-hint-no = [j/N]
+
+wizard-intro = Im Netzwerk wurde kein Lox-Audioserver gefunden. Richten wir die Bridge ein.
+wizard-searching = Suche nach Servern...
+wizard-server-manual = Server-URL manuell eingeben
+wizard-server-rescan = erneut suchen
+wizard-server-prompt = Server
+wizard-server-url-prompt = Server-URL (z. B. http://192.168.1.10:7090)
+wizard-server-url-invalid = Die URL muss mit http:// oder https:// beginnen
+wizard-unknown-choice = Unbekannte Auswahl: { $choice }
+wizard-no-devices = Keine Aufnahmegeräte gefunden; der Server kann später eines zuweisen.
+wizard-devices = Aufnahmegeräte:
+wizard-device-monitor = (Monitor)
+wizard-device-skip = überspringen, der Server weist ein Gerät zu
+wizard-device-prompt = Gerät
+wizard-test-levels = Eingangspegel jetzt testen?
+wizard-level-test = Bitte etwas am Eingang abspielen; Pegelanzeige für { $seconds } s...
+wizard-level-test-failed = Pegeltest fehlgeschlagen: { $error }
+wizard-config-written = Konfiguration geschrieben: { $path }
+wizard-install = systemd-Dienst jetzt installieren und starten?
+wizard-installed = Der Dienst läuft; dieser Prozess wird beendet.
+
+analyze-device = Aufnahmegerät: { $device }
+analyze-noise = Messe Grundrauschen für { $seconds } s, Eingang offen lassen oder abschließen...
+analyze-tones = Spiele Testtöne ab, Ausgang per Loopback-Kabel mit dem Eingang verbinden...
+analyze-noise-floor = Grundrauschen: { $rms } dBFS RMS, { $peak } dBFS Spitze
+analyze-noise-band = Rauschen { $hz } Hz: { $db } dBFS
+analyze-response-band = Frequenzgang { $hz } Hz: { $db } dB
+analyze-deviation = Abweichung Frequenzgang: ±{ $db } dB
+analyze-report = Bericht: { $path }
+
+calibrate-measuring = Messe Grundrauschen von { $device } für { $seconds } s, Quelle bitte stumm lassen...
+calibrate-floor = Grundrauschen: { $db } dBFS
+calibrate-proposed = Vorgeschlagener vad_threshold_db: { $db }
+
+discovery-gave-up = Nach { $attempts } Suchversuchen ({ $seconds } s) wurde kein Lox-Audioserver gefunden; Abbruch.
+
+dump-requested = Black-Box-Dump bei Bridge-Prozess { $pid } angefordert; die Dateien stehen in dessen Log.
+dump-no-bridge = Kein laufender Bridge-Prozess gefunden.
+
+pipe-started = Streame { $device } mit { $rate } Hz, s16le Stereo, nach { $target }
@@ -0,0 +1,135 @@
+# User-facing strings of the CLI, installer and setup wizard.
+# Log messages stay in English.
+
+examples-heading = Examples:
+run-options-without-run = { $option } only applies to `run`; put it after `run` or leave the subcommand out
+
+install-config = Config: { $path }
+install-unit-written = Wrote systemd unit: { $path }
+install-token-saved = Saved the server token to the config.
+install-audio-group-missing = Warning: { $user } is not in the { $group } group and may not be able to open the capture device. Add it with: usermod -aG { $group } { $user }
+install-no-start = Unit written, not started. Start it with: systemctl { $scope }daemon-reload && systemctl { $scope }enable --now lox-linein-bridge
+install-init-written = Wrote init script: { $path }
+install-init-no-start = Init script written, not started. Start it with: { $enable } && { $restart }
+install-user-linger = To start the bridge at boot without logging in, run: loginctl enable-linger $USER
+install-server-mac-saved = Preferring the server with MAC { $mac }.
+install-config-owner = Config directory handed to { $user }:{ $group }.
+install-servers-found = Found { $count } servers; which one should the bridge use?
+
+update-current = lox-linein-bridge { $version } is up to date.
+update-available = Version { $version } is available (installed: { $current }). Run `lox-linein-bridge update` to install it.
+update-downloading = Downloading { $version }...
+update-installed = Installed { $version } to { $path }.
+update-restarted = Restarted the service.
+update-no-config = No config file found; set up the bridge first.
+
+devices-none = No capture devices found.
+devices-default = (default)
+devices-channels = channels
+devices-rates = rates
+devices-formats = formats
+devices-probe-failed = could not read supported formats: { $error }
+
+test-capture-opened = Capturing { $device }: { $rate } Hz, { $channels } ch, { $format } for { $seconds } s...
+test-capture-peaks = Highest peak: { $peaks }
+test-capture-clipping = The input clips; lower the gain.
+test-capture-silent = No signal above -60 dBFS; check the wiring and the source.
+
+record-started = Recording { $device } at { $rate } Hz ({ $resampler }) for { $seconds } s to { $path }...
+record-capped = A WAV file holds at most { $seconds } s at this rate; the recording stops there.
+record-written = Wrote { $seconds } s to { $path }.
+
+status-not-running = No health file at { $path }; the bridge is not running or the file reporter is off.
+status-stale = The health file was last written { $seconds } s ago; the bridge is probably not running.
+status-bridges = { $streaming } of { $total } bridges streaming
+status-state = State
+status-device = Device
+status-input = Input
+status-ingest = Ingest
+status-rate = Rate
+status-rate-observed = { $rate } Hz (device delivers { $observed } Hz)
+status-throughput = Throughput
+status-throughput-value = { $rate }/s, { $total } sent
+status-connection = Connection
+status-connection-up = up for { $uptime }, { $reconnects } reconnects
+status-connection-down = down, { $reconnects } reconnects
+status-dropped = Dropped
+status-dropped-value = { $chunks } chunks, { $ms } ms
+status-last-error = Last error
+
+diagnose-step = Checking { $step }...
+diagnose-written = Wrote the diagnostic bundle to { $path }. Check it before attaching it to a report.
+
+discover-browsing = Looking for servers for { $seconds } s...
+discover-none = No _loxaudio._tcp services found.
+discover-host = host
+discover-addresses = addresses
+discover-url = URL
+discover-dns-sd-failed = DNS-SD lookup failed, showing mDNS results only: { $error }
+
+validate-checking = Checking { $path }
+validate-missing = No config file at { $path }.
+validate-error = error
+validate-warning = warning
+validate-ok = No problems found.
+validate-summary = { $errors } errors, { $warnings } warnings
+validate-unknown-key = unknown key; the bridge ignores it
+validate-empty = must not be empty
+validate-zero = must not be 0
+validate-bad-url = not a valid URL: { $error }
+validate-not-http = not an http:// or https:// URL
+validate-bad-mac = not a MAC address like aa:bb:cc:dd:ee:ff
+validate-bad-value = { $error }
+validate-bad-choice = { $value } is not one of: { $choices }
+validate-out-of-range = { $value } is outside { $min } to { $max }; the bridge clamps it
+validate-no-file = no such file: { $path }
+validate-no-interface = no network interface { $name } right now
+validate-no-device = no capture device { $device } right now; `lox-linein-bridge devices` lists them
+validate-no-source = no input named { $name } in sources.inputs
+validate-fft-drift = fft has a fixed ratio and doesn't follow clock drift; over hours the ingest buffer drains or overflows. Prefer sinc or sinc-fast
+secrets-no-config = No config file found; run the bridge once or pass --file.
+secrets-key-created = Created secret key: { $path }
+secrets-encrypted = Secrets encrypted in { $path }: { $count }
+
+confirm-hint-yes = [Y/n]
+confirm-hint-no = [y/N]
+
+wizard-intro = No Lox audio server was found on the network. Let's set up the bridge.
+wizard-searching = Searching for servers...
+wizard-server-manual = enter server URL manually
+wizard-server-rescan = search again
+wizard-server-prompt = Server
+wizard-server-url-prompt = Server URL (e.g. http://192.168.1.10:7090)
+wizard-server-url-invalid = The URL must start with http:// or https://
+wizard-unknown-choice = Unknown choice: { $choice }
+wizard-no-devices = No capture devices found; the server can assign one later.
+wizard-devices = Capture devices:
+wizard-device-monitor = (monitor)
+wizard-device-skip = skip, let the server assign a device
+wizard-device-prompt = Device
+wizard-test-levels = Test input levels now?
+wizard-level-test = Play something on the input; showing levels for { $seconds } s...
+wizard-level-test-failed = Level test failed: { $error }
+wizard-config-written = Wrote config: { $path }
+wizard-install = Install and start the systemd service now?
+wizard-installed = The service is running; this process will exit.
+
+analyze-device = Capture device: { $device }
+analyze-noise = Measuring noise floor for { $seconds } s, leave the input unconnected or terminated...
+analyze-tones = Playing test tones, connect the output to the input with a loopback cable...
+analyze-noise-floor = Noise floor: { $rms } dBFS RMS, { $peak } dBFS peak
+analyze-noise-band = noise { $hz } Hz: { $db } dBFS
+analyze-response-band = response { $hz } Hz: { $db } dB
+analyze-deviation = Response deviation: ±{ $db } dB
+analyze-report = Report: { $path }
+
+calibrate-measuring = Measuring noise floor of { $device } for { $seconds } s, keep the source silent...
+calibrate-floor = Noise floor: { $db } dBFS
+calibrate-proposed = Proposed vad_threshold_db: { $db }
+
+discovery-gave-up = No Lox audio server found after { $attempts } discovery attempts ({ $seconds } s); giving up.
+
+dump-requested = Black box dump requested from bridge process { $pid }; see its log for the files.
+dump-no-bridge = No running bridge process found.
+
+pipe-started = Streaming { $device } at { $rate } Hz, s16le stereo, to { $target }
@@ -0,0 +1,346 @@
+use crate::audio::{self, CaptureOptions, ResamplerMode, TARGET_CHANNELS};
+use crate::cli::DeviceArgs;
+use crate::config;
+use crate::i18n::t;
+use crate::limiter::OutputSettings;
+use crate::models::CaptureReportSummary;
+use anyhow::{Context, Result};
+use realfft::RealFftPlanner;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const REPORT_FILE: &str = "capture-report.json";
+const RATE: u32 = 48_000;
+const FFT_SIZE: usize = 8192;
+/// Octave band centres used for both the noise spectrum and the response.
+const BANDS_HZ: [f32; 9] = [
+    63.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+const REFERENCE_HZ: f32 = 1000.0;
+const TONE_DURATION: Duration = Duration::from_secs(1);
+/// Test tone level, -12 dBFS.
+const TONE_AMPLITUDE: f32 = 0.25;
+/// Window used to find the start of the first tone in the capture.
+const ONSET_WINDOW_MS: usize = 10;
+const FLOOR_DB: f32 = -140.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BandLevel {
+    pub hz: f32,
+    pub db: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureReport {
+    pub created: String,
+    pub device: String,
+    pub rate: u32,
+    /// Broadband RMS with the input terminated, in dBFS.
+    pub noise_floor_db: f32,
+    pub noise_peak_db: f32,
+    /// Noise power per octave band, in dBFS.
+    pub noise_bands: Vec<BandLevel>,
+    /// Loopback level per octave relative to 1 kHz, in dB.
+    #[serde(default)]
+    pub response: Option<Vec<BandLevel>>,
+    /// Largest deviation of `response` from flat, in dB.
+    #[serde(default)]
+    pub response_deviation_db: Option<f32>,
+}
+
+impl CaptureReport {
+    pub fn summary(&self) -> CaptureReportSummary {
+        CaptureReportSummary {
+            created: self.created.clone(),
+            device: self.device.clone(),
+            noise_floor_db: self.noise_floor_db,
+            response_deviation_db: self.response_deviation_db,
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct AnalyzeOptions {
+    #[command(flatten)]
+    capture: DeviceArgs,
+    /// Also plays test tones and measures the frequency response.
+    #[arg(long)]
+    loopback: bool,
+    /// Playback device for `--loopback`.
+    #[arg(long = "output", value_name = "NAME", requires = "loopback")]
+    output_device: Option<String>,
+    /// Length of the noise measurement.
+    #[arg(long, default_value_t = 5)]
+    seconds: u64,
+}
+
+pub fn report_path(config_path: &Path) -> PathBuf {
+    config_path.with_file_name(REPORT_FILE)
+}
+
+/// Summary of the stored report, attached to registration.
+pub fn load_summary(config_path: &Path) -> Option<CaptureReportSummary> {
+    let data = std::fs::read_to_string(report_path(config_path)).ok()?;
+    let report: CaptureReport = serde_json::from_str(&data).ok()?;
+    Some(report.summary())
+}
+
+pub async fn run_analyze(options: AnalyzeOptions) -> Result<()> {
+    let (_config, config_path) = config::load_or_create_config()?;
+    let device = audio::default_capture_device(options.capture.device)?;
+    println!("{}", t!("analyze-device", device = device.as_str()));
+
+    println!("{}", t!("analyze-noise", seconds = options.seconds));
+    let noise = record(&device, Duration::from_secs(options.seconds), None).await?;
+    let noise_floor_db = rms_db(&noise);
+    let noise_peak_db = amplitude_db(noise.iter().fold(0f32, |peak, s| peak.max(s.abs())));
+    let noise_bands = band_levels(&left_channel(&noise));
+
+    let response = if options.loopback {
+        println!("{}", t!("analyze-tones"));
+        Some(measure_response(&device, options.output_device.as_deref(), noise_floor_db).await?)
+    } else {
+        None
+    };
+    let response_deviation_db = response
+        .as_ref()
+        .map(|bands| bands.iter().map(|band| band.db.abs()).fold(0f32, f32::max));
+
+    let report = CaptureReport {
+        created: crate::timestamp::now_rfc3339(),
+        device,
+        rate: RATE,
+        noise_floor_db,
+        noise_peak_db,
+        noise_bands,
+        response,
+        response_deviation_db,
+    };
+    let path = report_path(&config_path);
+    let contents = serde_json::to_string_pretty(&report).context("serialize report")?;
+    std::fs::write(&path, contents).with_context(|| format!("write {}", path.display()))?;
+
+    print_report(&report);
+    println!(
+        "{}",
+        t!("analyze-report", path = path.display().to_string())
+    );
+    Ok(())
+}
+
+fn print_report(report: &CaptureReport) {
+    println!(
+        "{}",
+        t!(
+            "analyze-noise-floor",
+            rms = format!("{:.1}", report.noise_floor_db),
+            peak = format!("{:.1}", report.noise_peak_db),
+        )
+    );
+    for band in &report.noise_bands {
+        let line = t!(
+            "analyze-noise-band",
+            hz = format!("{:>6}", band.hz),
+            db = format!("{:>7.1}", band.db),
+        );
+        println!("  {}", line);
+    }
+    if let Some(response) = &report.response {
+        for band in response {
+            let line = t!(
+                "analyze-response-band",
+                hz = format!("{:>6}", band.hz),
+                db = format!("{:>+6.1}", band.db),
+            );
+            println!("  {}", line);
+        }
+    }
+    if let Some(deviation) = report.response_deviation_db {
+        println!(
+            "{}",
+            t!("analyze-deviation", db = format!("{:.1}", deviation))
+        );
+    }
+}
+
+/// Captures interleaved stereo samples, optionally playing tones meanwhile.
+async fn record(
+    device: &str,
+    duration: Duration,
+    tones: Option<(Option<&str>, &[f32])>,
+) -> Result<Vec<f32>> {
+    let session = audio::start_capture(
+        device,
+        RATE,
+        ResamplerMode::SincQuality,
+        CaptureOptions::default(),
+        OutputSettings::default(),
+    )?;
+    let audio::CaptureSession {
+        mut receiver,
+        streams,
+        ..
+    } = session;
+    let _capture_guard = streams;
+    let _playback_guard = match tones {
+        Some((output, frequencies)) => Some(audio::play_tones(
+            output,
+            frequencies,
+            TONE_DURATION,
+            TONE_AMPLITUDE,
+        )?),
+        None => None,
+    };
+
+    let wanted = (duration.as_secs_f64() * RATE as f64) as usize * TARGET_CHANNELS as usize;
+    let mut samples = Vec::with_capacity(wanted);
+    while samples.len() < wanted {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .context("capture stalled")?
+            .context("capture stopped")?;
+        samples.extend(
+            chunk
+                .chunks_exact(2)
+                .map(|pair| i16::from_le_bytes([pair[0], pair[1]]) as f32 / i16::MAX as f32),
+        );
+    }
+    samples.truncate(wanted);
+    Ok(samples)
+}
+
+async fn measure_response(
+    device: &str,
+    output: Option<&str>,
+    noise_floor_db: f32,
+) -> Result<Vec<BandLevel>> {
+    let duration = TONE_DURATION * BANDS_HZ.len() as u32 + Duration::from_secs(2);
+    let samples = left_channel(&record(device, duration, Some((output, &BANDS_HZ))).await?);
+
+    let window = RATE as usize * ONSET_WINDOW_MS / 1000;
+    let onset_db = (noise_floor_db + 20.0).max(-60.0);
+    let onset = samples
+        .chunks(window)
+        .position(|chunk| rms_db(chunk) >= onset_db)
+        .map(|idx| idx * window)
+        .context("no test tone captured, check the loopback cable")?;
+
+    let tone_len = (TONE_DURATION.as_secs_f64() * RATE as f64) as usize;
+    let mut levels = Vec::with_capacity(BANDS_HZ.len());
+    for (idx, hz) in BANDS_HZ.iter().enumerate() {
+        // Measure the middle half of each tone, away from the transitions.
+        let start = onset + idx * tone_len + tone_len / 4;
+        let end = (start + tone_len / 2).min(samples.len());
+        let db = if start < end {
+            amplitude_db(goertzel_amplitude(&samples[start..end], *hz))
+        } else {
+            FLOOR_DB
+        };
+        levels.push(BandLevel { hz: *hz, db });
+    }
+    let reference = levels
+        .iter()
+        .find(|band| band.hz == REFERENCE_HZ)
+        .map(|band| band.db)
+        .unwrap_or(0.0);
+    Ok(levels
+        .into_iter()
+        .map(|band| BandLevel {
+            hz: band.hz,
+            db: band.db - reference,
+        })
+        .collect())
+}
+
+fn left_channel(samples: &[f32]) -> Vec<f32> {
+    samples
+        .iter()
+        .step_by(TARGET_CHANNELS as usize)
+        .copied()
+        .collect()
+}
+
+/// Averaged Hann-windowed power per octave band, in dBFS.
+fn band_levels(samples: &[f32]) -> Vec<BandLevel> {
+    let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FFT_SIZE);
+    let window = (0..FFT_SIZE)
+        .map(|idx| {
+            let phase = 2.0 * std::f32::consts::PI * idx as f32 / FFT_SIZE as f32;
+            0.5 - 0.5 * phase.cos()
+        })
+        .collect::<Vec<_>>();
+    let window_power: f32 = window.iter().map(|w| w * w).sum();
+    let mut frame = fft.make_input_vec();
+    let mut spectrum = fft.make_output_vec();
+    let mut power = vec![0f32; spectrum.len()];
+    let mut frames = 0usize;
+    for chunk in samples.chunks_exact(FFT_SIZE) {
+        for (idx, slot) in frame.iter_mut().enumerate() {
+            *slot = chunk[idx] * window[idx];
+        }
+        if fft.process(&mut frame, &mut spectrum).is_err() {
+            continue;
+        }
+        for (acc, bin) in power.iter_mut().zip(&spectrum) {
+            *acc += bin.norm_sqr();
+        }
+        frames += 1;
+    }
+
+    let bin_hz = RATE as f32 / FFT_SIZE as f32;
+    let scale = 2.0 / (FFT_SIZE as f32 * window_power * frames.max(1) as f32);
+    BANDS_HZ
+        .iter()
+        .map(|hz| {
+            let low = ((hz / std::f32::consts::SQRT_2) / bin_hz) as usize;
+            let high = (((hz * std::f32::consts::SQRT_2) / bin_hz) as usize).min(power.len());
+            let band: f32 = power[low.min(high)..high].iter().sum::<f32>() * scale;
+            BandLevel {
+                hz: *hz,
+                db: power_db(band),
+            }
+        })
+        .collect()
+}
+
+fn goertzel_amplitude(samples: &[f32], hz: f32) -> f32 {
+    let omega = 2.0 * std::f32::consts::PI * hz / RATE as f32;
+    let coeff = 2.0 * omega.cos();
+    let (mut s1, mut s2) = (0f32, 0f32);
+    for sample in samples {
+        let s0 = sample + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    let power = (s1 * s1 + s2 * s2 - coeff * s1 * s2).max(0.0);
+    2.0 * power.sqrt() / samples.len().max(1) as f32
+}
+
+fn rms_db(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return FLOOR_DB;
+    }
+    let mean_square = samples
+        .iter()
+        .map(|s| (*s as f64) * (*s as f64))
+        .sum::<f64>()
+        / samples.len() as f64;
+    power_db(mean_square as f32)
+}
+
+fn power_db(power: f32) -> f32 {
+    if power <= 0.0 {
+        FLOOR_DB
+    } else {
+        (10.0 * power.log10()).max(FLOOR_DB)
+    }
+}
+
+fn amplitude_db(amplitude: f32) -> f32 {
+    if amplitude <= 0.0 {
+        FLOOR_DB
+    } else {
+        (20.0 * amplitude.log10()).max(FLOOR_DB)
+    }
+}
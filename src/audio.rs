@@ -1,23 +1,57 @@
+use crate::blackbox;
+use crate::limiter::{OutputSettings, OutputStage};
+use crate::pool::{Chunk, ChunkPool};
+use crate::ring;
+use crate::timestamp::now_unix_us;
 use anyhow::{Context, Result};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{HostId, SampleFormat, StreamConfig};
 use rubato::{
-    Resampler as RubatoResampler, SincFixedIn, SincInterpolationParameters, SincInterpolationType,
-    WindowFunction,
+    FftFixedIn, Resampler as RubatoResampler, SincFixedIn, SincInterpolationParameters,
+    SincInterpolationType, WindowFunction,
 };
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, VecDeque};
+use std::ffi::{CStr, CString};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 
 pub const TARGET_CHANNELS: u16 = 2;
+const MONITOR_SUFFIX: &str = ".monitor";
+const PULSE_PCM_NAMES: &[&str] = &["pulse", "pipewire"];
+const ALSA_PCM_PREFIXES: &[&str] = &["plughw", "sysdefault", "dsnoop", "front"];
+/// Measured rates further than this from the current input rate are treated
+/// as a different clock (wrong nominal rate) and rebuild the resampler;
+/// anything closer is drift and only nudges the ratio.
+const MAX_DRIFT: f64 = 0.01;
+/// Checks in a row that must put the rate past `MAX_DRIFT`, at about the
+/// same value, before the resampler is rebuilt.
+const CLOCK_CHANGE_CHECKS: u32 = 3;
+/// How often the input rate is measured.
+const RATE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// The rate is the slope of a least-squares line through (callback time,
+/// frames so far) over this window. Counting whole periods per window
+/// instead is off by up to a period, about 1% of 2 s at 1024 frames.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Window the estimate needs before it moves the ratio.
+const DRIFT_MIN_SPAN: Duration = Duration::from_secs(20);
+/// Weight of each new rate measurement in the drift estimate.
+const DRIFT_SMOOTHING: f64 = 0.1;
+/// Capture data the ring between callback and processing thread can hold.
+const RING_SECONDS: f64 = 0.5;
+/// Wake-up interval of the processing thread when no callback arrives.
+const PROCESS_IDLE_WAIT: Duration = Duration::from_millis(100);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ResamplerMode {
     Linear,
     SincFast,
     SincQuality,
+    /// FFT-based, much cheaper than sinc at adequate quality for
+    /// 44.1→48 kHz. Works on nominal rates only.
+    Fft,
 }
 
 impl ResamplerMode {
@@ -26,6 +60,7 @@ impl ResamplerMode {
             "linear" | "basic" => Some(Self::Linear),
             "sinc" | "rubato" | "quality" | "hq" => Some(Self::SincQuality),
             "sinc-fast" | "fast" | "medium" => Some(Self::SincFast),
+            "fft" | "rubato-fft" => Some(Self::Fft),
             _ => None,
         }
     }
@@ -35,18 +70,123 @@ impl ResamplerMode {
             Self::Linear => "linear",
             Self::SincFast => "sinc-fast",
             Self::SincQuality => "sinc",
+            Self::Fft => "fft",
         }
     }
 }
 
+/// How the capture device is opened. `exclusive` bypasses the plug/dsnoop
+/// layers by opening the matching raw `hw:` device directly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CaptureOptions {
+    pub exclusive: bool,
+    pub period_frames: Option<u32>,
+}
+
 pub struct CaptureSession {
-    pub receiver: mpsc::UnboundedReceiver<Vec<u8>>,
+    pub receiver: mpsc::UnboundedReceiver<Chunk>,
     pub error_receiver: mpsc::Receiver<String>,
-    pub stream: cpal::Stream,
+    /// Capture stops when these are dropped.
+    pub streams: Vec<cpal::Stream>,
     pub sample_rate: u32,
     pub channels: u16,
     pub format: SampleFormat,
     pub observed_rate: Arc<Mutex<Option<u32>>>,
+    pub counters: Arc<CaptureCounters>,
+    pub flush: CaptureFlush,
+}
+
+/// Running totals since capture started, polled into the status.
+#[derive(Debug, Default)]
+pub struct CaptureCounters {
+    /// NaN/Inf samples replaced with silence.
+    pub non_finite_samples: AtomicU64,
+    /// Chunks altered by the output ceiling.
+    pub ceiling_interventions: AtomicU64,
+    /// Frames dropped because processing fell behind the callback.
+    pub overrun_frames: AtomicU64,
+    /// Blocks the resampler failed on; their audio is lost.
+    pub resampler_errors: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CounterSnapshot {
+    pub non_finite_samples: u64,
+    pub ceiling_interventions: u64,
+    pub overrun_frames: u64,
+    pub resampler_errors: u64,
+}
+
+impl CaptureCounters {
+    pub fn snapshot(&self) -> CounterSnapshot {
+        CounterSnapshot {
+            non_finite_samples: self.non_finite_samples.load(Ordering::Relaxed),
+            ceiling_interventions: self.ceiling_interventions.load(Ordering::Relaxed),
+            overrun_frames: self.overrun_frames.load(Ordering::Relaxed),
+            resampler_errors: self.resampler_errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl CounterSnapshot {
+    /// Increase since `earlier`.
+    pub fn since(&self, earlier: &CounterSnapshot) -> CounterSnapshot {
+        CounterSnapshot {
+            non_finite_samples: self
+                .non_finite_samples
+                .saturating_sub(earlier.non_finite_samples),
+            ceiling_interventions: self
+                .ceiling_interventions
+                .saturating_sub(earlier.ceiling_interventions),
+            overrun_frames: self.overrun_frames.saturating_sub(earlier.overrun_frames),
+            resampler_errors: self
+                .resampler_errors
+                .saturating_sub(earlier.resampler_errors),
+        }
+    }
+}
+
+/// Emits the frames still held inside the resampler when a capture session
+/// ends, so the tail of the audio isn't lost.
+pub struct CaptureFlush {
+    parts: Vec<FlushPart>,
+}
+
+struct FlushPart {
+    /// Processing thread, which returns its state once capture closed.
+    worker: std::thread::JoinHandle<CaptureProcessor>,
+}
+
+impl CaptureFlush {
+    /// Combines the flushes of sessions that feed one stream.
+    pub fn merge(flushes: impl IntoIterator<Item = CaptureFlush>) -> Self {
+        Self {
+            parts: flushes.into_iter().flat_map(|flush| flush.parts).collect(),
+        }
+    }
+
+    /// Call after dropping the capture streams. Waits for the processing
+    /// thread to drain; the receiver closes once the tail has been delivered.
+    pub fn finish(self) {
+        for FlushPart { worker } in self.parts {
+            if let Ok(mut processor) = worker.join() {
+                processor.flush();
+            }
+        }
+    }
+}
+
+/// `explicit` if given, otherwise the first capture device that isn't a
+/// monitor.
+pub fn default_capture_device(explicit: Option<String>) -> Result<String> {
+    match explicit {
+        Some(device) => Ok(device),
+        None => list_input_device_details()?
+            .into_iter()
+            .find(|device| !device.is_monitor)
+            .map(|device| device.id)
+            .context("no capture device found"),
+    }
 }
 
 pub fn list_input_device_details() -> Result<Vec<crate::models::CaptureDeviceInfo>> {
@@ -68,31 +208,367 @@ pub fn list_input_device_details() -> Result<Vec<crate::models::CaptureDeviceInf
                 rates.insert(max);
             }
         }
+        let is_monitor = is_loopback_name(&name);
         results.push(crate::models::CaptureDeviceInfo {
             id: name.clone(),
             name,
             channels,
             sample_rates: rates.into_iter().collect(),
+            is_monitor,
         });
     }
+    results.extend(list_monitor_sources());
     Ok(results)
 }
 
+/// What a capture device supports, for `devices`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DeviceProbe {
+    /// Value for `capture_device`.
+    pub id: String,
+    pub is_default: bool,
+    pub is_monitor: bool,
+    pub channels: Vec<u16>,
+    /// Supported rate ranges in Hz; `min == max` for fixed rates.
+    pub rates: Vec<RateRange>,
+    pub formats: Vec<String>,
+    /// Why the supported configurations couldn't be read, e.g. device busy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct RateRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+/// Every capture device with its supported channel counts, rates and
+/// sample formats, plus PulseAudio/PipeWire monitor sources.
+pub fn probe_input_devices() -> Result<Vec<DeviceProbe>> {
+    let host = select_host()?;
+    let default = host
+        .default_input_device()
+        .and_then(|device| device.name().ok());
+    let mut probes = Vec::new();
+    for device in host.input_devices().context("enumerate input devices")? {
+        let id = device
+            .name()
+            .unwrap_or_else(|_| "Unknown Device".to_string());
+        let mut channels = BTreeSet::new();
+        let mut rates = BTreeSet::new();
+        let mut formats = BTreeSet::new();
+        let error = match device.supported_input_configs() {
+            Ok(configs) => {
+                for config in configs {
+                    channels.insert(config.channels());
+                    rates.insert(RateRange {
+                        min: config.min_sample_rate().0,
+                        max: config.max_sample_rate().0,
+                    });
+                    formats.insert(config.sample_format().to_string());
+                }
+                None
+            }
+            Err(err) => Some(err.to_string()),
+        };
+        probes.push(DeviceProbe {
+            is_default: default.as_deref() == Some(id.as_str()),
+            is_monitor: is_loopback_name(&id),
+            id,
+            channels: channels.into_iter().collect(),
+            rates: rates.into_iter().collect(),
+            formats: formats.into_iter().collect(),
+            error,
+        });
+    }
+    probes.extend(list_monitor_sources().into_iter().map(|source| {
+        DeviceProbe {
+            id: source.id,
+            is_default: false,
+            is_monitor: true,
+            channels: vec![source.channels],
+            rates: source
+                .sample_rates
+                .iter()
+                .map(|&rate| RateRange {
+                    min: rate,
+                    max: rate,
+                })
+                .collect(),
+            // Converted by the sound server.
+            formats: Vec::new(),
+            error: None,
+        }
+    }));
+    Ok(probes)
+}
+
+fn is_loopback_name(name: &str) -> bool {
+    name.contains("CARD=Loopback") || name.ends_with(MONITOR_SUFFIX)
+}
+
+/// Lists PulseAudio/PipeWire monitor sources via `pactl`. These are captured
+/// through the `pulse`/`pipewire` ALSA PCM, set to read from them.
+fn list_monitor_sources() -> Vec<crate::models::CaptureDeviceInfo> {
+    let output = match std::process::Command::new("pactl")
+        .args(["list", "short", "sources"])
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return Vec::new(),
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let name = fields.nth(1)?.trim();
+            if !name.ends_with(MONITOR_SUFFIX) {
+                return None;
+            }
+            let spec = fields.nth(1).unwrap_or_default();
+            let channels = spec
+                .split_whitespace()
+                .find_map(|part| part.strip_suffix("ch").and_then(|ch| ch.parse().ok()))
+                .unwrap_or(TARGET_CHANNELS);
+            let rate = spec
+                .split_whitespace()
+                .find_map(|part| part.strip_suffix("Hz").and_then(|hz| hz.parse().ok()));
+            Some(crate::models::CaptureDeviceInfo {
+                id: name.to_string(),
+                name: name.to_string(),
+                channels,
+                sample_rates: rate.into_iter().collect(),
+                is_monitor: true,
+            })
+        })
+        .collect()
+}
+
+fn find_input_device(host: &cpal::Host, device_name: &str) -> Result<cpal::Device> {
+    let found = host
+        .input_devices()
+        .context("enumerate input devices")?
+        .find(|dev| dev.name().map(|name| name == device_name).unwrap_or(false));
+    if let Some(device) = found {
+        return Ok(device);
+    }
+    if !device_name.ends_with(MONITOR_SUFFIX) {
+        anyhow::bail!("capture device not found");
+    }
+    // Monitor sources are not ALSA devices; open the pulse PCM with its
+    // source set to the monitor instead.
+    let plugin = host
+        .input_devices()
+        .context("enumerate input devices")?
+        .filter_map(|dev| dev.name().ok())
+        .find(|name| PULSE_PCM_NAMES.contains(&name.as_str()))
+        .context("monitor source requires the pulse or pipewire ALSA plugin")?;
+    let _lock = PCM_CONFIG_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let field = monitor_source_field(&plugin)?;
+    let source = CString::new(device_name).context("monitor source name")?;
+    let previous = set_config_string(&field, Some(&source))?;
+    // cpal opens the PCM while listing it, so the source only has to be set
+    // during the search.
+    let found = host.input_devices().map(|mut devices| {
+        devices.find(|dev| dev.name().map(|name| name == plugin).unwrap_or(false))
+    });
+    set_config_string(&field, previous.as_deref())?;
+    found
+        .context("enumerate input devices")?
+        .with_context(|| format!("open monitor source {} through {}", device_name, plugin))
+}
+
+/// Serializes changes to the ALSA configuration tree.
+static PCM_CONFIG_LOCK: Mutex<()> = Mutex::new(());
+
+/// Setting of the `pulse` or `pipewire` PCM that names the source to read,
+/// as an ALSA configuration path.
+fn monitor_source_field(plugin: &str) -> Result<CString> {
+    let field = match plugin {
+        "pipewire" => "capture_node",
+        _ => "device",
+    };
+    Ok(CString::new(format!("pcm.{}.{}", plugin, field))?)
+}
+
+/// Sets (or with `None` removes) a string in alsa-lib's in-memory
+/// configuration, which PCMs opened afterwards in this process use, and
+/// returns the previous value. Unlike `PULSE_SOURCE`, this leaves the
+/// environment of other threads and child processes alone.
+fn set_config_string(path: &CStr, value: Option<&CStr>) -> Result<Option<CString>> {
+    let path_text = path.to_string_lossy();
+    let (parent, id) = path_text
+        .rsplit_once('.')
+        .with_context(|| format!("invalid alsa config path {}", path_text))?;
+    let parent = CString::new(parent)?;
+    let id = CString::new(id)?;
+    // `snd_config_update_ref` keeps the global tree alive until `unref`;
+    // every node pointer below stays inside that window.
+    unsafe {
+        let mut top = std::ptr::null_mut();
+        let err = alsa_sys::snd_config_update_ref(&mut top);
+        if err < 0 {
+            anyhow::bail!("load alsa configuration: error {}", err);
+        }
+        let mut node = std::ptr::null_mut();
+        let mut previous = None;
+        if alsa_sys::snd_config_search(top, path.as_ptr(), &mut node) >= 0 {
+            let mut text = std::ptr::null();
+            if alsa_sys::snd_config_get_string(node, &mut text) < 0 || text.is_null() {
+                alsa_sys::snd_config_unref(top);
+                anyhow::bail!("alsa config {} is not a string", path_text);
+            }
+            previous = Some(CStr::from_ptr(text).to_owned());
+        } else {
+            node = std::ptr::null_mut();
+        }
+        let err = match (value, node.is_null()) {
+            (Some(value), false) => alsa_sys::snd_config_set_string(node, value.as_ptr()),
+            (Some(value), true) => {
+                let mut parent_node = std::ptr::null_mut();
+                let mut err = alsa_sys::snd_config_search(top, parent.as_ptr(), &mut parent_node);
+                if err >= 0 {
+                    err = alsa_sys::snd_config_imake_string(&mut node, id.as_ptr(), value.as_ptr());
+                }
+                if err >= 0 {
+                    err = alsa_sys::snd_config_add(parent_node, node);
+                    if err < 0 {
+                        alsa_sys::snd_config_delete(node);
+                    }
+                }
+                err
+            }
+            (None, false) => alsa_sys::snd_config_delete(node),
+            (None, true) => 0,
+        };
+        alsa_sys::snd_config_unref(top);
+        if err < 0 {
+            anyhow::bail!("set alsa config {}: error {}", path_text, err);
+        }
+        Ok(previous)
+    }
+}
+
+/// Maps an ALSA PCM name such as `plughw:CARD=Device,DEV=0` to the raw
+/// `hw:` device on the same card. Returns `None` for virtual PCMs.
+fn hw_device_name(device_name: &str) -> Option<String> {
+    if device_name.starts_with("hw:") {
+        return Some(device_name.to_string());
+    }
+    let (prefix, args) = device_name.split_once(':')?;
+    if !ALSA_PCM_PREFIXES.contains(&prefix) {
+        return None;
+    }
+    let mut card = None;
+    let mut dev = "0";
+    for part in args.split(',') {
+        if let Some(value) = part.strip_prefix("CARD=") {
+            card = Some(value);
+        } else if let Some(value) = part.strip_prefix("DEV=") {
+            dev = value;
+        }
+    }
+    Some(format!("hw:CARD={},DEV={}", card?, dev))
+}
+
+/// Reads the pid holding a `hw:` capture substream from `/proc/asound`.
+fn hw_owner_pid(hw_name: &str) -> Option<u32> {
+    let args = hw_name.strip_prefix("hw:")?;
+    let mut card = None;
+    let mut dev = "0";
+    for (idx, part) in args.split(',').enumerate() {
+        if let Some(value) = part.strip_prefix("CARD=") {
+            card = Some(value);
+        } else if let Some(value) = part.strip_prefix("DEV=") {
+            dev = value;
+        } else if idx == 0 {
+            card = Some(part);
+        } else if idx == 1 {
+            dev = part;
+        }
+    }
+    let card = card?;
+    let card_dir = if card.chars().all(|c| c.is_ascii_digit()) {
+        format!("card{}", card)
+    } else {
+        card.to_string()
+    };
+    let status =
+        std::fs::read_to_string(format!("/proc/asound/{}/pcm{}c/sub0/status", card_dir, dev))
+            .ok()?;
+    status.lines().find_map(|line| {
+        let value = line.strip_prefix("owner_pid")?;
+        value
+            .trim_start_matches([' ', '\t', ':'])
+            .trim()
+            .parse()
+            .ok()
+    })
+}
+
+fn busy_error(device_name: &str) -> anyhow::Error {
+    let hw_name = hw_device_name(device_name).unwrap_or_else(|| device_name.to_string());
+    match hw_owner_pid(&hw_name) {
+        Some(pid) => anyhow::anyhow!(
+            "capture device {} is busy (held by pid {})",
+            device_name,
+            pid
+        ),
+        None => anyhow::anyhow!("capture device {} is busy", device_name),
+    }
+}
+
+fn find_exclusive_device(host: &cpal::Host, device_name: &str) -> Result<cpal::Device> {
+    let hw_name = hw_device_name(device_name)
+        .with_context(|| format!("{} has no raw hw: device", device_name))?;
+    let found = host
+        .input_devices()
+        .context("enumerate input devices")?
+        .find(|dev| dev.name().map(|name| name == hw_name).unwrap_or(false));
+    match found {
+        Some(device) => Ok(device),
+        // ALSA hints skip PCMs that cannot be opened, so a missing hw: device
+        // usually means another process holds the card.
+        None if hw_owner_pid(&hw_name).is_some() => Err(busy_error(&hw_name)),
+        None => anyhow::bail!("{} not found", hw_name),
+    }
+}
+
 pub fn start_capture(
     device_name: &str,
     target_rate: u32,
     resampler_mode: ResamplerMode,
+    options: CaptureOptions,
+    output: OutputSettings,
 ) -> Result<CaptureSession> {
     let host = select_host()?;
-    let device = host
-        .input_devices()
-        .context("enumerate input devices")?
-        .find(|dev| dev.name().map(|name| name == device_name).unwrap_or(false))
-        .context("capture device not found")?;
+    let device = if options.exclusive {
+        match find_exclusive_device(&host, device_name) {
+            Ok(device) => device,
+            Err(err) => {
+                warn!(
+                    "exclusive capture unavailable, using {}: {}",
+                    device_name, err
+                );
+                find_input_device(&host, device_name)
+                    .with_context(|| format!("exclusive capture failed: {}", err))?
+            }
+        }
+    } else {
+        find_input_device(&host, device_name)?
+    };
+    let opened_name = device.name().unwrap_or_else(|_| device_name.to_string());
 
-    let supported_configs = device
-        .supported_input_configs()
-        .context("read supported input configs")?;
+    let supported_configs = match device.supported_input_configs() {
+        Ok(configs) => configs,
+        Err(cpal::SupportedStreamConfigsError::DeviceNotAvailable) => {
+            return Err(busy_error(&opened_name));
+        }
+        Err(err) => return Err(err).context("read supported input configs"),
+    };
     let mut selected = None;
     for config in supported_configs {
         if config.channels() != TARGET_CHANNELS {
@@ -113,81 +589,325 @@ pub fn start_capture(
             .context("read default input config")?,
     };
     let sample_format = supported.sample_format();
-    let config: StreamConfig = supported.into();
+    if !matches!(
+        sample_format,
+        SampleFormat::F32 | SampleFormat::I16 | SampleFormat::U16
+    ) {
+        anyhow::bail!("unsupported sample format {}", sample_format);
+    }
+    let mut config: StreamConfig = supported.into();
+    if let Some(period) = options.period_frames {
+        // cpal sizes the ALSA buffer as four periods.
+        config.buffer_size = cpal::BufferSize::Fixed(period.saturating_mul(4));
+    }
 
-    let (tx, rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    let (tx, rx) = mpsc::unbounded_channel::<Chunk>();
     let (err_tx, err_rx) = mpsc::channel::<String>(4);
     let observed_rate = Arc::new(Mutex::new(None));
-    let resampler = Arc::new(Mutex::new(Resampler::new(
+    let resampler = Resampler::new(
         config.sample_rate.0,
         config.channels,
         target_rate,
         resampler_mode,
         Arc::clone(&observed_rate),
-    )?));
+    )?;
+
+    // The callback only copies samples into the ring; resampling runs on a
+    // separate thread so it can't overrun the callback.
+    let ring_frames = (config.sample_rate.0 as f64 * RING_SECONDS) as usize;
+    let build = |config: &StreamConfig| {
+        let (producer, consumer) = ring::ring(ring_frames, config.channels);
+        build_input_stream(&device, config, sample_format, producer, err_tx.clone())
+            .map(|stream| (stream, consumer))
+    };
+    let built = match build(&config) {
+        Err(err) if config.buffer_size != cpal::BufferSize::Default && !is_busy(&err) => {
+            warn!(
+                "{} refused the requested period size, using its default: {}",
+                opened_name, err
+            );
+            config.buffer_size = cpal::BufferSize::Default;
+            build(&config)
+        }
+        built => built,
+    };
+    let (stream, consumer) = match built {
+        Ok(built) => built,
+        Err(err) if is_busy(&err) => return Err(busy_error(&opened_name)),
+        Err(err) => return Err(err).context("build input stream"),
+    };
+
+    let counters = Arc::new(CaptureCounters::default());
+    let processor = CaptureProcessor {
+        channels: config.channels,
+        resampler,
+        output: OutputStage::new(output, target_rate),
+        tx,
+        pool: ChunkPool::new(),
+        samples: Vec::new(),
+        scrubbed: Vec::new(),
+        pcm: Vec::new(),
+        counters: Arc::clone(&counters),
+        black_box: blackbox::tap(
+            &opened_name,
+            config.sample_rate.0,
+            config.channels,
+            target_rate,
+        ),
+    };
+    let worker = std::thread::Builder::new()
+        .name("capture-dsp".to_string())
+        .spawn(move || processor.run(consumer))
+        .context("spawn capture processing thread")?;
+    let flush = CaptureFlush {
+        parts: vec![FlushPart { worker }],
+    };
+
+    stream.play().context("start capture stream")?;
+
+    Ok(CaptureSession {
+        receiver: rx,
+        error_receiver: err_rx,
+        streams: vec![stream],
+        sample_rate: config.sample_rate.0,
+        channels: config.channels,
+        format: sample_format,
+        observed_rate,
+        counters,
+        flush,
+    })
+}
+
+fn is_busy(err: &cpal::BuildStreamError) -> bool {
+    matches!(err, cpal::BuildStreamError::DeviceNotAvailable)
+}
 
+/// Opens a capture stream that converts every sample to f32 and pushes it
+/// into `producer`. Errors go to `errors`.
+fn build_input_stream(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    sample_format: SampleFormat,
+    mut producer: ring::Producer,
+    errors: mpsc::Sender<String>,
+) -> Result<cpal::Stream, cpal::BuildStreamError> {
     let err_fn = move |err| {
         let message = format!("capture error: {}", err);
         warn!("{}", message);
-        let _ = err_tx.try_send(message);
+        let _ = errors.try_send(message);
     };
-
-    let tx_f32 = tx.clone();
-    let tx_i16 = tx.clone();
-    let tx_u16 = tx.clone();
-    let resampler_f32 = Arc::clone(&resampler);
-    let resampler_i16 = Arc::clone(&resampler);
-    let resampler_u16 = Arc::clone(&resampler);
-    let stream = match sample_format {
+    match sample_format {
         SampleFormat::F32 => device.build_input_stream(
-            &config,
-            move |data: &[f32], _| {
-                handle_samples_f32(data, config.channels, &resampler_f32, tx_f32.clone());
-            },
+            config,
+            move |data: &[f32], _| producer.push(data),
             err_fn,
             None,
-        )?,
+        ),
         SampleFormat::I16 => device.build_input_stream(
-            &config,
+            config,
             move |data: &[i16], _| {
-                let mut buffer = Vec::with_capacity(data.len());
-                for sample in data {
-                    buffer.push(*sample as f32 / i16::MAX as f32);
-                }
-                handle_samples_f32(&buffer, config.channels, &resampler_i16, tx_i16.clone());
+                producer.push_map(data, |sample| sample as f32 / i16::MAX as f32)
             },
             err_fn,
             None,
-        )?,
+        ),
         SampleFormat::U16 => device.build_input_stream(
-            &config,
+            config,
             move |data: &[u16], _| {
-                let mut buffer = Vec::with_capacity(data.len());
-                for sample in data {
-                    let shifted = *sample as i32 - (i16::MAX as i32 + 1);
-                    buffer.push(shifted as f32 / (i16::MAX as f32 + 1.0));
-                }
-                handle_samples_f32(&buffer, config.channels, &resampler_u16, tx_u16.clone());
+                producer.push_map(data, |sample| {
+                    let shifted = sample as i32 - (i16::MAX as i32 + 1);
+                    shifted as f32 / (i16::MAX as f32 + 1.0)
+                })
             },
             err_fn,
             None,
-        )?,
-        _ => anyhow::bail!("unsupported sample format"),
+        ),
+        other => Err(cpal::BuildStreamError::BackendSpecific {
+            err: cpal::BackendSpecificError {
+                description: format!("unsupported sample format {}", other),
+            },
+        }),
+    }
+}
+
+/// Plays a sequence of sine tones, each `tone_duration` long, on an output
+/// device (the default one when `device_name` is unset). Silence follows the
+/// last tone. Used for loopback measurements of the capture chain.
+pub fn play_tones(
+    device_name: Option<&str>,
+    frequencies: &[f32],
+    tone_duration: Duration,
+    amplitude: f32,
+) -> Result<cpal::Stream> {
+    let host = select_host()?;
+    let device = match device_name {
+        Some(name) => host
+            .output_devices()
+            .context("enumerate output devices")?
+            .find(|dev| dev.name().map(|found| found == name).unwrap_or(false))
+            .with_context(|| format!("output device {} not found", name))?,
+        None => host
+            .default_output_device()
+            .context("no default output device")?,
+    };
+    let supported = device
+        .default_output_config()
+        .context("read default output config")?;
+    let sample_format = supported.sample_format();
+    let config: StreamConfig = supported.into();
+    let rate = config.sample_rate.0 as f32;
+    let channels = config.channels as usize;
+    let frames_per_tone = (tone_duration.as_secs_f32() * rate).max(1.0) as u64;
+    let frequencies = frequencies.to_vec();
+    let mut frame = 0u64;
+    let mut next_sample = move || {
+        let tone = (frame / frames_per_tone) as usize;
+        let value = match frequencies.get(tone) {
+            Some(freq) => {
+                let t = (frame % frames_per_tone) as f32 / rate;
+                amplitude * (2.0 * std::f32::consts::PI * freq * t).sin()
+            }
+            None => 0.0,
+        };
+        frame += 1;
+        value
     };
 
-    stream.play().context("start capture stream")?;
+    let err_fn = |err| warn!("playback error: {}", err);
+    let stream = match sample_format {
+        SampleFormat::F32 => device.build_output_stream(
+            &config,
+            move |data: &mut [f32], _| {
+                for out in data.chunks_mut(channels) {
+                    out.fill(next_sample());
+                }
+            },
+            err_fn,
+            None,
+        ),
+        SampleFormat::I16 => device.build_output_stream(
+            &config,
+            move |data: &mut [i16], _| {
+                for out in data.chunks_mut(channels) {
+                    out.fill(f32_to_i16(next_sample()));
+                }
+            },
+            err_fn,
+            None,
+        ),
+        _ => anyhow::bail!("unsupported output sample format"),
+    }
+    .context("build output stream")?;
+    stream.play().context("start playback stream")?;
+    Ok(stream)
+}
 
-    Ok(CaptureSession {
-        receiver: rx,
-        error_receiver: err_rx,
-        stream,
+/// An output stream fed with interleaved stereo f32 through `producer`.
+/// Plays silence while the ring runs empty.
+pub struct Playback {
+    /// Playback stops when this is dropped.
+    _stream: cpal::Stream,
+    pub producer: ring::Producer,
+    pub sample_rate: u32,
+}
+
+/// Opens an output device (the default one when `device_name` is unset),
+/// at `rate` when the device supports it, else at its default rate.
+pub fn start_playback(device_name: Option<&str>, rate: u32, buffer: Duration) -> Result<Playback> {
+    let host = select_host()?;
+    let device = match device_name {
+        Some(name) => host
+            .output_devices()
+            .context("enumerate output devices")?
+            .find(|dev| dev.name().map(|found| found == name).unwrap_or(false))
+            .with_context(|| format!("output device {} not found", name))?,
+        None => host
+            .default_output_device()
+            .context("no default output device")?,
+    };
+    let selected = device
+        .supported_output_configs()
+        .context("read supported output configs")?
+        .find(|config| {
+            config.channels() == TARGET_CHANNELS
+                && (config.min_sample_rate().0..=config.max_sample_rate().0).contains(&rate)
+        })
+        .map(|config| config.with_sample_rate(cpal::SampleRate(rate)));
+    let supported = match selected {
+        Some(config) => config,
+        None => device
+            .default_output_config()
+            .context("read default output config")?,
+    };
+    let sample_format = supported.sample_format();
+    let config: StreamConfig = supported.into();
+    let channels = config.channels as usize;
+    let frames = (config.sample_rate.0 as f64 * buffer.as_secs_f64()) as usize;
+    let (producer, consumer) = ring::ring(frames, TARGET_CHANNELS);
+    let mut source = PlaybackSource {
+        consumer,
+        stereo: vec![0.0; frames * 2],
+    };
+
+    let err_fn = |err| warn!("playback error: {}", err);
+    let stream = match sample_format {
+        SampleFormat::F32 => device.build_output_stream(
+            &config,
+            move |data: &mut [f32], _| source.fill(data, channels, |value| value),
+            err_fn,
+            None,
+        ),
+        SampleFormat::I16 => device.build_output_stream(
+            &config,
+            move |data: &mut [i16], _| source.fill(data, channels, f32_to_i16),
+            err_fn,
+            None,
+        ),
+        _ => anyhow::bail!("unsupported output sample format"),
+    }
+    .context("build output stream")?;
+    stream.play().context("start playback stream")?;
+    Ok(Playback {
+        _stream: stream,
+        producer,
         sample_rate: config.sample_rate.0,
-        channels: config.channels,
-        format: sample_format,
-        observed_rate,
     })
 }
 
+/// Playback end of the monitor ring.
+struct PlaybackSource {
+    consumer: ring::Consumer,
+    /// Sized for the whole ring up front so callbacks don't allocate.
+    stereo: Vec<f32>,
+}
+
+impl PlaybackSource {
+    /// Fills a device buffer, with silence where the ring runs short.
+    fn fill<T>(&mut self, data: &mut [T], channels: usize, convert: impl Fn(f32) -> T) {
+        let frames = data.len() / channels.max(1);
+        let len = (frames * 2).min(self.stereo.len());
+        let got = self.consumer.pop_slice(&mut self.stereo[..len]);
+        self.stereo[got..len].fill(0.0);
+        for (index, out) in data.chunks_mut(channels.max(1)).enumerate() {
+            let (left, right) = match self.stereo[..len].get(index * 2..index * 2 + 2) {
+                Some(frame) => (frame[0], frame[1]),
+                None => (0.0, 0.0),
+            };
+            match out {
+                [mono] => *mono = convert((left + right) * 0.5),
+                [first, second, rest @ ..] => {
+                    *first = convert(left);
+                    *second = convert(right);
+                    for extra in rest {
+                        *extra = convert(0.0);
+                    }
+                }
+                [] => {}
+            }
+        }
+    }
+}
+
 fn select_host() -> Result<cpal::Host> {
     let hosts = cpal::available_hosts();
     if hosts.contains(&HostId::Alsa) {
@@ -196,43 +916,155 @@ fn select_host() -> Result<cpal::Host> {
     Ok(cpal::default_host())
 }
 
-fn handle_samples_f32(
-    data: &[f32],
-    channels: u16,
-    resampler: &Arc<Mutex<Resampler>>,
-    tx: mpsc::UnboundedSender<Vec<u8>>,
-) {
-    let output = {
-        let mut resampler = match resampler.lock() {
-            Ok(guard) => guard,
-            Err(_) => return,
-        };
-        resampler.observe_input(data.len(), channels);
-        if resampler.needs_resample_rate() {
-            resampler.process(data, channels)
+/// Replaces NaN/Inf with silence before they reach the resampler, whose
+/// filter would smear them into full-scale noise. Returns the number of
+/// replaced samples; `scrubbed` holds the cleaned copy when it is non-zero.
+fn scrub_non_finite(data: &[f32], scrubbed: &mut Vec<f32>) -> usize {
+    if data.iter().all(|sample| sample.is_finite()) {
+        return 0;
+    }
+    scrubbed.clear();
+    let mut count = 0;
+    scrubbed.extend(data.iter().map(|sample| {
+        if sample.is_finite() {
+            *sample
         } else {
-            convert_direct_to_i16(data, channels)
+            count += 1;
+            0.0
         }
-    };
+    }));
+    count
+}
 
-    if output.is_empty() {
-        return;
+/// Processing thread of a capture session: drains the ring, resamples,
+/// converts, applies gain and ceiling, and sends chunks to the stream. The
+/// scratch buffers keep their capacity and chunks come from a pool, so
+/// steady-state processing doesn't allocate.
+struct CaptureProcessor {
+    channels: u16,
+    resampler: Resampler,
+    output: OutputStage,
+    tx: mpsc::UnboundedSender<Chunk>,
+    pool: ChunkPool,
+    samples: Vec<f32>,
+    scrubbed: Vec<f32>,
+    pcm: Vec<i16>,
+    counters: Arc<CaptureCounters>,
+    black_box: Option<blackbox::Tap>,
+}
+
+impl CaptureProcessor {
+    /// Runs until the capture stream is dropped and the ring is drained,
+    /// then hands itself back for the flush.
+    fn run(mut self, mut consumer: ring::Consumer) -> Self {
+        consumer.wake_current_thread();
+        loop {
+            let closed = consumer.is_closed();
+            let mut samples = std::mem::take(&mut self.samples);
+            samples.clear();
+            consumer.pop_into(&mut samples);
+            if !samples.is_empty() {
+                self.process(&samples);
+            }
+            let idle = samples.is_empty();
+            self.samples = samples;
+
+            self.counters
+                .overrun_frames
+                .store(consumer.dropped_frames(), Ordering::Relaxed);
+            self.counters
+                .resampler_errors
+                .store(self.resampler.errors(), Ordering::Relaxed);
+
+            if closed {
+                return self;
+            }
+            if idle {
+                std::thread::park_timeout(PROCESS_IDLE_WAIT);
+            }
+        }
     }
 
-    let mut bytes = Vec::with_capacity(output.len() * 2);
-    for sample in output {
-        bytes.extend_from_slice(&sample.to_le_bytes());
+    fn process(&mut self, data: &[f32]) {
+        if let Some(tap) = self.black_box.as_ref() {
+            tap.raw(data);
+        }
+        // Broken drivers can deliver non-finite float samples.
+        let count = scrub_non_finite(data, &mut self.scrubbed);
+        if count == 0 {
+            self.emit(data);
+            return;
+        }
+        self.counters
+            .non_finite_samples
+            .fetch_add(count as u64, Ordering::Relaxed);
+        let scrubbed = std::mem::take(&mut self.scrubbed);
+        self.emit(&scrubbed);
+        self.scrubbed = scrubbed;
+    }
+
+    fn emit(&mut self, data: &[f32]) {
+        self.pcm.clear();
+        self.resampler.observe_input(data.len(), self.channels);
+        self.resampler
+            .convert_into(data, self.channels, &mut self.pcm);
+
+        if self.pcm.is_empty() {
+            return;
+        }
+
+        let mut chunk = self.pool.take(self.pcm.len() * 2);
+        extend_i16_bytes(&mut chunk, &self.pcm);
+        self.send(chunk);
+    }
+
+    /// Sends the frames still held inside the resampler.
+    fn flush(&mut self) {
+        let tail = self.resampler.flush();
+        self.counters
+            .resampler_errors
+            .store(self.resampler.errors(), Ordering::Relaxed);
+        if !tail.is_empty() {
+            self.send(Chunk::from(i16_to_bytes(&tail)));
+        }
     }
 
-    let _ = tx.send(bytes);
+    fn send(&mut self, mut chunk: Chunk) {
+        // The newest frame left the device roughly now; ring and resampler
+        // latency are small next to network jitter.
+        chunk.captured_at_us = now_unix_us();
+        if self.output.process(&mut chunk) {
+            self.counters
+                .ceiling_interventions
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(tap) = self.black_box.as_ref() {
+            tap.processed(&chunk);
+        }
+        let _ = self.tx.send(chunk);
+    }
 }
 
-fn convert_direct_to_i16(data: &[f32], channels: u16) -> Vec<i16> {
+pub(crate) fn i16_to_bytes(samples: &[i16]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(samples.len() * 2);
+    extend_i16_bytes(&mut bytes, samples);
+    bytes
+}
+
+fn extend_i16_bytes(bytes: &mut Vec<u8>, samples: &[i16]) {
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+}
+
+/// Appends `data` as stereo i16 without resampling.
+fn convert_direct_to_i16(data: &[f32], channels: u16, out: &mut Vec<i16>) {
     if channels == TARGET_CHANNELS && data.len().is_multiple_of(2) {
-        return data.iter().map(|s| f32_to_i16(*s)).collect();
+        crate::simd::f32_to_i16(data, out);
+        return;
     }
 
-    let mut out = Vec::with_capacity(data.len());
+    out.reserve(data.len());
     let mut idx = 0;
     while idx + channels as usize <= data.len() {
         let frame = &data[idx..idx + channels as usize];
@@ -241,7 +1073,6 @@ fn convert_direct_to_i16(data: &[f32], channels: u16) -> Vec<i16> {
         out.push(f32_to_i16(right));
         idx += channels as usize;
     }
-    out
 }
 
 fn map_channels(frame: &[f32], channels: u16) -> (f32, f32) {
@@ -257,16 +1088,24 @@ fn f32_to_i16(sample: f32) -> i16 {
     (clamped * i16::MAX as f32) as i16
 }
 
-struct Resampler {
+pub(crate) struct Resampler {
     mode: ResamplerMode,
     in_rate: u32,
     target_rate: u32,
+    /// Smoothed measured input rate the ratio currently follows.
+    effective_rate: f64,
     linear: LinearResampler,
-    sinc: Option<SincResampler>,
-    rate_frames: u64,
-    rate_start: Instant,
+    block: Option<BlockResampler>,
+    rate: RateEstimator,
+    /// Past-`MAX_DRIFT` measurements in a row, and the first of them.
+    clock_change: Option<(u32, f64)>,
+    last_rate_check: Instant,
     last_rate_log: Instant,
+    /// Logged once when a fixed-ratio resampler can't follow drift.
+    drift_ignored: bool,
     observed_rate: Arc<Mutex<Option<u32>>>,
+    /// Failed ratio updates and resets; block failures count in `block`.
+    errors: u64,
 }
 
 impl Resampler {
@@ -277,18 +1116,12 @@ impl Resampler {
         mode: ResamplerMode,
         observed_rate: Arc<Mutex<Option<u32>>>,
     ) -> Result<Self> {
-        let sinc = match mode {
+        let block = match mode {
             ResamplerMode::Linear => None,
-            ResamplerMode::SincFast => Some(SincResampler::new(
-                in_rate,
-                target_rate,
-                SincQuality::Fast,
-                in_channels,
-            )?),
-            ResamplerMode::SincQuality => Some(SincResampler::new(
+            _ => Some(BlockResampler::new(
                 in_rate,
                 target_rate,
-                SincQuality::Quality,
+                mode,
                 in_channels,
             )?),
         };
@@ -296,52 +1129,123 @@ impl Resampler {
             mode,
             in_rate,
             target_rate,
+            effective_rate: in_rate as f64,
             linear: LinearResampler::new(in_channels),
-            sinc,
-            rate_frames: 0,
-            rate_start: Instant::now(),
+            block,
+            rate: RateEstimator::default(),
+            clock_change: None,
+            last_rate_check: Instant::now(),
             last_rate_log: Instant::now(),
+            drift_ignored: false,
             observed_rate,
+            errors: 0,
         })
     }
 
+    /// Resampler without rate observation, for replaying recorded input.
+    pub(crate) fn offline(
+        in_rate: u32,
+        in_channels: u16,
+        target_rate: u32,
+        mode: ResamplerMode,
+    ) -> Result<Self> {
+        Self::new(
+            in_rate,
+            in_channels,
+            target_rate,
+            mode,
+            Arc::new(Mutex::new(None)),
+        )
+    }
+
+    fn errors(&self) -> u64 {
+        self.errors + self.block.as_ref().map_or(0, |block| block.errors)
+    }
+
     fn needs_resample_rate(&self) -> bool {
-        self.in_rate != self.target_rate
+        self.effective_rate != self.target_rate as f64
+    }
+
+    /// Converts interleaved input to stereo i16 at the target rate.
+    pub(crate) fn convert(&mut self, input: &[f32], in_channels: u16) -> Vec<i16> {
+        let mut out = Vec::new();
+        self.convert_into(input, in_channels, &mut out);
+        out
     }
 
-    fn process(&mut self, input: &[f32], in_channels: u16) -> Vec<i16> {
+    /// Like `convert`, appending to `out`.
+    fn convert_into(&mut self, input: &[f32], in_channels: u16, out: &mut Vec<i16>) {
+        if self.needs_resample_rate() {
+            self.process(input, in_channels, out)
+        } else {
+            convert_direct_to_i16(input, in_channels, out)
+        }
+    }
+
+    fn process(&mut self, input: &[f32], in_channels: u16, out: &mut Vec<i16>) {
         if input.is_empty() || in_channels == 0 {
-            return Vec::new();
+            return;
         }
 
         match self.mode {
-            ResamplerMode::Linear => {
-                self.linear
-                    .process(input, in_channels, self.in_rate, self.target_rate)
-            }
-            ResamplerMode::SincFast | ResamplerMode::SincQuality => {
-                if let Some(sinc) = self.sinc.as_mut() {
-                    sinc.process(input, in_channels)
-                } else {
-                    Vec::new()
+            ResamplerMode::Linear => self.linear.process(
+                input,
+                in_channels,
+                self.effective_rate,
+                self.target_rate,
+                out,
+            ),
+            ResamplerMode::SincFast | ResamplerMode::SincQuality | ResamplerMode::Fft => {
+                if let Some(block) = self.block.as_mut() {
+                    block.process(input, in_channels, out)
                 }
             }
         }
     }
 
+    /// Drains frames buffered inside the resampler, padding its last block
+    /// with silence. Only block resamplers hold back a meaningful amount.
+    pub(crate) fn flush(&mut self) -> Vec<i16> {
+        if !self.needs_resample_rate() {
+            return Vec::new();
+        }
+        match self.block.as_mut() {
+            Some(block) => block.flush(),
+            None => Vec::new(),
+        }
+    }
+
     fn observe_input(&mut self, samples: usize, in_channels: u16) {
         let frames = samples / in_channels as usize;
-        self.rate_frames = self.rate_frames.saturating_add(frames as u64);
-        let elapsed = self.rate_start.elapsed();
-        if elapsed < Duration::from_secs(2) {
+        self.rate.add(frames as u64);
+        if self.last_rate_check.elapsed() < RATE_CHECK_INTERVAL {
             return;
         }
-        let observed = (self.rate_frames as f64 / elapsed.as_secs_f64()).round() as u32;
-        if observed == 0 {
+        self.last_rate_check = Instant::now();
+        let Some((measured, span)) = self.rate.estimate() else {
+            return;
+        };
+        if measured < 1.0 {
             return;
         }
 
-        if observed != self.in_rate {
+        let base = self.effective_rate;
+        if (measured - base).abs() / base > MAX_DRIFT {
+            // One odd measurement (a stall, a burst after an xrun) must not
+            // rebuild the resampler at a wrong rate.
+            let (checks, first) = match self.clock_change {
+                Some((checks, first)) if (measured - first).abs() / first <= MAX_DRIFT / 2.0 => {
+                    (checks + 1, first)
+                }
+                _ => (1, measured),
+            };
+            self.clock_change = Some((checks, first));
+            if checks < CLOCK_CHANGE_CHECKS {
+                return;
+            }
+            self.clock_change = None;
+            self.rate = RateEstimator::default();
+            let observed = measured.round() as u32;
             info!(
                 "observed input rate: {} Hz (was {} Hz, target {} Hz, resampler={})",
                 observed,
@@ -350,50 +1254,125 @@ impl Resampler {
                 self.mode.label()
             );
             self.in_rate = observed;
+            self.effective_rate = observed as f64;
             self.reset_resampler();
             self.last_rate_log = Instant::now();
-        } else if self.last_rate_log.elapsed() >= Duration::from_secs(10) {
-            info!(
-                "observed input rate: {} Hz (target {} Hz, resampler={})",
-                observed,
-                self.target_rate,
-                self.mode.label()
-            );
-            self.last_rate_log = Instant::now();
+        } else {
+            self.clock_change = None;
+            if span < DRIFT_MIN_SPAN {
+                return;
+            }
+            let estimate = base + (measured - base) * DRIFT_SMOOTHING;
+            self.follow_drift(estimate);
+            if self.last_rate_log.elapsed() >= Duration::from_secs(10) {
+                info!(
+                    "observed input rate: {:.2} Hz ({:+.0} ppm vs {} Hz, target {} Hz, resampler={})",
+                    self.effective_rate,
+                    (self.effective_rate / self.in_rate as f64 - 1.0) * 1e6,
+                    self.in_rate,
+                    self.target_rate,
+                    self.mode.label()
+                );
+                self.last_rate_log = Instant::now();
+            }
         }
         if let Ok(mut slot) = self.observed_rate.lock() {
-            *slot = Some(observed);
+            *slot = Some(self.effective_rate.round() as u32);
         }
-        self.rate_frames = 0;
-        self.rate_start = Instant::now();
     }
 
-    fn reset_resampler(&mut self) {
-        match self.mode {
-            ResamplerMode::Linear => {
-                self.linear.reset();
-            }
-            ResamplerMode::SincFast => {
-                if let Some(sinc) = self.sinc.as_mut() {
-                    if let Err(err) = sinc.reset(self.in_rate, self.target_rate, SincQuality::Fast)
-                    {
-                        warn!("resampler reset failed: {}", err);
+    /// Moves the resample ratio to the drift estimate without a reset, so
+    /// long-term output stays locked to the target rate.
+    fn follow_drift(&mut self, estimate: f64) {
+        match self.block.as_mut() {
+            None => self.effective_rate = estimate,
+            Some(block) => match block.set_input_rate(estimate, self.target_rate) {
+                Ok(true) => self.effective_rate = estimate,
+                // Fixed-ratio resampler: stays on the nominal rate.
+                Ok(false) => {
+                    if !self.drift_ignored {
+                        warn!(
+                            "the {} resampler has a fixed ratio and doesn't follow the {:+.0} ppm clock drift; use sinc or sinc-fast",
+                            self.mode.label(),
+                            (estimate / self.in_rate as f64 - 1.0) * 1e6
+                        );
+                        self.drift_ignored = true;
                     }
                 }
-            }
-            ResamplerMode::SincQuality => {
-                if let Some(sinc) = self.sinc.as_mut() {
-                    if let Err(err) =
-                        sinc.reset(self.in_rate, self.target_rate, SincQuality::Quality)
-                    {
-                        warn!("resampler reset failed: {}", err);
-                    }
+                Err(err) => {
+                    self.errors += 1;
+                    warn!("resampler ratio update failed: {}", err);
+                }
+            },
+        }
+    }
+
+    fn reset_resampler(&mut self) {
+        match self.block.as_mut() {
+            None => self.linear.reset(),
+            Some(block) => {
+                if let Err(err) = block.reset(self.in_rate, self.target_rate, self.mode) {
+                    self.errors += 1;
+                    warn!("resampler reset failed: {}", err);
                 }
             }
         }
     }
 }
 
+/// Input rate from a least-squares fit of frames received against time,
+/// over the last `RATE_WINDOW`.
+struct RateEstimator {
+    origin: Instant,
+    frames: u64,
+    /// Seconds since `origin` and frames received by then.
+    points: VecDeque<(f64, u64)>,
+}
+
+impl Default for RateEstimator {
+    fn default() -> Self {
+        Self {
+            origin: Instant::now(),
+            frames: 0,
+            points: VecDeque::new(),
+        }
+    }
+}
+
+impl RateEstimator {
+    fn add(&mut self, frames: u64) {
+        self.frames = self.frames.saturating_add(frames);
+        let now = self.origin.elapsed().as_secs_f64();
+        self.points.push_back((now, self.frames));
+        let oldest = now - RATE_WINDOW.as_secs_f64();
+        while self.points.front().is_some_and(|(at, _)| *at < oldest) {
+            self.points.pop_front();
+        }
+    }
+
+    /// Frames per second and the time span the fit covers.
+    fn estimate(&self) -> Option<(f64, Duration)> {
+        let (first_at, first_frames) = *self.points.front()?;
+        let (last_at, _) = *self.points.back()?;
+        if self.points.len() < 3 || last_at <= first_at {
+            return None;
+        }
+        let n = self.points.len() as f64;
+        let points = || {
+            self.points
+                .iter()
+                .map(|(at, frames)| (at - first_at, (frames - first_frames) as f64))
+        };
+        let (sum_t, sum_f) = points().fold((0.0, 0.0), |(t, f), (at, frames)| (t + at, f + frames));
+        let (mean_t, mean_f) = (sum_t / n, sum_f / n);
+        let (cov, var) = points().fold((0.0, 0.0), |(cov, var), (at, frames)| {
+            let dt = at - mean_t;
+            (cov + dt * (frames - mean_f), var + dt * dt)
+        });
+        (var > 0.0).then(|| (cov / var, Duration::from_secs_f64(last_at - first_at)))
+    }
+}
+
 struct LinearResampler {
     pos: f64,
     buffer: Vec<f32>,
@@ -416,16 +1395,17 @@ impl LinearResampler {
         &mut self,
         input: &[f32],
         in_channels: u16,
-        in_rate: u32,
+        in_rate: f64,
         target_rate: u32,
-    ) -> Vec<i16> {
+        out: &mut Vec<i16>,
+    ) {
         if input.is_empty() || in_channels == 0 {
-            return Vec::new();
+            return;
         }
 
         self.buffer.extend_from_slice(input);
         let in_channels_usize = in_channels as usize;
-        let step = in_rate as f64 / target_rate as f64;
+        let step = in_rate / target_rate as f64;
         let max_samples = in_channels_usize * target_rate as usize;
 
         if self.buffer.len() > max_samples {
@@ -436,7 +1416,6 @@ impl LinearResampler {
         }
 
         let available_frames = self.buffer.len() / in_channels_usize;
-        let mut out = Vec::new();
 
         while self.pos + 1.0 < available_frames as f64 {
             let idx = self.pos.floor() as usize;
@@ -465,47 +1444,125 @@ impl LinearResampler {
             self.buffer.drain(0..drop_samples);
             self.pos -= drop_frames as f64;
         }
-
-        out
     }
 }
 
-#[derive(Clone, Copy)]
-enum SincQuality {
-    Fast,
-    Quality,
+/// rubato resampler working on fixed input blocks.
+enum BlockKind {
+    Sinc(SincFixedIn<f32>),
+    Fft(FftFixedIn<f32>),
+}
+
+impl BlockKind {
+    fn input_frames_next(&self) -> usize {
+        match self {
+            Self::Sinc(resampler) => resampler.input_frames_next(),
+            Self::Fft(resampler) => resampler.input_frames_next(),
+        }
+    }
+
+    /// Returns false for resamplers with a fixed ratio.
+    fn set_input_rate(&mut self, in_rate: f64, target_rate: u32) -> rubato::ResampleResult<bool> {
+        match self {
+            Self::Sinc(resampler) => {
+                resampler.set_resample_ratio(target_rate as f64 / in_rate, true)?;
+                Ok(true)
+            }
+            Self::Fft(_) => Ok(false),
+        }
+    }
+
+    fn output_buffer_allocate(&self) -> Vec<Vec<f32>> {
+        match self {
+            Self::Sinc(resampler) => resampler.output_buffer_allocate(true),
+            Self::Fft(resampler) => resampler.output_buffer_allocate(true),
+        }
+    }
+
+    /// Resamples one block into `output`, returning the frames written.
+    fn process_into_buffer(
+        &mut self,
+        input: &[&[f32]],
+        output: &mut [Vec<f32>],
+    ) -> rubato::ResampleResult<usize> {
+        let (_, frames) = match self {
+            Self::Sinc(resampler) => resampler.process_into_buffer(input, output, None)?,
+            Self::Fft(resampler) => resampler.process_into_buffer(input, output, None)?,
+        };
+        Ok(frames)
+    }
+
+    /// Processes a short final block, or only the internal delay line when
+    /// `input` is `None`.
+    fn process_partial(
+        &mut self,
+        input: Option<&[Vec<f32>]>,
+    ) -> rubato::ResampleResult<Vec<Vec<f32>>> {
+        match self {
+            Self::Sinc(resampler) => resampler.process_partial(input, None),
+            Self::Fft(resampler) => resampler.process_partial(input, None),
+        }
+    }
+
+    fn output_delay(&self) -> usize {
+        match self {
+            Self::Sinc(resampler) => resampler.output_delay(),
+            Self::Fft(resampler) => resampler.output_delay(),
+        }
+    }
 }
 
-struct SincResampler {
-    resampler: SincFixedIn<f32>,
+struct BlockResampler {
+    resampler: BlockKind,
     pending_left: Vec<f32>,
     pending_right: Vec<f32>,
     pending_offset: usize,
+    /// Reused output of `process_into_buffer`.
+    output: Vec<Vec<f32>>,
+    errors: u64,
+    ratio: f64,
+    /// Output frames the input so far accounts for but that haven't been
+    /// emitted yet, besides the delay line.
+    owed_frames: f64,
 }
 
-impl SincResampler {
-    fn new(in_rate: u32, target_rate: u32, quality: SincQuality, in_channels: u16) -> Result<Self> {
-        let resampler = build_sinc_resampler(in_rate, target_rate, quality)?;
+impl BlockResampler {
+    fn new(in_rate: u32, target_rate: u32, mode: ResamplerMode, in_channels: u16) -> Result<Self> {
+        let resampler = build_block_resampler(in_rate, target_rate, mode)?;
         Ok(Self {
+            output: resampler.output_buffer_allocate(),
+            errors: 0,
             resampler,
             pending_left: Vec::with_capacity(in_channels as usize * 2048),
             pending_right: Vec::with_capacity(in_channels as usize * 2048),
             pending_offset: 0,
+            ratio: block_ratio(in_rate, target_rate, mode),
+            owed_frames: 0.0,
         })
     }
 
-    fn reset(&mut self, in_rate: u32, target_rate: u32, quality: SincQuality) -> Result<()> {
-        self.resampler = build_sinc_resampler(in_rate, target_rate, quality)?;
+    fn set_input_rate(&mut self, in_rate: f64, target_rate: u32) -> rubato::ResampleResult<bool> {
+        let adjusted = self.resampler.set_input_rate(in_rate, target_rate)?;
+        if adjusted {
+            self.ratio = target_rate as f64 / in_rate;
+        }
+        Ok(adjusted)
+    }
+
+    fn reset(&mut self, in_rate: u32, target_rate: u32, mode: ResamplerMode) -> Result<()> {
+        self.resampler = build_block_resampler(in_rate, target_rate, mode)?;
+        self.output = self.resampler.output_buffer_allocate();
         self.pending_left.clear();
         self.pending_right.clear();
         self.pending_offset = 0;
+        self.ratio = block_ratio(in_rate, target_rate, mode);
+        self.owed_frames = 0.0;
         Ok(())
     }
 
-    fn process(&mut self, input: &[f32], in_channels: u16) -> Vec<i16> {
+    fn process(&mut self, input: &[f32], in_channels: u16, out: &mut Vec<i16>) {
         self.push_stereo_frames(input, in_channels);
 
-        let mut out = Vec::new();
         loop {
             let needed = self.resampler.input_frames_next();
             let available = self.pending_left.len().saturating_sub(self.pending_offset);
@@ -514,14 +1571,22 @@ impl SincResampler {
             }
             let start = self.pending_offset;
             let end = start + needed;
-            let input_chunk = vec![
-                self.pending_left[start..end].to_vec(),
-                self.pending_right[start..end].to_vec(),
+            let input_chunk = [
+                &self.pending_left[start..end],
+                &self.pending_right[start..end],
             ];
-            match self.resampler.process(&input_chunk, None) {
-                Ok(output) => out.extend(interleave_to_i16(&output)),
+            match self
+                .resampler
+                .process_into_buffer(&input_chunk, &mut self.output)
+            {
+                Ok(frames) => {
+                    self.owed_frames += needed as f64 * self.ratio - frames as f64;
+                    interleave_to_i16(&self.output, frames, out);
+                }
                 Err(err) => {
-                    warn!("resampler failed: {}", err);
+                    // Counted and reported as audio loss by the status.
+                    self.errors += 1;
+                    debug!("resampler failed: {}", err);
                     break;
                 }
             }
@@ -532,7 +1597,44 @@ impl SincResampler {
                 self.pending_offset = 0;
             }
         }
+    }
 
+    /// Emits the pending frames plus the resampler's delay line, trimmed to
+    /// the frames that carry input.
+    fn flush(&mut self) -> Vec<i16> {
+        let remaining = self.pending_left.len().saturating_sub(self.pending_offset);
+        if remaining == 0 {
+            return Vec::new();
+        }
+        let wanted = (self.owed_frames + remaining as f64 * self.ratio)
+            .round()
+            .max(0.0) as usize
+            + self.resampler.output_delay();
+        self.owed_frames = 0.0;
+        let input = vec![
+            self.pending_left[self.pending_offset..].to_vec(),
+            self.pending_right[self.pending_offset..].to_vec(),
+        ];
+        self.pending_left.clear();
+        self.pending_right.clear();
+        self.pending_offset = 0;
+
+        let mut out = Vec::with_capacity(wanted * TARGET_CHANNELS as usize);
+        let mut next = Some(input.as_slice());
+        while out.len() < wanted * TARGET_CHANNELS as usize {
+            match self.resampler.process_partial(next.take()) {
+                Ok(output) if output.first().is_some_and(|ch| !ch.is_empty()) => {
+                    interleave_to_i16(&output, output[0].len(), &mut out)
+                }
+                Ok(_) => break,
+                Err(err) => {
+                    self.errors += 1;
+                    warn!("resampler flush failed: {}", err);
+                    break;
+                }
+            }
+        }
+        out.truncate(wanted * TARGET_CHANNELS as usize);
         out
     }
 
@@ -550,14 +1652,44 @@ impl SincResampler {
     }
 }
 
-fn build_sinc_resampler(
-    in_rate: u32,
-    target_rate: u32,
-    quality: SincQuality,
-) -> Result<SincFixedIn<f32>> {
-    let (sinc_len, oversampling_factor, interpolation, f_cutoff) = match quality {
-        SincQuality::Fast => (128, 64, SincInterpolationType::Quadratic, 0.9),
-        SincQuality::Quality => (256, 256, SincInterpolationType::Cubic, 0.95),
+/// Common rates the FFT resampler snaps the observed input rate to; an
+/// arbitrary measured rate would make its FFT sizes explode.
+const NOMINAL_RATES: &[u32] = &[
+    8_000, 11_025, 16_000, 22_050, 24_000, 32_000, 44_100, 48_000, 88_200, 96_000, 176_400, 192_000,
+];
+
+fn nominal_rate(rate: u32) -> u32 {
+    NOMINAL_RATES
+        .iter()
+        .copied()
+        .min_by_key(|nominal| nominal.abs_diff(rate))
+        .unwrap_or(rate)
+}
+
+fn block_ratio(in_rate: u32, target_rate: u32, mode: ResamplerMode) -> f64 {
+    let in_rate = match mode {
+        ResamplerMode::Fft => nominal_rate(in_rate),
+        _ => in_rate,
+    };
+    target_rate as f64 / in_rate.max(1) as f64
+}
+
+fn build_block_resampler(in_rate: u32, target_rate: u32, mode: ResamplerMode) -> Result<BlockKind> {
+    let (sinc_len, oversampling_factor, interpolation, f_cutoff) = match mode {
+        ResamplerMode::Fft => {
+            let resampler = FftFixedIn::<f32>::new(
+                nominal_rate(in_rate) as usize,
+                target_rate as usize,
+                1024,
+                2,
+                TARGET_CHANNELS as usize,
+            )?;
+            return Ok(BlockKind::Fft(resampler));
+        }
+        ResamplerMode::SincFast | ResamplerMode::Linear => {
+            (128, 64, SincInterpolationType::Quadratic, 0.9)
+        }
+        ResamplerMode::SincQuality => (256, 256, SincInterpolationType::Cubic, 0.95),
     };
     let params = SincInterpolationParameters {
         sinc_len,
@@ -568,20 +1700,20 @@ fn build_sinc_resampler(
     };
     let ratio = target_rate as f64 / in_rate as f64;
     let resampler = SincFixedIn::<f32>::new(ratio, 2.0, params, 1024, TARGET_CHANNELS as usize)?;
-    Ok(resampler)
+    Ok(BlockKind::Sinc(resampler))
 }
 
-fn interleave_to_i16(output: &[Vec<f32>]) -> Vec<i16> {
+/// Appends the first `frames` frames of planar stereo `output`.
+fn interleave_to_i16(output: &[Vec<f32>], frames: usize, out: &mut Vec<i16>) {
     if output.len() < TARGET_CHANNELS as usize {
-        return Vec::new();
+        return;
     }
     let left = &output[0];
     let right = &output[1];
-    let frames = left.len().min(right.len());
-    let mut out = Vec::with_capacity(frames * 2);
+    let frames = frames.min(left.len()).min(right.len());
+    out.reserve(frames * 2);
     for idx in 0..frames {
         out.push(f32_to_i16(left[idx]));
         out.push(f32_to_i16(right[idx]));
     }
-    out
 }
@@ -0,0 +1,278 @@
+//! "Black box" for diagnosing clicks and dropouts: every capture keeps the
+//! last few seconds of raw device audio (before resampling) and of the
+//! processed output in memory. A dump writes both to WAV files so they can
+//! be compared side by side. Dumps are triggered by `SIGUSR1`, the `dump`
+//! command or the server's `black_box_dump` command.
+
+use crate::audio::TARGET_CHANNELS;
+use crate::timestamp;
+use crate::wav;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+pub const DEFAULT_DIR: &str = "/var/lib/lox-linein-bridge/blackbox";
+const PROCESS_NAME: &str = "lox-linein-bridge";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlackBoxConfig {
+    /// Audio kept per capture, for raw and processed each.
+    #[serde(default = "default_seconds")]
+    pub seconds: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+}
+
+fn default_seconds() -> u64 {
+    30
+}
+
+struct State {
+    seconds: u64,
+    dir: PathBuf,
+    /// Rings by capture device; a reopened device replaces its rings.
+    taps: BTreeMap<String, Arc<Mutex<Rings>>>,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+fn with_state<T>(f: impl FnOnce(&mut Option<State>) -> T) -> T {
+    let mut state = match STATE.lock() {
+        Ok(state) => state,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    f(&mut state)
+}
+
+/// Turns the black box on; captures started afterwards are recorded. With
+/// several bridges in one process the last call sets size and directory.
+pub fn init(config: &BlackBoxConfig) {
+    let dir = PathBuf::from(config.dir.as_deref().unwrap_or(DEFAULT_DIR));
+    info!(
+        "black box keeps {} s per capture, dumps go to {}",
+        config.seconds,
+        dir.display()
+    );
+    with_state(|state| {
+        let taps = state.take().map(|state| state.taps).unwrap_or_default();
+        *state = Some(State {
+            seconds: config.seconds.max(1),
+            dir,
+            taps,
+        })
+    });
+}
+
+#[derive(Clone)]
+struct Ring {
+    rate: u32,
+    channels: u16,
+    samples: VecDeque<i16>,
+    capacity: usize,
+}
+
+impl Ring {
+    fn new(rate: u32, channels: u16, seconds: u64) -> Self {
+        let capacity = rate as usize * channels as usize * seconds as usize;
+        Self {
+            rate,
+            channels,
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    fn extend(&mut self, samples: impl Iterator<Item = i16>) {
+        self.samples.extend(samples);
+        let excess = self.samples.len().saturating_sub(self.capacity);
+        self.samples.drain(..excess);
+    }
+
+    fn write_wav(&self, path: &Path) -> Result<()> {
+        let data_len = (self.samples.len() * 2) as u32;
+        let mut out = Vec::with_capacity(44 + data_len as usize);
+        out.extend_from_slice(&wav::header_with_channels(
+            self.rate,
+            self.channels,
+            Some(data_len),
+        ));
+        for sample in &self.samples {
+            out.extend_from_slice(&sample.to_le_bytes());
+        }
+        std::fs::write(path, out).with_context(|| format!("write {}", path.display()))
+    }
+}
+
+struct Rings {
+    raw: Ring,
+    processed: Ring,
+}
+
+/// Recording end of one capture, used on its processing thread.
+pub struct Tap {
+    rings: Arc<Mutex<Rings>>,
+}
+
+impl Tap {
+    /// Interleaved device samples as the capture callback delivered them.
+    pub fn raw(&self, samples: &[f32]) {
+        if let Ok(mut rings) = self.rings.lock() {
+            rings.raw.extend(
+                samples
+                    .iter()
+                    .map(|sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16),
+            );
+        }
+    }
+
+    /// s16le stereo after resampling, gain and ceiling.
+    pub fn processed(&self, pcm: &[u8]) {
+        if let Ok(mut rings) = self.rings.lock() {
+            rings.processed.extend(
+                pcm.chunks_exact(2)
+                    .map(|sample| i16::from_le_bytes([sample[0], sample[1]])),
+            );
+        }
+    }
+}
+
+/// A tap for a capture that was just opened, or `None` while the black box
+/// is off.
+pub fn tap(device: &str, rate: u32, channels: u16, output_rate: u32) -> Option<Tap> {
+    with_state(|state| {
+        let state = state.as_mut()?;
+        let rings = Arc::new(Mutex::new(Rings {
+            raw: Ring::new(rate, channels, state.seconds),
+            processed: Ring::new(output_rate, TARGET_CHANNELS, state.seconds),
+        }));
+        state.taps.insert(device.to_string(), Arc::clone(&rings));
+        Some(Tap { rings })
+    })
+}
+
+/// Writes `<time>-<device>-raw.wav` and `-processed.wav` per capture and
+/// returns the files written. The rings are copied first, so the captures
+/// aren't held up while the files are written.
+pub fn dump() -> Result<Vec<PathBuf>> {
+    let (dir, taps) = with_state(|state| {
+        state
+            .as_ref()
+            .map(|state| (state.dir.clone(), state.taps.clone()))
+    })
+    .context("black box is not enabled")?;
+    std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    let stamp = timestamp::file_stamp();
+    let mut written = Vec::new();
+    for (device, rings) in taps {
+        let (raw, processed) = {
+            let rings = match rings.lock() {
+                Ok(rings) => rings,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            (rings.raw.clone(), rings.processed.clone())
+        };
+        let name = file_safe(&device);
+        for (kind, ring) in [("raw", &raw), ("processed", &processed)] {
+            let path = dir.join(format!("{}-{}-{}.wav", stamp, name, kind));
+            ring.write_wav(&path)?;
+            written.push(path);
+        }
+    }
+    if written.is_empty() {
+        anyhow::bail!("no capture has run yet");
+    }
+    Ok(written)
+}
+
+/// Dumps on the blocking pool and logs the result; for the signal and
+/// server triggers.
+pub fn dump_and_log(trigger: &'static str) {
+    tokio::task::spawn_blocking(move || match dump() {
+        Ok(paths) => {
+            for path in paths {
+                info!("black box dump ({}): {}", trigger, path.display());
+            }
+        }
+        Err(err) => warn!("black box dump ({}) failed: {:#}", trigger, err),
+    });
+}
+
+/// Dumps whenever the process receives `SIGUSR1`.
+pub fn spawn_signal_trigger() {
+    let mut signal =
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1()) {
+            Ok(signal) => signal,
+            Err(err) => {
+                warn!("black box signal trigger unavailable: {}", err);
+                return;
+            }
+        };
+    tokio::spawn(async move {
+        while signal.recv().await.is_some() {
+            dump_and_log("SIGUSR1");
+        }
+    });
+}
+
+/// Sends `SIGUSR1` to every other running bridge that handles it, i.e. runs
+/// the bridge rather than a command like `pipe`, which the signal would
+/// end; for the `dump` command. Returns the signalled pids.
+pub fn signal_running() -> Result<Vec<u32>> {
+    let own = std::process::id();
+    let mut pids = Vec::new();
+    for entry in std::fs::read_dir("/proc").context("read /proc")? {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if pid == own {
+            continue;
+        }
+        // `comm` is cut to 15 characters, so match the executable instead.
+        // After an update replaced the file, the link ends in " (deleted)".
+        let exe = std::fs::read_link(entry.path().join("exe")).unwrap_or_default();
+        let name = exe.file_name().and_then(|name| name.to_str());
+        if name.map(|name| name.trim_end_matches(" (deleted)")) != Some(PROCESS_NAME) {
+            continue;
+        }
+        if !catches_sigusr1(&entry.path()) {
+            continue;
+        }
+        if unsafe { libc::kill(pid as libc::pid_t, libc::SIGUSR1) } == 0 {
+            pids.push(pid);
+        }
+    }
+    Ok(pids)
+}
+
+/// Whether `SigCgt` in `/proc/<pid>/status` has the `SIGUSR1` bit set.
+fn catches_sigusr1(proc_dir: &Path) -> bool {
+    let status = std::fs::read_to_string(proc_dir.join("status")).unwrap_or_default();
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("SigCgt:"))
+        .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+        .is_some_and(|mask| mask & (1 << (libc::SIGUSR1 - 1)) != 0)
+}
+
+fn file_safe(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
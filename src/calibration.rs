@@ -0,0 +1,146 @@
+use crate::audio::{self, CaptureOptions, ResamplerMode};
+use crate::cli::DeviceArgs;
+use crate::i18n::t;
+use crate::limiter::OutputSettings;
+use crate::meter::LevelMeter;
+use crate::models::CalibrationStatus;
+use anyhow::{Context, Result};
+use std::time::{Duration, Instant};
+
+/// Proposed VAD threshold sits this far above the measured noise floor.
+pub const MARGIN_DB: f32 = 6.0;
+pub const DEFAULT_SECONDS: u64 = 10;
+const MIN_THRESHOLD_DB: f32 = -80.0;
+const MAX_THRESHOLD_DB: f32 = -20.0;
+/// Percentile of chunk levels taken as the floor, so short sounds during the
+/// measurement don't inflate it.
+const FLOOR_PERCENTILE: f32 = 0.2;
+
+/// Noise floor measurement over chunk RMS levels.
+pub struct Calibration {
+    started: Instant,
+    duration: Duration,
+    apply: bool,
+    levels: Vec<f32>,
+}
+
+impl Calibration {
+    pub fn new(duration: Duration, apply: bool) -> Self {
+        Self {
+            started: Instant::now(),
+            duration,
+            apply,
+            levels: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, rms_db: f32) {
+        self.levels.push(rms_db);
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.started.elapsed() >= self.duration
+    }
+
+    pub fn running_status(&self) -> CalibrationStatus {
+        CalibrationStatus {
+            state: "running".to_string(),
+            noise_floor_db: None,
+            proposed_threshold_db: None,
+            applied: false,
+        }
+    }
+
+    pub fn finish(mut self) -> CalibrationStatus {
+        match noise_floor(&mut self.levels) {
+            Some(floor) => CalibrationStatus {
+                state: "done".to_string(),
+                noise_floor_db: Some(floor),
+                proposed_threshold_db: Some(proposed_threshold(floor)),
+                applied: false,
+            },
+            None => CalibrationStatus {
+                state: "failed".to_string(),
+                noise_floor_db: None,
+                proposed_threshold_db: None,
+                applied: false,
+            },
+        }
+    }
+
+    pub fn apply(&self) -> bool {
+        self.apply
+    }
+}
+
+pub fn noise_floor(levels: &mut [f32]) -> Option<f32> {
+    if levels.is_empty() {
+        return None;
+    }
+    levels.sort_by(|a, b| a.total_cmp(b));
+    let idx = ((levels.len() - 1) as f32 * FLOOR_PERCENTILE) as usize;
+    Some(levels[idx])
+}
+
+pub fn proposed_threshold(noise_floor_db: f32) -> f32 {
+    (noise_floor_db + MARGIN_DB).clamp(MIN_THRESHOLD_DB, MAX_THRESHOLD_DB)
+}
+
+#[derive(Debug, clap::Args)]
+pub struct CalibrateOptions {
+    #[command(flatten)]
+    capture: DeviceArgs,
+    #[arg(long, default_value_t = DEFAULT_SECONDS)]
+    seconds: u64,
+}
+
+/// Measures the noise floor of a capture device from the command line and
+/// prints the proposed `vad_threshold_db`.
+pub async fn run_calibrate(options: CalibrateOptions) -> Result<()> {
+    const RATE: u32 = 48_000;
+    let device = audio::default_capture_device(options.capture.device)?;
+    println!(
+        "{}",
+        t!(
+            "calibrate-measuring",
+            device = device.as_str(),
+            seconds = options.seconds,
+        )
+    );
+    let session = audio::start_capture(
+        &device,
+        RATE,
+        ResamplerMode::SincQuality,
+        CaptureOptions::default(),
+        OutputSettings::default(),
+    )?;
+    let audio::CaptureSession {
+        mut receiver,
+        streams,
+        ..
+    } = session;
+    let _capture_guard = streams;
+    let mut meter = LevelMeter::new(RATE);
+    let mut calibration = Calibration::new(Duration::from_secs(options.seconds), false);
+    while !calibration.is_done() {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .context("capture stalled")?
+            .context("capture stopped")?;
+        if let Some(levels) = meter.process(&chunk) {
+            calibration.push(levels.rms_db);
+        }
+    }
+    let result = calibration.finish();
+    match (result.noise_floor_db, result.proposed_threshold_db) {
+        (Some(floor), Some(threshold)) => {
+            println!("{}", t!("calibrate-floor", db = format!("{:.1}", floor)));
+            println!(
+                "{}",
+                t!("calibrate-proposed", db = format!("{:.1}", threshold))
+            );
+            Ok(())
+        }
+        _ => anyhow::bail!("no audio captured"),
+    }
+}
@@ -0,0 +1,251 @@
+//! Command line: global logging and path flags, then one subcommand with
+//! its own options. Without a subcommand the bridge runs, so existing unit
+//! files and `lox-linein-bridge --log-level info` keep working.
+
+use crate::discovery::DiscoveryLimits;
+use crate::i18n::t;
+use crate::logging::{LogFormat, LogRotation, LogTarget};
+use crate::server_api::ApiMode;
+use crate::{
+    analysis, calibration, devices, diagnose, discover, fixtures, install, pipe, record, secrets,
+    status, test_capture, update, validate_config,
+};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "lox-linein-bridge",
+    version,
+    about,
+    next_help_heading = "Global options"
+)]
+pub struct Cli {
+    /// off, error, warn, info, debug or trace; also LOX_LINEIN_BRIDGE_LOG_LEVEL.
+    #[arg(long, global = true, value_name = "LEVEL")]
+    pub log_level: Option<String>,
+    /// journal (stderr), file or both.
+    #[arg(long, global = true, value_name = "TARGET", default_value = "journal", value_parser = parse_log_target)]
+    pub log_target: LogTarget,
+    /// Defaults to /tmp/lox-linein-bridge.log.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+    /// text or json.
+    #[arg(long, global = true, value_name = "FORMAT", default_value = "text", value_parser = parse_log_format)]
+    pub log_format: LogFormat,
+    /// Starts a new log file past this size; 0 never rotates.
+    #[arg(long, global = true, value_name = "MB", default_value_t = 0)]
+    pub log_max_size_mb: u64,
+    /// Rotated log files to keep.
+    #[arg(long, global = true, value_name = "N", default_value_t = 5)]
+    pub log_max_files: usize,
+    /// OTLP/HTTP endpoint for trace export.
+    #[arg(long, global = true, value_name = "URL")]
+    pub otlp_endpoint: Option<String>,
+    /// Config file instead of /etc/lox-linein-bridge/config.toml; also
+    /// LOX_LINEIN_BRIDGE_CONFIG.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Health file instead of the one from `[health.file]`.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub health_path: Option<PathBuf>,
+    /// `run` options, also accepted before `run` or without a subcommand;
+    /// other subcommands reject them.
+    #[command(flatten)]
+    pub run: RunArgs,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// Parses the process arguments; exits with clap's message on errors,
+    /// `--help` and `--version`.
+    pub fn parse_args() -> Self {
+        let mut command = Self::command().after_help(examples());
+        let matches = command.get_matches_mut();
+        let cli = Self::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+        if !matches!(cli.command, None | Some(Command::Run(_))) {
+            if let Some(option) = cli.run.first_given() {
+                command
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        t!("run-options-without-run", option = option),
+                    )
+                    .exit();
+            }
+        }
+        cli
+    }
+
+    /// `--log-level`, then `LOX_LINEIN_BRIDGE_LOG_LEVEL`.
+    pub fn log_level(&self) -> Option<String> {
+        self.log_level.clone().or_else(|| {
+            std::env::var("LOX_LINEIN_BRIDGE_LOG_LEVEL")
+                .ok()
+                .filter(|level| !level.is_empty())
+        })
+    }
+
+    pub fn log_rotation(&self) -> LogRotation {
+        LogRotation {
+            max_bytes: self.log_max_size_mb.saturating_mul(1024 * 1024),
+            max_files: self.log_max_files,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Discover the server, register and stream (the default).
+    Run(RunArgs),
+    /// Install and start the service.
+    Install(install::InstallOptions),
+    /// Install the latest release.
+    Update(update::UpdateOptions),
+    /// Measure the noise floor and propose a VAD threshold.
+    Calibrate(calibration::CalibrateOptions),
+    /// Measure noise and frequency response of the capture chain.
+    Analyze(analysis::AnalyzeOptions),
+    /// Run the pipeline fixtures.
+    Fixtures(fixtures::FixtureOptions),
+    /// Ask the running bridge for a black box dump.
+    Dump,
+    /// Write a support bundle for bug reports.
+    Diagnose(diagnose::DiagnoseOptions),
+    /// List the servers announced on the network.
+    Discover(discover::DiscoverOptions),
+    /// Summarize the running bridge's health file.
+    Status(status::StatusOptions),
+    /// List capture devices and what they support.
+    Devices(devices::DevicesOptions),
+    /// Show a live level meter for a capture device.
+    TestCapture(test_capture::TestCaptureOptions),
+    /// Write the processed capture to a WAV file.
+    Record(record::RecordOptions),
+    /// Write the processed stream to stdout or a FIFO.
+    Pipe(pipe::PipeOptions),
+    /// Check the config file for errors.
+    ValidateConfig(validate_config::ValidateConfigOptions),
+    /// Encrypt the tokens in the config file, creating a key if needed.
+    EncryptSecrets(secrets::EncryptSecretsOptions),
+}
+
+#[derive(Debug, Clone, Default, Args)]
+#[command(next_help_heading = "Run options")]
+pub struct RunArgs {
+    /// Give up discovery after this many seconds.
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub discovery_timeout: Option<Duration>,
+    /// Give up discovery after this many rounds.
+    #[arg(long, value_name = "N", value_parser = parse_attempts)]
+    pub max_discovery_attempts: Option<u32>,
+    /// Also write every server exchange to this directory.
+    #[arg(long, value_name = "DIR", conflicts_with = "replay_api")]
+    pub record_api: Option<PathBuf>,
+    /// Answer from a recorded directory instead of a server.
+    #[arg(long, value_name = "DIR")]
+    pub replay_api: Option<PathBuf>,
+    /// Run every bridge listed in this manifest.
+    #[arg(long, value_name = "PATH")]
+    pub manifest: Option<PathBuf>,
+}
+
+impl RunArgs {
+    /// Options given after `run` win over those before it.
+    pub fn merge(self, before: RunArgs) -> Self {
+        Self {
+            discovery_timeout: self.discovery_timeout.or(before.discovery_timeout),
+            max_discovery_attempts: self
+                .max_discovery_attempts
+                .or(before.max_discovery_attempts),
+            record_api: self.record_api.or(before.record_api),
+            replay_api: self.replay_api.or(before.replay_api),
+            manifest: self.manifest.or(before.manifest),
+        }
+    }
+
+    /// The first option that was set, as written on the command line.
+    fn first_given(&self) -> Option<&'static str> {
+        [
+            (self.discovery_timeout.is_some(), "--discovery-timeout"),
+            (
+                self.max_discovery_attempts.is_some(),
+                "--max-discovery-attempts",
+            ),
+            (self.record_api.is_some(), "--record-api"),
+            (self.replay_api.is_some(), "--replay-api"),
+            (self.manifest.is_some(), "--manifest"),
+        ]
+        .into_iter()
+        .find_map(|(given, option)| given.then_some(option))
+    }
+
+    pub fn discovery_limits(&self) -> DiscoveryLimits {
+        DiscoveryLimits {
+            timeout: self.discovery_timeout,
+            max_attempts: self.max_discovery_attempts,
+        }
+    }
+
+    pub fn api_mode(&self) -> ApiMode {
+        match (&self.record_api, &self.replay_api) {
+            (Some(dir), _) => ApiMode::Record(dir.clone()),
+            (None, Some(dir)) => ApiMode::Replay(dir.clone()),
+            (None, None) => ApiMode::Live,
+        }
+    }
+}
+
+/// `--json` for commands that print something scripts may want to read.
+#[derive(Debug, Clone, Copy, Default, Args)]
+pub struct OutputArgs {
+    /// Print JSON instead of text.
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// `--device` for commands that capture on their own.
+#[derive(Debug, Clone, Default, Args)]
+pub struct DeviceArgs {
+    /// Capture device; defaults to the first one that isn't a monitor.
+    #[arg(long)]
+    pub device: Option<String>,
+}
+
+fn examples() -> String {
+    let mut help = format!("{}\n", t!("examples-heading"));
+    for example in [
+        "lox-linein-bridge --log-level info run",
+        "lox-linein-bridge --log-level debug --log-target both run",
+        "lox-linein-bridge install",
+        "lox-linein-bridge analyze --loopback",
+        "lox-linein-bridge pipe | aplay -f S16_LE -c 2 -r 48000",
+    ] {
+        help.push_str(&format!("  {}\n", example));
+    }
+    help
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    value
+        .parse()
+        .map(Duration::from_secs)
+        .map_err(|_| format!("invalid number of seconds: {}", value))
+}
+
+fn parse_attempts(value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(attempts) if attempts > 0 => Ok(attempts),
+        _ => Err(format!("invalid number of attempts: {}", value)),
+    }
+}
+
+fn parse_log_target(value: &str) -> Result<LogTarget, String> {
+    LogTarget::parse(value).ok_or_else(|| format!("unknown log target: {}", value))
+}
+
+fn parse_log_format(value: &str) -> Result<LogFormat, String> {
+    LogFormat::parse(value).ok_or_else(|| format!("unknown log format: {}", value))
+}
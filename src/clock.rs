@@ -0,0 +1,148 @@
+//! Offset and drift between the bridge and server clocks, estimated from
+//! the server time in status responses. A growing offset means one side
+//! isn't NTP-synced, which later synchronized playback can't tolerate.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// One sample is kept per bucket, the one with the shortest round trip.
+const BUCKET: Duration = Duration::from_secs(300);
+/// 12 hours of buckets.
+const MAX_BUCKETS: usize = 144;
+/// Minimum span before a drift estimate is reported.
+const MIN_SPAN: Duration = Duration::from_secs(1800);
+const REPORT_INTERVAL: Duration = Duration::from_secs(3600);
+/// NTP keeps both clocks within a few ms and a few ppm of each other.
+const WARN_OFFSET_MS: f64 = 250.0;
+const WARN_DRIFT_PPM: f64 = 20.0;
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Instant,
+    offset_ms: f64,
+    rtt_ms: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockReport {
+    /// Server clock minus bridge clock.
+    pub offset_ms: f64,
+    /// Rate at which the offset grows, once enough history exists.
+    pub drift_ppm: Option<f64>,
+}
+
+#[derive(Default)]
+pub struct ClockTracker {
+    samples: VecDeque<Sample>,
+    bucket_start: Option<Instant>,
+    last_report: Option<Instant>,
+    warned: bool,
+}
+
+impl ClockTracker {
+    /// Records a status round trip. `sent_ms`/`received_ms` are bridge Unix
+    /// times around the request; the server time is assumed to be taken
+    /// halfway through.
+    pub fn observe(&mut self, sent_ms: f64, received_ms: f64, server_ms: f64) -> ClockReport {
+        let now = Instant::now();
+        let sample = Sample {
+            at: now,
+            offset_ms: server_ms - (sent_ms + received_ms) / 2.0,
+            rtt_ms: (received_ms - sent_ms).max(0.0),
+        };
+        let same_bucket = self
+            .bucket_start
+            .is_some_and(|start| now.duration_since(start) < BUCKET);
+        match self.samples.back_mut() {
+            Some(last) if same_bucket => {
+                if sample.rtt_ms < last.rtt_ms {
+                    *last = sample;
+                }
+            }
+            _ => {
+                self.bucket_start = Some(now);
+                self.samples.push_back(sample);
+                if self.samples.len() > MAX_BUCKETS {
+                    self.samples.pop_front();
+                }
+            }
+        }
+
+        let report = ClockReport {
+            offset_ms: sample.offset_ms,
+            drift_ppm: self.drift_ppm(),
+        };
+        self.log(report, now);
+        report
+    }
+
+    /// Least-squares slope of offset over time.
+    fn drift_ppm(&self) -> Option<f64> {
+        let first = self.samples.front()?;
+        let last = self.samples.back()?;
+        if last.at.duration_since(first.at) < MIN_SPAN {
+            return None;
+        }
+        let points = self
+            .samples
+            .iter()
+            .map(|sample| {
+                (
+                    sample.at.duration_since(first.at).as_secs_f64(),
+                    sample.offset_ms,
+                )
+            })
+            .collect::<Vec<_>>();
+        let count = points.len() as f64;
+        let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / count;
+        let mean_offset = points.iter().map(|(_, offset)| offset).sum::<f64>() / count;
+        let mut covariance = 0.0;
+        let mut variance = 0.0;
+        for (t, offset) in &points {
+            covariance += (t - mean_t) * (offset - mean_offset);
+            variance += (t - mean_t) * (t - mean_t);
+        }
+        if variance <= 0.0 {
+            return None;
+        }
+        // ms per second, i.e. parts per thousand.
+        Some(covariance / variance * 1000.0)
+    }
+
+    fn log(&mut self, report: ClockReport, now: Instant) {
+        let large = report.offset_ms.abs() > WARN_OFFSET_MS
+            || report
+                .drift_ppm
+                .is_some_and(|drift| drift.abs() > WARN_DRIFT_PPM);
+        if large && !self.warned {
+            warn!(
+                "clock differs from server: offset {:.0} ms, drift {} (is NTP running?)",
+                report.offset_ms,
+                format_drift(report.drift_ppm)
+            );
+            self.warned = true;
+            self.last_report = Some(now);
+            return;
+        }
+        self.warned = large;
+        let due = self
+            .last_report
+            .is_none_or(|last| now.duration_since(last) >= REPORT_INTERVAL);
+        if due && report.drift_ppm.is_some() {
+            info!(
+                "clock vs server: offset {:.1} ms, drift {}",
+                report.offset_ms,
+                format_drift(report.drift_ppm)
+            );
+            self.last_report = Some(now);
+        }
+    }
+}
+
+fn format_drift(drift_ppm: Option<f64>) -> String {
+    match drift_ppm {
+        Some(drift) => format!("{:+.1} ppm", drift),
+        None => "not yet known".to_string(),
+    }
+}
@@ -1,12 +1,60 @@
+use crate::blackbox::BlackBoxConfig;
+use crate::health::HealthConfig;
+use crate::hls::HlsConfig;
+use crate::local_api::LocalApiConfig;
+use crate::monitor::MonitorConfig;
+use crate::multicast::MulticastConfig;
+use crate::overrides::Overrides;
+use crate::redact::RedactionConfig;
+use crate::secrets;
+use crate::snapcast::SnapcastConfig;
+use crate::sources::SourcesConfig;
+use crate::tls::ServerTlsConfig;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
 
 const CONFIG_DIR_SYSTEM: &str = "/etc/lox-linein-bridge";
 const CONFIG_DIR_FALLBACK: &str = ".config/lox-linein-bridge";
 const CONFIG_FILE: &str = "config.toml";
+/// `.invalid.<time>` copies of unreadable configs that are kept.
+const MAX_INVALID_BACKUPS: usize = 5;
+
+/// Also replaces both default locations; `--config` wins over it.
+const CONFIG_ENV: &str = "LOX_LINEIN_BRIDGE_CONFIG";
+
+/// `--config`; replaces both default locations.
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+const BRIDGE_ID_ENV: &str = "LOX_LINEIN_BRIDGE_BRIDGE_ID";
+
+type ConfigField = fn(&mut Config) -> &mut Option<String>;
+
+/// Variables that replace a field of the config file, so containers can be
+/// set up without writing one. They are applied on load and never saved.
+const ENV_OVERRIDES: &[(&str, ConfigField)] = &[
+    ("LOX_LINEIN_BRIDGE_SERVER_URL", |config| {
+        &mut config.server_url
+    }),
+    ("LOX_LINEIN_BRIDGE_PREFERRED_SERVER_NAME", |config| {
+        &mut config.preferred_server_name
+    }),
+    ("LOX_LINEIN_BRIDGE_PREFERRED_SERVER_MAC", |config| {
+        &mut config.preferred_server_mac
+    }),
+    ("LOX_LINEIN_BRIDGE_SERVER_TOKEN", |config| {
+        &mut config.server_token
+    }),
+    ("LOX_LINEIN_BRIDGE_CAPTURE_DEVICE", |config| {
+        &mut config.capture_device
+    }),
+];
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
@@ -15,36 +63,234 @@ pub struct Config {
     pub preferred_server_name: Option<String>,
     #[serde(default)]
     pub preferred_server_mac: Option<String>,
+    /// Server base URL used instead of mDNS discovery.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_url: Option<String>,
+    /// API paths for `server_url`, in place of the defaults; `{bridge_id}`
+    /// is replaced in the status path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub register_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_path: Option<String>,
+    /// Standby servers, tried in order when `server_url` fails.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_servers: Vec<String>,
+    /// Unicast DNS-SD domain, asked when mDNS finds nothing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_sd_domain: Option<String>,
+    /// Nameserver for `dns_sd_domain`; the system's by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_sd_server: Option<String>,
+    /// Network interface (`eth0`) for discovery, the reported identity and
+    /// ingest connections on hosts with more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_interface: Option<String>,
+    /// Local address to use instead of a whole interface.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<String>,
+    /// Release URL for `update`; GitHub releases by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_url: Option<String>,
+    /// Hex Ed25519 key; when set, updates must carry a matching signature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_public_key: Option<String>,
+    /// CA, client certificate and verification for `https` servers.
+    #[serde(default, skip_serializing_if = "ServerTlsConfig::is_default")]
+    pub server_tls: ServerTlsConfig,
+    /// Sent as `Authorization: Bearer <token>` on every server API call.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_token: Option<String>,
+    /// Capture device used when the server has not assigned one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_device: Option<String>,
+    /// Copies the outgoing PCM into a named pipe.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub tee_pipe: bool,
+    /// Directory for the tee pipe instead of the runtime directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tee_pipe_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "RedactionConfig::is_default")]
+    pub redact: RedactionConfig,
+    /// Local sources sharing the assigned input; replaces the single
+    /// capture device when set.
+    #[serde(default, skip_serializing_if = "SourcesConfig::is_empty")]
+    pub sources: SourcesConfig,
+    /// Where health snapshots are reported.
+    #[serde(default, skip_serializing_if = "HealthConfig::is_default")]
+    pub health: HealthConfig,
+    /// Snapcast stream source fed alongside, or instead of, the ingest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapcast: Option<SnapcastConfig>,
+    /// Multicast output for LAN listeners, alongside or instead of the
+    /// ingest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multicast: Option<MulticastConfig>,
+    /// Local HLS output for listening on the LAN.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hls: Option<HlsConfig>,
+    /// In-memory ring of recent capture audio for diagnosing dropouts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub black_box: Option<BlackBoxConfig>,
+    /// Local playback of the processed stream, e.g. on headphones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<MonitorConfig>,
+    /// HTTP status and control API on the bridge.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api: Option<LocalApiConfig>,
+    /// Values that win over the server's config.
+    #[serde(default, skip_serializing_if = "Overrides::is_empty")]
+    pub overrides: Overrides,
+}
+
+pub fn set_config_path(path: PathBuf) {
+    let _ = CONFIG_PATH.set(path);
+}
+
+/// The config file from `--config` or `LOX_LINEIN_BRIDGE_CONFIG`, if any.
+pub fn config_path_override() -> Option<PathBuf> {
+    CONFIG_PATH.get().cloned().or_else(|| {
+        std::env::var_os(CONFIG_ENV)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    })
 }
 
 pub fn preferred_config_path() -> PathBuf {
-    PathBuf::from(CONFIG_DIR_SYSTEM).join(CONFIG_FILE)
+    config_path_override().unwrap_or_else(|| PathBuf::from(CONFIG_DIR_SYSTEM).join(CONFIG_FILE))
 }
 
 pub fn fallback_config_path() -> Result<PathBuf> {
+    if let Some(path) = config_path_override() {
+        return Ok(path);
+    }
     let home = std::env::var("HOME").context("HOME is not set")?;
     Ok(PathBuf::from(home)
         .join(CONFIG_DIR_FALLBACK)
         .join(CONFIG_FILE))
 }
 
+/// The config file in either location, if there is one.
+pub fn existing_config_path() -> Option<PathBuf> {
+    let fallback = fallback_config_path().ok();
+    [Some(preferred_config_path()), fallback]
+        .into_iter()
+        .flatten()
+        .find(|path| path.exists())
+}
+
+/// The config from either location, without creating one.
+pub fn load_existing_config() -> Option<Config> {
+    existing_config_path()
+        .and_then(|path| load_decrypted(&path).ok())
+        .map(|mut config| {
+            apply_env_overrides(&mut config);
+            config
+        })
+}
+
+/// True when a config file exists in either location.
+pub fn config_exists() -> bool {
+    preferred_config_path().exists()
+        || fallback_config_path()
+            .map(|path| path.exists())
+            .unwrap_or(false)
+}
+
 pub fn write_config(config: &Config) -> Result<PathBuf> {
-    let contents = toml::to_string_pretty(config).context("serialize config")?;
     let preferred = preferred_config_path();
-    if try_write(&preferred, &contents).is_ok() {
+    if file_contents(config, &preferred)
+        .and_then(|contents| try_write(&preferred, &contents))
+        .is_ok()
+    {
         return Ok(preferred);
     }
 
     let fallback = fallback_config_path()?;
+    let contents = file_contents(config, &fallback)?;
     try_write(&fallback, &contents).context("write fallback config")?;
     Ok(fallback)
 }
 
+/// Writes the config back to the file it was loaded from.
+pub fn save_config(config: &Config, path: &Path) -> Result<()> {
+    let contents = file_contents(config, path)?;
+    try_write(path, &contents)
+}
+
+/// What `path` should hold: without the environment overrides, and with
+/// the secrets encrypted when there is a key.
+fn file_contents(config: &Config, path: &Path) -> Result<String> {
+    let mut config = without_env_overrides(config, path);
+    secrets::encrypt(&mut config, path)?;
+    toml::to_string_pretty(&config).context("serialize config")
+}
+
+/// True while `LOX_LINEIN_BRIDGE_BRIDGE_ID` decides the bridge id, so a
+/// new one can't be kept by saving the config.
+pub fn bridge_id_from_env() -> bool {
+    env_override(BRIDGE_ID_ENV).is_some()
+}
+
+fn env_override(var: &str) -> Option<String> {
+    std::env::var(var).ok().filter(|value| !value.is_empty())
+}
+
+fn apply_env_overrides(config: &mut Config) {
+    if let Some(bridge_id) = env_override(BRIDGE_ID_ENV) {
+        info!("{} overrides the config file", BRIDGE_ID_ENV);
+        config.bridge_id = bridge_id;
+    }
+    for (var, field) in ENV_OVERRIDES {
+        if let Some(value) = env_override(var) {
+            info!("{} overrides the config file", var);
+            *field(config) = Some(value);
+        }
+    }
+}
+
+/// `config` with the overridden fields as they are in `path`, so saving
+/// doesn't turn the environment into file contents.
+fn without_env_overrides(config: &Config, path: &Path) -> Config {
+    let mut config = config.clone();
+    let mut on_disk = load_decrypted(path).ok();
+    if env_override(BRIDGE_ID_ENV).is_some() {
+        if let Some(on_disk) = &on_disk {
+            config.bridge_id = on_disk.bridge_id.clone();
+        }
+    }
+    for (var, field) in ENV_OVERRIDES {
+        if env_override(var).is_some() {
+            *field(&mut config) = on_disk.as_mut().and_then(|on_disk| field(on_disk).take());
+        }
+    }
+    config
+}
+
+/// The config with `LOX_LINEIN_BRIDGE_*` overrides applied, and the file it
+/// came from.
 pub fn load_or_create_config() -> Result<(Config, PathBuf)> {
+    let (mut config, path) = load_or_create_config_file()?;
+    apply_env_overrides(&mut config);
+    Ok((config, path))
+}
+
+/// Reads `path` again, with the environment overrides; for hot-reload.
+pub fn reload_config(path: &Path) -> Result<Config> {
+    let mut config = load_config_file_reporting(path)?;
+    secrets::decrypt(&mut config, path)?;
+    apply_env_overrides(&mut config);
+    Ok(config)
+}
+
+fn load_or_create_config_file() -> Result<(Config, PathBuf)> {
     let preferred = preferred_config_path();
     if preferred.exists() {
-        match load_config_file(&preferred) {
-            Ok(config) => return Ok((config, preferred)),
+        match load_config_file_reporting(&preferred) {
+            Ok(mut config) => {
+                // A missing key must not get the config moved aside.
+                secrets::decrypt(&mut config, &preferred)?;
+                return Ok((config, preferred));
+            }
             Err(err) => {
                 backup_invalid_config(&preferred, &err)?;
             }
@@ -53,8 +299,12 @@ pub fn load_or_create_config() -> Result<(Config, PathBuf)> {
 
     let fallback = fallback_config_path()?;
     if fallback.exists() {
-        match load_config_file(&fallback) {
-            Ok(config) => return Ok((config, fallback)),
+        match load_config_file_reporting(&fallback) {
+            Ok(mut config) => {
+                // A missing key must not get the config moved aside.
+                secrets::decrypt(&mut config, &fallback)?;
+                return Ok((config, fallback));
+            }
             Err(err) => {
                 backup_invalid_config(&fallback, &err)?;
             }
@@ -65,14 +315,73 @@ pub fn load_or_create_config() -> Result<(Config, PathBuf)> {
         bridge_id: uuid::Uuid::new_v4().to_string(),
         preferred_server_name: None,
         preferred_server_mac: None,
+        server_url: None,
+        register_path: None,
+        status_path: None,
+        fallback_servers: Vec::new(),
+        dns_sd_domain: None,
+        dns_sd_server: None,
+        bind_interface: None,
+        bind_address: None,
+        update_url: None,
+        update_public_key: None,
+        server_tls: ServerTlsConfig::default(),
+        server_token: None,
+        capture_device: None,
+        tee_pipe: false,
+        tee_pipe_dir: None,
+        redact: RedactionConfig::default(),
+        sources: SourcesConfig::default(),
+        health: HealthConfig::default(),
+        snapcast: None,
+        multicast: None,
+        hls: None,
+        black_box: None,
+        monitor: None,
+        api: None,
+        overrides: Overrides::default(),
     };
     let path = write_config(&config)?;
     Ok((config, path))
 }
 
 fn load_config_file(path: &Path) -> Result<Config> {
+    read_config_file(path).map(|(config, _)| config)
+}
+
+fn load_decrypted(path: &Path) -> Result<Config> {
+    let mut config = load_config_file(path)?;
+    secrets::decrypt(&mut config, path)?;
+    Ok(config)
+}
+
+/// Like `load_config_file`, and logs the keys it had to ignore.
+fn load_config_file_reporting(path: &Path) -> Result<Config> {
+    let (config, unknown) = read_config_file(path)?;
+    for key in unknown {
+        warn!(
+            "{}: unknown key `{}` is ignored; check it with `lox-linein-bridge validate-config`",
+            path.display(),
+            key
+        );
+    }
+    Ok(config)
+}
+
+/// The config in `path` and its unknown keys.
+pub fn read_config_file(path: &Path) -> Result<(Config, Vec<String>)> {
     let data = fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
-    toml::from_str(&data).with_context(|| format!("parse {}", path.display()))
+    parse_config(&data).with_context(|| format!("parse {}", path.display()))
+}
+
+/// Parses config file contents. Keys the bridge doesn't know, e.g. typos,
+/// are returned as dotted paths instead of being dropped silently.
+pub fn parse_config(data: &str) -> Result<(Config, Vec<String>), toml::de::Error> {
+    let mut unknown = Vec::new();
+    let config = serde_ignored::deserialize(toml::Deserializer::new(data), |path| {
+        unknown.push(path.to_string())
+    })?;
+    Ok((config, unknown))
 }
 
 fn backup_invalid_config(path: &Path, err: &anyhow::Error) -> Result<()> {
@@ -81,6 +390,12 @@ fn backup_invalid_config(path: &Path, err: &anyhow::Error) -> Result<()> {
         .unwrap_or_default()
         .as_secs();
     let backup = path.with_extension(format!("invalid.{}", timestamp));
+    warn!(
+        "{} could not be read and is moved to {}: {:#}",
+        path.display(),
+        backup.display(),
+        err
+    );
     fs::rename(path, &backup)
         .or_else(|_| {
             fs::copy(path, &backup)
@@ -88,12 +403,83 @@ fn backup_invalid_config(path: &Path, err: &anyhow::Error) -> Result<()> {
                 .and_then(|_| fs::remove_file(path))
         })
         .with_context(|| format!("backup invalid config {}: {}", path.display(), err))?;
+    prune_invalid_backups(path);
     Ok(())
 }
 
+/// Keeps the newest `MAX_INVALID_BACKUPS` of `<name>.invalid.<time>`.
+fn prune_invalid_backups(path: &Path) {
+    let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+        return;
+    };
+    let prefix = format!("{}.invalid.", stem.to_string_lossy());
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut backups: Vec<(u64, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            let timestamp = name.to_str()?.strip_prefix(&prefix)?.parse().ok()?;
+            Some((timestamp, entry.path()))
+        })
+        .collect();
+    backups.sort();
+    let excess = backups.len().saturating_sub(MAX_INVALID_BACKUPS);
+    for (_, backup) in backups.into_iter().take(excess) {
+        if let Err(err) = fs::remove_file(&backup) {
+            warn!(
+                "removing old config backup {} failed: {}",
+                backup.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Writes a temp file next to `path`, syncs it and renames it over `path`,
+/// so a power cut leaves either the old or the new config, never half of
+/// one. The old file's permissions and owner are kept, e.g. 0600 for a
+/// token or the service account's config written by root. A symlinked
+/// config is written where the link points, so the link stays.
 fn try_write(path: &Path, contents: &str) -> Result<()> {
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+    let resolved = fs::canonicalize(path).ok();
+    let path = resolved.as_deref().unwrap_or(path);
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = parent.join(tmp_name);
+    let written = (|| -> std::io::Result<()> {
+        // Owner-only until the target's own mode is known, so a new config
+        // holding tokens is never readable by others.
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)?;
+        if let Ok(meta) = fs::metadata(path) {
+            file.set_permissions(meta.permissions())?;
+            let created = file.metadata()?;
+            if (created.uid(), created.gid()) != (meta.uid(), meta.gid()) {
+                std::os::unix::fs::fchown(&file, Some(meta.uid()), Some(meta.gid()))?;
+            }
+        } else {
+            // A leftover temp file keeps its old mode.
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        // The rename itself is only durable once the directory is synced.
+        fs::File::open(parent)?.sync_all()
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
     }
-    fs::write(path, contents).with_context(|| format!("write {}", path.display()))
+    written.with_context(|| format!("write {}", path.display()))
 }
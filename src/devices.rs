@@ -0,0 +1,61 @@
+//! `lox-linein-bridge devices`: lists capture devices with what they
+//! support, to pick a `capture_device` over SSH without starting the
+//! bridge. `--json` prints the same for scripts.
+
+use crate::audio::{self, DeviceProbe, RateRange};
+use crate::cli::OutputArgs;
+use crate::i18n::t;
+use anyhow::{Context, Result};
+
+#[derive(Debug, clap::Args)]
+pub struct DevicesOptions {
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+pub fn run_devices(options: DevicesOptions) -> Result<()> {
+    let devices = audio::probe_input_devices()?;
+    if options.output.json {
+        let json = serde_json::to_string_pretty(&devices).context("serialize devices")?;
+        println!("{}", json);
+        return Ok(());
+    }
+    if devices.is_empty() {
+        println!("{}", t!("devices-none"));
+        return Ok(());
+    }
+    for device in &devices {
+        print_device(device);
+    }
+    Ok(())
+}
+
+fn print_device(device: &DeviceProbe) {
+    let mut tags = Vec::new();
+    if device.is_default {
+        tags.push(t!("devices-default"));
+    }
+    if device.is_monitor {
+        tags.push(t!("wizard-device-monitor"));
+    }
+    println!("{} {}", device.id, tags.join(" "));
+    if let Some(error) = device.error.as_deref() {
+        println!("  {}", t!("devices-probe-failed", error = error));
+        return;
+    }
+    let channels: Vec<String> = device.channels.iter().map(u16::to_string).collect();
+    let rates: Vec<String> = device.rates.iter().map(format_rates).collect();
+    println!("  {}: {}", t!("devices-channels"), channels.join(", "));
+    println!("  {}: {}", t!("devices-rates"), rates.join(", "));
+    if !device.formats.is_empty() {
+        println!("  {}: {}", t!("devices-formats"), device.formats.join(", "));
+    }
+}
+
+fn format_rates(range: &RateRange) -> String {
+    if range.min == range.max {
+        format!("{} Hz", range.min)
+    } else {
+        format!("{}-{} Hz", range.min, range.max)
+    }
+}
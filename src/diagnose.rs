@@ -0,0 +1,276 @@
+//! `diagnose` command: collects what a bug report needs into one JSON file:
+//! the config with secrets redacted, capture devices, an mDNS (and unicast
+//! DNS-SD) scan, server reachability, a short capture probe and the health
+//! snapshot. Each part records its own error, so one failing check doesn't
+//! hide the others.
+
+use crate::audio::{self, CaptureOptions, ResamplerMode};
+use crate::config::{self, Config};
+use crate::discovery::{self, DiscoveredServer};
+use crate::health;
+use crate::i18n::t;
+use crate::limiter::OutputSettings;
+use crate::meter::LevelMeter;
+use crate::net::Binding;
+use crate::server_api;
+use crate::timestamp;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const BROWSE_TIMEOUT: Duration = Duration::from_secs(5);
+const PROBE_RATE: u32 = 48_000;
+const PROBE_DURATION: Duration = Duration::from_secs(3);
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, clap::Args)]
+pub struct DiagnoseOptions {
+    /// Defaults to `lox-linein-bridge-diagnose-<time>.json`.
+    #[arg(long, value_name = "PATH")]
+    out: Option<PathBuf>,
+    /// Skips the capture probe, e.g. while the bridge holds the device.
+    #[arg(long)]
+    no_capture: bool,
+}
+
+pub async fn run_diagnose(options: DiagnoseOptions) -> Result<()> {
+    let out = options.out.unwrap_or_else(|| {
+        PathBuf::from(format!(
+            "lox-linein-bridge-diagnose-{}.json",
+            timestamp::file_stamp()
+        ))
+    });
+    let config = config::load_existing_config();
+    let binding = config
+        .as_ref()
+        .and_then(|config| {
+            Binding::new(
+                config.bind_interface.as_deref(),
+                config.bind_address.as_deref(),
+            )
+            .ok()
+        })
+        .unwrap_or_default();
+
+    println!("{}", t!("diagnose-step", step = "devices"));
+    let devices = section(audio::probe_input_devices());
+
+    println!("{}", t!("diagnose-step", step = "discovery"));
+    let found = scan(config.clone(), binding.clone()).await;
+    let mut servers = config
+        .as_ref()
+        .map(discovery::configured_servers)
+        .unwrap_or_default();
+    if let Ok(found) = &found {
+        for server in found {
+            if !servers
+                .iter()
+                .any(|known| known.base_url == server.base_url)
+            {
+                servers.push(server.clone());
+            }
+        }
+    }
+    let discovered = section(found);
+
+    println!("{}", t!("diagnose-step", step = "servers"));
+    let reachability = match &config {
+        Some(config) => reachability(config, &servers).await,
+        None => Vec::new(),
+    };
+
+    let capture = if !options.no_capture {
+        println!("{}", t!("diagnose-step", step = "capture"));
+        let device = config
+            .as_ref()
+            .and_then(|config| config.capture_device.clone());
+        section(capture_probe(device).await)
+    } else {
+        json!({ "skipped": true })
+    };
+
+    let health_config = config
+        .as_ref()
+        .map(|config| config.health.clone())
+        .unwrap_or_default();
+    let health_path = health::file_path(&health_config);
+    let health = section(
+        std::fs::read_to_string(&health_path)
+            .with_context(|| format!("read {}", health_path))
+            .and_then(|data| serde_json::from_str::<Value>(&data).context("parse health file")),
+    );
+
+    let bundle = json!({
+        "created": timestamp::now_rfc3339(),
+        "version": env!("CARGO_PKG_VERSION"),
+        "target": env!("BUILD_TARGET"),
+        "system": system_info(),
+        "config": match &config {
+            Some(config) => section(serde_json::to_value(config).map(redact).map_err(anyhow::Error::from)),
+            None => json!({ "error": "no config file" }),
+        },
+        "devices": devices,
+        "discovery": discovered,
+        "servers": reachability,
+        "capture": capture,
+        "health": health,
+    });
+    let data = serde_json::to_string_pretty(&bundle).context("serialize bundle")?;
+    std::fs::write(&out, data).with_context(|| format!("write {}", out.display()))?;
+    println!(
+        "{}",
+        t!("diagnose-written", path = out.display().to_string())
+    );
+    Ok(())
+}
+
+/// The value of a check, or its error in place of it.
+fn section<T: serde::Serialize>(result: Result<T>) -> Value {
+    match result.and_then(|value| serde_json::to_value(value).map_err(anyhow::Error::from)) {
+        Ok(value) => value,
+        Err(err) => json!({ "error": format!("{:#}", err) }),
+    }
+}
+
+/// Blanks values whose key names a secret, at any depth.
+fn redact(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| {
+                    if is_secret(&key) && !value.is_null() {
+                        (key, Value::String(REDACTED.to_string()))
+                    } else {
+                        (key, redact(value))
+                    }
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(redact).collect()),
+        other => other,
+    }
+}
+
+fn is_secret(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    ["token", "password", "secret", "private"]
+        .iter()
+        .any(|word| key.contains(word))
+        || (key.ends_with("key") && !key.contains("public"))
+}
+
+fn system_info() -> Value {
+    let uname = std::process::Command::new("uname")
+        .arg("-a")
+        .output()
+        .ok()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    let os_release = std::fs::read_to_string("/etc/os-release")
+        .ok()
+        .and_then(|data| {
+            data.lines()
+                .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+                .map(|name| name.trim_matches('"').to_string())
+        });
+    json!({ "uname": uname, "os": os_release })
+}
+
+/// mDNS, then unicast DNS-SD when a domain is configured.
+async fn scan(config: Option<Config>, binding: Binding) -> Result<Vec<DiscoveredServer>> {
+    tokio::task::spawn_blocking(move || {
+        let mut found = discovery::browse_servers(BROWSE_TIMEOUT, &binding)?;
+        if let Some(config) = &config {
+            if let Some(domain) = config.dns_sd_domain.as_deref() {
+                found.extend(discovery::browse_dns_sd(
+                    domain,
+                    config.dns_sd_server.as_deref(),
+                )?);
+            }
+        }
+        Ok(found)
+    })
+    .await
+    .context("discovery task failed")?
+}
+
+/// An HTTP request to each server's base URL; any answer counts as
+/// reachable.
+async fn reachability(config: &Config, servers: &[DiscoveredServer]) -> Vec<Value> {
+    let client = match server_api::http_client(&config.server_tls.settings()) {
+        Ok(client) => client,
+        Err(err) => return vec![json!({ "error": format!("{:#}", err) })],
+    };
+    let mut results = Vec::new();
+    for server in servers {
+        let started = Instant::now();
+        let result = match client.get(&server.base_url).send().await {
+            Ok(response) => json!({
+                "url": server.base_url,
+                "status": response.status().as_u16(),
+                "ms": started.elapsed().as_millis() as u64,
+            }),
+            Err(err) => json!({
+                "url": server.base_url,
+                "error": format!("{:#}", anyhow::Error::from(err)),
+            }),
+        };
+        results.push(result);
+    }
+    results
+}
+
+/// Opens the device for a few seconds and reports what arrived.
+async fn capture_probe(device: Option<String>) -> Result<Value> {
+    let device = audio::default_capture_device(device)?;
+    let session = audio::start_capture(
+        &device,
+        PROBE_RATE,
+        ResamplerMode::SincQuality,
+        CaptureOptions::default(),
+        OutputSettings::default(),
+    )
+    .with_context(|| format!("open {}", device))?;
+    let audio::CaptureSession {
+        mut receiver,
+        mut error_receiver,
+        streams,
+        sample_rate,
+        channels,
+        format,
+        observed_rate,
+        ..
+    } = session;
+    let _capture_guard = streams;
+    let mut meter = LevelMeter::new(PROBE_RATE);
+    let mut chunks = 0u64;
+    let mut peak_db = f32::MIN;
+    let mut rms_db = None;
+    let mut errors = Vec::new();
+    let deadline = tokio::time::Instant::now() + PROBE_DURATION;
+    loop {
+        tokio::select! {
+            chunk = receiver.recv() => {
+                let Some(chunk) = chunk else { break };
+                chunks += 1;
+                if let Some(levels) = meter.process(&chunk) {
+                    peak_db = peak_db.max(levels.peak_db);
+                    rms_db = Some(levels.rms_long_db);
+                }
+            }
+            Some(err) = error_receiver.recv() => errors.push(err),
+            _ = tokio::time::sleep_until(deadline) => break,
+        }
+    }
+    Ok(json!({
+        "device": device,
+        "rate": sample_rate,
+        "channels": channels,
+        "format": format.to_string(),
+        "observed_rate": observed_rate.lock().ok().and_then(|rate| *rate),
+        "chunks": chunks,
+        "rms_db": rms_db,
+        "peak_db": (chunks > 0).then_some(peak_db),
+        "errors": errors,
+    }))
+}
@@ -0,0 +1,110 @@
+//! `discover` command: browses for `_loxaudio._tcp` the way the bridge does
+//! and prints every service with its TXT record, addresses and the API
+//! paths the bridge would use, to tell network problems from registration
+//! problems.
+
+use crate::cli::OutputArgs;
+use crate::config;
+use crate::discovery::{self, Announcement};
+use crate::i18n::t;
+use crate::net::Binding;
+use anyhow::{Context, Result};
+use std::time::Duration;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 5;
+
+#[derive(Debug, clap::Args)]
+pub struct DiscoverOptions {
+    /// How long to browse.
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_TIMEOUT_SECS)]
+    timeout: u64,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+pub async fn run_discover(options: DiscoverOptions) -> Result<()> {
+    let config = config::load_existing_config();
+    let binding = match &config {
+        Some(config) => Binding::new(
+            config.bind_interface.as_deref(),
+            config.bind_address.as_deref(),
+        )?,
+        None => Binding::default(),
+    };
+    let dns_sd = config
+        .as_ref()
+        .and_then(|config| Some((config.dns_sd_domain.clone()?, config.dns_sd_server.clone())));
+    if !options.output.json {
+        println!("{}", t!("discover-browsing", seconds = options.timeout));
+    }
+    let timeout = Duration::from_secs(options.timeout);
+    // A failed unicast DNS-SD query is reported, but doesn't hide what
+    // mDNS found.
+    let (announcements, dns_sd_error) = tokio::task::spawn_blocking(
+        move || -> Result<(Vec<Announcement>, Option<anyhow::Error>)> {
+            let mut announcements = discovery::browse_announcements(timeout, &binding)?;
+            let mut dns_sd_error = None;
+            if let Some((domain, server)) = dns_sd {
+                match discovery::dns_sd_announcements(&domain, server.as_deref()) {
+                    Ok(found) => announcements.extend(found),
+                    Err(err) => dns_sd_error = Some(err),
+                }
+            }
+            Ok((announcements, dns_sd_error))
+        },
+    )
+    .await
+    .context("discovery task failed")??;
+    if let Some(err) = &dns_sd_error {
+        eprintln!(
+            "{}",
+            t!("discover-dns-sd-failed", error = format!("{:#}", err))
+        );
+    }
+
+    if options.output.json {
+        let json = serde_json::to_string_pretty(&announcements).context("serialize services")?;
+        println!("{}", json);
+        return Ok(());
+    }
+    if announcements.is_empty() {
+        println!("{}", t!("discover-none"));
+        return Ok(());
+    }
+    for announcement in &announcements {
+        print_announcement(announcement);
+    }
+    Ok(())
+}
+
+fn print_announcement(announcement: &Announcement) {
+    let server = &announcement.server;
+    println!();
+    println!("{} ({})", announcement.instance, announcement.source);
+    println!(
+        "  {}: {}:{}",
+        t!("discover-host"),
+        announcement.hostname,
+        announcement.port
+    );
+    if !announcement.addresses.is_empty() {
+        let addresses: Vec<String> = announcement
+            .addresses
+            .iter()
+            .map(|address| address.to_string())
+            .collect();
+        println!("  {}: {}", t!("discover-addresses"), addresses.join(", "));
+    }
+    println!("  {}: {}", t!("discover-url"), server.base_url);
+    println!("  register: {}", server.register_path);
+    println!("  status: {}", server.status_path);
+    println!("  unregister: {}", server.unregister_path);
+    println!("  control: {}", server.control_path);
+    println!("  status batch: {}", server.status_batch_path);
+    let mut txt: Vec<_> = server.txt.iter().collect();
+    txt.sort();
+    println!("  TXT:");
+    for (key, value) in txt {
+        println!("    {}={}", key, value);
+    }
+}
@@ -1,101 +1,389 @@
+use crate::config::Config;
+use crate::dnssd;
+use crate::net::{self, Binding};
 use anyhow::{Context, Result};
-use mdns_sd::{ServiceDaemon, ServiceEvent};
+use mdns_sd::{IfKind, ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::time::{Duration, Instant};
+use tracing::{debug, warn};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiscoveredServer {
     pub base_url: String,
     pub register_path: String,
     pub status_path: String,
+    /// `DELETE` target for the goodbye on shutdown.
+    pub unregister_path: String,
+    pub control_path: String,
+    /// Upload of statuses spooled while the server was unreachable.
+    pub status_batch_path: String,
+    /// mDNS instance name; `None` for a configured URL.
+    pub instance: Option<String>,
     pub txt: HashMap<String, String>,
 }
 
-pub fn discover_server(
-    preferred_name: Option<&str>,
-    preferred_mac: Option<&str>,
-) -> Result<DiscoveredServer> {
-    const SERVICE_TYPE: &str = "_loxaudio._tcp.local.";
-    let mdns = ServiceDaemon::new().context("start mDNS daemon")?;
+const SERVICE_TYPE: &str = "_loxaudio._tcp.local.";
+const DEFAULT_API_PREFIX: &str = "/api";
+/// How often the monitor checks whether its session is still running.
+const WATCH_POLL: Duration = Duration::from_secs(1);
+
+/// Bounds on discovery before the first server is found. Unbounded by
+/// default, so the service keeps retrying until a server shows up.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiscoveryLimits {
+    pub timeout: Option<Duration>,
+    pub max_attempts: Option<u32>,
+}
+
+impl DiscoveryLimits {
+    pub fn exhausted(&self, attempts: u32, started: Instant) -> bool {
+        self.max_attempts.is_some_and(|max| attempts >= max)
+            || self
+                .timeout
+                .is_some_and(|timeout| started.elapsed() >= timeout)
+    }
+}
+
+/// Returned by `run_bridge` when [`DiscoveryLimits`] ran out before any
+/// server was found; the caller decides how the process ends.
+#[derive(Debug)]
+pub struct NoServer {
+    pub attempts: u32,
+    pub elapsed: Duration,
+}
+
+impl std::fmt::Display for NoServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "no server found after {} discovery attempts ({} s)",
+            self.attempts,
+            self.elapsed.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for NoServer {}
+
+/// All servers answering on mDNS, best first: the preferred MAC, then the
+/// preferred name, then by the TXT `priority` (lowest first; servers
+/// without one come last). Later entries are failover candidates. When
+/// mDNS finds nothing and `dns_sd_domain` is set, that domain is asked over
+/// unicast DNS-SD instead.
+pub fn discover_servers(config: &Config, binding: &Binding) -> Result<Vec<DiscoveredServer>> {
+    let preferred_name = config.preferred_server_name.as_deref();
+    let preferred_mac = config.preferred_server_mac.as_deref();
+    let mut candidates = match browse_servers(Duration::from_secs(8), binding) {
+        Ok(candidates) => candidates,
+        Err(err) if config.dns_sd_domain.is_some() => {
+            warn!("mDNS browsing failed: {:#}", err);
+            Vec::new()
+        }
+        Err(err) => return Err(err),
+    };
+    if let (true, Some(domain)) = (candidates.is_empty(), config.dns_sd_domain.as_deref()) {
+        debug!("no mDNS answer, asking unicast DNS-SD in {}", domain);
+        candidates = browse_dns_sd(domain, config.dns_sd_server.as_deref())?;
+    }
+    if candidates.is_empty() {
+        anyhow::bail!("no _loxaudio._tcp services found");
+    }
+    let txt_is = |server: &DiscoveredServer, key: &str, wanted: Option<&str>| {
+        wanted
+            .zip(server.txt.get(key))
+            .is_some_and(|(wanted, value)| {
+                // Case doesn't matter; MACs are written either way.
+                value.eq_ignore_ascii_case(wanted)
+            })
+    };
+    candidates.sort_by_key(|server| {
+        (
+            !txt_is(server, "mac", preferred_mac),
+            !txt_is(server, "name", preferred_name),
+            server
+                .txt
+                .get("priority")
+                .and_then(|priority| priority.parse::<u32>().ok())
+                .unwrap_or(u32::MAX),
+        )
+    });
+    Ok(candidates)
+}
+
+/// Lists the servers registered for unicast DNS-SD in `domain`.
+pub fn browse_dns_sd(domain: &str, nameserver: Option<&str>) -> Result<Vec<DiscoveredServer>> {
+    Ok(dns_sd_announcements(domain, nameserver)?
+        .into_iter()
+        .map(|announcement| announcement.server)
+        .collect())
+}
+
+/// Like [`browse_dns_sd`], with the details `discover` shows.
+pub fn dns_sd_announcements(domain: &str, nameserver: Option<&str>) -> Result<Vec<Announcement>> {
+    Ok(dnssd::browse(domain, nameserver)
+        .with_context(|| format!("unicast DNS-SD in {}", domain))?
+        .into_iter()
+        .map(|instance| {
+            let url = base_url(&instance.host, instance.port);
+            debug!("unicast DNS-SD: {} at {}", instance.name, url);
+            Announcement {
+                source: "dns-sd",
+                server: server_from_txt(url, None, instance.txt),
+                instance: instance.name,
+                hostname: instance.host,
+                port: instance.port,
+                addresses: Vec::new(),
+            }
+        })
+        .collect())
+}
+
+/// Builds the server entry for a manually configured base URL, using the
+/// default API paths.
+pub fn server_from_url(base_url: &str) -> DiscoveredServer {
+    DiscoveredServer {
+        base_url: base_url.trim_end_matches('/').to_string(),
+        register_path: format!("{}/linein/bridges/register", DEFAULT_API_PREFIX),
+        status_path: format!("{}/linein/bridges/{{bridge_id}}/status", DEFAULT_API_PREFIX),
+        unregister_path: format!("{}/linein/bridges/{{bridge_id}}", DEFAULT_API_PREFIX),
+        control_path: format!(
+            "{}/linein/bridges/{{bridge_id}}/control",
+            DEFAULT_API_PREFIX
+        ),
+        status_batch_path: format!(
+            "{}/linein/bridges/{{bridge_id}}/status/batch",
+            DEFAULT_API_PREFIX
+        ),
+        instance: None,
+        txt: HashMap::new(),
+    }
+}
+
+/// Servers set in the config or the environment, which bypass mDNS:
+/// `server_url` first, then `fallback_servers`. Empty without a
+/// `server_url`. `LOX_LINEIN_BRIDGE_SERVER_URL`,
+/// `LOX_LINEIN_BRIDGE_REGISTER_PATH` and `LOX_LINEIN_BRIDGE_STATUS_PATH` win
+/// over the file; the paths apply to every server.
+pub fn configured_servers(config: &Config) -> Vec<DiscoveredServer> {
+    let Some(url) = env_or("LOX_LINEIN_BRIDGE_SERVER_URL", config.server_url.as_deref()) else {
+        return Vec::new();
+    };
+    let register_path = env_or(
+        "LOX_LINEIN_BRIDGE_REGISTER_PATH",
+        config.register_path.as_deref(),
+    );
+    let status_path = env_or(
+        "LOX_LINEIN_BRIDGE_STATUS_PATH",
+        config.status_path.as_deref(),
+    );
+    std::iter::once(&url)
+        .chain(&config.fallback_servers)
+        .map(|url| {
+            let mut server = server_from_url(url);
+            if let Some(path) = register_path.clone() {
+                server.register_path = normalize_path(path);
+            }
+            if let Some(path) = status_path.clone() {
+                server.status_path = normalize_path(path);
+            }
+            server
+        })
+        .collect()
+}
+
+fn env_or(key: &str, fallback: Option<&str>) -> Option<String> {
+    std::env::var(key)
+        .ok()
+        .filter(|value| !value.is_empty())
+        .or_else(|| fallback.map(str::to_string))
+}
+
+/// Lists all servers answering on mDNS within `timeout`.
+pub fn browse_servers(timeout: Duration, binding: &Binding) -> Result<Vec<DiscoveredServer>> {
+    Ok(browse_announcements(timeout, binding)?
+        .into_iter()
+        .map(|announcement| announcement.server)
+        .collect())
+}
+
+/// A service as it was announced, with everything `discover` shows.
+#[derive(Debug, Clone, Serialize)]
+pub struct Announcement {
+    /// `mdns` or `dns-sd`.
+    pub source: &'static str,
+    pub instance: String,
+    pub hostname: String,
+    pub port: u16,
+    /// Empty for unicast DNS-SD, which only names the host.
+    pub addresses: Vec<IpAddr>,
+    pub server: DiscoveredServer,
+}
+
+/// Every `_loxaudio._tcp` service answering on mDNS within `timeout`, once
+/// per instance.
+pub fn browse_announcements(timeout: Duration, binding: &Binding) -> Result<Vec<Announcement>> {
+    let mdns = start_mdns(binding)?;
     let receiver = mdns.browse(SERVICE_TYPE).context("browse mDNS services")?;
-    let deadline = Instant::now() + Duration::from_secs(8);
-    let mut candidates = Vec::new();
+    let deadline = Instant::now() + timeout;
+    let mut announcements: Vec<Announcement> = Vec::new();
 
     while Instant::now() < deadline {
         let timeout = deadline.saturating_duration_since(Instant::now());
         match receiver.recv_timeout(timeout) {
             Ok(event) => {
                 if let ServiceEvent::ServiceResolved(info) = event {
-                    let txt = info
-                        .get_properties()
-                        .iter()
-                        .map(|prop| (prop.key().to_string(), prop.val_str().to_string()))
-                        .collect::<HashMap<_, _>>();
-                    let host = resolve_host(info.get_addresses(), info.get_hostname());
-                    let port = info.get_port();
-                    let base_url = format!("http://{}:{}", host, port);
-                    let api_prefix = txt
-                        .get("api")
-                        .cloned()
-                        .unwrap_or_else(|| "/api".to_string());
-                    let register_path = normalize_path(
-                        txt.get("linein_register")
-                            .cloned()
-                            .unwrap_or_else(|| format!("{}/linein/bridges/register", api_prefix)),
-                    );
-                    let status_path =
-                        normalize_path(txt.get("linein_status").cloned().unwrap_or_else(|| {
-                            format!("{}/linein/bridges/{{bridge_id}}/status", api_prefix)
-                        }));
-                    candidates.push(DiscoveredServer {
-                        base_url,
-                        register_path,
-                        status_path,
-                        txt,
-                    });
+                    let mut addresses: Vec<IpAddr> = info.get_addresses().iter().copied().collect();
+                    addresses.sort();
+                    let announcement = Announcement {
+                        source: "mdns",
+                        instance: info.get_fullname().to_string(),
+                        hostname: info.get_hostname().trim_end_matches('.').to_string(),
+                        port: info.get_port(),
+                        addresses,
+                        server: server_from_info(&info),
+                    };
+                    // A later answer replaces an earlier one of the instance.
+                    announcements.retain(|known| known.instance != announcement.instance);
+                    announcements.push(announcement);
                 }
             }
             Err(_) => break,
         }
     }
 
-    if candidates.is_empty() {
-        shutdown_mdns(&mdns, SERVICE_TYPE);
-        anyhow::bail!("no _loxaudio._tcp services found");
-    }
-
-    if candidates.len() == 1 {
-        shutdown_mdns(&mdns, SERVICE_TYPE);
-        return Ok(candidates.remove(0));
-    }
+    shutdown_mdns(&mdns, SERVICE_TYPE);
+    Ok(announcements)
+}
 
-    if let Some(mac) = preferred_mac {
-        if let Some(server) = candidates
-            .iter()
-            .find(|server| server.txt.get("mac").map(|v| v == mac).unwrap_or(false))
-        {
-            shutdown_mdns(&mdns, SERVICE_TYPE);
-            return Ok(server.clone());
+/// Keeps browsing while a session runs and returns the new location once
+/// the service `current` was found as shows up at another address or port,
+/// e.g. after a DHCP change. Returns `None` once `stopped` says the session
+/// is over.
+pub fn watch_server(
+    current: &DiscoveredServer,
+    binding: &Binding,
+    stopped: impl Fn() -> bool,
+) -> Result<Option<DiscoveredServer>> {
+    let Some(instance) = current.instance.as_deref() else {
+        return Ok(None);
+    };
+    let mdns = start_mdns(binding)?;
+    let receiver = mdns.browse(SERVICE_TYPE).context("browse mDNS services")?;
+    let mut moved = None;
+    while !stopped() {
+        match receiver.recv_timeout(WATCH_POLL) {
+            Ok(ServiceEvent::ServiceResolved(info)) if info.get_fullname() == instance => {
+                let port = info.get_port();
+                let same = info
+                    .get_addresses()
+                    .iter()
+                    .any(|addr| base_url(&addr.to_string(), port) == current.base_url);
+                if !same {
+                    moved = Some(server_from_info(&info));
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(_) if receiver.is_disconnected() => break,
+            Err(_) => {}
         }
     }
+    shutdown_mdns(&mdns, SERVICE_TYPE);
+    Ok(moved)
+}
 
-    if let Some(name) = preferred_name {
-        if let Some(server) = candidates
-            .iter()
-            .find(|server| server.txt.get("name").map(|v| v == name).unwrap_or(false))
-        {
-            shutdown_mdns(&mdns, SERVICE_TYPE);
-            return Ok(server.clone());
-        }
+/// mDNS daemon limited to the bound interface or address, if any.
+fn start_mdns(binding: &Binding) -> Result<ServiceDaemon> {
+    let mdns = ServiceDaemon::new().context("start mDNS daemon")?;
+    let only = match (&binding.interface, binding.address) {
+        (Some(name), _) => IfKind::Name(name.clone()),
+        (None, Some(address)) => IfKind::Addr(address),
+        (None, None) => return Ok(mdns),
+    };
+    mdns.disable_interface(IfKind::All)
+        .context("limit mDNS interfaces")?;
+    mdns.enable_interface(only)
+        .context("limit mDNS interfaces")?;
+    Ok(mdns)
+}
+
+fn server_from_info(info: &ServiceInfo) -> DiscoveredServer {
+    let txt = info
+        .get_properties()
+        .iter()
+        .map(|prop| (prop.key().to_string(), prop.val_str().to_string()))
+        .collect::<HashMap<_, _>>();
+    let host = resolve_host(info.get_addresses(), info.get_hostname());
+    server_from_txt(
+        base_url(&host, info.get_port()),
+        Some(info.get_fullname().to_string()),
+        txt,
+    )
+}
+
+/// Server entry from the announced TXT keys, the same for mDNS and unicast
+/// DNS-SD.
+fn server_from_txt(
+    base_url: String,
+    instance: Option<String>,
+    txt: HashMap<String, String>,
+) -> DiscoveredServer {
+    let api_prefix = txt
+        .get("api")
+        .cloned()
+        .unwrap_or_else(|| DEFAULT_API_PREFIX.to_string());
+    let register_path = normalize_path(
+        txt.get("linein_register")
+            .cloned()
+            .unwrap_or_else(|| format!("{}/linein/bridges/register", api_prefix)),
+    );
+    let status_path = normalize_path(
+        txt.get("linein_status")
+            .cloned()
+            .unwrap_or_else(|| format!("{}/linein/bridges/{{bridge_id}}/status", api_prefix)),
+    );
+    let unregister_path = normalize_path(
+        txt.get("linein_unregister")
+            .cloned()
+            .unwrap_or_else(|| format!("{}/linein/bridges/{{bridge_id}}", api_prefix)),
+    );
+    let control_path = normalize_path(
+        txt.get("linein_control")
+            .cloned()
+            .unwrap_or_else(|| format!("{}/linein/bridges/{{bridge_id}}/control", api_prefix)),
+    );
+    let status_batch_path =
+        normalize_path(txt.get("linein_status_batch").cloned().unwrap_or_else(|| {
+            format!("{}/linein/bridges/{{bridge_id}}/status/batch", api_prefix)
+        }));
+    DiscoveredServer {
+        base_url,
+        register_path,
+        status_path,
+        unregister_path,
+        control_path,
+        status_batch_path,
+        instance,
+        txt,
     }
+}
 
-    shutdown_mdns(&mdns, SERVICE_TYPE);
-    Ok(candidates.remove(0))
+fn base_url(host: &str, port: u16) -> String {
+    format!("http://{}", net::host_port(host, port))
 }
 
+/// Prefers IPv4, then a routable IPv6 address, then the mDNS host name.
 fn resolve_host(addresses: &std::collections::HashSet<IpAddr>, hostname: &str) -> String {
-    if let Some(addr) = addresses.iter().find(|addr| addr.is_ipv4()) {
+    if let Some(addr) = addresses.iter().filter(|addr| addr.is_ipv4()).min() {
+        return addr.to_string();
+    }
+    if let Some(addr) = addresses
+        .iter()
+        .filter(|addr| addr.is_ipv6() && !net::is_link_local(addr))
+        .min()
+    {
         return addr.to_string();
     }
     let trimmed = hostname.trim_end_matches('.');
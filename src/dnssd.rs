@@ -0,0 +1,251 @@
+//! Unicast DNS-SD (RFC 6763 over plain DNS) for networks that drop
+//! multicast, e.g. enterprise Wi-Fi. The service is looked up in an explicit
+//! domain: PTR `_loxaudio._tcp.<domain>` lists the instances, SRV and TXT
+//! give each one's host, port and the same TXT keys mDNS announces.
+//!
+//! Only what that needs is implemented: one UDP query at a time, no
+//! recursion of our own and no TCP retry for truncated answers.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+const SERVICE: &str = "_loxaudio._tcp";
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+/// One service instance found in the domain.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub txt: HashMap<String, String>,
+}
+
+/// Lists the `_loxaudio._tcp` instances of `domain`. `nameserver` is
+/// `ip` or `ip:port`; without it the first one in /etc/resolv.conf is asked.
+pub fn browse(domain: &str, nameserver: Option<&str>) -> Result<Vec<Instance>> {
+    let server = match nameserver {
+        Some(server) => parse_nameserver(server)?,
+        None => system_nameserver()?,
+    };
+    let service = labels(&format!("{}.{}", SERVICE, domain.trim_matches('.')));
+    let mut instances = Vec::new();
+    for record in query(server, &service, TYPE_PTR)? {
+        let Record::Ptr(name) = record else {
+            continue;
+        };
+        let Some((host, port)) = query(server, &name, TYPE_SRV)?
+            .into_iter()
+            .filter_map(|record| match record {
+                Record::Srv {
+                    priority,
+                    port,
+                    target,
+                } => Some((priority, target, port)),
+                _ => None,
+            })
+            .min_by_key(|(priority, _, _)| *priority)
+            .map(|(_, target, port)| (target.join("."), port))
+        else {
+            continue;
+        };
+        let txt = query(server, &name, TYPE_TXT)?
+            .into_iter()
+            .filter_map(|record| match record {
+                Record::Txt(entries) => Some(entries),
+                _ => None,
+            })
+            .flatten()
+            .map(|entry| {
+                let (key, value) = entry.split_once('=').unwrap_or((entry.as_str(), ""));
+                (key.to_string(), value.to_string())
+            })
+            .collect();
+        instances.push(Instance {
+            name: name.join("."),
+            host,
+            port,
+            txt,
+        });
+    }
+    Ok(instances)
+}
+
+fn labels(name: &str) -> Vec<String> {
+    name.split('.').map(str::to_string).collect()
+}
+
+pub fn parse_nameserver(server: &str) -> Result<SocketAddr> {
+    if let Ok(addr) = server.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    let ip = server
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .with_context(|| format!("invalid dns_sd_server {}", server))?;
+    Ok(SocketAddr::new(ip, 53))
+}
+
+fn system_nameserver() -> Result<SocketAddr> {
+    let conf = fs::read_to_string(RESOLV_CONF).with_context(|| format!("read {}", RESOLV_CONF))?;
+    conf.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .find_map(|ip| ip.trim().parse().ok())
+        .map(|ip| SocketAddr::new(ip, 53))
+        .with_context(|| format!("no nameserver in {}", RESOLV_CONF))
+}
+
+/// Names are kept as labels: instance labels may contain dots.
+#[derive(Debug)]
+enum Record {
+    Ptr(Vec<String>),
+    Srv {
+        priority: u16,
+        port: u16,
+        target: Vec<String>,
+    },
+    Txt(Vec<String>),
+}
+
+fn query(server: SocketAddr, name: &[String], qtype: u16) -> Result<Vec<Record>> {
+    let bind: SocketAddr = if server.is_ipv4() {
+        "0.0.0.0:0".parse()?
+    } else {
+        "[::]:0".parse()?
+    };
+    let socket = UdpSocket::bind(bind).context("bind DNS socket")?;
+    socket.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    let id = (uuid::Uuid::new_v4().as_u128() & 0xffff) as u16;
+    socket
+        .send_to(&encode_query(id, name, qtype)?, server)
+        .with_context(|| format!("send DNS query to {}", server))?;
+    let mut buf = [0u8; 4096];
+    loop {
+        let (len, from) = socket
+            .recv_from(&mut buf)
+            .with_context(|| format!("no DNS answer from {} for {}", server, name.join(".")))?;
+        // Stray datagrams, or an answer to an earlier query.
+        if from != server || len < 12 || u16::from_be_bytes([buf[0], buf[1]]) != id {
+            continue;
+        }
+        return parse_answers(&buf[..len], qtype)
+            .with_context(|| format!("invalid DNS answer for {}", name.join(".")));
+    }
+}
+
+fn encode_query(id: u16, name: &[String], qtype: u16) -> Result<Vec<u8>> {
+    let mut msg = Vec::with_capacity(512);
+    msg.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question.
+    msg.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name {
+        anyhow::ensure!(
+            !label.is_empty() && label.len() < 64,
+            "invalid DNS name {}",
+            name.join(".")
+        );
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(msg)
+}
+
+fn parse_answers(msg: &[u8], qtype: u16) -> Result<Vec<Record>> {
+    let flags = read_u16(msg, 2)?;
+    let rcode = flags & 0x000f;
+    // NXDOMAIN just means nothing is registered under the name.
+    if rcode == 3 {
+        return Ok(Vec::new());
+    }
+    anyhow::ensure!(rcode == 0, "DNS error code {}", rcode);
+    let questions = read_u16(msg, 4)?;
+    let answers = read_u16(msg, 6)?;
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = read_name(msg, pos)?.1 + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        let next = read_name(msg, pos)?.1;
+        let rtype = read_u16(msg, next)?;
+        let rdlen = read_u16(msg, next + 8)? as usize;
+        let rdata = next + 10;
+        let end = rdata + rdlen;
+        anyhow::ensure!(end <= msg.len(), "record past the end");
+        if rtype == qtype {
+            records.push(match rtype {
+                TYPE_PTR => Record::Ptr(read_name(msg, rdata)?.0),
+                TYPE_SRV => Record::Srv {
+                    priority: read_u16(msg, rdata)?,
+                    port: read_u16(msg, rdata + 4)?,
+                    target: read_name(msg, rdata + 6)?.0,
+                },
+                _ => Record::Txt(read_txt(&msg[rdata..end])),
+            });
+        }
+        pos = end;
+    }
+    Ok(records)
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> Result<u16> {
+    msg.get(pos..pos + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .context("message too short")
+}
+
+/// Decodes the (possibly compressed) name at `pos`. Returns its labels and
+/// the position after it in the record.
+fn read_name(msg: &[u8], mut pos: usize) -> Result<(Vec<String>, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Each pointer must go backwards, which rules out loops.
+    let mut limit = pos;
+    loop {
+        let len = *msg.get(pos).context("name past the end")? as usize;
+        match len {
+            0 => {
+                return Ok((labels, end.unwrap_or(pos + 1)));
+            }
+            len if len & 0xc0 == 0xc0 => {
+                let target = (read_u16(msg, pos)? & 0x3fff) as usize;
+                anyhow::ensure!(target < limit, "bad name pointer");
+                end.get_or_insert(pos + 2);
+                limit = target;
+                pos = target;
+            }
+            len => {
+                let label = msg
+                    .get(pos + 1..pos + 1 + len)
+                    .context("label past the end")?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + len;
+            }
+        }
+    }
+}
+
+fn read_txt(mut data: &[u8]) -> Vec<String> {
+    let mut entries = Vec::new();
+    while let Some((&len, rest)) = data.split_first() {
+        let len = (len as usize).min(rest.len());
+        if len > 0 {
+            entries.push(String::from_utf8_lossy(&rest[..len]).into_owned());
+        }
+        data = &rest[len..];
+    }
+    entries
+}
@@ -0,0 +1,203 @@
+//! Fault injection hooks for resilience testing. Only active with the
+//! `testing` feature; otherwise every hook is a no-op.
+
+use std::time::Duration;
+
+#[cfg(feature = "testing")]
+mod inner {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::UnixListener;
+
+    const DEFAULT_SOCKET_PATH: &str = "/run/lox-linein-bridge/faults.sock";
+
+    #[derive(Debug, Default)]
+    struct FaultState {
+        drop_chunks: u32,
+        fail_connects: u32,
+        send_delay_ms: u64,
+        device_loss: bool,
+    }
+
+    static STATE: Mutex<FaultState> = Mutex::new(FaultState {
+        drop_chunks: 0,
+        fail_connects: 0,
+        send_delay_ms: 0,
+        device_loss: false,
+    });
+
+    fn with_state<T>(f: impl FnOnce(&mut FaultState) -> T) -> T {
+        let mut state = match STATE.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        f(&mut state)
+    }
+
+    pub fn take_drop_chunk() -> bool {
+        with_state(|state| {
+            if state.drop_chunks > 0 {
+                state.drop_chunks -= 1;
+                true
+            } else {
+                false
+            }
+        })
+    }
+
+    pub fn take_connect_failure() -> bool {
+        with_state(|state| {
+            if state.fail_connects > 0 {
+                state.fail_connects -= 1;
+                true
+            } else {
+                false
+            }
+        })
+    }
+
+    pub fn send_delay() -> Option<Duration> {
+        with_state(|state| {
+            (state.send_delay_ms > 0).then(|| Duration::from_millis(state.send_delay_ms))
+        })
+    }
+
+    pub fn take_device_loss() -> bool {
+        with_state(|state| std::mem::take(&mut state.device_loss))
+    }
+
+    /// Applies one control command and returns the reply line.
+    pub fn apply_command(line: &str) -> String {
+        let mut parts = line.split_whitespace();
+        let command = parts.next().unwrap_or_default();
+        let value = parts.next();
+        let count = || value.and_then(|v| v.parse::<u32>().ok()).unwrap_or(1);
+        with_state(|state| {
+            match command {
+                "drop" => state.drop_chunks = count(),
+                "fail-connect" => state.fail_connects = count(),
+                "delay" => match value.and_then(|v| v.parse::<u64>().ok()) {
+                    Some(ms) => state.send_delay_ms = ms,
+                    None => return "error: delay requires milliseconds".to_string(),
+                },
+                "device-loss" => state.device_loss = true,
+                "clear" => *state = FaultState::default(),
+                "show" | "" => {}
+                other => return format!("error: unknown command {:?}", other),
+            }
+            format!("ok {:?}", state)
+        })
+    }
+
+    pub fn spawn_control() {
+        let path = PathBuf::from(
+            std::env::var("LOX_LINEIN_BRIDGE_FAULTS_SOCKET")
+                .unwrap_or_else(|_| DEFAULT_SOCKET_PATH.to_string()),
+        );
+        // A stale socket from an earlier run is replaced; anything else at
+        // the path is left alone.
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_socket() => {
+                let _ = std::fs::remove_file(&path);
+            }
+            Ok(_) => {
+                tracing::warn!(
+                    "fault control socket {} not started: the path exists and is not a socket",
+                    path.display()
+                );
+                return;
+            }
+            Err(_) => {}
+        }
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::warn!("fault control socket {} failed: {}", path.display(), err);
+                return;
+            }
+        };
+        let _ = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600));
+        tracing::warn!("fault injection enabled, control socket {}", path.display());
+        tokio::spawn(async move {
+            loop {
+                let socket = match listener.accept().await {
+                    Ok((socket, _)) => socket,
+                    Err(err) => {
+                        tracing::warn!("fault control socket: accept failed: {}", err);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+                tokio::spawn(async move {
+                    let (reader, mut writer) = socket.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let reply = apply_command(line.trim());
+                        tracing::info!("fault command {:?}: {}", line.trim(), reply);
+                        if writer
+                            .write_all(format!("{}\n", reply).as_bytes())
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+    }
+}
+
+#[cfg(not(feature = "testing"))]
+mod inner {
+    use std::time::Duration;
+
+    pub fn take_drop_chunk() -> bool {
+        false
+    }
+
+    pub fn take_connect_failure() -> bool {
+        false
+    }
+
+    pub fn send_delay() -> Option<Duration> {
+        None
+    }
+
+    pub fn take_device_loss() -> bool {
+        false
+    }
+
+    pub fn spawn_control() {}
+}
+
+/// Returns true when the next captured chunk should be discarded.
+pub fn take_drop_chunk() -> bool {
+    inner::take_drop_chunk()
+}
+
+/// Returns true when the next ingest connect attempt should fail.
+pub fn take_connect_failure() -> bool {
+    inner::take_connect_failure()
+}
+
+/// Extra delay to apply before each ingest send.
+pub fn send_delay() -> Option<Duration> {
+    inner::send_delay()
+}
+
+/// Returns true once after a fake device loss was requested.
+pub fn take_device_loss() -> bool {
+    inner::take_device_loss()
+}
+
+/// Starts the local fault control socket.
+pub fn spawn_control() {
+    inner::spawn_control();
+}
@@ -0,0 +1,434 @@
+//! Record-and-compare fixtures for the audio pipeline. A fixture is a TOML
+//! file describing the input (synthetic segments or a raw PCM file) and the
+//! pipeline config; its `[expected]` table holds the output hash, or a
+//! reference PCM file plus a tolerance when bit-exact output isn't expected.
+
+use crate::audio::{Resampler, ResamplerMode};
+use crate::limiter::{OutputSettings, OutputStage};
+use crate::stream::{GateChange, GateStage, VadSettings};
+use crate::track::TrackDetectMode;
+use crate::vad::VadMode;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const DEFAULT_DIR: &str = "fixtures";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Fixture {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    description: String,
+    input: Input,
+    #[serde(default)]
+    pipeline: Pipeline,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expected: Option<Expected>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Input {
+    rate: u32,
+    #[serde(default = "default_channels")]
+    channels: u16,
+    /// Interleaved f32le samples, relative to the fixture file. Takes
+    /// precedence over `segments`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pcm: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    segments: Vec<Segment>,
+}
+
+/// Synthetic signal: sum of tones plus deterministic noise, silence when
+/// both are absent.
+#[derive(Debug, Serialize, Deserialize)]
+struct Segment {
+    seconds: f32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tones_hz: Vec<f32>,
+    /// Peak level of each tone.
+    #[serde(default = "default_tone_db")]
+    tone_db: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    noise_db: Option<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct Pipeline {
+    output_rate: u32,
+    resampler: String,
+    /// Frames handed to the resampler per call, like an ALSA period.
+    period_frames: usize,
+    gain_db: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ceiling_db: Option<f32>,
+    vad_enabled: bool,
+    vad_mode: String,
+    vad_open_db: f32,
+    vad_close_db: f32,
+    vad_attack_ms: u64,
+    vad_hold_ms: u64,
+    silence_fill: bool,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self {
+            output_rate: 48_000,
+            resampler: "sinc".to_string(),
+            period_frames: 1024,
+            gain_db: 0.0,
+            ceiling_db: None,
+            vad_enabled: true,
+            vad_mode: "rms".to_string(),
+            vad_open_db: -45.0,
+            vad_close_db: -48.0,
+            vad_attack_ms: 0,
+            vad_hold_ms: 3000,
+            silence_fill: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Expected {
+    /// FNV-1a 64 of the sent PCM; checked when `tolerance_lsb` is unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    /// Reference PCM (s16le stereo) compared sample by sample, relative to
+    /// the fixture file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pcm: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tolerance_lsb: Option<u16>,
+    frames: usize,
+    gate_opens: u32,
+}
+
+fn default_channels() -> u16 {
+    2
+}
+
+fn default_tone_db() -> f32 {
+    -12.0
+}
+
+struct Output {
+    pcm: Vec<u8>,
+    gate_opens: u32,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct FixtureOptions {
+    #[arg(long, default_value = DEFAULT_DIR)]
+    dir: PathBuf,
+    /// Writes new expectations instead of checking them.
+    #[arg(long)]
+    record: bool,
+    /// Only fixtures whose name contains this.
+    filter: Option<String>,
+}
+
+/// Runs every fixture in the directory, or records new expectations with
+/// `--record`. Fails if any fixture doesn't match.
+pub fn run_fixtures(options: FixtureOptions) -> Result<()> {
+    let mut paths = std::fs::read_dir(&options.dir)
+        .with_context(|| format!("read fixtures from {}", options.dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .filter(|path| match &options.filter {
+            Some(filter) => path
+                .file_stem()
+                .is_some_and(|stem| stem.to_string_lossy().contains(filter.as_str())),
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+    if paths.is_empty() {
+        anyhow::bail!("no fixtures found in {}", options.dir.display());
+    }
+
+    let mut failures = 0;
+    for path in &paths {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let result = if options.record {
+            record(path).map(|_| "recorded".to_string())
+        } else {
+            check(path)
+        };
+        match result {
+            Ok(outcome) => println!("ok    {} ({})", name, outcome),
+            Err(err) => {
+                failures += 1;
+                println!("FAIL  {}: {:#}", name, err);
+            }
+        }
+    }
+    if failures > 0 {
+        anyhow::bail!("{} of {} fixtures failed", failures, paths.len());
+    }
+    Ok(())
+}
+
+fn load(path: &Path) -> Result<Fixture> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    toml::from_str(&contents).with_context(|| format!("parse {}", path.display()))
+}
+
+fn check(path: &Path) -> Result<String> {
+    let fixture = load(path)?;
+    let expected = fixture
+        .expected
+        .as_ref()
+        .context("no [expected] table, run with --record")?;
+    let output = run_pipeline(&fixture, path)?;
+    let frames = output.pcm.len() / 4;
+    if frames != expected.frames {
+        anyhow::bail!("{} frames, expected {}", frames, expected.frames);
+    }
+    if output.gate_opens != expected.gate_opens {
+        anyhow::bail!(
+            "gate opened {} times, expected {}",
+            output.gate_opens,
+            expected.gate_opens
+        );
+    }
+    match (expected.tolerance_lsb, &expected.pcm) {
+        (Some(tolerance), Some(reference)) => {
+            let reference_path = relative_to(path, reference);
+            let reference = std::fs::read(&reference_path)
+                .with_context(|| format!("read {}", reference_path.display()))?;
+            let max_diff = max_sample_diff(&output.pcm, &reference);
+            if max_diff > tolerance {
+                anyhow::bail!("max deviation {} LSB, tolerance {}", max_diff, tolerance);
+            }
+            Ok(format!("max deviation {} LSB", max_diff))
+        }
+        _ => {
+            let hash = pcm_hash(&output.pcm);
+            match expected.hash.as_deref() {
+                Some(expected) if expected == hash => Ok("bit-exact".to_string()),
+                Some(expected) => anyhow::bail!("hash {}, expected {}", hash, expected),
+                None => anyhow::bail!("no expected hash or reference pcm"),
+            }
+        }
+    }
+}
+
+/// Rewrites the `[expected]` table from the current pipeline output. With a
+/// tolerance set, the reference PCM is rewritten instead of the hash.
+fn record(path: &Path) -> Result<()> {
+    let mut fixture = load(path)?;
+    let output = run_pipeline(&fixture, path)?;
+    let tolerance = fixture
+        .expected
+        .as_ref()
+        .and_then(|expected| expected.tolerance_lsb);
+    let pcm = match tolerance {
+        Some(_) => {
+            let reference = path.with_extension("pcm");
+            std::fs::write(&reference, &output.pcm)
+                .with_context(|| format!("write {}", reference.display()))?;
+            reference.file_name().map(PathBuf::from)
+        }
+        None => None,
+    };
+    fixture.expected = Some(Expected {
+        hash: tolerance.is_none().then(|| pcm_hash(&output.pcm)),
+        pcm,
+        tolerance_lsb: tolerance,
+        frames: output.pcm.len() / 4,
+        gate_opens: output.gate_opens,
+    });
+    let contents = toml::to_string_pretty(&fixture).context("serialize fixture")?;
+    std::fs::write(path, contents).with_context(|| format!("write {}", path.display()))
+}
+
+/// Runs the capture-side pipeline the way a stream does: each period goes
+/// through the resampler and the output stage like a capture chunk, then
+/// through the stream's gate stage, with time derived from the sample count.
+fn run_pipeline(fixture: &Fixture, path: &Path) -> Result<Output> {
+    let input = &fixture.input;
+    let pipeline = &fixture.pipeline;
+    let channels = input.channels.max(1);
+    let samples = match &input.pcm {
+        Some(pcm) => read_f32(&relative_to(path, pcm))?,
+        None => synthesize(input.rate, channels, &input.segments),
+    };
+    let mode = ResamplerMode::parse(&pipeline.resampler)
+        .with_context(|| format!("unknown resampler {}", pipeline.resampler))?;
+    let vad_mode = VadMode::parse(&pipeline.vad_mode)
+        .with_context(|| format!("unknown vad mode {}", pipeline.vad_mode))?;
+    let rate = pipeline.output_rate;
+
+    let vad = VadSettings {
+        enabled: pipeline.vad_enabled,
+        mode: vad_mode,
+        open_db: pipeline.vad_open_db,
+        close_db: pipeline.vad_close_db,
+        attack: Duration::from_millis(pipeline.vad_attack_ms),
+        hold: Duration::from_millis(pipeline.vad_hold_ms),
+        preroll: Duration::ZERO,
+        silence_fill: pipeline.silence_fill,
+        track_detect: TrackDetectMode::Gap,
+    };
+    let mut resampler = Resampler::offline(input.rate, channels, rate, mode)?;
+    let mut output_stage = OutputStage::new(
+        OutputSettings {
+            gain_db: pipeline.gain_db,
+            ceiling_db: pipeline.ceiling_db,
+        },
+        rate,
+    );
+    let mut stage = GateStage::new(vad.mode, rate);
+    let start = Instant::now();
+    let mut elapsed_frames = 0u64;
+    let mut output = Output {
+        pcm: Vec::with_capacity(samples.len() * 2),
+        gate_opens: 0,
+    };
+    let mut process = |converted: Vec<i16>| {
+        if converted.is_empty() {
+            return;
+        }
+        let mut chunk = Vec::with_capacity(converted.len() * 2);
+        for sample in converted {
+            chunk.extend_from_slice(&sample.to_le_bytes());
+        }
+        output_stage.process(&mut chunk);
+        elapsed_frames += (chunk.len() / 4) as u64;
+        let now = start + Duration::from_secs_f64(elapsed_frames as f64 / rate as f64);
+        if let Some(GateChange::Opened { .. }) = stage.process(&chunk, now, &vad).change {
+            output.gate_opens += 1;
+        }
+        if stage.is_active() {
+            output.pcm.extend_from_slice(&chunk);
+        } else if vad.silence_fill {
+            output.pcm.resize(output.pcm.len() + chunk.len(), 0);
+        }
+    };
+    let period = pipeline.period_frames.max(1) * channels as usize;
+    for block in samples.chunks(period) {
+        process(resampler.convert(block, channels));
+    }
+    process(resampler.flush());
+    Ok(output)
+}
+
+fn synthesize(rate: u32, channels: u16, segments: &[Segment]) -> Vec<f32> {
+    let mut samples = Vec::new();
+    // Fixed-seed LCG so noise is identical on every run and platform.
+    let mut seed: u32 = 0x1234_5678;
+    let mut frame_idx = 0u64;
+    for segment in segments {
+        let frames = (segment.seconds.max(0.0) * rate as f32) as u64;
+        let tone_amp = db_to_amplitude(segment.tone_db);
+        let noise_amp = segment.noise_db.map(db_to_amplitude).unwrap_or(0.0);
+        for _ in 0..frames {
+            let t = frame_idx as f64 / rate as f64;
+            let mut value = segment
+                .tones_hz
+                .iter()
+                .map(|freq| (2.0 * std::f64::consts::PI * *freq as f64 * t).sin() as f32 * tone_amp)
+                .sum::<f32>();
+            if noise_amp > 0.0 {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                value += ((seed >> 8) as f32 / (1u32 << 24) as f32 * 2.0 - 1.0) * noise_amp;
+            }
+            for _ in 0..channels {
+                samples.push(value);
+            }
+            frame_idx += 1;
+        }
+    }
+    samples
+}
+
+fn db_to_amplitude(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+fn read_f32(path: &Path) -> Result<Vec<f32>> {
+    let bytes = std::fs::read(path).with_context(|| format!("read {}", path.display()))?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+fn relative_to(fixture: &Path, file: &Path) -> PathBuf {
+    fixture
+        .parent()
+        .map(|dir| dir.join(file))
+        .unwrap_or_else(|| file.to_path_buf())
+}
+
+fn pcm_hash(pcm: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in pcm {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("fnv1a:{:016x}", hash)
+}
+
+fn max_sample_diff(output: &[u8], reference: &[u8]) -> u16 {
+    let samples = |bytes: &[u8]| {
+        bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect::<Vec<_>>()
+    };
+    let output = samples(output);
+    let reference = samples(reference);
+    if output.len() != reference.len() {
+        return u16::MAX;
+    }
+    output
+        .iter()
+        .zip(&reference)
+        .map(|(a, b)| a.abs_diff(*b))
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_fixture(name: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join(DEFAULT_DIR)
+            .join(format!("{}.toml", name));
+        if let Err(err) = check(&path) {
+            panic!("fixture {}: {:#}", name, err);
+        }
+    }
+
+    #[test]
+    fn sinc_resampler() {
+        check_fixture("sinc-44k1-tone");
+    }
+
+    #[test]
+    fn fft_resampler() {
+        check_fixture("fft-44k1-tone");
+    }
+
+    #[test]
+    fn linear_resampler_mono() {
+        check_fixture("linear-mono-32k");
+    }
+
+    #[test]
+    fn gain_and_ceiling() {
+        check_fixture("ceiling-gain");
+    }
+
+    #[test]
+    fn vad_gate() {
+        check_fixture("vad-gap");
+    }
+}
@@ -0,0 +1,168 @@
+//! Minimal fragmented MP4 (ISO/IEC 14496-12) writer for a single Opus
+//! track, as HLS needs it: one init segment with the track description and
+//! media segments of `moof` + `mdat`. Opus mapping per the "Encapsulation
+//! of Opus in ISO Base Media File Format" spec.
+
+/// Opus always decodes at 48 kHz.
+pub const TIMESCALE: u32 = 48_000;
+/// Samples per channel in one 20 ms Opus packet.
+pub const PACKET_SAMPLES: u32 = 960;
+const TRACK_ID: u32 = 1;
+const CHANNELS: u16 = 2;
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+pub fn init_segment(pre_skip: u16) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_box(&mut out, b"ftyp", |out| {
+        out.extend_from_slice(b"iso6");
+        out.extend_from_slice(&0u32.to_be_bytes());
+        out.extend_from_slice(b"iso6mp41");
+    });
+    write_box(&mut out, b"moov", |out| {
+        full_box(out, b"mvhd", 0, 0, |out| {
+            put_u32s(out, &[0, 0, TIMESCALE, 0, 0x0001_0000]);
+            out.extend_from_slice(&0x0100u16.to_be_bytes());
+            out.extend_from_slice(&[0; 10]);
+            put_u32s(out, &MATRIX);
+            out.extend_from_slice(&[0; 24]);
+            put_u32s(out, &[TRACK_ID + 1]);
+        });
+        write_box(out, b"trak", |out| {
+            // Flags: track enabled and in movie.
+            full_box(out, b"tkhd", 0, 3, |out| {
+                put_u32s(out, &[0, 0, TRACK_ID, 0, 0, 0, 0]);
+                // Layer, alternate group, volume, reserved.
+                out.extend_from_slice(&[0, 0, 0, 0, 0x01, 0x00, 0, 0]);
+                put_u32s(out, &MATRIX);
+                put_u32s(out, &[0, 0]);
+            });
+            write_box(out, b"mdia", |out| {
+                full_box(out, b"mdhd", 0, 0, |out| {
+                    put_u32s(out, &[0, 0, TIMESCALE, 0]);
+                    // Language "und", packed.
+                    out.extend_from_slice(&0x55c4u16.to_be_bytes());
+                    out.extend_from_slice(&[0, 0]);
+                });
+                full_box(out, b"hdlr", 0, 0, |out| {
+                    put_u32s(out, &[0]);
+                    out.extend_from_slice(b"soun");
+                    put_u32s(out, &[0, 0, 0]);
+                    out.extend_from_slice(b"SoundHandler\0");
+                });
+                write_box(out, b"minf", |out| {
+                    full_box(out, b"smhd", 0, 0, |out| out.extend_from_slice(&[0; 4]));
+                    write_box(out, b"dinf", |out| {
+                        full_box(out, b"dref", 0, 0, |out| {
+                            put_u32s(out, &[1]);
+                            // Flag 1: media data is in this file.
+                            full_box(out, b"url ", 0, 1, |_| {});
+                        });
+                    });
+                    write_box(out, b"stbl", |out| {
+                        full_box(out, b"stsd", 0, 0, |out| {
+                            put_u32s(out, &[1]);
+                            opus_sample_entry(out, pre_skip);
+                        });
+                        full_box(out, b"stts", 0, 0, |out| put_u32s(out, &[0]));
+                        full_box(out, b"stsc", 0, 0, |out| put_u32s(out, &[0]));
+                        full_box(out, b"stsz", 0, 0, |out| put_u32s(out, &[0, 0]));
+                        full_box(out, b"stco", 0, 0, |out| put_u32s(out, &[0]));
+                    });
+                });
+            });
+        });
+        write_box(out, b"mvex", |out| {
+            full_box(out, b"trex", 0, 0, |out| {
+                put_u32s(out, &[TRACK_ID, 1, PACKET_SAMPLES, 0, 0]);
+            });
+        });
+    });
+    out
+}
+
+fn opus_sample_entry(out: &mut Vec<u8>, pre_skip: u16) {
+    write_box(out, b"Opus", |out| {
+        out.extend_from_slice(&[0; 6]);
+        // Data reference index.
+        out.extend_from_slice(&1u16.to_be_bytes());
+        put_u32s(out, &[0, 0]);
+        out.extend_from_slice(&CHANNELS.to_be_bytes());
+        // Sample size, pre-defined, reserved.
+        out.extend_from_slice(&[0, 16, 0, 0, 0, 0]);
+        put_u32s(out, &[TIMESCALE << 16]);
+        write_box(out, b"dOps", |out| {
+            out.push(0);
+            out.push(CHANNELS as u8);
+            out.extend_from_slice(&pre_skip.to_be_bytes());
+            put_u32s(out, &[TIMESCALE]);
+            // Output gain, channel mapping family 0 (mono/stereo).
+            out.extend_from_slice(&[0, 0, 0]);
+        });
+    });
+}
+
+/// One fragment holding `packets`, each `PACKET_SAMPLES` long, starting at
+/// `decode_time` (in `TIMESCALE` units).
+pub fn media_segment(sequence: u32, decode_time: u64, packets: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut data_offset_at = 0;
+    write_box(&mut out, b"moof", |out| {
+        full_box(out, b"mfhd", 0, 0, |out| put_u32s(out, &[sequence]));
+        write_box(out, b"traf", |out| {
+            // Flags: default sample duration present, offsets relative to moof.
+            full_box(out, b"tfhd", 0, 0x02_0008, |out| {
+                put_u32s(out, &[TRACK_ID, PACKET_SAMPLES]);
+            });
+            full_box(out, b"tfdt", 1, 0, |out| {
+                out.extend_from_slice(&decode_time.to_be_bytes());
+            });
+            // Flags: data offset and per-sample sizes present.
+            full_box(out, b"trun", 0, 0x00_0201, |out| {
+                put_u32s(out, &[packets.len() as u32]);
+                data_offset_at = out.len();
+                put_u32s(out, &[0]);
+                for packet in packets {
+                    put_u32s(out, &[packet.len() as u32]);
+                }
+            });
+        });
+    });
+    // Samples start right after the mdat header that follows the moof.
+    let data_offset = (out.len() + 8) as u32;
+    out[data_offset_at..data_offset_at + 4].copy_from_slice(&data_offset.to_be_bytes());
+    write_box(&mut out, b"mdat", |out| {
+        for packet in packets {
+            out.extend_from_slice(packet);
+        }
+    });
+    out
+}
+
+fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], body: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(kind);
+    body(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn full_box(
+    out: &mut Vec<u8>,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    body: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(out, kind, |out| {
+        out.push(version);
+        out.extend_from_slice(&flags.to_be_bytes()[1..]);
+        body(out);
+    });
+}
+
+fn put_u32s(out: &mut Vec<u8>, values: &[u32]) {
+    for value in values {
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
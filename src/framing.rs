@@ -0,0 +1,115 @@
+//! Optional framing of ingest payloads. In `timestamped` mode every payload
+//! is prefixed with a header so the server can detect gaps and line up
+//! several bridges:
+//!
+//! | bytes | field                                              |
+//! |-------|----------------------------------------------------|
+//! | 0..4  | magic `LXF1`                                       |
+//! | 4..8  | sequence number, u32 BE, +1 per payload            |
+//! | 8..16 | capture time of the first frame, Unix µs, u64 BE   |
+//! | 16..20| frames in the payload, u32 BE                      |
+
+use crate::timestamp::now_unix_us;
+
+pub const HEADER_LEN: usize = 20;
+const MAGIC: &[u8; 4] = b"LXF1";
+/// Bytes per interleaved stereo s16le frame.
+const FRAME_BYTES: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IngestFraming {
+    /// Bare PCM, as before framing existed.
+    #[default]
+    Raw,
+    Timestamped,
+}
+
+impl IngestFraming {
+    pub const ALL: [Self; 2] = [Self::Raw, Self::Timestamped];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "raw" | "none" => Some(Self::Raw),
+            "timestamped" => Some(Self::Timestamped),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Raw => "raw",
+            Self::Timestamped => "timestamped",
+        }
+    }
+}
+
+/// Adds headers to outgoing payloads. The sequence keeps counting across
+/// reconnects, so a jump tells the server what was lost in between.
+pub struct Framer {
+    framing: IngestFraming,
+    sequence: u32,
+}
+
+impl Framer {
+    pub fn new(framing: IngestFraming) -> Self {
+        Self {
+            framing,
+            sequence: 0,
+        }
+    }
+
+    pub fn is_raw(&self) -> bool {
+        self.framing == IngestFraming::Raw
+    }
+
+    /// Returns `payload` framed for the wire; raw payloads pass unchanged.
+    pub fn frame(&mut self, payload: Vec<u8>, captured_at_us: u64) -> Vec<u8> {
+        if self.is_raw() {
+            return payload;
+        }
+        let mut framed = Vec::with_capacity(HEADER_LEN + payload.len());
+        framed.extend_from_slice(MAGIC);
+        framed.extend_from_slice(&self.sequence.to_be_bytes());
+        framed.extend_from_slice(&captured_at_us.to_be_bytes());
+        framed.extend_from_slice(&((payload.len() / FRAME_BYTES) as u32).to_be_bytes());
+        framed.extend_from_slice(&payload);
+        self.sequence = self.sequence.wrapping_add(1);
+        framed
+    }
+}
+
+/// Capture time of the oldest buffered byte, derived from when the newest
+/// chunk ended and how much audio is queued in front of it.
+pub struct PendingClock {
+    rate: u32,
+    end_us: Option<u64>,
+}
+
+impl PendingClock {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate: rate.max(1),
+            end_us: None,
+        }
+    }
+
+    /// Records a received chunk; `captured_at_us` is when its last frame was
+    /// captured, 0 when unknown.
+    pub fn on_chunk(&mut self, captured_at_us: u64) {
+        self.end_us = Some(if captured_at_us > 0 {
+            captured_at_us
+        } else {
+            now_unix_us()
+        });
+    }
+
+    /// Capture time of the first of `pending_bytes` queued bytes. Without
+    /// any chunk yet (silence fill) the current time is used.
+    pub fn front_us(&self, pending_bytes: usize) -> u64 {
+        let Some(end_us) = self.end_us else {
+            return now_unix_us();
+        };
+        let frames = (pending_bytes / FRAME_BYTES) as u64;
+        end_us.saturating_sub(frames * 1_000_000 / self.rate as u64)
+    }
+}
@@ -1,48 +1,518 @@
+//! Health snapshots and the outputs they are reported through. One producer
+//! samples the bridge status (or, in manifest mode, every bridge's); each
+//! reporter picks up the latest snapshot at its own interval.
+
+use crate::models::RecentError;
+use crate::mqtt::MqttClient;
+use crate::net;
 use crate::stream::StatusHandle;
-use serde::Serialize;
+use crate::timestamp::now_rfc3339;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
+use std::future::Future;
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{info, warn};
 
 const DEFAULT_HEALTH_PATH: &str = "/tmp/lox-linein-bridge.status.json";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+/// How often the producer samples the status; reporter intervals below this
+/// see repeated snapshots.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `--health-path`; wins over the config and the environment.
+static FILE_PATH: OnceLock<String> = OnceLock::new();
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HealthSnapshot {
     pub ts: String,
     pub state: String,
     pub device: String,
     pub ingest: String,
+    pub input_id: Option<String>,
+    pub input_name: Option<String>,
+    pub zone_name: Option<String>,
+    pub active_source: Option<String>,
+    /// Rate the device was opened at, and the one it actually delivers.
+    pub rate: Option<u32>,
+    pub observed_rate: Option<u32>,
+    pub device_suspect: bool,
+    pub capture_overrun_frames: u64,
+    pub dropped_chunks: u64,
+    pub resampler_errors: u64,
+    /// Sends that stalled and forced a reconnect.
+    pub send_timeouts: u64,
+    /// Audio held for disconnected ingest targets, and dropped from their
+    /// full buffers since start.
+    pub buffered_ms: u64,
+    pub dropped_ms: u64,
+    pub clock_offset_ms: Option<f64>,
+    pub clock_drift_ppm: Option<f64>,
     pub last_error: Option<String>,
+    /// Up to 20 errors, oldest first.
+    pub recent_errors: Vec<RecentError>,
     pub bytes_sent_total: u64,
+    /// Send rate over the last few seconds.
+    #[serde(default)]
+    pub bytes_per_sec: u64,
     pub last_chunk_ts: Option<String>,
+    /// Ingest connections re-established after a drop, since start.
+    pub reconnect_count: u64,
+    pub last_connect_ts: Option<String>,
+    /// `None` while no ingest connection is up.
+    pub current_connection_uptime_secs: Option<u64>,
+    /// VAD gate transitions since start.
+    pub gate_opens: u64,
+    pub gate_closes: u64,
+}
+
+/// `[health]` in the config file. The file reporter is on by default, the
+/// others only when their section is present.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    pub file: FileReporterConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpReporterConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mqtt: Option<MqttReporterConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub journald: Option<JournaldReporterConfig>,
+}
+
+impl HealthConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileReporterConfig {
+    pub enabled: bool,
+    /// Defaults to `LOX_LINEIN_BRIDGE_HEALTH_PATH`, then
+    /// `/tmp/lox-linein-bridge.status.json`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub interval_secs: u64,
+}
+
+impl Default for FileReporterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+            interval_secs: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HttpReporterConfig {
+    /// Address serving `GET /health`, e.g. `127.0.0.1:8099`.
+    pub listen: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MqttReporterConfig {
+    /// `host:port` of the broker.
+    pub broker: String,
+    /// Defaults to `lox-linein-bridge/<bridge_id>/health`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default = "default_mqtt_interval")]
+    pub interval_secs: u64,
+}
+
+fn default_mqtt_interval() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournaldReporterConfig {
+    #[serde(default = "default_journald_interval")]
+    pub interval_secs: u64,
+}
+
+fn default_journald_interval() -> u64 {
+    300
+}
+
+/// What reporters publish: a single bridge's snapshot, or the aggregate
+/// in manifest mode.
+pub trait Snapshot: Serialize + Clone + Send + Sync + 'static {
+    /// One-line summary used as the journal message.
+    fn summary(&self) -> String;
+    /// `LOX_*` journal fields next to the full JSON.
+    fn journal_fields(&self) -> Vec<(&'static str, String)>;
+}
+
+impl Snapshot for HealthSnapshot {
+    fn summary(&self) -> String {
+        format!(
+            "health: {} on {} to {} ({} bytes sent)",
+            self.state, self.device, self.ingest, self.bytes_sent_total
+        )
+    }
+
+    fn journal_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("LOX_STATE", self.state.clone()),
+            ("LOX_DEVICE", self.device.clone()),
+            ("LOX_INPUT_ID", self.input_id.clone().unwrap_or_default()),
+            ("LOX_BYTES_SENT", self.bytes_sent_total.to_string()),
+            ("LOX_DROPPED_CHUNKS", self.dropped_chunks.to_string()),
+            ("LOX_RECONNECTS", self.reconnect_count.to_string()),
+        ]
+    }
+}
+
+/// Health of every bridge run from a manifest, keyed by bridge name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AggregateSnapshot {
+    pub ts: String,
+    pub bridges_total: usize,
+    pub bridges_streaming: usize,
+    pub bytes_sent_total: u64,
+    pub dropped_chunks: u64,
+    pub bridges: BTreeMap<String, HealthSnapshot>,
+}
+
+impl AggregateSnapshot {
+    fn collect(bridges: &[(String, StatusHandle)]) -> Self {
+        let bridges = bridges
+            .iter()
+            .map(|(name, status)| (name.clone(), status.health_snapshot()))
+            .collect::<BTreeMap<_, _>>();
+        Self {
+            ts: now_rfc3339(),
+            bridges_total: bridges.len(),
+            bridges_streaming: bridges
+                .values()
+                .filter(|bridge| bridge.state == "STREAMING")
+                .count(),
+            bytes_sent_total: bridges.values().map(|bridge| bridge.bytes_sent_total).sum(),
+            dropped_chunks: bridges.values().map(|bridge| bridge.dropped_chunks).sum(),
+            bridges,
+        }
+    }
+}
+
+impl Snapshot for AggregateSnapshot {
+    fn summary(&self) -> String {
+        format!(
+            "health: {}/{} bridges streaming ({} bytes sent)",
+            self.bridges_streaming, self.bridges_total, self.bytes_sent_total
+        )
+    }
+
+    fn journal_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("LOX_BRIDGES", self.bridges_total.to_string()),
+            ("LOX_BRIDGES_STREAMING", self.bridges_streaming.to_string()),
+            ("LOX_BYTES_SENT", self.bytes_sent_total.to_string()),
+            ("LOX_DROPPED_CHUNKS", self.dropped_chunks.to_string()),
+        ]
+    }
+}
+
+pub fn set_file_path(path: String) {
+    let _ = FILE_PATH.set(path);
+}
+
+/// Where the file reporter writes.
+pub fn file_path(config: &HealthConfig) -> String {
+    if let Some(path) = FILE_PATH.get() {
+        return path.clone();
+    }
+    config.file.path.clone().unwrap_or_else(|| {
+        std::env::var("LOX_LINEIN_BRIDGE_HEALTH_PATH")
+            .unwrap_or_else(|_| DEFAULT_HEALTH_PATH.to_string())
+    })
+}
+
+/// An output for health snapshots.
+pub trait Reporter<S: Snapshot>: Send + 'static {
+    fn name(&self) -> String;
+    fn interval(&self) -> Duration;
+    fn report(&mut self, snapshot: &S) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Starts the snapshot producer and every configured reporter for one
+/// bridge.
+pub fn spawn(status: StatusHandle, config: &HealthConfig, bridge_id: &str) {
+    spawn_producer(move || status.health_snapshot(), config, bridge_id);
+}
+
+/// Like `spawn`, for the combined health of several bridges.
+pub fn spawn_aggregate(bridges: Vec<(String, StatusHandle)>, config: &HealthConfig, id: &str) {
+    spawn_producer(move || AggregateSnapshot::collect(&bridges), config, id);
 }
 
-pub fn spawn(status: StatusHandle) {
-    let path = std::env::var("LOX_LINEIN_BRIDGE_HEALTH_PATH")
-        .unwrap_or_else(|_| DEFAULT_HEALTH_PATH.to_string());
+fn spawn_producer<S: Snapshot>(
+    sample: impl Fn() -> S + Send + 'static,
+    config: &HealthConfig,
+    id: &str,
+) {
+    let (tx, rx) = watch::channel(sample());
     tokio::spawn(async move {
-        let mut last_write_ok = true;
         loop {
-            let snapshot = status.health_snapshot();
-            let payload = match serde_json::to_string_pretty(&snapshot) {
-                Ok(payload) => payload,
-                Err(err) => {
-                    if last_write_ok {
-                        tracing::warn!("health snapshot serialize failed: {}", err);
-                        last_write_ok = false;
+            tokio::time::sleep(SNAPSHOT_INTERVAL).await;
+            if tx.send(sample()).is_err() {
+                break;
+            }
+        }
+    });
+
+    if config.file.enabled {
+        run(
+            FileReporter {
+                path: file_path(config),
+                interval: interval_secs(config.file.interval_secs),
+            },
+            rx.clone(),
+        );
+    }
+    if let Some(http) = config.http.clone() {
+        let rx = rx.clone();
+        tokio::spawn(async move {
+            match HttpReporter::bind(&http.listen).await {
+                Ok(reporter) => run(reporter, rx),
+                Err(err) => warn!("health http reporter disabled: {:#}", err),
+            }
+        });
+    }
+    if let Some(mqtt) = config.mqtt.clone() {
+        let topic = mqtt
+            .topic
+            .clone()
+            .unwrap_or_else(|| format!("lox-linein-bridge/{}/health", id));
+        run(
+            MqttReporter {
+                interval: interval_secs(mqtt.interval_secs),
+                client_id: format!("lox-linein-bridge-{}", id),
+                topic,
+                config: mqtt,
+                client: None,
+            },
+            rx.clone(),
+        );
+    }
+    if let Some(journald) = &config.journald {
+        run(
+            JournaldReporter {
+                interval: interval_secs(journald.interval_secs),
+            },
+            rx,
+        );
+    }
+}
+
+fn interval_secs(secs: u64) -> Duration {
+    Duration::from_secs(secs.max(1))
+}
+
+/// Reports the latest snapshot every interval. Failures are logged once
+/// until the reporter recovers.
+fn run<S: Snapshot, R: Reporter<S>>(mut reporter: R, rx: watch::Receiver<S>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(reporter.interval());
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut last_ok = true;
+        loop {
+            ticker.tick().await;
+            let snapshot = rx.borrow().clone();
+            match reporter.report(&snapshot).await {
+                Ok(()) => {
+                    if !last_ok {
+                        info!("health {} reporter recovered", reporter.name());
                     }
-                    tokio::time::sleep(Duration::from_secs(5)).await;
-                    continue;
+                    last_ok = true;
                 }
-            };
-            if let Err(err) = fs::write(&path, payload) {
-                if last_write_ok {
-                    tracing::warn!("health snapshot write failed: {}", err);
-                    last_write_ok = false;
+                Err(err) => {
+                    if last_ok {
+                        warn!("health {} reporter failed: {:#}", reporter.name(), err);
+                    }
+                    last_ok = false;
                 }
-            } else {
-                last_write_ok = true;
             }
-            tokio::time::sleep(Duration::from_secs(5)).await;
         }
     });
 }
+
+struct FileReporter {
+    path: String,
+    interval: Duration,
+}
+
+impl<S: Snapshot> Reporter<S> for FileReporter {
+    fn name(&self) -> String {
+        format!("file {}", self.path)
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    async fn report(&mut self, snapshot: &S) -> Result<()> {
+        let payload = serde_json::to_string_pretty(snapshot).context("serialize snapshot")?;
+        fs::write(&self.path, payload).with_context(|| format!("write {}", self.path))
+    }
+}
+
+/// Serves the latest snapshot as JSON over plain HTTP.
+struct HttpReporter {
+    listen: String,
+    latest: Arc<Mutex<String>>,
+}
+
+impl HttpReporter {
+    async fn bind(listen: &str) -> Result<Self> {
+        let listener = TcpListener::bind(listen)
+            .await
+            .with_context(|| format!("bind {}", listen))?;
+        info!("health http reporter listening on {}", listen);
+        let latest = Arc::new(Mutex::new(String::from("{}")));
+        let served = Arc::clone(&latest);
+        tokio::spawn(async move {
+            loop {
+                let mut socket = net::accept(&listener, "health http reporter").await;
+                let served = Arc::clone(&served);
+                tokio::spawn(async move {
+                    let mut request = [0u8; 1024];
+                    let Ok(Ok(len)) =
+                        tokio::time::timeout(Duration::from_secs(5), socket.read(&mut request))
+                            .await
+                    else {
+                        return;
+                    };
+                    let request = String::from_utf8_lossy(&request[..len]);
+                    let path = request.split_whitespace().nth(1).unwrap_or("");
+                    let response = if path == "/health" || path == "/" {
+                        let body = served.lock().map(|body| body.clone()).unwrap_or_default();
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            body.len(),
+                            body
+                        )
+                    } else {
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_string()
+                    };
+                    let _ = socket.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        Ok(Self {
+            listen: listen.to_string(),
+            latest,
+        })
+    }
+}
+
+impl<S: Snapshot> Reporter<S> for HttpReporter {
+    fn name(&self) -> String {
+        format!("http {}", self.listen)
+    }
+
+    fn interval(&self) -> Duration {
+        SNAPSHOT_INTERVAL
+    }
+
+    async fn report(&mut self, snapshot: &S) -> Result<()> {
+        let payload = serde_json::to_string(snapshot).context("serialize snapshot")?;
+        if let Ok(mut latest) = self.latest.lock() {
+            *latest = payload;
+        }
+        Ok(())
+    }
+}
+
+/// Publishes retained snapshots, reconnecting on the next interval after
+/// a failure.
+struct MqttReporter {
+    config: MqttReporterConfig,
+    interval: Duration,
+    client_id: String,
+    topic: String,
+    client: Option<MqttClient>,
+}
+
+impl<S: Snapshot> Reporter<S> for MqttReporter {
+    fn name(&self) -> String {
+        format!("mqtt {}", self.config.broker)
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    async fn report(&mut self, snapshot: &S) -> Result<()> {
+        let payload = serde_json::to_vec(snapshot).context("serialize snapshot")?;
+        let mut client = match self.client.take() {
+            Some(client) => client,
+            None => {
+                let credentials = self
+                    .config
+                    .username
+                    .as_deref()
+                    .map(|user| (user, self.config.password.as_deref().unwrap_or("")));
+                MqttClient::connect(&self.config.broker, &self.client_id, credentials).await?
+            }
+        };
+        client.publish(&self.topic, &payload, true).await?;
+        self.client = Some(client);
+        Ok(())
+    }
+}
+
+/// Writes a structured journal entry, with the snapshot fields as
+/// `LOX_*` fields next to a one-line summary.
+struct JournaldReporter {
+    interval: Duration,
+}
+
+impl<S: Snapshot> Reporter<S> for JournaldReporter {
+    fn name(&self) -> String {
+        "journald".to_string()
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    async fn report(&mut self, snapshot: &S) -> Result<()> {
+        let mut entry = String::new();
+        let mut field = |key: &str, value: &str| {
+            entry.push_str(key);
+            entry.push('=');
+            entry.push_str(&value.replace('\n', " "));
+            entry.push('\n');
+        };
+        field("MESSAGE", &snapshot.summary());
+        field("PRIORITY", "6");
+        field("SYSLOG_IDENTIFIER", "lox-linein-bridge");
+        for (key, value) in snapshot.journal_fields() {
+            field(key, &value);
+        }
+        field(
+            "LOX_HEALTH",
+            &serde_json::to_string(snapshot).context("serialize snapshot")?,
+        );
+        let socket = UnixDatagram::unbound().context("create journal socket")?;
+        socket
+            .send_to(entry.as_bytes(), JOURNALD_SOCKET)
+            .with_context(|| format!("send to {}", JOURNALD_SOCKET))?;
+        Ok(())
+    }
+}
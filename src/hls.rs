@@ -0,0 +1,305 @@
+//! Local HLS output, so a phone or browser on the LAN can listen to the
+//! line-in without the audioserver. The paced output is encoded to Opus,
+//! packed into short fMP4 segments and served with a live playlist from
+//! the bridge's own HTTP listener. Encoding needs libopus and is only built
+//! with the `hls` feature.
+
+use crate::payload_queue::PayloadSender;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Audio waiting for the encoder before new payloads are dropped.
+#[cfg_attr(not(feature = "hls"), allow(dead_code))]
+const QUEUE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HlsConfig {
+    /// Address serving the player and playlist, e.g. `0.0.0.0:8098`.
+    pub listen: String,
+    /// Segment length; a multiple of 20 ms. Shorter is lower latency.
+    #[serde(default = "default_segment_ms")]
+    pub segment_ms: u64,
+    /// Segments listed in the live playlist.
+    #[serde(default = "default_window")]
+    pub window: usize,
+    #[serde(default = "default_bitrate_kbps")]
+    pub bitrate_kbps: u32,
+}
+
+fn default_segment_ms() -> u64 {
+    1000
+}
+
+fn default_window() -> usize {
+    6
+}
+
+fn default_bitrate_kbps() -> u32 {
+    128
+}
+
+/// Copy of the paced output for the encoder. Never blocks the stream: when
+/// the encoder falls behind, payloads are dropped.
+#[derive(Clone)]
+#[cfg_attr(not(feature = "hls"), allow(dead_code))]
+pub struct HlsFeed {
+    tx: PayloadSender,
+}
+
+impl HlsFeed {
+    /// `pcm` is interleaved s16le stereo at `rate`.
+    pub fn write(&self, pcm: &[u8], rate: u32) {
+        self.tx.try_send(pcm, rate);
+    }
+}
+
+#[cfg(not(feature = "hls"))]
+pub async fn start(_config: &HlsConfig) -> anyhow::Result<HlsFeed> {
+    anyhow::bail!("this build has no HLS support (cargo feature `hls`)")
+}
+
+#[cfg(feature = "hls")]
+pub use output::start;
+
+#[cfg(feature = "hls")]
+mod output {
+    use super::{HlsConfig, HlsFeed, QUEUE};
+    use crate::audio::{Resampler, ResamplerMode};
+    use crate::fmp4;
+    use crate::net;
+    use crate::payload_queue::{self, PayloadReceiver};
+    use anyhow::{Context, Result};
+    use audiopus::coder::Encoder;
+    use audiopus::{Application, Bitrate, Channels, SampleRate};
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tracing::{info, warn};
+
+    /// Interleaved stereo samples in one Opus packet.
+    const PACKET_LEN: usize = fmp4::PACKET_SAMPLES as usize * 2;
+    /// Largest packet libopus produces.
+    const MAX_PACKET: usize = 1275;
+
+    const PLAYER_PAGE: &str = "<!doctype html><meta name=viewport content=\"width=device-width\">\
+<title>Line-in</title><audio controls autoplay src=\"index.m3u8\"></audio>";
+
+    struct Segment {
+        sequence: u64,
+        duration: f64,
+        data: Arc<Vec<u8>>,
+    }
+
+    #[derive(Default)]
+    struct Playlist {
+        init: Arc<Vec<u8>>,
+        segments: VecDeque<Segment>,
+    }
+
+    pub async fn start(config: &HlsConfig) -> Result<HlsFeed> {
+        let segment_packets = (config.segment_ms / 20).max(1) as usize;
+        let listener = TcpListener::bind(&config.listen)
+            .await
+            .with_context(|| format!("bind hls {}", config.listen))?;
+        let mut encoder = Encoder::new(SampleRate::Hz48000, Channels::Stereo, Application::Audio)
+            .context("create opus encoder")?;
+        encoder
+            .set_bitrate(Bitrate::BitsPerSecond(config.bitrate_kbps as i32 * 1000))
+            .context("set opus bitrate")?;
+        let pre_skip = encoder.lookahead().unwrap_or(312) as u16;
+        let playlist = Arc::new(Mutex::new(Playlist {
+            init: Arc::new(fmp4::init_segment(pre_skip)),
+            segments: VecDeque::new(),
+        }));
+
+        let (tx, rx) = payload_queue::channel(QUEUE);
+        let encoded = Arc::clone(&playlist);
+        let window = config.window.max(2);
+        std::thread::Builder::new()
+            .name("hls-encode".to_string())
+            .spawn(move || encode(rx, encoder, segment_packets, window, encoded))
+            .context("spawn hls encoder")?;
+
+        let target_duration = (segment_packets as u64 * 20).div_ceil(1000).max(1);
+        let bandwidth = config.bitrate_kbps as u64 * 1000;
+        tokio::spawn(async move {
+            loop {
+                let socket = net::accept(&listener, "hls output").await;
+                let playlist = Arc::clone(&playlist);
+                tokio::spawn(serve(socket, playlist, target_duration, bandwidth));
+            }
+        });
+        info!(
+            "hls output on http://{}/ ({} ms segments, opus {} kbit/s)",
+            config.listen,
+            segment_packets * 20,
+            config.bitrate_kbps
+        );
+        Ok(HlsFeed { tx })
+    }
+
+    /// Encoder thread: resamples to 48 kHz when needed, encodes 20 ms Opus
+    /// packets and publishes a segment every `segment_packets` packets.
+    fn encode(
+        rx: PayloadReceiver,
+        encoder: Encoder,
+        segment_packets: usize,
+        window: usize,
+        playlist: Arc<Mutex<Playlist>>,
+    ) {
+        let mut resampler: Option<(u32, Resampler)> = None;
+        let mut samples: Vec<i16> = Vec::new();
+        let mut packets: Vec<Vec<u8>> = Vec::with_capacity(segment_packets);
+        let mut packet = [0u8; MAX_PACKET];
+        let mut sequence: u64 = 0;
+        let mut decode_time: u64 = 0;
+        while let Ok((rate, pcm)) = rx.recv() {
+            let input = pcm
+                .chunks_exact(2)
+                .map(|sample| i16::from_le_bytes([sample[0], sample[1]]));
+            if rate == fmp4::TIMESCALE {
+                samples.extend(input);
+            } else {
+                if resampler.as_ref().map(|(from, _)| *from) != Some(rate) {
+                    resampler =
+                        Resampler::offline(rate, 2, fmp4::TIMESCALE, ResamplerMode::SincFast)
+                            .map_err(|err| warn!("hls resampler failed: {:#}", err))
+                            .ok()
+                            .map(|resampler| (rate, resampler));
+                }
+                let Some((_, resampler)) = resampler.as_mut() else {
+                    continue;
+                };
+                let input: Vec<f32> = input.map(|sample| sample as f32 / 32768.0).collect();
+                samples.extend(resampler.convert(&input, 2));
+            }
+
+            let mut consumed = 0;
+            while samples.len() - consumed >= PACKET_LEN {
+                let frame = &samples[consumed..consumed + PACKET_LEN];
+                consumed += PACKET_LEN;
+                match encoder.encode(frame, &mut packet) {
+                    Ok(len) => packets.push(packet[..len].to_vec()),
+                    Err(err) => {
+                        warn!("opus encode failed: {}", err);
+                        continue;
+                    }
+                }
+                if packets.len() < segment_packets {
+                    continue;
+                }
+                let data = fmp4::media_segment(sequence as u32 + 1, decode_time, &packets);
+                decode_time += packets.len() as u64 * fmp4::PACKET_SAMPLES as u64;
+                let duration =
+                    packets.len() as f64 * fmp4::PACKET_SAMPLES as f64 / fmp4::TIMESCALE as f64;
+                packets.clear();
+                if let Ok(mut playlist) = playlist.lock() {
+                    playlist.segments.push_back(Segment {
+                        sequence,
+                        duration,
+                        data: Arc::new(data),
+                    });
+                    while playlist.segments.len() > window {
+                        playlist.segments.pop_front();
+                    }
+                }
+                sequence += 1;
+            }
+            samples.drain(..consumed);
+        }
+    }
+
+    async fn serve(
+        mut socket: TcpStream,
+        playlist: Arc<Mutex<Playlist>>,
+        target_duration: u64,
+        bandwidth: u64,
+    ) {
+        let mut request = [0u8; 1024];
+        let Ok(Ok(len)) =
+            tokio::time::timeout(Duration::from_secs(5), socket.read(&mut request)).await
+        else {
+            return;
+        };
+        let request = String::from_utf8_lossy(&request[..len]);
+        let path = request.split_whitespace().nth(1).unwrap_or("");
+        let path = path.split('?').next().unwrap_or("");
+        let (content_type, body): (&str, Arc<Vec<u8>>) = match path {
+            "/" | "/index.html" => ("text/html", Arc::new(PLAYER_PAGE.as_bytes().to_vec())),
+            "/index.m3u8" => (
+                "application/vnd.apple.mpegurl",
+                Arc::new(
+                    format!(
+                        "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-STREAM-INF:BANDWIDTH={},CODECS=\"opus\"\nstream.m3u8\n",
+                        bandwidth
+                    )
+                    .into_bytes(),
+                ),
+            ),
+            "/stream.m3u8" => {
+                let text = match playlist.lock() {
+                    Ok(playlist) => media_playlist(&playlist, target_duration),
+                    Err(_) => return,
+                };
+                ("application/vnd.apple.mpegurl", Arc::new(text.into_bytes()))
+            }
+            "/init.mp4" => match playlist.lock() {
+                Ok(playlist) => ("video/mp4", Arc::clone(&playlist.init)),
+                Err(_) => return,
+            },
+            _ => {
+                let segment = path
+                    .strip_prefix("/seg-")
+                    .and_then(|rest| rest.strip_suffix(".m4s"))
+                    .and_then(|sequence| sequence.parse::<u64>().ok())
+                    .and_then(|sequence| {
+                        let playlist = playlist.lock().ok()?;
+                        playlist
+                            .segments
+                            .iter()
+                            .find(|segment| segment.sequence == sequence)
+                            .map(|segment| Arc::clone(&segment.data))
+                    });
+                match segment {
+                    Some(data) => ("video/iso.segment", data),
+                    None => {
+                        let _ = socket
+                            .write_all(
+                                b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                            )
+                            .await;
+                        return;
+                    }
+                }
+            }
+        };
+        let header = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+            content_type,
+            body.len()
+        );
+        if socket.write_all(header.as_bytes()).await.is_ok() {
+            let _ = socket.write_all(&body).await;
+        }
+    }
+
+    fn media_playlist(playlist: &Playlist, target_duration: u64) -> String {
+        let first = playlist
+            .segments
+            .front()
+            .map_or(0, |segment| segment.sequence);
+        let mut text = format!(
+            "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:{}\n#EXT-X-MAP:URI=\"init.mp4\"\n",
+            target_duration, first
+        );
+        for segment in &playlist.segments {
+            text.push_str(&format!(
+                "#EXTINF:{:.3},\nseg-{}.m4s\n",
+                segment.duration, segment.sequence
+            ));
+        }
+        text
+    }
+}
@@ -0,0 +1,90 @@
+//! Translations of user-facing CLI, installer and wizard strings. Messages
+//! live in `locales/<lang>/bridge.ftl` and are compiled into the binary.
+
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use std::sync::OnceLock;
+use unic_langid::LanguageIdentifier;
+
+const FALLBACK_LANGUAGE: &str = "en";
+const LOCALES: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en/bridge.ftl")),
+    ("de", include_str!("../locales/de/bridge.ftl")),
+];
+
+struct Catalog {
+    bundles: Vec<FluentBundle<FluentResource>>,
+}
+
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+impl Catalog {
+    fn load() -> Self {
+        let language = language();
+        let mut bundles = bundle(language).into_iter().collect::<Vec<_>>();
+        if language != FALLBACK_LANGUAGE {
+            bundles.extend(bundle(FALLBACK_LANGUAGE));
+        }
+        Self { bundles }
+    }
+}
+
+/// Language from `LOX_LINEIN_BRIDGE_LANG` or the usual locale variables,
+/// reduced to a supported language code.
+pub fn language() -> &'static str {
+    ["LOX_LINEIN_BRIDGE_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|key| std::env::var(key).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| {
+            let code = value
+                .split(['_', '-', '.', '@'])
+                .next()
+                .unwrap_or_default()
+                .to_lowercase();
+            LOCALES
+                .iter()
+                .find(|(lang, _)| *lang == code)
+                .map(|(lang, _)| *lang)
+        })
+        .unwrap_or(FALLBACK_LANGUAGE)
+}
+
+fn bundle(lang: &str) -> Option<FluentBundle<FluentResource>> {
+    let (_, source) = LOCALES.iter().find(|(code, _)| *code == lang)?;
+    let langid: LanguageIdentifier = lang.parse().ok()?;
+    let resource = FluentResource::try_new(source.to_string()).ok()?;
+    let mut bundle = FluentBundle::new_concurrent(vec![langid]);
+    // Unicode isolation marks show up as garbage in many terminals.
+    bundle.set_use_isolating(false);
+    bundle.add_resource(resource).ok()?;
+    Some(bundle)
+}
+
+/// Looks up a message, falling back to English and then to the id itself.
+pub fn tr(id: &str, args: Option<&FluentArgs>) -> String {
+    let catalog = CATALOG.get_or_init(Catalog::load);
+    for bundle in &catalog.bundles {
+        if let Some(pattern) = bundle.get_message(id).and_then(|message| message.value()) {
+            let mut errors = Vec::new();
+            return bundle
+                .format_pattern(pattern, args, &mut errors)
+                .into_owned();
+        }
+    }
+    id.to_string()
+}
+
+/// `t!("id")` or `t!("id", name = value, ...)`.
+macro_rules! t {
+    ($id:expr) => {
+        $crate::i18n::tr($id, None)
+    };
+    ($id:expr, $($key:ident = $value:expr),+ $(,)?) => {{
+        let mut args = fluent_bundle::FluentArgs::new();
+        $(args.set(stringify!($key), $value);)+
+        $crate::i18n::tr($id, Some(&args))
+    }};
+}
+
+pub(crate) use t;
@@ -1,33 +1,383 @@
 use crate::config;
+use crate::i18n::t;
+use crate::wizard;
 use anyhow::{Context, Result};
 use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 const SYSTEMD_UNIT_PATH: &str = "/etc/systemd/system/lox-linein-bridge.service";
+const UNIT_NAME: &str = "lox-linein-bridge.service";
+const DEFAULT_BINARY: &str = "/usr/local/bin/lox-linein-bridge";
+/// Owns the ALSA device nodes on most distributions.
+const AUDIO_GROUP: &str = "audio";
+const INIT_SCRIPT_PATH: &str = "/etc/init.d/lox-linein-bridge";
+const INIT_LOG_FILE: &str = "/var/log/lox-linein-bridge.log";
 
-pub async fn run_install() -> Result<()> {
-    let (_config, config_path) = config::load_or_create_config()?;
-    println!("Config: {}", config_path.display());
+/// Service manager the bridge is installed for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InitSystem {
+    Systemd,
+    /// Alpine, Gentoo.
+    OpenRc,
+    /// Plain `/etc/init.d` script with `start-stop-daemon`.
+    SysV,
+}
+
+impl InitSystem {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "systemd" => Some(Self::Systemd),
+            "openrc" => Some(Self::OpenRc),
+            "sysv" | "sysvinit" => Some(Self::SysV),
+            _ => None,
+        }
+    }
+
+    fn detect() -> Self {
+        if Path::new("/run/systemd/system").is_dir() {
+            Self::Systemd
+        } else if Path::new("/sbin/openrc-run").exists() || Path::new("/run/openrc").is_dir() {
+            Self::OpenRc
+        } else {
+            Self::SysV
+        }
+    }
+}
+
+#[derive(Debug, Default, clap::Args)]
+pub struct InstallOptions {
+    /// File holding the token to store as `server_token` before the
+    /// service starts; `-` reads it from stdin. Never taken as an argument,
+    /// where `ps` would show it.
+    #[arg(long, value_name = "PATH")]
+    token_file: Option<PathBuf>,
+    /// Stored as `preferred_server_mac`, skipping the server picker.
+    #[arg(long, value_name = "MAC")]
+    server_mac: Option<String>,
+    /// User unit under `~/.config/systemd/user`, no root needed.
+    #[arg(long)]
+    user: bool,
+    /// Runs the system unit as this account instead of root.
+    #[arg(long, value_name = "NAME")]
+    service_user: Option<String>,
+    /// Only writes the unit; `systemctl` is left to the caller.
+    #[arg(long)]
+    no_start: bool,
+    /// systemd, openrc or sysv instead of the detected init system.
+    #[arg(long, value_parser = parse_init)]
+    init: Option<InitSystem>,
+}
+
+/// The first line of `source`, or of stdin for `-`.
+fn read_token(source: &Path) -> Result<String> {
+    let text = if source == Path::new("-") {
+        let mut line = String::new();
+        std::io::stdin()
+            .read_line(&mut line)
+            .context("read the token from stdin")?;
+        line
+    } else {
+        fs::read_to_string(source).with_context(|| format!("read {}", source.display()))?
+    };
+    let token = text.lines().next().unwrap_or_default().trim();
+    if token.is_empty() {
+        anyhow::bail!("no token in {}", source.display());
+    }
+    Ok(token.to_string())
+}
+
+fn parse_init(name: &str) -> Result<InitSystem, String> {
+    InitSystem::parse(name)
+        .ok_or_else(|| format!("unknown init system: {} (systemd, openrc, sysv)", name))
+}
+
+pub async fn run_install(options: InstallOptions) -> Result<()> {
+    let init = options.init.unwrap_or_else(InitSystem::detect);
+    if options.user && init != InitSystem::Systemd {
+        anyhow::bail!("--user needs systemd");
+    }
+    let binary = binary_path()?;
+    let (mut config, mut config_path) = config::load_or_create_config()?;
+    let mut changed = false;
+    if let Some(source) = options.token_file.as_deref() {
+        config.server_token = Some(read_token(source)?);
+        changed = true;
+        println!("{}", t!("install-token-saved"));
+    }
+    if let Some(mac) = options.server_mac {
+        println!("{}", t!("install-server-mac-saved", mac = mac.as_str()));
+        config.preferred_server_mac = Some(mac);
+        changed = true;
+    } else if wizard::is_interactive()
+        && config.server_url.is_none()
+        && config.preferred_server_name.is_none()
+        && config.preferred_server_mac.is_none()
+    {
+        changed |= wizard::pick_server(&mut config)?;
+    }
+    if changed {
+        config_path = config::write_config(&config)?;
+    }
+    if config.server_token.is_some() {
+        fs::set_permissions(&config_path, fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("chmod {}", config_path.display()))?;
+    }
+    println!(
+        "{}",
+        t!("install-config", path = config_path.display().to_string())
+    );
 
-    let unit = systemd_unit();
-    fs::write(SYSTEMD_UNIT_PATH, unit).context("write systemd unit")?;
-    println!("Wrote systemd unit: {}", SYSTEMD_UNIT_PATH);
+    // The service has to find a config given by flag or environment too.
+    let args = match config::config_path_override() {
+        Some(_) => {
+            let path = std::path::absolute(&config_path).unwrap_or_else(|_| config_path.clone());
+            // The path goes unquoted into ExecStart, command_args and
+            // DAEMON_ARGS, which each split and unescape differently.
+            let path = path.display().to_string();
+            if !path.chars().all(is_plain_path_char) {
+                anyhow::bail!(
+                    "the service can't be given {}: use a config path without spaces, quotes or %, $ and \\",
+                    path
+                );
+            }
+            format!("--config {}", path)
+        }
+        None => String::new(),
+    };
+    let account = match options.service_user.as_deref() {
+        Some(user) => {
+            let group =
+                primary_group(user).with_context(|| format!("unknown service user {}", user))?;
+            Some((user.to_string(), group))
+        }
+        None => None,
+    };
+    if let Some((user, group)) = account.as_ref().filter(|_| !options.user) {
+        hand_over_config(&config_path, user)?;
+        println!(
+            "{}",
+            t!(
+                "install-config-owner",
+                user = user.as_str(),
+                group = group.as_str()
+            )
+        );
+    }
+    if init != InitSystem::Systemd {
+        return install_init_script(init, &binary, &args, account.as_ref(), options.no_start);
+    }
+
+    let unit_path = if options.user {
+        user_unit_path()?
+    } else {
+        PathBuf::from(SYSTEMD_UNIT_PATH)
+    };
+    let unit = if options.user {
+        let user = std::env::var("USER").unwrap_or_default();
+        check_audio_group(&user);
+        user_systemd_unit(&binary, &args)
+    } else {
+        let audio_group = group_exists(AUDIO_GROUP);
+        systemd_unit(&binary, &args, account.as_ref(), audio_group)
+    };
+    if let Some(parent) = unit_path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+    }
+    fs::write(&unit_path, unit).context("write systemd unit")?;
+    println!(
+        "{}",
+        t!(
+            "install-unit-written",
+            path = unit_path.display().to_string()
+        )
+    );
 
-    run_systemctl(&["daemon-reload"])?;
-    run_systemctl(&["enable", "--now", "lox-linein-bridge"])?;
-    run_systemctl(&["restart", "lox-linein-bridge"])?;
+    if options.no_start {
+        println!(
+            "{}",
+            t!(
+                "install-no-start",
+                scope = if options.user { "--user " } else { "" }
+            )
+        );
+        return Ok(());
+    }
+    run_systemctl(options.user, &["daemon-reload"])?;
+    // Without --no-block these wait until the bridge found its server.
+    run_systemctl(
+        options.user,
+        &["enable", "--now", "--no-block", "lox-linein-bridge"],
+    )?;
+    run_systemctl(
+        options.user,
+        &["restart", "--no-block", "lox-linein-bridge"],
+    )?;
+    if options.user {
+        println!("{}", t!("install-user-linger"));
+    }
     Ok(())
 }
 
-fn systemd_unit() -> String {
+/// OpenRC or SysV: writes `/etc/init.d/lox-linein-bridge`, adds it to the
+/// default runlevel and (re)starts it.
+fn install_init_script(
+    init: InitSystem,
+    binary: &Path,
+    args: &str,
+    account: Option<&(String, String)>,
+    no_start: bool,
+) -> Result<()> {
+    // Without systemd's SupplementaryGroups, the account itself needs it.
+    if let Some((user, _)) = account {
+        check_audio_group(user);
+    }
+    let script = match init {
+        InitSystem::OpenRc => openrc_script(binary, args, account),
+        _ => sysv_script(binary, args, account),
+    };
+    fs::write(INIT_SCRIPT_PATH, script).with_context(|| format!("write {}", INIT_SCRIPT_PATH))?;
+    fs::set_permissions(INIT_SCRIPT_PATH, fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("chmod {}", INIT_SCRIPT_PATH))?;
+    println!("{}", t!("install-init-written", path = INIT_SCRIPT_PATH));
+
+    let (enable, restart): (&[&str], &[&str]) = match init {
+        InitSystem::OpenRc => (
+            &["rc-update", "add", "lox-linein-bridge", "default"],
+            &["rc-service", "lox-linein-bridge", "restart"],
+        ),
+        _ => (
+            &["update-rc.d", "lox-linein-bridge", "defaults"],
+            &[INIT_SCRIPT_PATH, "restart"],
+        ),
+    };
+    if no_start {
+        println!(
+            "{}",
+            t!(
+                "install-init-no-start",
+                enable = enable.join(" "),
+                restart = restart.join(" ")
+            )
+        );
+        return Ok(());
+    }
+    run_command(enable)?;
+    run_command(restart)
+}
+
+fn openrc_script(binary: &Path, args: &str, account: Option<&(String, String)>) -> String {
+    let mut lines = vec![
+        "#!/sbin/openrc-run".to_string(),
+        String::new(),
+        "name=\"Lox Line-in Bridge\"".to_string(),
+        format!("command=\"{}\"", binary.display()),
+        // Restarts the bridge like systemd's Restart=always.
+        "supervisor=\"supervise-daemon\"".to_string(),
+        "respawn_delay=2".to_string(),
+        "respawn_max=0".to_string(),
+        format!("output_log=\"{}\"", INIT_LOG_FILE),
+        format!("error_log=\"{}\"", INIT_LOG_FILE),
+    ];
+    if !args.is_empty() {
+        lines.push(format!("command_args=\"{}\"", args));
+    }
+    if let Some((user, group)) = account {
+        lines.push(format!("command_user=\"{}:{}\"", user, group));
+    }
+    lines.extend(["", "depend() {", "\tneed net", "\tafter firewall", "}", ""].map(str::to_string));
+    lines.join("\n")
+}
+
+/// LSB init script. Unlike the other two, nothing restarts the bridge if it
+/// exits.
+fn sysv_script(binary: &Path, args: &str, account: Option<&(String, String)>) -> String {
+    let chuid = account
+        .map(|(user, group)| format!("{}:{}", user, group))
+        .unwrap_or_else(|| "root".to_string());
+    // A service account can't write to /var/log; it keeps the default file.
+    let mut daemon_args = match account {
+        Some(_) => "--log-target file".to_string(),
+        None => format!("--log-target file --log-file {}", INIT_LOG_FILE),
+    };
+    if !args.is_empty() {
+        daemon_args = format!("{} {}", daemon_args, args);
+    }
+    [
+        "#!/bin/sh",
+        "### BEGIN INIT INFO",
+        "# Provides:          lox-linein-bridge",
+        "# Required-Start:    $network $remote_fs",
+        "# Required-Stop:     $network $remote_fs",
+        "# Default-Start:     2 3 4 5",
+        "# Default-Stop:      0 1 6",
+        "# Short-Description: Lox Line-in Bridge",
+        "### END INIT INFO",
+        "",
+        &format!("DAEMON=\"{}\"", binary.display()),
+        &format!("DAEMON_ARGS=\"{}\"", daemon_args),
+        "PIDFILE=/run/lox-linein-bridge.pid",
+        &format!("CHUID={}", chuid),
+        "",
+        "case \"$1\" in",
+        "start)",
+        "\tstart-stop-daemon --start --background --make-pidfile --pidfile \"$PIDFILE\" \\",
+        "\t\t--chuid \"$CHUID\" --exec \"$DAEMON\" -- $DAEMON_ARGS",
+        "\t;;",
+        "stop)",
+        // The bridge says goodbye to the server on SIGTERM; give it time.
+        "\tstart-stop-daemon --stop --pidfile \"$PIDFILE\" --retry TERM/15/KILL/5",
+        "\trm -f \"$PIDFILE\"",
+        "\t;;",
+        "restart)",
+        "\t\"$0\" stop",
+        "\t\"$0\" start",
+        "\t;;",
+        "status)",
+        "\tstart-stop-daemon --status --pidfile \"$PIDFILE\" && echo running || echo stopped",
+        "\t;;",
+        "*)",
+        "\techo \"Usage: $0 {start|stop|restart|status}\"",
+        "\texit 1",
+        "\t;;",
+        "esac",
+        "",
+    ]
+    .join("\n")
+}
+
+/// System unit. `account` is the user and group to run as (root without
+/// one); with an `audio` group on the host it is added as a supplementary
+/// group, so the ALSA devices can be opened either way.
+fn systemd_unit(
+    binary: &Path,
+    args: &str,
+    account: Option<&(String, String)>,
+    audio_group: bool,
+) -> String {
+    let exec_start = exec_start(binary, args);
+    let mut service = vec!["Type=notify".to_string(), "NotifyAccess=main".to_string()];
+    if let Some((user, group)) = account {
+        service.push(format!("User={}", user));
+        service.push(format!("Group={}", group));
+    }
+    if audio_group {
+        service.push(format!("SupplementaryGroups={}", AUDIO_GROUP));
+    }
+    service.push(exec_start);
+    service.push("ExecReload=/bin/kill -HUP $MAINPID".to_string());
+    let service = service.join("\n");
     [
         "[Unit]",
         "Description=Lox Line-in Bridge",
         "After=network-online.target",
         "",
         "[Service]",
-        "Type=simple",
-        "ExecStart=/usr/local/bin/lox-linein-bridge",
+        &service,
+        // Ready means registered, which may wait for the server.
+        "TimeoutStartSec=infinity",
+        "WatchdogSec=60",
         "Nice=-5",
         "CPUSchedulingPolicy=rr",
         "CPUSchedulingPriority=50",
@@ -42,8 +392,207 @@ fn systemd_unit() -> String {
     .join("\n")
 }
 
-fn run_systemctl(args: &[&str]) -> Result<()> {
-    let status = Command::new("systemctl")
+/// Unit for the user's own service manager. It runs with the user's
+/// PipeWire/PulseAudio session; raising the priority needs root, so the
+/// scheduling settings of the system unit are left out.
+fn user_systemd_unit(binary: &Path, args: &str) -> String {
+    [
+        "[Unit]",
+        "Description=Lox Line-in Bridge",
+        "After=pipewire.service pipewire-pulse.service",
+        "",
+        "[Service]",
+        "Type=notify",
+        "NotifyAccess=main",
+        &exec_start(binary, args),
+        "ExecReload=/bin/kill -HUP $MAINPID",
+        "TimeoutStartSec=infinity",
+        "WatchdogSec=60",
+        "Restart=always",
+        "RestartSec=2",
+        "",
+        "[Install]",
+        "WantedBy=default.target",
+        "",
+    ]
+    .join("\n")
+}
+
+/// Characters that mean the same to systemd, OpenRC and a plain shell.
+fn is_plain_path_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "/._-+:@,=".contains(c)
+}
+
+fn exec_start(binary: &Path, args: &str) -> String {
+    if args.is_empty() {
+        format!("ExecStart={}", binary.display())
+    } else {
+        format!("ExecStart={} {}", binary.display(), args)
+    }
+}
+
+/// Warns when `user` can't open ALSA devices because it isn't in the
+/// `audio` group. User units can't add the group themselves; hosts without
+/// it manage access some other way.
+fn check_audio_group(user: &str) {
+    if user.is_empty() || user == "root" || !group_exists(AUDIO_GROUP) {
+        return;
+    }
+    if !in_group(user, AUDIO_GROUP) {
+        println!(
+            "{}",
+            t!(
+                "install-audio-group-missing",
+                user = user,
+                group = AUDIO_GROUP
+            )
+        );
+    }
+}
+
+/// Entries of an `/etc/passwd`-style file, split at `:`.
+fn entries(path: &str) -> Vec<Vec<String>> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split(':').map(str::to_string).collect())
+        .collect()
+}
+
+fn group_exists(group: &str) -> bool {
+    entries("/etc/group").iter().any(|entry| entry[0] == group)
+}
+
+/// Name of the user's login group.
+fn primary_group(user: &str) -> Option<String> {
+    let gid = entries("/etc/passwd")
+        .into_iter()
+        .find(|entry| entry[0] == user)?
+        .get(3)?
+        .clone();
+    entries("/etc/group")
+        .into_iter()
+        .find(|entry| entry.get(2) == Some(&gid))
+        .map(|entry| entry[0].clone())
+}
+
+fn in_group(user: &str, group: &str) -> bool {
+    primary_group(user).as_deref() == Some(group)
+        || entries("/etc/group").iter().any(|entry| {
+            entry[0] == group
+                && entry
+                    .get(3)
+                    .is_some_and(|members| members.split(',').any(|member| member == user))
+        })
+}
+
+fn user_unit_path() -> Result<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME").context("HOME is not set")?).join(".config"),
+    };
+    Ok(config_home.join("systemd/user").join(UNIT_NAME))
+}
+
+/// Where this binary is, so the unit doesn't depend on it being installed
+/// to /usr/local/bin. The path goes unquoted into the unit or init script,
+/// like the config path.
+fn binary_path() -> Result<PathBuf> {
+    let binary = std::env::current_exe().unwrap_or_else(|_| PathBuf::from(DEFAULT_BINARY));
+    let path = binary.display().to_string();
+    if !path.chars().all(is_plain_path_char) {
+        anyhow::bail!(
+            "the service can't run {}: install the binary to a path without spaces, quotes or %, $ and \\",
+            path
+        );
+    }
+    Ok(binary)
+}
+
+/// Gives the service account the config file and, when it sits in the
+/// bridge's own directory, that directory and the files in it (secret and
+/// redaction keys, status spool, server cache), so the service can read its
+/// token and write back what it changes.
+fn hand_over_config(config_path: &Path, user: &str) -> Result<()> {
+    let (uid, gid) = account_ids(user).with_context(|| format!("unknown service user {}", user))?;
+    let chown = |path: &Path| {
+        std::os::unix::fs::lchown(path, Some(uid), Some(gid))
+            .with_context(|| format!("chown {}", path.display()))
+    };
+    chown(config_path)?;
+    let Some(dir) = config_path.parent().filter(|dir| {
+        dir.file_name()
+            .is_some_and(|name| name == "lox-linein-bridge")
+    }) else {
+        return Ok(());
+    };
+    chown(dir)?;
+    for entry in fs::read_dir(dir).with_context(|| format!("read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_file() {
+            chown(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Uid and login gid from `/etc/passwd`.
+fn account_ids(user: &str) -> Option<(u32, u32)> {
+    let entry = entries("/etc/passwd")
+        .into_iter()
+        .find(|entry| entry[0] == user)?;
+    Some((entry.get(2)?.parse().ok()?, entry.get(3)?.parse().ok()?))
+}
+
+/// Restarts an installed, running service so it picks up a new binary.
+/// Returns false when there is none to restart.
+pub fn restart_service() -> Result<bool> {
+    match InitSystem::detect() {
+        InitSystem::Systemd => {
+            let root = unsafe { libc::geteuid() } == 0;
+            let unit_exists = if root {
+                Path::new(SYSTEMD_UNIT_PATH).exists()
+            } else {
+                user_unit_path().is_ok_and(|path| path.exists())
+            };
+            if !unit_exists {
+                return Ok(false);
+            }
+            // Only restarts a service that is running.
+            run_systemctl(!root, &["try-restart", "--no-block", "lox-linein-bridge"])?;
+            Ok(true)
+        }
+        InitSystem::OpenRc if Path::new(INIT_SCRIPT_PATH).exists() => {
+            run_command(&["rc-service", "lox-linein-bridge", "restart"])?;
+            Ok(true)
+        }
+        InitSystem::SysV if Path::new(INIT_SCRIPT_PATH).exists() => {
+            run_command(&[INIT_SCRIPT_PATH, "restart"])?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+fn run_command(command: &[&str]) -> Result<()> {
+    let status = Command::new(command[0])
+        .args(&command[1..])
+        .status()
+        .with_context(|| format!("run {}", command.join(" ")))?;
+    if status.success() {
+        Ok(())
+    } else {
+        anyhow::bail!("{} failed", command.join(" "));
+    }
+}
+
+fn run_systemctl(user: bool, args: &[&str]) -> Result<()> {
+    let mut command = Command::new("systemctl");
+    if user {
+        command.arg("--user");
+    }
+    let status = command
         .args(args)
         .status()
         .with_context(|| format!("run systemctl {}", args.join(" ")))?;
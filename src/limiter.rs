@@ -0,0 +1,106 @@
+use crate::audio::TARGET_CHANNELS;
+
+/// Applies a fixed gain to interleaved s16le samples, saturating at full
+/// scale.
+pub fn apply_gain(bytes: &mut [u8], gain_db: f32) {
+    let gain = 10f32.powf(gain_db / 20.0);
+    for sample in bytes.chunks_exact_mut(2) {
+        let value = i16::from_le_bytes([sample[0], sample[1]]) as f32 * gain;
+        let scaled = value.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        sample.copy_from_slice(&scaled.to_le_bytes());
+    }
+}
+
+/// Gain recovery time after the ceiling was hit.
+const RELEASE_SECS: f32 = 0.05;
+
+/// Brick-wall output ceiling over interleaved stereo s16le chunks. Gain drops
+/// instantly on any frame above the ceiling and recovers over the release
+/// time; a final clamp guarantees no sample ever exceeds the ceiling.
+pub struct Ceiling {
+    limit: f32,
+    gain: f32,
+    release: f32,
+}
+
+impl Ceiling {
+    pub fn new(ceiling_db: f32, rate: u32) -> Self {
+        let limit = (10f32.powf(ceiling_db.min(0.0) / 20.0) * i16::MAX as f32).floor();
+        let release_frames = RELEASE_SECS * rate.max(1) as f32;
+        Self {
+            limit,
+            gain: 1.0,
+            release: 1.0 - (-1.0 / release_frames).exp(),
+        }
+    }
+
+    /// Limits the chunk in place. Returns true when the chunk was altered.
+    pub fn process(&mut self, bytes: &mut [u8]) -> bool {
+        let frame_bytes = TARGET_CHANNELS as usize * 2;
+        let mut intervened = false;
+        for frame in bytes.chunks_exact_mut(frame_bytes) {
+            let mut samples = [0f32; TARGET_CHANNELS as usize];
+            let mut peak = 0f32;
+            for (idx, sample) in samples.iter_mut().enumerate() {
+                *sample = i16::from_le_bytes([frame[idx * 2], frame[idx * 2 + 1]]) as f32;
+                peak = peak.max(sample.abs());
+            }
+            let required = if peak > self.limit {
+                self.limit / peak
+            } else {
+                1.0
+            };
+            if required < self.gain {
+                self.gain = required;
+            } else {
+                self.gain += (required - self.gain) * self.release;
+                if self.gain > 0.9999 {
+                    self.gain = 1.0;
+                }
+            }
+            if self.gain >= 1.0 && peak <= self.limit {
+                continue;
+            }
+            intervened = true;
+            for (idx, sample) in samples.iter().enumerate() {
+                let limited = (sample * self.gain).round().clamp(-self.limit, self.limit) as i16;
+                frame[idx * 2..idx * 2 + 2].copy_from_slice(&limited.to_le_bytes());
+            }
+        }
+        intervened
+    }
+}
+
+/// Input gain and output ceiling of a capture session.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OutputSettings {
+    pub gain_db: f32,
+    /// Absolute ceiling in dBFS.
+    pub ceiling_db: Option<f32>,
+}
+
+/// Gain followed by the ceiling, applied to each converted chunk before it
+/// is metered and sent.
+pub struct OutputStage {
+    gain_db: f32,
+    ceiling: Option<Ceiling>,
+}
+
+impl OutputStage {
+    pub fn new(settings: OutputSettings, rate: u32) -> Self {
+        Self {
+            gain_db: settings.gain_db,
+            ceiling: settings.ceiling_db.map(|db| Ceiling::new(db, rate)),
+        }
+    }
+
+    /// Returns true when the ceiling altered the chunk.
+    pub fn process(&mut self, bytes: &mut [u8]) -> bool {
+        if self.gain_db != 0.0 {
+            apply_gain(bytes, self.gain_db);
+        }
+        self.ceiling
+            .as_mut()
+            .is_some_and(|ceiling| ceiling.process(bytes))
+    }
+}
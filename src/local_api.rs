@@ -0,0 +1,240 @@
+//! Small HTTP API on the bridge itself, for checking on a bridge from the
+//! LAN while the server is down:
+//! - `GET /status`: the health snapshot and the config the bridge runs with;
+//! - `GET /devices`: capture devices as reported to the server;
+//! - `POST /restart-capture`: closes and reopens the capture;
+//! - `POST /log`: switches the log level and target, e.g. to capture a
+//!   repro to the log file and turn it off again.
+//!
+//! Plain HTTP without authentication, so bind it to a trusted network. The
+//! `POST`s need `Content-Type: application/json`, which a web page can't
+//! send cross-origin without a preflight this API never answers, so a
+//! browser on the LAN can't be used to restart the capture. For the same
+//! reason `POST /log` can't change the log file path.
+
+use crate::audio;
+use crate::logging::{self, LogHandle, LogTarget};
+use crate::net;
+use crate::stream::StatusHandle;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocalApiConfig {
+    /// Address to serve on, e.g. `0.0.0.0:8097`.
+    pub listen: String,
+}
+
+/// Body of `POST /log`; an unset field stays as it is.
+#[derive(Debug, Deserialize)]
+struct LogRequest {
+    level: Option<String>,
+    target: Option<String>,
+}
+
+/// State shared between the API and the bridge loop.
+#[derive(Clone)]
+pub struct LocalApi {
+    status: StatusHandle,
+    runtime: Arc<Mutex<Value>>,
+    restart: Arc<Notify>,
+    /// `None` for manifest bridges, which share the process's logging.
+    log: Option<LogHandle>,
+}
+
+impl LocalApi {
+    pub fn new(status: StatusHandle, log: Option<LogHandle>) -> Self {
+        Self {
+            status,
+            runtime: Arc::new(Mutex::new(Value::Null)),
+            restart: Arc::new(Notify::new()),
+            log,
+        }
+    }
+
+    /// Config shown under `config` in `GET /status`.
+    pub fn set_runtime(&self, runtime: Value) {
+        if let Ok(mut current) = self.runtime.lock() {
+            *current = runtime;
+        }
+    }
+
+    /// Resolves on the next `POST /restart-capture`. Requests made while
+    /// nothing waits are not kept.
+    pub async fn restart_requested(&self) {
+        self.restart.notified().await;
+    }
+
+    pub async fn serve(&self, config: &LocalApiConfig) -> Result<()> {
+        let listener = TcpListener::bind(&config.listen)
+            .await
+            .with_context(|| format!("bind {}", config.listen))?;
+        info!("local api listening on {}", config.listen);
+        let api = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let socket = net::accept(&listener, "local api").await;
+                let api = api.clone();
+                tokio::spawn(async move { api.handle(socket).await });
+            }
+        });
+        Ok(())
+    }
+
+    async fn handle(&self, mut socket: TcpStream) {
+        let mut request = [0u8; 1024];
+        let mut len = 0;
+        // The body of a `POST` may come in a later segment than the headers.
+        while len < request.len() {
+            let Ok(Ok(read)) =
+                tokio::time::timeout(Duration::from_secs(5), socket.read(&mut request[len..]))
+                    .await
+            else {
+                return;
+            };
+            len += read;
+            if read == 0 || is_complete(&request[..len]) {
+                break;
+            }
+        }
+        let request = String::from_utf8_lossy(&request[..len]);
+        let mut parts = request.split_whitespace();
+        let method = parts.next().unwrap_or("");
+        let path = parts.next().unwrap_or("").split('?').next().unwrap_or("");
+        let (code, body) = match (method, path) {
+            ("GET", "/status") => {
+                let runtime = self
+                    .runtime
+                    .lock()
+                    .map(|runtime| runtime.clone())
+                    .unwrap_or(Value::Null);
+                (
+                    "200 OK",
+                    json!({ "health": self.status.health_snapshot(), "config": runtime }),
+                )
+            }
+            // Enumerating ALSA devices blocks.
+            ("GET", "/devices") => {
+                match tokio::task::spawn_blocking(audio::list_input_device_details).await {
+                    Ok(Ok(devices)) => ("200 OK", json!(devices)),
+                    Ok(Err(err)) => (
+                        "500 Internal Server Error",
+                        json!({ "error": format!("{:#}", err) }),
+                    ),
+                    Err(err) => (
+                        "500 Internal Server Error",
+                        json!({ "error": err.to_string() }),
+                    ),
+                }
+            }
+            ("POST", "/restart-capture" | "/log") if !is_json(&request) => (
+                "415 Unsupported Media Type",
+                json!({ "error": "send Content-Type: application/json" }),
+            ),
+            ("POST", "/restart-capture") => {
+                warn!("capture restart requested through the local api");
+                self.restart.notify_waiters();
+                ("202 Accepted", json!({ "restarting": true }))
+            }
+            ("POST", "/log") => self.switch_log(body(&request)),
+            (_, "/status" | "/devices" | "/restart-capture" | "/log") => (
+                "405 Method Not Allowed",
+                json!({ "error": "method not allowed" }),
+            ),
+            _ => ("404 Not Found", json!({ "error": "not found" })),
+        };
+        let body = body.to_string();
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            code,
+            body.len(),
+            body
+        );
+        let _ = socket.write_all(response.as_bytes()).await;
+    }
+
+    fn switch_log(&self, body: &str) -> (&'static str, Value) {
+        let Some(log) = self.log.as_ref() else {
+            return (
+                "409 Conflict",
+                json!({ "error": "the bridges of a manifest share the process's logging" }),
+            );
+        };
+        let request: LogRequest = match serde_json::from_str(body) {
+            Ok(request) => request,
+            Err(err) => return ("400 Bad Request", json!({ "error": err.to_string() })),
+        };
+        if let Some(level) = request.level.as_deref() {
+            if !logging::is_valid_level(level) {
+                return (
+                    "400 Bad Request",
+                    json!({ "error": format!("invalid log level {:?}", level) }),
+                );
+            }
+        }
+        if let Some(target) = request.target.as_deref() {
+            if LogTarget::parse(target).is_none() {
+                return (
+                    "400 Bad Request",
+                    json!({ "error": format!("unknown log target {:?}", target) }),
+                );
+            }
+        }
+        warn!(
+            "log settings changed through the local api: level={:?}, target={:?}",
+            request.level, request.target
+        );
+        log.apply(request.level.as_deref(), request.target.as_deref(), None);
+        let (level, target) = log.current();
+        (
+            "200 OK",
+            json!({ "level": level, "target": target.label() }),
+        )
+    }
+}
+
+/// Whether the headers and as much body as they announce have arrived.
+fn is_complete(request: &[u8]) -> bool {
+    let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") else {
+        return false;
+    };
+    let headers = String::from_utf8_lossy(&request[..end]);
+    let length = headers
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    request.len() - (end + 4) >= length
+}
+
+/// The request body, after the blank line that ends the headers.
+fn body(request: &str) -> &str {
+    request
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body)
+        .unwrap_or("")
+}
+
+/// Whether the request headers declare a JSON body.
+fn is_json(request: &str) -> bool {
+    request
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .any(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("content-type")
+                && value
+                    .trim()
+                    .to_ascii_lowercase()
+                    .starts_with("application/json")
+        })
+}
@@ -0,0 +1,381 @@
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+const DEFAULT_LOG_FILE: &str = "/tmp/lox-linein-bridge.log";
+
+/// Where log lines go. `Journal` writes to stderr, which systemd forwards
+/// into the journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogTarget {
+    Journal,
+    File,
+    Both,
+}
+
+impl LogTarget {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "journal" | "stderr" | "journald" => Some(Self::Journal),
+            "file" => Some(Self::File),
+            "both" | "all" => Some(Self::Both),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Journal => "journal",
+            Self::File => "file",
+            Self::Both => "both",
+        }
+    }
+
+    fn journal(&self) -> bool {
+        matches!(self, Self::Journal | Self::Both)
+    }
+
+    fn file(&self) -> bool {
+        matches!(self, Self::File | Self::Both)
+    }
+}
+
+/// Line format for both stderr and the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "text" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Size-based rotation of the log file: `bridge.log` moves to
+/// `bridge.log.1`, older files shift up and the oldest beyond `max_files`
+/// is deleted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRotation {
+    /// Zero never rotates.
+    pub max_bytes: u64,
+    /// Rotated files kept next to the live one.
+    pub max_files: usize,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self {
+            max_bytes: 0,
+            max_files: 5,
+        }
+    }
+}
+
+struct Sinks {
+    target: LogTarget,
+    file_path: PathBuf,
+    file: Option<File>,
+    /// Bytes in the live file, for rotation.
+    file_len: u64,
+    rotation: LogRotation,
+}
+
+impl Sinks {
+    fn open_file(&mut self, path: &Path) -> Result<()> {
+        let file = open_log_file(path)?;
+        self.file_len = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn write_file(&mut self, buf: &[u8]) -> io::Result<()> {
+        let rotation = self.rotation;
+        if rotation.max_bytes > 0
+            && self.file_len > 0
+            && self.file_len + buf.len() as u64 > rotation.max_bytes
+        {
+            self.file = None;
+            rotate(&self.file_path, rotation.max_files);
+            let path = self.file_path.clone();
+            if let Err(err) = self.open_file(&path) {
+                return Err(io::Error::other(format!("{:#}", err)));
+            }
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(buf)?;
+            self.file_len += buf.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+/// Shifts `path.N` to `path.N+1`, dropping what falls beyond `max_files`,
+/// and moves `path` to `path.1`.
+fn rotate(path: &Path, max_files: usize) {
+    let numbered = |index: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    };
+    if max_files == 0 {
+        let _ = std::fs::remove_file(path);
+        return;
+    }
+    let _ = std::fs::remove_file(numbered(max_files));
+    for index in (1..max_files).rev() {
+        let _ = std::fs::rename(numbered(index), numbered(index + 1));
+    }
+    let _ = std::fs::rename(path, numbered(1));
+}
+
+/// Log settings as a server config carries them: level, target, file.
+type ServerSettings = (Option<String>, Option<String>, Option<String>);
+
+/// Handle for changing the log level and target of the running process.
+#[derive(Clone)]
+pub struct LogHandle {
+    filter: reload::Handle<EnvFilter, Registry>,
+    level: Arc<Mutex<String>>,
+    sinks: Arc<Mutex<Sinks>>,
+    /// Last settings from the server, so only its changes are applied.
+    server: Arc<Mutex<Option<ServerSettings>>>,
+}
+
+pub fn init(
+    level: Option<String>,
+    target: LogTarget,
+    file_path: Option<PathBuf>,
+    format: LogFormat,
+    rotation: LogRotation,
+    otlp_endpoint: Option<&str>,
+) -> Result<LogHandle> {
+    let level = level.unwrap_or_else(|| "off".to_string());
+    let file_path = file_path.unwrap_or_else(|| PathBuf::from(DEFAULT_LOG_FILE));
+    let mut sinks = Sinks {
+        target,
+        file_path: file_path.clone(),
+        file: None,
+        file_len: 0,
+        rotation,
+    };
+    if target.file() {
+        sinks.open_file(&file_path)?;
+    }
+    let sinks = Arc::new(Mutex::new(sinks));
+    let writer = |file| SinkWriter {
+        sinks: Arc::clone(&sinks),
+        file,
+    };
+    let (filter, filter_handle) = reload::Layer::new(EnvFilter::new(&level));
+    let text = format == LogFormat::Text;
+    let stderr_text = text.then(|| tracing_subscriber::fmt::layer().with_writer(writer(false)));
+    let file_text = text.then(|| {
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(writer(true))
+    });
+    let stderr_json = (!text).then(|| {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(writer(false))
+    });
+    let file_json = (!text).then(|| {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(writer(true))
+    });
+    // The level filters log output only; exported spans don't depend on it.
+    let output = Layer::and_then(stderr_text, file_text)
+        .and_then(stderr_json)
+        .and_then(file_json)
+        .with_filter(filter);
+    tracing_subscriber::registry()
+        .with(output)
+        .with(crate::otel::layer(otlp_endpoint)?)
+        .init();
+    Ok(LogHandle {
+        filter: filter_handle,
+        level: Arc::new(Mutex::new(level)),
+        sinks,
+        server: Arc::new(Mutex::new(None)),
+    })
+}
+
+/// Whether `level` is a filter the bridge can switch to, e.g. `debug` or
+/// `info,lox_linein_bridge=trace`.
+pub fn is_valid_level(level: &str) -> bool {
+    EnvFilter::try_new(level).is_ok()
+}
+
+impl LogHandle {
+    /// Applies a level, target, or file path change. Unset values and values
+    /// equal to the current ones are ignored.
+    pub fn apply(&self, level: Option<&str>, target: Option<&str>, file_path: Option<&str>) {
+        if let Some(level) = level {
+            self.set_level(level);
+        }
+        if target.is_some() || file_path.is_some() {
+            let target = match target.map(|name| (name, LogTarget::parse(name))) {
+                Some((_, Some(target))) => Some(target),
+                Some((name, None)) => {
+                    tracing::warn!("unknown log target {:?}", name);
+                    None
+                }
+                None => None,
+            };
+            self.set_target(target, file_path.map(PathBuf::from));
+        }
+    }
+
+    /// Applies the log settings of a server config when they differ from
+    /// the last ones it sent, so a switch through the local API holds until
+    /// the server changes its own.
+    pub fn apply_server(&self, level: Option<&str>, target: Option<&str>, file_path: Option<&str>) {
+        let next = (
+            level.map(str::to_string),
+            target.map(str::to_string),
+            file_path.map(str::to_string),
+        );
+        {
+            let mut last = match self.server.lock() {
+                Ok(last) => last,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            if last.as_ref() == Some(&next) {
+                return;
+            }
+            *last = Some(next);
+        }
+        self.apply(level, target, file_path);
+    }
+
+    /// The active level filter and target.
+    pub fn current(&self) -> (String, LogTarget) {
+        let level = match self.level.lock() {
+            Ok(level) => level.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        let target = match self.sinks.lock() {
+            Ok(sinks) => sinks.target,
+            Err(poisoned) => poisoned.into_inner().target,
+        };
+        (level, target)
+    }
+
+    fn set_level(&self, level: &str) {
+        let mut current = match self.level.lock() {
+            Ok(current) => current,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if *current == level {
+            return;
+        }
+        match self.filter.reload(EnvFilter::new(level)) {
+            Ok(()) => {
+                *current = level.to_string();
+                tracing::info!("log level set to {}", level);
+            }
+            Err(err) => tracing::warn!("log level change failed: {}", err),
+        }
+    }
+
+    fn set_target(&self, target: Option<LogTarget>, file_path: Option<PathBuf>) {
+        let message = {
+            let mut sinks = match self.sinks.lock() {
+                Ok(sinks) => sinks,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let next_target = target.unwrap_or(sinks.target);
+            let next_path = file_path.unwrap_or_else(|| sinks.file_path.clone());
+            if next_target == sinks.target && next_path == sinks.file_path {
+                return;
+            }
+            if next_path != sinks.file_path {
+                sinks.file = None;
+            }
+            if next_target.file() && sinks.file.is_none() {
+                match sinks.open_file(&next_path) {
+                    Ok(()) => {}
+                    Err(err) => {
+                        drop(sinks);
+                        tracing::warn!("log target change failed: {:#}", err);
+                        return;
+                    }
+                }
+            }
+            if !next_target.file() {
+                sinks.file = None;
+            }
+            sinks.target = next_target;
+            sinks.file_path = next_path;
+            format!(
+                "log target set to {} (file {})",
+                next_target.label(),
+                sinks.file_path.display()
+            )
+        };
+        tracing::info!("{}", message);
+    }
+}
+
+fn open_log_file(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("open log file {}", path.display()))
+}
+
+struct SinkWriter {
+    sinks: Arc<Mutex<Sinks>>,
+    file: bool,
+}
+
+impl<'a> MakeWriter<'a> for SinkWriter {
+    type Writer = SinkWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        SinkWriter {
+            sinks: Arc::clone(&self.sinks),
+            file: self.file,
+        }
+    }
+}
+
+impl Write for SinkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut sinks = match self.sinks.lock() {
+            Ok(sinks) => sinks,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if self.file {
+            if sinks.target.file() {
+                sinks.write_file(buf)?;
+            }
+        } else if sinks.target.journal() {
+            io::stderr().write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,158 +1,1019 @@
 mod alsa_silence;
+mod analysis;
 mod audio;
+mod blackbox;
+mod calibration;
+mod cli;
+mod clock;
 mod config;
+mod devices;
+mod diagnose;
+mod discover;
 mod discovery;
+mod dnssd;
+mod faults;
+mod fixtures;
+mod flac;
+#[cfg(feature = "hls")]
+mod fmp4;
+mod framing;
 mod health;
+mod hex;
+mod hls;
+mod i18n;
 mod install;
+mod limiter;
+mod local_api;
+mod logging;
+mod manifest;
+mod meter;
 mod models;
+mod monitor;
+mod mqtt;
+mod multicast;
+mod net;
+mod otel;
+mod overrides;
+mod payload_queue;
+mod pipe;
+mod pool;
+mod presets;
+mod record;
+mod recorder;
+mod redact;
+mod reload;
+mod ring;
+mod rtp;
+mod sd_notify;
+mod secrets;
 mod server_api;
+mod server_cache;
+mod shutdown;
+mod simd;
+mod sink;
+mod snapcast;
+mod sources;
+mod spool;
+mod status;
 mod stream;
+mod tee;
+mod telemetry;
+mod test_capture;
 mod timestamp;
+mod tls;
+mod track;
+mod update;
+mod vad;
+mod validate_config;
+mod wav;
+mod wizard;
 
 use anyhow::{Context, Result};
-use std::time::Duration;
-use tracing::{info, warn};
+use std::time::{Duration, Instant};
+use tracing::{info, warn, Instrument};
+
+const IDENTITY_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_STATUS_INTERVAL_SECS: u64 = 5;
+/// Failed status posts in a row before the bridge moves to the next server.
+const FAILOVER_AFTER_FAILURES: u32 = 3;
+/// How often a bridge on a standby server checks for the primary.
+const FAILBACK_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+/// Pause after the last candidate refused the registration, before
+/// discovering again.
+const REDISCOVER_DELAY: Duration = Duration::from_secs(5);
+/// Probes in a row the primary has to answer before the bridge fails back,
+/// so a flapping primary doesn't move it back and forth.
+const FAILBACK_PROBES: u32 = 3;
+/// Default gap between the VAD open and close thresholds.
+const VAD_HYSTERESIS_DB: f32 = 3.0;
+/// Exit code when bounded discovery finds no server (EX_UNAVAILABLE).
+const EXIT_NO_SERVER: i32 = 69;
+/// How long a stopping stream may take to send its remaining audio.
+const STREAM_DRAIN_TIMEOUT: Duration = Duration::from_secs(3);
+/// Bound for the last status post on shutdown.
+const OFFLINE_REPORT_TIMEOUT: Duration = Duration::from_secs(3);
+/// Audio each ingest target keeps while it reconnects.
+const DEFAULT_RECONNECT_BUFFER_MS: u64 = 5_000;
+/// Duration of each paced ingest payload.
+const DEFAULT_PACKET_MS: u64 = 40;
+const DEFAULT_RECORD_MAX_SECS: u64 = 60 * 60;
+const DEFAULT_RECORD_KEEP_DAYS: u64 = 7;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     alsa_silence::init();
-    let (command, log_level) = parse_args()?;
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::new(
-            log_level.unwrap_or_else(|| "off".to_string()),
-        ))
-        .init();
+    let cli = cli::Cli::parse_args();
+    if let Some(path) = cli.config.clone() {
+        config::set_config_path(path);
+    }
+    if let Some(path) = &cli.health_path {
+        health::set_file_path(path.display().to_string());
+    }
+    let log = logging::init(
+        cli.log_level(),
+        cli.log_target,
+        cli.log_file.clone(),
+        cli.log_format,
+        cli.log_rotation(),
+        cli.otlp_endpoint.as_deref(),
+    )?;
 
-    match command.as_deref() {
-        Some("--help") | Some("-h") => {
-            print_usage();
-            Ok(())
-        }
-        Some("--version") | Some("-V") => {
-            println!("lox-linein-bridge {}", env!("CARGO_PKG_VERSION"));
-            Ok(())
-        }
-        Some("install") => install::run_install().await,
-        Some("run") | None => run().await,
-        _ => {
-            print_usage();
-            anyhow::bail!("unknown command");
+    reload::install_hangup_handler();
+
+    let command = cli
+        .command
+        .unwrap_or_else(|| cli::Command::Run(cli::RunArgs::default()));
+    let result = match command {
+        cli::Command::Install(options) => install::run_install(options).await,
+        cli::Command::Update(options) => update::run_update(options).await,
+        cli::Command::Calibrate(options) => calibration::run_calibrate(options).await,
+        cli::Command::Analyze(options) => analysis::run_analyze(options).await,
+        cli::Command::Fixtures(options) => fixtures::run_fixtures(options),
+        cli::Command::Dump => run_dump(),
+        cli::Command::Diagnose(options) => diagnose::run_diagnose(options).await,
+        cli::Command::Discover(options) => discover::run_discover(options).await,
+        cli::Command::Status(options) => status::run_status(options),
+        cli::Command::Devices(options) => devices::run_devices(options),
+        cli::Command::TestCapture(options) => test_capture::run_test_capture(options).await,
+        cli::Command::Record(options) => record::run_record(options).await,
+        cli::Command::Pipe(options) => pipe::run_pipe(options).await,
+        cli::Command::ValidateConfig(options) => validate_config::run_validate_config(options),
+        cli::Command::EncryptSecrets(options) => secrets::run_encrypt_secrets(options),
+        cli::Command::Run(args) => {
+            let args = args.merge(cli.run);
+            blackbox::spawn_signal_trigger();
+            let shutdown = shutdown::Shutdown::install();
+            let restart = shutdown.clone();
+            let result = match &args.manifest {
+                Some(path) => {
+                    manifest::run(path, args.discovery_limits(), args.api_mode(), shutdown).await
+                }
+                None => run(log, args.discovery_limits(), args.api_mode(), shutdown).await,
+            };
+            if result.is_ok() && restart.restart_requested() {
+                otel::shutdown();
+                return Err(update::reexec());
+            }
+            if let Some(gave_up) = result
+                .as_ref()
+                .err()
+                .and_then(|err| err.downcast_ref::<discovery::NoServer>())
+            {
+                eprintln!(
+                    "{}",
+                    i18n::t!(
+                        "discovery-gave-up",
+                        attempts = gave_up.attempts,
+                        seconds = gave_up.elapsed.as_secs(),
+                    )
+                );
+                otel::shutdown();
+                std::process::exit(EXIT_NO_SERVER);
+            }
+            result
         }
-    }
+    };
+    otel::shutdown();
+    result
 }
 
-async fn run() -> Result<()> {
+async fn run(
+    log: logging::LogHandle,
+    limits: discovery::DiscoveryLimits,
+    api_mode: server_api::ApiMode,
+    shutdown: shutdown::Shutdown,
+) -> Result<()> {
+    let first_run = !config::config_exists();
     let (config, path) = config::load_or_create_config()?;
     info!("loaded config from {}", path.display());
-    let hostname = hostname::get()
-        .unwrap_or_else(|_| "unknown".into())
-        .to_string_lossy()
-        .to_string();
-    let (ip, mac) = local_identity()?;
+    faults::spawn_control();
+    let status = stream::StatusHandle::new("", "");
+    sd_notify::spawn_watchdog(vec![(config.bridge_id.clone(), status.clone())]);
+    health::spawn(status.clone(), &config.health, &config.bridge_id);
+    run_bridge(BridgeSetup {
+        offer_wizard: first_run && wizard::is_interactive(),
+        writable_config: true,
+        config,
+        path,
+        status,
+        log: Some(log),
+        limits,
+        api_mode,
+        shutdown,
+    })
+    .await
+}
+
+/// Where the next session of `run_bridge` connects.
+#[derive(Debug, Clone, Default)]
+enum NextServer {
+    /// The server of the last session.
+    #[default]
+    Same,
+    /// The mDNS monitor saw the server at a new address.
+    Moved(Box<discovery::DiscoveredServer>),
+    /// The next candidate, or a fresh discovery after the last one.
+    Failover,
+    /// Back to the first candidate.
+    Failback,
+    /// The reloaded config changes which server to use, or how.
+    Reload,
+}
+
+/// Everything one logical bridge needs to run.
+struct BridgeSetup {
+    config: config::Config,
+    /// Config file location; the capture report is stored next to it.
+    path: std::path::PathBuf,
+    status: stream::StatusHandle,
+    /// Handle for server-driven log settings; `None` when several bridges
+    /// share the process and must not override each other's logging.
+    log: Option<logging::LogHandle>,
+    limits: discovery::DiscoveryLimits,
+    api_mode: server_api::ApiMode,
+    offer_wizard: bool,
+    /// `path` is this bridge's own config file, so ids the server assigns
+    /// can be written back and changes to it reloaded. Manifest bridges
+    /// share one file that isn't a config.
+    writable_config: bool,
+    shutdown: shutdown::Shutdown,
+}
+
+/// Discovers the server, registers, and streams until the process ends or
+/// a shutdown is requested.
+async fn run_bridge(setup: BridgeSetup) -> Result<()> {
+    let BridgeSetup {
+        mut config,
+        path,
+        status,
+        log,
+        limits,
+        api_mode,
+        mut offer_wizard,
+        writable_config,
+        mut shutdown,
+    } = setup;
+    sd_notify::spawn_beat(status.clone());
+    let binding = net::Binding::new(
+        config.bind_interface.as_deref(),
+        config.bind_address.as_deref(),
+    )?;
+    let snapcast = config
+        .snapcast
+        .as_ref()
+        .map(|snapcast| snapcast::SnapcastTarget::parse(&snapcast.target))
+        .transpose()?;
+    let multicast = config
+        .multicast
+        .as_ref()
+        .map(|multicast| {
+            multicast::parse_group(&multicast.group).map(|group| (group, multicast.ttl))
+        })
+        .transpose()?;
+    // Local outputs that replace the server-assigned ingest.
+    let local_only = config
+        .snapcast
+        .as_ref()
+        .is_some_and(|snapcast| snapcast.replace_ingest)
+        || config
+            .multicast
+            .as_ref()
+            .is_some_and(|multicast| multicast.replace_ingest);
+    let hls = match config.hls.as_ref() {
+        Some(hls_config) => match hls::start(hls_config).await {
+            Ok(feed) => Some(feed),
+            Err(err) => {
+                warn!("hls output disabled: {:#}", err);
+                None
+            }
+        },
+        None => None,
+    };
+    let monitor = match config.monitor.as_ref() {
+        Some(monitor_config) => match monitor::start(monitor_config) {
+            Ok(feed) => Some(feed),
+            Err(err) => {
+                warn!("monitor output disabled: {:#}", err);
+                None
+            }
+        },
+        None => None,
+    };
+    let recorder = recorder::Recorder::start()?;
+    let _recording = recorder.finish_on_drop();
+    let local_api = local_api::LocalApi::new(status.clone(), log.clone());
+    if let Some(api_config) = config.api.as_ref() {
+        if let Err(err) = local_api.serve(api_config).await {
+            warn!("local api disabled: {:#}", err);
+        }
+    }
+    if let Some(black_box) = config.black_box.as_ref() {
+        blackbox::init(black_box);
+    }
+    let mut reloads = if writable_config {
+        reload::Reloads::spawn(path.clone(), config.clone())
+    } else {
+        reload::Reloads::disabled()
+    };
+    let discovery_started = Instant::now();
+    let mut discovery_attempts = 0u32;
+    let mut found_server = false;
+    // Candidates, best first, and the one in use.
+    let mut servers = Vec::new();
+    let mut current = 0;
+    let mut next = NextServer::Same;
+    // The last discovery's servers, tried once before discovering again.
+    let mut cached = server_cache::load(&path, &config.bridge_id);
+    let mut from_cache = false;
+    // Set until a fresh discovery led to a registration, then cached.
+    let mut cache_servers = false;
+    // Discovery running after a start from the cache.
+    let mut background: Option<tokio::sync::oneshot::Receiver<Vec<discovery::DiscoveredServer>>> =
+        None;
+    let spool = spool::Spool::open(&path, &config.bridge_id).await;
+    // Snapshots taken while there is no server session.
+    let mut offline_spool: Option<tokio::task::JoinHandle<()>> = None;
 
     loop {
-        let server = loop {
-            match discovery::discover_server(
-                config.preferred_server_name.as_deref(),
-                config.preferred_server_mac.as_deref(),
-            ) {
-                Ok(server) => {
-                    info!("discovered server: {}", server.base_url);
-                    break server;
+        if let Some(fresh) = reloads.take() {
+            config = fresh;
+        }
+        // The background discovery's servers replace the cached ones once
+        // the session on a cached server ends.
+        if let Some(found) = background.as_mut() {
+            match found.try_recv() {
+                Ok(found) => {
+                    background = None;
+                    servers = found;
+                    current = 0;
+                    from_cache = false;
+                    if !matches!(next, NextServer::Reload) {
+                        next = NextServer::Same;
+                    }
                 }
-                Err(err) => {
-                    warn!("mDNS discovery failed: {}", err);
-                    tokio::time::sleep(Duration::from_secs(5)).await;
+                Err(tokio::sync::oneshot::error::TryRecvError::Empty) => {}
+                Err(tokio::sync::oneshot::error::TryRecvError::Closed) => background = None,
+            }
+        }
+        match std::mem::take(&mut next) {
+            NextServer::Same => {}
+            NextServer::Moved(server) => {
+                if let Some(slot) = servers.get_mut(current) {
+                    *slot = *server;
                 }
+                // Cached once the registration with the new address worked.
+                cache_servers = discovery::configured_servers(&config).is_empty();
             }
-        };
+            NextServer::Failover => current += 1,
+            NextServer::Failback => current = 0,
+            NextServer::Reload => {
+                servers.clear();
+                cached = None;
+            }
+        }
+        if current >= servers.len() {
+            current = 0;
+            from_cache = false;
+            background = None;
+            servers = match discovery::configured_servers(&config) {
+                _ if matches!(api_mode, server_api::ApiMode::Replay(_)) => {
+                    vec![discovery::server_from_url("http://replay.invalid")]
+                }
+                configured if !configured.is_empty() => {
+                    for server in &configured {
+                        info!("using configured server: {}", server.base_url);
+                    }
+                    configured
+                }
+                _ if cached.is_some() => {
+                    let cached = cached.take().unwrap_or_default();
+                    for server in &cached {
+                        info!("using cached server: {}", server.base_url);
+                    }
+                    from_cache = true;
+                    background = Some(spawn_background_discovery(
+                        config.clone(),
+                        binding.clone(),
+                        path.clone(),
+                    ));
+                    cached
+                }
+                _ => loop {
+                    let discovered = tracing::info_span!("discovery")
+                        .in_scope(|| discovery::discover_servers(&config, &binding));
+                    match discovered {
+                        Ok(found) => {
+                            for server in &found {
+                                info!("discovered server: {}", server.base_url);
+                            }
+                            cache_servers = true;
+                            break found;
+                        }
+                        Err(err) if offer_wizard => {
+                            warn!("mDNS discovery failed: {}", err);
+                            offer_wizard = false;
+                            config = wizard::run(config).await?;
+                            let configured = discovery::configured_servers(&config);
+                            if !configured.is_empty() {
+                                break configured;
+                            }
+                        }
+                        Err(err) => {
+                            warn!("mDNS discovery failed: {}", err);
+                            discovery_attempts += 1;
+                            if !found_server
+                                && limits.exhausted(discovery_attempts, discovery_started)
+                            {
+                                return Err(discovery::NoServer {
+                                    attempts: discovery_attempts,
+                                    elapsed: discovery_started.elapsed(),
+                                }
+                                .into());
+                            }
+                            tokio::select! {
+                                _ = tokio::time::sleep(Duration::from_secs(5)) => {}
+                                _ = shutdown.requested() => return Ok(()),
+                                fresh = reloads.next() => {
+                                    config = fresh;
+                                    let configured = discovery::configured_servers(&config);
+                                    if !configured.is_empty() {
+                                        break configured;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+            };
+        }
+        let server = servers[current].clone();
+        found_server = true;
+        // Discovery blocks, so a stop may have arrived meanwhile.
+        if shutdown.is_requested() {
+            return Ok(());
+        }
 
         let api = server_api::ServerApi::new(
-            &server.base_url,
-            &server.register_path,
-            &server.status_path,
+            &server,
+            &config.server_tls.settings(),
+            config.server_token.as_deref(),
+            &api_mode,
         )?;
-        info!("server: {}", server.base_url);
+        match &api_mode {
+            server_api::ApiMode::Live => info!("server: {}", server.base_url),
+            server_api::ApiMode::Record(dir) => info!(
+                "server: {} (recording API exchanges to {})",
+                server.base_url,
+                dir.display()
+            ),
+            server_api::ApiMode::Replay(dir) => {
+                info!("replaying recorded API responses from {}", dir.display())
+            }
+        }
 
+        let identity = local_identity(&binding)?;
         let capture_devices = audio::list_input_device_details()?;
-        let register = models::BridgeRegisterRequest {
-            bridge_id: config.bridge_id.clone(),
-            hostname: hostname.clone(),
-            version: env!("CARGO_PKG_VERSION").to_string(),
-            ip: ip.clone(),
-            mac: mac.clone(),
-            capture_devices: capture_devices.clone(),
-        };
+        let redactor = redact::Redactor::new(config.redact.clone(), &config.bridge_id, &path);
+        let capture_report = analysis::load_summary(&path);
+        let capabilities = bridge_capabilities(
+            local_only,
+            &[
+                ("multicast", multicast.is_some()),
+                ("snapcast", snapcast.is_some()),
+                ("fifo", config.tee_pipe),
+                ("hls", hls.is_some()),
+            ],
+        );
+        let register = register_request(
+            &config.bridge_id,
+            &identity,
+            &capture_devices,
+            capture_report.as_ref(),
+            &redactor,
+            &capabilities,
+        );
         info!("registering bridge {}", config.bridge_id);
-        let initial_config = api.register_bridge(&register).await?;
+        let registered = async {
+            if from_cache {
+                // A stale entry shouldn't cost the full retries.
+                api.register_bridge_once(&register).await
+            } else {
+                api.register_bridge(&register).await
+            }
+        }
+        .instrument(tracing::info_span!("register", bridge_id = %config.bridge_id))
+        .await;
+        let mut initial_config = match registered {
+            Ok(initial_config) => initial_config,
+            // The cache may be stale; discover instead of giving up.
+            Err(err) if current + 1 < servers.len() || from_cache => {
+                warn!(
+                    "registering with {} failed, failing over: {:#}",
+                    server.base_url, err
+                );
+                next = NextServer::Failover;
+                continue;
+            }
+            // Every candidate refused; wait a little and discover again.
+            Err(err) => {
+                warn!(
+                    "registering with {} failed, discovering again: {:#}",
+                    server.base_url, err
+                );
+                next = NextServer::Failover;
+                tokio::select! {
+                    _ = tokio::time::sleep(REDISCOVER_DELAY) => {}
+                    _ = shutdown.requested() => return Ok(()),
+                }
+                continue;
+            }
+        };
+        // Nothing to keep yet: what the registration leaves out is unset.
+        initial_config.keep_missing(None);
+        if let Some(task) = offline_spool.take() {
+            task.abort();
+        }
+        sd_notify::ready();
         info!(
-            "registration response: assigned_input_id={:?}, capture_device={:?}",
-            initial_config.assigned_input_id, initial_config.capture_device
+            "registration response: assigned_input_id={:?}, input={:?}, zone={:?}, capture_device={:?}",
+            initial_config.assigned_input_id.clone().flatten(),
+            initial_config.assigned_input_name.clone().flatten(),
+            initial_config.assigned_zone_name.clone().flatten(),
+            initial_config.capture_device.clone().flatten()
         );
 
+        if let Some(assigned) = initial_config
+            .bridge_id
+            .as_deref()
+            .filter(|id| *id != config.bridge_id)
+        {
+            adopt_bridge_id(&mut config, &path, writable_config, assigned, &spool).await;
+        }
+        if std::mem::take(&mut cache_servers) {
+            server_cache::store(&path, &config.bridge_id, &servers);
+        }
+        let protocol = server_api::accepted_protocol(initial_config.protocol_version);
+        info!("bridge API protocol version {}", protocol);
+        let server_boot_id = initial_config.server_boot_id.clone();
+        let config_revision = initial_config.config_revision.clone();
+        let local = LocalSettings::new(&config);
+        if let Some(overrides) = local.overrides.report() {
+            info!("local overrides: {}", overrides);
+        }
+        let status_every = status_interval(
+            local
+                .overrides
+                .status_interval_secs
+                .or(initial_config.status_interval_secs),
+        );
+        // Only ids that arrive later trigger a dump.
+        let black_box_id = initial_config.black_box_dump.clone();
+        update::apply_remote(
+            &initial_config,
+            &status,
+            config.update_public_key.as_deref(),
+            &shutdown,
+        );
+        // Applied again with new local settings after a config reload.
+        let last_response = initial_config.clone();
+        let initial_config = local.apply(initial_config);
+        // Logging overrides count from the registration on, not just from
+        // the first config update.
+        if let Some(log) = log.as_ref() {
+            log.apply_server(
+                initial_config.log_level.as_deref(),
+                initial_config.log_target.as_deref(),
+                initial_config.log_file.as_deref(),
+            );
+        }
         let runtime = RuntimeConfig::from_response(initial_config);
         let (config_tx, mut config_rx) = tokio::sync::watch::channel(runtime.clone());
-        let (vad_tx, vad_rx) = tokio::sync::watch::channel((
-            runtime.vad_threshold_db,
-            std::time::Duration::from_millis(runtime.vad_hold_ms),
-        ));
+        let (vad_tx, vad_rx) = tokio::sync::watch::channel(runtime.vad_settings());
+        recorder.configure(runtime.record_settings());
         let (rediscover_tx, mut rediscover_rx) = tokio::sync::watch::channel(false);
+        let (assigned_id_tx, assigned_id_rx) = tokio::sync::watch::channel(None::<String>);
+        let (next_tx, next_rx) = tokio::sync::watch::channel(NextServer::Same);
+        if server.instance.is_some() {
+            let watched = server.clone();
+            let watch_binding = binding.clone();
+            let next_tx = next_tx.clone();
+            let rediscover_tx = rediscover_tx.clone();
+            std::thread::spawn(move || {
+                match discovery::watch_server(&watched, &watch_binding, || next_tx.is_closed()) {
+                    Ok(Some(moved)) => {
+                        info!(
+                            "server moved from {} to {}, reconnecting",
+                            watched.base_url, moved.base_url
+                        );
+                        let _ = next_tx.send(NextServer::Moved(Box::new(moved)));
+                        let _ = rediscover_tx.send(true);
+                    }
+                    Ok(None) => {}
+                    Err(err) => warn!("mDNS monitoring stopped: {:#}", err),
+                }
+            });
+        }
+        // On a standby, go back once the primary answers again steadily.
+        if current > 0 {
+            let primary = servers[0].clone();
+            let next_tx = next_tx.clone();
+            let rediscover_tx = rediscover_tx.clone();
+            let tls = config.server_tls.settings();
+            let local = binding.local_addrs();
+            tokio::spawn(async move {
+                let mut answered = 0;
+                while !next_tx.is_closed() {
+                    tokio::time::sleep(FAILBACK_PROBE_INTERVAL).await;
+                    if server_api::probe(&primary, &tls, &local).await {
+                        answered += 1;
+                    } else {
+                        answered = 0;
+                    }
+                    if answered >= FAILBACK_PROBES && !next_tx.is_closed() {
+                        info!(
+                            "primary server {} is answering again, failing back",
+                            primary.base_url
+                        );
+                        let _ = next_tx.send(NextServer::Failback);
+                        let _ = rediscover_tx.send(true);
+                        return;
+                    }
+                }
+            });
+        }
 
-        let status = stream::StatusHandle::new("", "");
-        health::spawn(status.clone());
+        let (local_tx, mut local_rx) = tokio::sync::watch::channel(local.clone());
+        {
+            let mut reloads = reloads.clone();
+            let mut session_config = config.clone();
+            let next_tx = next_tx.clone();
+            let rediscover_tx = rediscover_tx.clone();
+            let path = path.clone();
+            tokio::spawn(async move {
+                loop {
+                    let fresh = tokio::select! {
+                        fresh = reloads.next() => fresh,
+                        _ = next_tx.closed() => return,
+                    };
+                    let changes = reload::Changes::between(&session_config, &fresh);
+                    let restart = changes.restart();
+                    if !restart.is_empty() {
+                        warn!(
+                            "{} changed in {}; restart the bridge to apply it",
+                            restart.join(", "),
+                            path.display()
+                        );
+                    }
+                    if changes.session() {
+                        info!("config changed the server session, registering again");
+                        let _ = next_tx.send(NextServer::Reload);
+                        let _ = rediscover_tx.send(true);
+                        return;
+                    }
+                    if changes.local() {
+                        let _ = local_tx.send(LocalSettings::new(&fresh));
+                    }
+                    session_config = fresh;
+                }
+            });
+        }
+
+        runtime.publish_assignment(&status);
 
         let status_api = api.clone();
+        let log = log.clone();
         let bridge_id = config.bridge_id.clone();
         let status_handle = status.clone();
         let rediscover_rx_status = rediscover_rx.clone();
         let rediscover_tx_status = rediscover_tx.clone();
+        let next_tx_status = next_tx.clone();
+        let identity_binding = binding.clone();
+        let status_redactor = redactor.clone();
+        let status_shutdown = shutdown.clone();
+        let update_public_key = config.update_public_key.clone();
+        let devices_handle = std::sync::Arc::new(std::sync::Mutex::new(capture_devices.clone()));
+        let status_devices = devices_handle.clone();
+        let status_recorder = recorder.clone();
+        let session_spool = spool.clone();
+        let (control_tx, mut control_rx) = tokio::sync::mpsc::channel(8);
+        let control_api = api.clone();
+        let control_bridge_id = config.bridge_id.clone();
+        let control_local = binding.local_addrs();
+        if protocol >= server_api::CONTROL_CHANNEL_VERSION {
+            tokio::spawn(async move {
+                control_api
+                    .follow_control(&control_bridge_id, &control_local, control_tx)
+                    .await
+            });
+        }
         tokio::spawn(async move {
             let mut runtime = runtime;
+            let mut local = local;
+            let mut last_response = last_response;
             let mut last_devices_hash = None;
             let mut devices = capture_devices;
+            let spool = session_spool;
             let mut failures = 0u32;
+            let mut status_every = status_every;
+            let mut identity = identity;
+            let mut identity_changed = false;
+            let mut identity_checked = Instant::now();
+            let mut server_boot_id = server_boot_id;
+            let mut config_revision = config_revision;
+            let mut calibration_id: Option<String> = None;
+            let mut black_box_id = black_box_id;
+            let mut errors_id: Option<String> = None;
+            let mut send_errors = false;
+            let mut clock = clock::ClockTracker::default();
+            let mut telemetry = telemetry::TelemetrySampler::default();
+            let mut next_post = tokio::time::Instant::now();
+            let mut pushed = None;
             loop {
                 if *rediscover_rx_status.borrow() {
                     break;
                 }
-                let mut snapshot = status_handle.bridge_status();
-                let current_hash = hash_capture_devices(&devices);
-                if last_devices_hash != Some(current_hash) {
-                    snapshot.capture_devices = Some(devices.clone());
-                    last_devices_hash = Some(current_hash);
+                if let Some((result, apply)) = status_handle.poll_calibration() {
+                    info!(
+                        "noise floor calibration {}: noise_floor_db={:?}, proposed_threshold_db={:?}",
+                        result.state, result.noise_floor_db, result.proposed_threshold_db
+                    );
+                    if let (true, Some(threshold)) = (apply, result.proposed_threshold_db) {
+                        runtime.calibrated_threshold_db = Some(threshold);
+                        status_handle.mark_calibration_applied();
+                        let _ = vad_tx.send(runtime.vad_settings());
+                        let _ = config_tx.send(runtime.clone());
+                    }
+                }
+                if identity_checked.elapsed() >= IDENTITY_REFRESH_INTERVAL {
+                    identity_checked = Instant::now();
+                    if let Ok(next) = local_identity(&identity_binding) {
+                        if next != identity {
+                            info!(
+                                "identity changed: hostname={}, ip={}, mac={}",
+                                next.hostname, next.ip, next.mac
+                            );
+                            identity = next;
+                            identity_changed = true;
+                        }
+                    }
                 }
-                match status_api.post_status(&bridge_id, &snapshot).await {
-                    Ok(update) => {
+                // Pushed config is handled like a status reply, without a post.
+                let posted = pushed.is_none();
+                let mut unsent = None;
+                let result = match pushed.take() {
+                    Some(update) => Ok((Some(update), None)),
+                    None => {
+                        let mut snapshot = status_handle.bridge_status();
+                        let current_hash = hash_capture_devices(&devices);
+                        if last_devices_hash != Some(current_hash) {
+                            snapshot.capture_devices = Some(status_redactor.devices(&devices));
+                            last_devices_hash = Some(current_hash);
+                        }
+                        if identity_changed {
+                            snapshot.hostname = Some(status_redactor.hostname(&identity.hostname));
+                            snapshot.ip = Some(status_redactor.ip(&identity.ip));
+                            snapshot.ips = Some(
+                                identity
+                                    .ips
+                                    .iter()
+                                    .map(|ip| status_redactor.ip(ip))
+                                    .collect(),
+                            );
+                            snapshot.mac = Some(status_redactor.mac(&identity.mac));
+                        }
+                        snapshot.redaction = status_redactor.policy();
+                        snapshot.overrides = local.overrides.report();
+                        let system = telemetry.sample();
+                        snapshot.uptime_secs = system.uptime_secs;
+                        snapshot.cpu_percent = system.cpu_percent;
+                        snapshot.rss_bytes = system.rss_bytes;
+                        snapshot.soc_temp_c = system.soc_temp_c;
+                        snapshot.throttled = system.throttled;
+                        if send_errors {
+                            snapshot.recent_errors = Some(status_handle.recent_errors());
+                        }
+                        snapshot.config_revision = config_revision.clone();
+                        let sent_ms = timestamp::now_unix_ms();
+                        let result = status_api
+                            .post_status(&bridge_id, &snapshot)
+                            .await
+                            .map(|update| (update, Some(sent_ms)));
+                        if result.is_err() {
+                            unsent = Some(snapshot);
+                        }
+                        result
+                    }
+                };
+                match result {
+                    // 304: the config applied last is still current.
+                    Ok((None, _)) => {
                         failures = 0;
+                        identity_changed = false;
+                        send_errors = false;
+                    }
+                    Ok((Some(mut update), sent_ms)) => {
+                        if posted {
+                            failures = 0;
+                            identity_changed = false;
+                            send_errors = false;
+                        }
+                        let server_time = update
+                            .server_time
+                            .as_deref()
+                            .filter(|_| !status_api.is_replay());
+                        if let (Some(sent_ms), Some(server_ms)) =
+                            (sent_ms, server_time.and_then(timestamp::parse_unix_ms))
+                        {
+                            let report =
+                                clock.observe(sent_ms, timestamp::now_unix_ms(), server_ms);
+                            status_handle.set_clock(Some(report));
+                        }
+                        let boot_id = update.server_boot_id.clone();
+                        if boot_id.is_some()
+                            && server_boot_id.is_some()
+                            && boot_id != server_boot_id
+                        {
+                            info!(
+                                "server restart detected (boot id {:?}), re-registering",
+                                boot_id
+                            );
+                            let register = register_request(
+                                &bridge_id,
+                                &identity,
+                                &devices,
+                                capture_report.as_ref(),
+                                &status_redactor,
+                                &capabilities,
+                            );
+                            match status_api.register_bridge(&register).await {
+                                Ok(config) => {
+                                    last_devices_hash = Some(hash_capture_devices(&devices));
+                                    server_boot_id = boot_id;
+                                    update = config;
+                                }
+                                Err(err) => warn!("re-registration failed: {}", err),
+                            }
+                        } else if boot_id.is_some() {
+                            server_boot_id = boot_id;
+                        }
+                        if let Some(assigned) = update
+                            .bridge_id
+                            .as_ref()
+                            .filter(|id| **id != bridge_id && valid_bridge_id(id))
+                        {
+                            info!(
+                                "server assigned bridge id {}, restarting the session",
+                                assigned
+                            );
+                            let _ = assigned_id_tx.send(Some(assigned.clone()));
+                            let _ = rediscover_tx_status.send(true);
+                            break;
+                        }
+                        if update.config_revision.is_some() {
+                            config_revision = update.config_revision.clone();
+                        }
+                        if let Some(command) = update.vad_calibrate.as_ref() {
+                            if calibration_id.as_ref() != Some(&command.id) {
+                                calibration_id = Some(command.id.clone());
+                                let seconds =
+                                    command.seconds.unwrap_or(calibration::DEFAULT_SECONDS);
+                                info!("starting noise floor calibration ({} s)", seconds);
+                                status_handle.start_calibration(
+                                    Duration::from_secs(seconds),
+                                    command.apply.unwrap_or(false),
+                                );
+                            }
+                        }
+                        if update.black_box_dump.is_some() && update.black_box_dump != black_box_id
+                        {
+                            black_box_id = update.black_box_dump.clone();
+                            blackbox::dump_and_log("server");
+                        }
+                        update::apply_remote(
+                            &update,
+                            &status_handle,
+                            update_public_key.as_deref(),
+                            &status_shutdown,
+                        );
+                        if update.report_errors.is_some() && update.report_errors != errors_id {
+                            errors_id = update.report_errors.clone();
+                            send_errors = true;
+                            info!(
+                                "server asked for recent errors, sending them with the next status"
+                            );
+                        }
+                        update.keep_missing(Some(&last_response));
+                        last_response = update.clone();
+                        let update = local.apply(update);
+                        if update.status_interval_secs.is_some() {
+                            let next = status_interval(update.status_interval_secs);
+                            if next != status_every {
+                                info!("status interval changed to {:?}", next);
+                                status_every = next;
+                            }
+                        }
+                        if let Some(log) = log.as_ref() {
+                            log.apply_server(
+                                update.log_level.as_deref(),
+                                update.log_target.as_deref(),
+                                update.log_file.as_deref(),
+                            );
+                        }
                         if let Some(updated) = runtime.update(update) {
                             info!(
-                                "config update: assigned_input_id={:?}, capture_device={:?}, vad_threshold_db={}, vad_hold_ms={}, target_rate={}, resampler={}",
-                                updated.assigned_input_id,
+                                "config update: input={}, zone={:?}, capture_device={:?}, vad_enabled={}, vad_mode={}, vad_threshold_db={}, vad_threshold_open_db={:?}, vad_threshold_close_db={:?}, vad_hold_ms={}, track_detect={}, target_rate={}, resampler={}",
+                                updated.input_label(),
+                                updated.zone_name,
                                 updated.capture_device,
+                                updated.vad_enabled,
+                                updated.vad_mode.label(),
                                 updated.vad_threshold_db,
+                                updated.vad_threshold_open_db,
+                                updated.vad_threshold_close_db,
                                 updated.vad_hold_ms,
+                                updated.track_detect.label(),
                                 updated.target_rate,
                                 updated.resampler.label()
                             );
-                            let _ = vad_tx.send((
-                                updated.vad_threshold_db,
-                                std::time::Duration::from_millis(updated.vad_hold_ms),
-                            ));
+                            updated.publish_assignment(&status_handle);
+                            let _ = vad_tx.send(updated.vad_settings());
+                            status_recorder.configure(updated.record_settings());
                             let _ = config_tx.send(updated);
                         }
                     }
                     Err(err) => {
+                        if let Some(snapshot) = &unsent {
+                            spool.push(snapshot).await;
+                        }
+                        if let Some(code) = server_api::registration_lost(&err) {
+                            // The server dropped the bridge or its session;
+                            // registering again restores it.
+                            warn!(
+                                "server answered the status post with {}, re-registering",
+                                code
+                            );
+                            let register = register_request(
+                                &bridge_id,
+                                &identity,
+                                &devices,
+                                capture_report.as_ref(),
+                                &status_redactor,
+                                &capabilities,
+                            );
+                            match status_api.register_bridge(&register).await {
+                                Ok(config) => {
+                                    last_devices_hash = Some(hash_capture_devices(&devices));
+                                    pushed = Some(config);
+                                    continue;
+                                }
+                                Err(err) => warn!("re-registration failed: {}", err),
+                            }
+                        } else {
+                            tracing::debug!("status post failed: {}", err);
+                        }
                         failures = failures.saturating_add(1);
-                        tracing::debug!("status post failed: {}", err);
-                        if failures >= 3 {
-                            warn!("status posts failed repeatedly, re-discovering server");
+                        if failures >= FAILOVER_AFTER_FAILURES {
+                            warn!("status posts failed repeatedly, trying the next server");
+                            let _ = next_tx_status.send(NextServer::Failover);
                             let _ = rediscover_tx_status.send(true);
                             break;
                         }
                     }
                 }
-                tokio::time::sleep(Duration::from_secs(5)).await;
+                if posted && failures == 0 && !spool.is_empty() {
+                    let backlog = spool.entries();
+                    match status_api.post_status_batch(&bridge_id, &backlog).await {
+                        Ok(()) => {
+                            info!("uploaded {} spooled statuses", backlog.len());
+                            spool.remove(backlog.len()).await;
+                        }
+                        Err(err) => tracing::debug!("uploading spooled statuses failed: {:#}", err),
+                    }
+                }
+                if posted {
+                    next_post = tokio::time::Instant::now() + status_delay(status_every, failures);
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep_until(next_post) => {}
+                    Some(update) = control_rx.recv() => {
+                        pushed = Some(update);
+                        continue;
+                    }
+                    Ok(()) = local_rx.changed() => {
+                        local = local_rx.borrow_and_update().clone();
+                        info!(
+                            "local settings changed: capture_device={:?}, overrides={}",
+                            local.device,
+                            local.overrides.report().unwrap_or_default()
+                        );
+                        pushed = Some(last_response.clone());
+                        continue;
+                    }
+                }
                 if let Ok(new_devices) = audio::list_input_device_details() {
+                    if let Ok(mut shared) = status_devices.lock() {
+                        *shared = new_devices.clone();
+                    }
                     devices = new_devices;
                 }
             }
@@ -160,14 +1021,20 @@ async fn run() -> Result<()> {
 
         let mut backoff = Backoff::new();
         loop {
+            if shutdown.is_requested() {
+                report_offline(&api, &config.bridge_id, &status).await;
+                return Ok(());
+            }
             if *rediscover_rx.borrow() {
                 break;
             }
             let current = config_rx.borrow().clone();
-            if !current.is_ready() {
+            local_api.set_runtime(current.api_view());
+            if !current.is_ready(local_only) {
                 status.set_state("IDLE");
                 tokio::select! {
                     _ = config_rx.changed() => {}
+                    _ = shutdown.requested() => {}
                     _ = rediscover_rx.changed() => {
                         if *rediscover_rx.borrow() {
                             break;
@@ -176,26 +1043,73 @@ async fn run() -> Result<()> {
                 }
                 continue;
             }
-            let ingest = match current.ingest_target() {
-                Some(target) => target,
-                None => {
-                    status.set_state("IDLE");
-                    tokio::select! {
-                        _ = config_rx.changed() => {}
-                        _ = rediscover_rx.changed() => {
-                            if *rediscover_rx.borrow() {
-                                break;
-                            }
+            let mut ingest = if local_only {
+                Vec::new()
+            } else {
+                current.ingest_targets()
+            };
+            ingest.extend(snapcast.clone().map(stream::IngestTarget::Snapcast));
+            ingest.extend(
+                multicast.map(|(group, ttl)| stream::IngestTarget::UdpMulticast { group, ttl }),
+            );
+            if ingest.is_empty() {
+                status.set_state("IDLE");
+                tokio::select! {
+                    _ = config_rx.changed() => {}
+                    _ = shutdown.requested() => {}
+                    _ = rediscover_rx.changed() => {
+                        if *rediscover_rx.borrow() {
+                            break;
                         }
                     }
-                    continue;
                 }
+                continue;
+            }
+            let assigned_device = current.capture_device.clone().unwrap_or_default();
+            status.set_device(&assigned_device);
+            if let Some(preset) = presets::for_device(&assigned_device) {
+                info!("using {} capture preset", preset.name);
+            }
+            let capture_device = match devices_handle.lock() {
+                Ok(devices) => redactor.resolve_device(&assigned_device, &devices),
+                Err(_) => assigned_device.clone(),
             };
-            let capture_device = current.capture_device.clone().unwrap_or_default();
-            status.set_device(&capture_device);
-            status.set_ingest(&current.ingest_label());
+            if !config.sources.is_empty() {
+                status.set_device(&config.sources.label());
+            }
+            let ingest_label = ingest
+                .iter()
+                .map(stream::IngestTarget::label)
+                .collect::<Vec<_>>()
+                .join(", ");
+            status.set_ingest(&ingest_label);
+            info!(
+                "streaming input {} to {} (framing={})",
+                current.input_label(),
+                ingest_label,
+                current.ingest_framing.label()
+            );
+            status.set_ceiling_enabled(current.output_ceiling_db.is_some());
 
-            match audio::start_capture(&capture_device, current.target_rate, current.resampler) {
+            let session = if config.sources.is_empty() {
+                audio::start_capture(
+                    &capture_device,
+                    current.target_rate,
+                    current.resampler,
+                    current.capture_options,
+                    current.output_settings(),
+                )
+            } else {
+                sources::start_capture(
+                    &config.sources,
+                    current.target_rate,
+                    current.resampler,
+                    current.capture_options,
+                    current.output_settings(),
+                    status.clone(),
+                )
+            };
+            match session {
                 Ok(session) => {
                     backoff.reset();
                     status.set_capture_info(
@@ -214,19 +1128,28 @@ async fn run() -> Result<()> {
                     let audio::CaptureSession {
                         receiver,
                         error_receiver,
-                        stream,
+                        streams,
                         observed_rate,
+                        counters,
+                        flush,
                         ..
                     } = session;
-                    let _stream_guard = stream;
+                    let capture_streams = streams;
                     let observed_handle = observed_rate.clone();
                     let status_handle = status.clone();
-                    tokio::spawn(async move {
+                    // Dropped when the session ends, however it ends, which
+                    // aborts the reporter with it.
+                    let mut session_tasks = tokio::task::JoinSet::new();
+                    session_tasks.spawn(async move {
+                        let mut counters_seen = audio::CounterSnapshot::default();
                         loop {
                             let observed = observed_handle.lock().ok().and_then(|value| *value);
                             if let Some(rate) = observed {
                                 status_handle.set_observed_rate(rate);
                             }
+                            let snapshot = counters.snapshot();
+                            status_handle.record_capture(&snapshot.since(&counters_seen));
+                            counters_seen = snapshot;
                             tokio::time::sleep(Duration::from_secs(2)).await;
                         }
                     });
@@ -234,16 +1157,32 @@ async fn run() -> Result<()> {
                         ingest,
                         rx: receiver,
                         err_rx: error_receiver,
-                        threshold_db: current.vad_threshold_db,
-                        hold_duration: std::time::Duration::from_millis(current.vad_hold_ms),
+                        vad: current.vad_settings(),
                         vad_updates: Some(vad_rx.clone()),
                         status: status.clone(),
                         output_rate: current.target_rate,
+                        tee_path: tee_path(&config, &current),
+                        framing: current.ingest_framing,
+                        socket: sink::SocketOptions {
+                            local: binding.local_addrs(),
+                            ..current.ingest_socket
+                        },
+                        reconnect_buffer: Duration::from_millis(current.ingest_reconnect_buffer_ms),
+                        packet: Duration::from_millis(current.ingest_packet_ms),
+                        hls: hls.clone(),
+                        monitor: monitor.clone(),
+                        recorder: Some(recorder.clone()),
                     };
 
                     let current_key = current.stream_key();
+                    let session_span = tracing::info_span!(
+                        "capture_session",
+                        device = %capture_device,
+                        rate = current.target_rate,
+                        input = %current.input_label(),
+                    );
                     let mut stream_task =
-                        tokio::spawn(async move { stream::stream_audio(params).await });
+                        tokio::spawn(stream::stream_audio(params).instrument(session_span));
                     tokio::select! {
                         result = &mut stream_task => {
                             match result.context("stream task join")? {
@@ -258,9 +1197,17 @@ async fn run() -> Result<()> {
                         _ = config_rx.changed() => {
                             let next = config_rx.borrow().clone();
                             if next.stream_key() != current_key {
-                                stream_task.abort();
+                                drain_stream(capture_streams, flush, stream_task).await;
                             }
                         }
+                        _ = shutdown.requested() => {
+                            info!("draining the stream before exit");
+                            drain_stream(capture_streams, flush, stream_task).await;
+                        }
+                        _ = local_api.restart_requested() => {
+                            info!("restarting capture");
+                            drain_stream(capture_streams, flush, stream_task).await;
+                        }
                         _ = rediscover_rx.changed() => {
                             if *rediscover_rx.borrow() {
                                 stream_task.abort();
@@ -275,6 +1222,8 @@ async fn run() -> Result<()> {
                     warn!("capture failed: {}", err);
                     tokio::select! {
                         _ = tokio::time::sleep(backoff.next_delay()) => {}
+                        _ = shutdown.requested() => {}
+                        _ = local_api.restart_requested() => {}
                         _ = rediscover_rx.changed() => {
                             if *rediscover_rx.borrow() {
                                 break;
@@ -284,96 +1233,474 @@ async fn run() -> Result<()> {
                 }
             }
         }
+        let assigned = assigned_id_rx.borrow().clone();
+        if let Some(assigned) = assigned {
+            adopt_bridge_id(&mut config, &path, writable_config, &assigned, &spool).await;
+        }
+        next = next_rx.borrow().clone();
+        if matches!(next, NextServer::Failback | NextServer::Reload) {
+            // Let the standby, or the server left behind, drop the bridge
+            // right away.
+            report_offline(&api, &config.bridge_id, &status).await;
+        }
+        if !shutdown.is_requested() && offline_spool.is_none() {
+            status.set_state("OFFLINE");
+            offline_spool = Some(spawn_offline_spool(
+                spool.clone(),
+                status.clone(),
+                status_every,
+            ));
+        }
     }
 }
 
-fn print_usage() {
-    eprintln!("Usage:");
-    eprintln!("  lox-linein-bridge [--log-level <level>]");
-    eprintln!("  lox-linein-bridge [--log-level <level>] install");
-    eprintln!("  lox-linein-bridge --help");
-    eprintln!("  lox-linein-bridge --version");
-    eprintln!();
-    eprintln!("Examples:");
-    eprintln!("  lox-linein-bridge --log-level info run");
-    eprintln!("  lox-linein-bridge install");
-    eprintln!("  lox-linein-bridge run");
+/// Switches to a bridge id the server assigned in place of ours and keeps it
+/// in the config file, so the next start registers under it. The id ends up
+/// in file names, so anything but letters, digits, `.`, `_` and `-` is
+/// refused.
+async fn adopt_bridge_id(
+    config: &mut config::Config,
+    path: &std::path::Path,
+    writable: bool,
+    assigned: &str,
+    spool: &spool::Spool,
+) {
+    if !valid_bridge_id(assigned) {
+        warn!(
+            "server assigned the invalid bridge id {:?}, keeping {}",
+            assigned, config.bridge_id
+        );
+        return;
+    }
+    warn!(
+        "server replaced bridge id {} with {}",
+        config.bridge_id, assigned
+    );
+    server_cache::rename(path, &config.bridge_id, assigned);
+    spool.rename(path, assigned).await;
+    config.bridge_id = assigned.to_string();
+    if config::bridge_id_from_env() {
+        warn!("set LOX_LINEIN_BRIDGE_BRIDGE_ID={} to keep it", assigned);
+        return;
+    }
+    if !writable {
+        warn!(
+            "set bridge_id = {:?} in {} to keep it",
+            assigned,
+            path.display()
+        );
+        return;
+    }
+    match config::save_config(config, path) {
+        Ok(()) => info!("saved bridge id {} to {}", assigned, path.display()),
+        Err(err) => warn!("saving the bridge id failed: {:#}", err),
+    }
 }
 
-fn parse_args() -> Result<(Option<String>, Option<String>)> {
-    let mut args = std::env::args().skip(1);
-    let mut command = None;
-    let mut log_level = None;
+fn valid_bridge_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
 
-    while let Some(arg) = args.next() {
-        if arg == "--log-level" {
-            let level = args
-                .next()
-                .ok_or_else(|| anyhow::anyhow!("--log-level requires a value"))?;
-            log_level = Some(level);
-            continue;
-        }
-        if let Some(level) = arg.strip_prefix("--log-level=") {
-            log_level = Some(level.to_string());
-            continue;
+/// Stops capturing, then lets the stream send the resampler tail and what
+/// it has queued.
+async fn drain_stream(
+    capture_streams: Vec<cpal::Stream>,
+    flush: audio::CaptureFlush,
+    mut stream_task: tokio::task::JoinHandle<Result<()>>,
+) {
+    drop(capture_streams);
+    flush.finish();
+    match tokio::time::timeout(STREAM_DRAIN_TIMEOUT, &mut stream_task).await {
+        Ok(Ok(Err(err))) => warn!("streaming stopped: {}", err),
+        Ok(_) => {}
+        Err(_) => stream_task.abort(),
+    }
+}
+
+/// Discovers in the background after a start from the cache and refreshes
+/// the cache with the result, which the bridge switches to when its session
+/// ends.
+fn spawn_background_discovery(
+    config: config::Config,
+    binding: net::Binding,
+    path: std::path::PathBuf,
+) -> tokio::sync::oneshot::Receiver<Vec<discovery::DiscoveredServer>> {
+    let (found_tx, found_rx) = tokio::sync::oneshot::channel();
+    tokio::task::spawn_blocking(move || {
+        let discovered = tracing::info_span!("discovery")
+            .in_scope(|| discovery::discover_servers(&config, &binding));
+        match discovered {
+            Ok(found) => {
+                server_cache::store(&path, &config.bridge_id, &found);
+                let _ = found_tx.send(found);
+            }
+            Err(err) => tracing::debug!("background discovery failed: {}", err),
         }
-        if command.is_none() {
-            command = Some(arg);
+    });
+    found_rx
+}
+
+/// Keeps spooling snapshots while the bridge looks for a server, so the
+/// backlog covers the whole outage and not just the failed posts before
+/// failing over. Aborted once a registration succeeds.
+fn spawn_offline_spool(
+    spool: spool::Spool,
+    status: stream::StatusHandle,
+    every: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(status_delay(every, 0)).await;
+            spool.push(&status.bridge_status()).await;
         }
+    })
+}
+
+/// Final status post and goodbye on shutdown, so the server doesn't wait
+/// for missed status updates to notice the bridge is gone.
+async fn report_offline(
+    api: &server_api::ServerApi,
+    bridge_id: &str,
+    status: &stream::StatusHandle,
+) {
+    status.set_state("OFFLINE");
+    let snapshot = status.bridge_status();
+    match tokio::time::timeout(
+        OFFLINE_REPORT_TIMEOUT,
+        api.post_status(bridge_id, &snapshot),
+    )
+    .await
+    {
+        Ok(Ok(_)) => info!("reported OFFLINE to the server"),
+        Ok(Err(err)) => warn!("reporting OFFLINE failed: {:#}", err),
+        Err(_) => warn!("reporting OFFLINE timed out"),
+    }
+    match tokio::time::timeout(OFFLINE_REPORT_TIMEOUT, api.unregister_bridge(bridge_id)).await {
+        Ok(Ok(())) => info!("unregistered from the server"),
+        Ok(Err(err)) => warn!("unregistering failed: {:#}", err),
+        Err(_) => warn!("unregistering timed out"),
     }
+}
 
-    Ok((command, log_level))
+/// `dump`: asks running bridges to write their black box.
+fn run_dump() -> Result<()> {
+    let pids = blackbox::signal_running()?;
+    if pids.is_empty() {
+        anyhow::bail!("{}", i18n::t!("dump-no-bridge"));
+    }
+    for pid in pids {
+        println!("{}", i18n::t!("dump-requested", pid = pid));
+    }
+    Ok(())
 }
 
 #[derive(Debug, Clone)]
 struct RuntimeConfig {
     assigned_input_id: Option<String>,
+    input_name: Option<String>,
+    zone_name: Option<String>,
     ingest_ws_url: Option<String>,
     ingest_tcp_host: Option<String>,
     ingest_tcp_port: Option<u16>,
+    ingest_wav_header: bool,
+    ingest_tls: bool,
+    ingest_tls_settings: tls::TlsSettings,
+    ingest_framing: framing::IngestFraming,
+    ingest_rtp_addr: Option<String>,
+    ingest_rtp_payload_type: Option<u8>,
+    ingest_rtp_ssrc: Option<u32>,
+    ingest_extra: Vec<models::IngestTargetConfig>,
+    ingest_socket: sink::SocketOptions,
+    ingest_reconnect_buffer_ms: u64,
+    ingest_packet_ms: u64,
+    record_enabled: bool,
+    record_dir: Option<String>,
+    record_format: recorder::RecordFormat,
+    record_max_secs: u64,
+    record_max_mb: u64,
+    record_keep_files: usize,
+    record_keep_days: u64,
     capture_device: Option<String>,
+    vad_enabled: bool,
+    vad_mode: vad::VadMode,
     vad_threshold_db: f32,
+    /// Threshold from an applied noise floor calibration; cleared when the
+    /// server sends a new `vad_threshold_db` or `vad_threshold_open_db`.
+    calibrated_threshold_db: Option<f32>,
+    vad_threshold_open_db: Option<f32>,
+    vad_threshold_close_db: Option<f32>,
+    vad_attack_ms: u64,
     vad_hold_ms: u64,
+    vad_preroll_ms: u64,
+    vad_silence_fill: bool,
+    track_detect: track::TrackDetectMode,
     target_rate: u32,
     resampler: audio::ResamplerMode,
+    input_gain_db: f32,
+    output_ceiling_db: Option<f32>,
+    capture_options: audio::CaptureOptions,
 }
 
 impl RuntimeConfig {
     fn from_response(response: models::BridgeConfigResponse) -> Self {
+        let response = presets::apply(response);
+        let ingest_socket = socket_options(&response);
         Self {
-            assigned_input_id: response.assigned_input_id,
-            ingest_ws_url: response.ingest_ws_url,
-            ingest_tcp_host: response.ingest_tcp_host,
-            ingest_tcp_port: response.ingest_tcp_port,
-            capture_device: response.capture_device,
+            assigned_input_id: response.assigned_input_id.flatten(),
+            input_name: response.assigned_input_name.flatten(),
+            zone_name: response.assigned_zone_name.flatten(),
+            ingest_ws_url: response.ingest_ws_url.flatten(),
+            ingest_tcp_host: response.ingest_tcp_host.flatten(),
+            ingest_tcp_port: response.ingest_tcp_port.flatten(),
+            ingest_wav_header: response.ingest_wav_header.unwrap_or(false),
+            ingest_tls: response.ingest_tls.unwrap_or(false),
+            ingest_tls_settings: tls::TlsSettings {
+                ca: response.ingest_tls_ca.flatten(),
+                pin_sha256: response.ingest_tls_pin_sha256.flatten(),
+                client_cert: response.ingest_tls_client_cert.flatten(),
+                client_key: response.ingest_tls_client_key.flatten(),
+                skip_verify: false,
+            },
+            ingest_framing: parse_framing(response.ingest_framing.as_deref()),
+            ingest_rtp_addr: response.ingest_rtp_addr.flatten(),
+            ingest_rtp_payload_type: response.ingest_rtp_payload_type.flatten(),
+            ingest_rtp_ssrc: response.ingest_rtp_ssrc.flatten(),
+            ingest_extra: parse_ingest_targets(response.ingest_targets),
+            ingest_socket,
+            ingest_reconnect_buffer_ms: response
+                .ingest_reconnect_buffer_ms
+                .unwrap_or(DEFAULT_RECONNECT_BUFFER_MS),
+            ingest_packet_ms: packet_ms(response.ingest_packet_ms),
+            record_enabled: response.record_enabled.unwrap_or(false),
+            record_dir: response.record_dir.flatten(),
+            record_format: parse_record_format(response.record_format.as_deref()),
+            record_max_secs: response.record_max_secs.unwrap_or(DEFAULT_RECORD_MAX_SECS),
+            record_max_mb: response.record_max_mb.unwrap_or(0),
+            record_keep_files: response.record_keep_files.unwrap_or(0),
+            record_keep_days: response
+                .record_keep_days
+                .unwrap_or(DEFAULT_RECORD_KEEP_DAYS),
+            capture_device: response.capture_device.flatten(),
+            vad_enabled: response.vad_enabled.unwrap_or(true),
+            vad_mode: parse_vad_mode(response.vad_mode.as_deref()),
             vad_threshold_db: response.vad_threshold_db.unwrap_or(-45.0),
+            calibrated_threshold_db: None,
+            vad_threshold_open_db: response.vad_threshold_open_db.flatten(),
+            vad_threshold_close_db: response.vad_threshold_close_db.flatten(),
+            vad_attack_ms: response.vad_attack_ms.unwrap_or(0),
             vad_hold_ms: response.vad_hold_ms.unwrap_or(2000),
+            vad_preroll_ms: response.vad_preroll_ms.unwrap_or(500),
+            vad_silence_fill: response.vad_silence_fill.unwrap_or(false),
+            track_detect: parse_track_detect(response.track_detect.as_deref()),
             target_rate: response.ingest_sample_rate.unwrap_or(48_000),
             resampler: parse_resampler(response.ingest_resampler.as_deref()),
+            input_gain_db: response.input_gain_db.unwrap_or(0.0),
+            output_ceiling_db: response.output_ceiling_db.flatten(),
+            capture_options: audio::CaptureOptions {
+                exclusive: response.capture_exclusive.unwrap_or(false),
+                period_frames: response.capture_period_frames.flatten(),
+            },
         }
     }
 
     fn update(&mut self, response: models::BridgeConfigResponse) -> Option<Self> {
+        let response = presets::apply(response);
         let mut changed = false;
-        if response.assigned_input_id != self.assigned_input_id {
-            self.assigned_input_id = response.assigned_input_id;
-            changed = true;
+        if let Some(id) = response.assigned_input_id {
+            if id != self.assigned_input_id {
+                self.assigned_input_id = id;
+                changed = true;
+            }
         }
-        if response.ingest_ws_url != self.ingest_ws_url {
-            self.ingest_ws_url = response.ingest_ws_url;
-            changed = true;
+        if let Some(name) = response.assigned_input_name {
+            if name != self.input_name {
+                self.input_name = name;
+                changed = true;
+            }
+        }
+        if let Some(name) = response.assigned_zone_name {
+            if name != self.zone_name {
+                self.zone_name = name;
+                changed = true;
+            }
         }
-        if response.ingest_tcp_host != self.ingest_tcp_host {
-            self.ingest_tcp_host = response.ingest_tcp_host;
-            changed = true;
+        if let Some(url) = response.ingest_ws_url {
+            if url != self.ingest_ws_url {
+                self.ingest_ws_url = url;
+                changed = true;
+            }
         }
-        if response.ingest_tcp_port != self.ingest_tcp_port {
-            self.ingest_tcp_port = response.ingest_tcp_port;
-            changed = true;
+        if let Some(host) = response.ingest_tcp_host {
+            if host != self.ingest_tcp_host {
+                self.ingest_tcp_host = host;
+                changed = true;
+            }
         }
-        if response.capture_device != self.capture_device {
-            self.capture_device = response.capture_device;
-            changed = true;
+        if let Some(port) = response.ingest_tcp_port {
+            if port != self.ingest_tcp_port {
+                self.ingest_tcp_port = port;
+                changed = true;
+            }
+        }
+        if let Some(wav_header) = response.ingest_wav_header {
+            if wav_header != self.ingest_wav_header {
+                self.ingest_wav_header = wav_header;
+                changed = true;
+            }
+        }
+        if let Some(tls) = response.ingest_tls {
+            if tls != self.ingest_tls {
+                self.ingest_tls = tls;
+                changed = true;
+            }
+        }
+        if let Some(ca) = response.ingest_tls_ca {
+            if ca != self.ingest_tls_settings.ca {
+                self.ingest_tls_settings.ca = ca;
+                changed = true;
+            }
+        }
+        if let Some(pin) = response.ingest_tls_pin_sha256 {
+            if pin != self.ingest_tls_settings.pin_sha256 {
+                self.ingest_tls_settings.pin_sha256 = pin;
+                changed = true;
+            }
+        }
+        if let Some(cert) = response.ingest_tls_client_cert {
+            if cert != self.ingest_tls_settings.client_cert {
+                self.ingest_tls_settings.client_cert = cert;
+                changed = true;
+            }
+        }
+        if let Some(key) = response.ingest_tls_client_key {
+            if key != self.ingest_tls_settings.client_key {
+                self.ingest_tls_settings.client_key = key;
+                changed = true;
+            }
+        }
+        if let Some(framing) = response.ingest_framing {
+            let next = parse_framing(Some(framing.as_str()));
+            if next != self.ingest_framing {
+                self.ingest_framing = next;
+                changed = true;
+            }
+        }
+        if let Some(addr) = response.ingest_rtp_addr {
+            if addr != self.ingest_rtp_addr {
+                self.ingest_rtp_addr = addr;
+                changed = true;
+            }
+        }
+        if let Some(payload_type) = response.ingest_rtp_payload_type {
+            if payload_type != self.ingest_rtp_payload_type {
+                self.ingest_rtp_payload_type = payload_type;
+                changed = true;
+            }
+        }
+        if let Some(ssrc) = response.ingest_rtp_ssrc {
+            if ssrc != self.ingest_rtp_ssrc {
+                self.ingest_rtp_ssrc = ssrc;
+                changed = true;
+            }
+        }
+        if let Some(targets) = response.ingest_targets {
+            let next = parse_ingest_targets(Some(targets));
+            if next != self.ingest_extra {
+                self.ingest_extra = next;
+                changed = true;
+            }
+        }
+        if let Some(secs) = response.ingest_keepalive_idle_secs {
+            let idle = Duration::from_secs(secs);
+            if idle != self.ingest_socket.keepalive_idle {
+                self.ingest_socket.keepalive_idle = idle;
+                changed = true;
+            }
+        }
+        if let Some(secs) = response.ingest_keepalive_interval_secs {
+            let interval = Duration::from_secs(secs);
+            if interval != self.ingest_socket.keepalive_interval {
+                self.ingest_socket.keepalive_interval = interval;
+                changed = true;
+            }
+        }
+        if let Some(count) = response.ingest_keepalive_count {
+            if count != self.ingest_socket.keepalive_count {
+                self.ingest_socket.keepalive_count = count;
+                changed = true;
+            }
+        }
+        if let Some(ms) = response.ingest_send_timeout_ms {
+            let timeout = send_timeout(ms);
+            if timeout != self.ingest_socket.send_timeout {
+                self.ingest_socket.send_timeout = timeout;
+                changed = true;
+            }
+        }
+        if let Some(buffer) = response.ingest_reconnect_buffer_ms {
+            if buffer != self.ingest_reconnect_buffer_ms {
+                self.ingest_reconnect_buffer_ms = buffer;
+                changed = true;
+            }
+        }
+        if response.ingest_packet_ms.is_some() {
+            let next = packet_ms(response.ingest_packet_ms);
+            if next != self.ingest_packet_ms {
+                self.ingest_packet_ms = next;
+                changed = true;
+            }
+        }
+        if let Some(enabled) = response.record_enabled {
+            if enabled != self.record_enabled {
+                self.record_enabled = enabled;
+                changed = true;
+            }
+        }
+        if let Some(dir) = response.record_dir {
+            if dir != self.record_dir {
+                self.record_dir = dir;
+                changed = true;
+            }
+        }
+        if let Some(format) = response.record_format {
+            let next = parse_record_format(Some(format.as_str()));
+            if next != self.record_format {
+                self.record_format = next;
+                changed = true;
+            }
+        }
+        if let Some(secs) = response.record_max_secs {
+            if secs != self.record_max_secs {
+                self.record_max_secs = secs;
+                changed = true;
+            }
+        }
+        if let Some(mb) = response.record_max_mb {
+            if mb != self.record_max_mb {
+                self.record_max_mb = mb;
+                changed = true;
+            }
+        }
+        if let Some(files) = response.record_keep_files {
+            if files != self.record_keep_files {
+                self.record_keep_files = files;
+                changed = true;
+            }
+        }
+        if let Some(days) = response.record_keep_days {
+            if days != self.record_keep_days {
+                self.record_keep_days = days;
+                changed = true;
+            }
+        }
+        if let Some(device) = response.capture_device {
+            if device != self.capture_device {
+                self.capture_device = device;
+                changed = true;
+            }
         }
         if let Some(rate) = response.ingest_sample_rate {
             if rate != self.target_rate {
@@ -388,9 +1715,66 @@ impl RuntimeConfig {
                 changed = true;
             }
         }
+        if let Some(gain) = response.input_gain_db {
+            if (gain - self.input_gain_db).abs() > f32::EPSILON {
+                self.input_gain_db = gain;
+                changed = true;
+            }
+        }
+        if let Some(ceiling) = response.output_ceiling_db {
+            if ceiling != self.output_ceiling_db {
+                self.output_ceiling_db = ceiling;
+                changed = true;
+            }
+        }
+        if let Some(exclusive) = response.capture_exclusive {
+            if exclusive != self.capture_options.exclusive {
+                self.capture_options.exclusive = exclusive;
+                changed = true;
+            }
+        }
+        if let Some(frames) = response.capture_period_frames {
+            if frames != self.capture_options.period_frames {
+                self.capture_options.period_frames = frames;
+                changed = true;
+            }
+        }
+        if let Some(enabled) = response.vad_enabled {
+            if enabled != self.vad_enabled {
+                self.vad_enabled = enabled;
+                changed = true;
+            }
+        }
+        if let Some(mode) = response.vad_mode {
+            let next = parse_vad_mode(Some(mode.as_str()));
+            if next != self.vad_mode {
+                self.vad_mode = next;
+                changed = true;
+            }
+        }
         if let Some(vad) = response.vad_threshold_db {
             if (vad - self.vad_threshold_db).abs() > f32::EPSILON {
                 self.vad_threshold_db = vad;
+                self.calibrated_threshold_db = None;
+                changed = true;
+            }
+        }
+        if let Some(open_db) = response.vad_threshold_open_db {
+            if open_db != self.vad_threshold_open_db {
+                self.vad_threshold_open_db = open_db;
+                self.calibrated_threshold_db = None;
+                changed = true;
+            }
+        }
+        if let Some(close_db) = response.vad_threshold_close_db {
+            if close_db != self.vad_threshold_close_db {
+                self.vad_threshold_close_db = close_db;
+                changed = true;
+            }
+        }
+        if let Some(attack) = response.vad_attack_ms {
+            if attack != self.vad_attack_ms {
+                self.vad_attack_ms = attack;
                 changed = true;
             }
         }
@@ -400,6 +1784,25 @@ impl RuntimeConfig {
                 changed = true;
             }
         }
+        if let Some(preroll) = response.vad_preroll_ms {
+            if preroll != self.vad_preroll_ms {
+                self.vad_preroll_ms = preroll;
+                changed = true;
+            }
+        }
+        if let Some(fill) = response.vad_silence_fill {
+            if fill != self.vad_silence_fill {
+                self.vad_silence_fill = fill;
+                changed = true;
+            }
+        }
+        if let Some(mode) = response.track_detect {
+            let next = parse_track_detect(Some(mode.as_str()));
+            if next != self.track_detect {
+                self.track_detect = next;
+                changed = true;
+            }
+        }
         if changed {
             Some(self.clone())
         } else {
@@ -407,77 +1810,395 @@ impl RuntimeConfig {
         }
     }
 
-    fn is_ready(&self) -> bool {
+    fn output_settings(&self) -> limiter::OutputSettings {
+        limiter::OutputSettings {
+            gain_db: self.input_gain_db,
+            ceiling_db: self.output_ceiling_db,
+        }
+    }
+
+    fn vad_settings(&self) -> stream::VadSettings {
+        // An applied calibration wins until the server sends a new threshold.
+        let open_db = self
+            .calibrated_threshold_db
+            .or(self.vad_threshold_open_db)
+            .unwrap_or(self.vad_threshold_db);
+        let close_db = self
+            .vad_threshold_close_db
+            .unwrap_or(open_db - VAD_HYSTERESIS_DB)
+            .min(open_db);
+        stream::VadSettings {
+            enabled: self.vad_enabled,
+            mode: self.vad_mode,
+            open_db,
+            close_db,
+            attack: Duration::from_millis(self.vad_attack_ms),
+            hold: Duration::from_millis(self.vad_hold_ms),
+            preroll: Duration::from_millis(self.vad_preroll_ms),
+            silence_fill: self.vad_silence_fill,
+            track_detect: self.track_detect,
+        }
+    }
+
+    fn record_settings(&self) -> Option<recorder::RecordSettings> {
+        if !self.record_enabled {
+            return None;
+        }
+        Some(recorder::RecordSettings {
+            dir: self
+                .record_dir
+                .clone()
+                .unwrap_or_else(|| recorder::DEFAULT_DIR.to_string())
+                .into(),
+            prefix: recorder::file_prefix(self.assigned_input_id.as_deref()),
+            format: self.record_format,
+            max_duration: Duration::from_secs(self.record_max_secs),
+            max_bytes: self.record_max_mb.saturating_mul(1024 * 1024),
+            keep_files: self.record_keep_files,
+            keep_age: Duration::from_secs(self.record_keep_days.saturating_mul(24 * 60 * 60)),
+        })
+    }
+
+    /// With a local ingest (Snapcast only) the server needn't assign an
+    /// input or ingest, just a device.
+    fn is_ready(&self, local_ingest: bool) -> bool {
+        if local_ingest {
+            return self.capture_device.is_some();
+        }
         self.assigned_input_id.is_some()
             && self.capture_device.is_some()
             && (self.ingest_ws_url.is_some()
-                || (self.ingest_tcp_host.is_some() && self.ingest_tcp_port.is_some()))
+                || self.ingest_rtp_addr.is_some()
+                || (self.ingest_tcp_host.is_some() && self.ingest_tcp_port.is_some())
+                || !self.ingest_extra.is_empty())
+    }
+
+    /// The primary ingest followed by the extra `ingest_targets`.
+    fn ingest_targets(&self) -> Vec<stream::IngestTarget> {
+        let mut targets: Vec<_> = self.ingest_target().into_iter().collect();
+        for extra in &self.ingest_extra {
+            let target = match extra.clone() {
+                models::IngestTargetConfig::Tcp {
+                    host,
+                    port,
+                    wav_header,
+                    tls,
+                } => match &self.assigned_input_id {
+                    Some(header) => stream::IngestTarget::Tcp {
+                        host,
+                        port,
+                        header: header.clone(),
+                        wav_header,
+                        tls: self.tcp_tls(tls),
+                    },
+                    None => continue,
+                },
+                models::IngestTargetConfig::Ws { url } => stream::IngestTarget::Ws {
+                    url,
+                    tls: self.ingest_tls_settings.clone(),
+                },
+                models::IngestTargetConfig::Rtp {
+                    addr,
+                    payload_type,
+                    ssrc,
+                } => stream::IngestTarget::Rtp {
+                    addr,
+                    payload_type: payload_type
+                        .unwrap_or_else(|| rtp::default_payload_type(self.target_rate)),
+                    ssrc: ssrc.unwrap_or_else(|| default_ssrc(self.assigned_input_id.as_deref())),
+                },
+            };
+            targets.push(target);
+        }
+        targets
     }
 
     fn ingest_target(&self) -> Option<stream::IngestTarget> {
         if let Some(url) = &self.ingest_ws_url {
-            return Some(stream::IngestTarget::Ws { url: url.clone() });
+            return Some(stream::IngestTarget::Ws {
+                url: url.clone(),
+                tls: self.ingest_tls_settings.clone(),
+            });
+        }
+        if let Some(addr) = &self.ingest_rtp_addr {
+            return Some(stream::IngestTarget::Rtp {
+                addr: addr.clone(),
+                payload_type: self
+                    .ingest_rtp_payload_type
+                    .unwrap_or_else(|| rtp::default_payload_type(self.target_rate)),
+                ssrc: self
+                    .ingest_rtp_ssrc
+                    .unwrap_or_else(|| default_ssrc(self.assigned_input_id.as_deref())),
+            });
         }
         let host = self.ingest_tcp_host.clone()?;
         let port = self.ingest_tcp_port?;
         let header = self.assigned_input_id.clone()?;
-        Some(stream::IngestTarget::Tcp { host, port, header })
+        Some(stream::IngestTarget::Tcp {
+            host,
+            port,
+            header,
+            wav_header: self.ingest_wav_header,
+            tls: self.tcp_tls(self.ingest_tls),
+        })
     }
 
-    fn ingest_label(&self) -> String {
-        if let Some(url) = &self.ingest_ws_url {
-            return url.clone();
-        }
-        match (&self.ingest_tcp_host, self.ingest_tcp_port) {
-            (Some(host), Some(port)) => format!("{}:{}", host, port),
-            _ => "unassigned".to_string(),
+    fn tcp_tls(&self, enabled: bool) -> Option<tls::TlsSettings> {
+        enabled.then(|| self.ingest_tls_settings.clone())
+    }
+
+    /// Input name with its id, e.g. `Kitchen (li-3f2a)`.
+    fn input_label(&self) -> String {
+        match (&self.input_name, &self.assigned_input_id) {
+            (Some(name), Some(id)) => format!("{} ({})", name, id),
+            (None, Some(id)) => id.clone(),
+            (Some(name), None) => name.clone(),
+            (None, None) => "unassigned".to_string(),
         }
     }
 
+    /// What the local API shows as the running config; leaves out TLS
+    /// material.
+    fn api_view(&self) -> serde_json::Value {
+        let vad = self.vad_settings();
+        serde_json::json!({
+            "input": self.input_label(),
+            "zone": self.zone_name,
+            "capture_device": self.capture_device,
+            "ingest": self
+                .ingest_targets()
+                .iter()
+                .map(stream::IngestTarget::label)
+                .collect::<Vec<_>>(),
+            "ingest_framing": self.ingest_framing.label(),
+            "ingest_packet_ms": self.ingest_packet_ms,
+            "target_rate": self.target_rate,
+            "resampler": self.resampler.label(),
+            "input_gain_db": self.input_gain_db,
+            "output_ceiling_db": self.output_ceiling_db,
+            "vad_enabled": vad.enabled,
+            "vad_mode": vad.mode.label(),
+            "vad_open_db": vad.open_db,
+            "vad_close_db": vad.close_db,
+            "vad_hold_ms": self.vad_hold_ms,
+            "track_detect": self.track_detect.label(),
+            "record_enabled": self.record_enabled,
+        })
+    }
+
+    fn publish_assignment(&self, status: &stream::StatusHandle) {
+        status.set_assignment(
+            self.assigned_input_id.as_deref(),
+            self.input_name.as_deref(),
+            self.zone_name.as_deref(),
+        );
+    }
+
     fn stream_key(&self) -> StreamKey {
         StreamKey {
             assigned_input_id: self.assigned_input_id.clone(),
             ingest_ws_url: self.ingest_ws_url.clone(),
             ingest_tcp_host: self.ingest_tcp_host.clone(),
             ingest_tcp_port: self.ingest_tcp_port,
+            ingest_wav_header: self.ingest_wav_header,
+            ingest_tls: self.ingest_tls,
+            ingest_tls_settings: self.ingest_tls_settings.clone(),
+            ingest_framing: self.ingest_framing,
+            ingest_rtp_addr: self.ingest_rtp_addr.clone(),
+            ingest_rtp_payload_type: self.ingest_rtp_payload_type,
+            ingest_rtp_ssrc: self.ingest_rtp_ssrc,
+            ingest_extra: self.ingest_extra.clone(),
+            ingest_socket: self.ingest_socket,
+            ingest_reconnect_buffer_ms: self.ingest_reconnect_buffer_ms,
+            ingest_packet_ms: self.ingest_packet_ms,
             capture_device: self.capture_device.clone(),
             target_rate: self.target_rate,
             resampler: self.resampler,
+            input_gain_db: self.input_gain_db,
+            output_ceiling_db: self.output_ceiling_db,
+            capture_options: self.capture_options,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 struct StreamKey {
     assigned_input_id: Option<String>,
     ingest_ws_url: Option<String>,
     ingest_tcp_host: Option<String>,
     ingest_tcp_port: Option<u16>,
+    ingest_wav_header: bool,
+    ingest_tls: bool,
+    ingest_tls_settings: tls::TlsSettings,
+    ingest_framing: framing::IngestFraming,
+    ingest_rtp_addr: Option<String>,
+    ingest_rtp_payload_type: Option<u8>,
+    ingest_rtp_ssrc: Option<u32>,
+    ingest_extra: Vec<models::IngestTargetConfig>,
+    ingest_socket: sink::SocketOptions,
+    ingest_reconnect_buffer_ms: u64,
+    ingest_packet_ms: u64,
     capture_device: Option<String>,
     target_rate: u32,
     resampler: audio::ResamplerMode,
+    input_gain_db: f32,
+    output_ceiling_db: Option<f32>,
+    capture_options: audio::CaptureOptions,
 }
 
-fn local_identity() -> Result<(String, String)> {
-    let mut ip = None;
-    if let Ok(ifaces) = get_if_addrs::get_if_addrs() {
-        for iface in ifaces {
-            if iface.is_loopback() {
-                continue;
-            }
-            if let std::net::IpAddr::V4(addr) = iface.ip() {
-                ip = Some(addr.to_string());
-                break;
-            }
-        }
+fn register_request(
+    bridge_id: &str,
+    identity: &Identity,
+    capture_devices: &[models::CaptureDeviceInfo],
+    capture_report: Option<&models::CaptureReportSummary>,
+    redactor: &redact::Redactor,
+    capabilities: &models::BridgeCapabilities,
+) -> models::BridgeRegisterRequest {
+    models::BridgeRegisterRequest {
+        bridge_id: bridge_id.to_string(),
+        hostname: redactor.hostname(&identity.hostname),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        ip: redactor.ip(&identity.ip),
+        ips: identity.ips.iter().map(|ip| redactor.ip(ip)).collect(),
+        mac: redactor.mac(&identity.mac),
+        capture_devices: redactor.devices(capture_devices),
+        capture_report: capture_report.map(|report| models::CaptureReportSummary {
+            device: redactor.device_id(&report.device),
+            ..report.clone()
+        }),
+        redaction: redactor.policy(),
+        protocol_version: server_api::PROTOCOL_VERSION,
+        capabilities: capabilities.clone(),
     }
-    let ip = ip.unwrap_or_else(|| "0.0.0.0".to_string());
-    let mac = mac_address::get_mac_address()
+}
+
+/// Transports of the sinks this bridge runs: the ones the server can
+/// assign, unless a local output replaces the ingest, and the local
+/// `outputs` that are on.
+fn bridge_capabilities(
+    local_only: bool,
+    outputs: &[(&'static str, bool)],
+) -> models::BridgeCapabilities {
+    let assignable: &[&'static str] = if local_only {
+        &[]
+    } else {
+        &["tcp", "tls", "ws", "wss", "rtp"]
+    };
+    let transports = assignable
+        .iter()
+        .copied()
+        .chain(outputs.iter().filter(|(_, on)| *on).map(|(name, _)| *name))
+        .collect();
+    models::BridgeCapabilities {
+        codecs: vec!["pcm_s16le"],
+        transports,
+        framings: framing::IngestFraming::ALL
+            .iter()
+            .map(framing::IngestFraming::label)
+            .collect(),
+        vad_modes: vad::VadMode::ALL.iter().map(vad::VadMode::label).collect(),
+        max_inputs: 1,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Identity {
+    hostname: String,
+    ip: String,
+    /// All routable addresses, IPv4 before IPv6.
+    ips: Vec<String>,
+    mac: String,
+}
+
+fn local_identity(binding: &net::Binding) -> Result<Identity> {
+    let hostname = hostname::get()
+        .unwrap_or_else(|_| "unknown".into())
+        .to_string_lossy()
+        .to_string();
+    let mut addrs: Vec<std::net::IpAddr> = binding
+        .interfaces()
+        .into_iter()
+        .map(|(_, addr)| addr)
+        .filter(|addr| !net::is_link_local(addr))
+        .collect();
+    // IPv4 first, so `ip` stays what older servers expect.
+    addrs.sort_by_key(|addr| addr.is_ipv6());
+    addrs.dedup();
+    let ips: Vec<String> = addrs.iter().map(ToString::to_string).collect();
+    let ip = ips
+        .first()
+        .cloned()
+        .unwrap_or_else(|| "0.0.0.0".to_string());
+    let mac = match binding.interface_name() {
+        Some(name) => mac_address::mac_address_by_name(&name),
+        None => mac_address::get_mac_address(),
+    };
+    let mac = mac
         .ok()
         .flatten()
         .map(|mac| mac.to_string())
         .unwrap_or_else(|| "00:00:00:00:00:00".to_string());
-    Ok((ip, mac))
+    Ok(Identity {
+        hostname,
+        ip,
+        ips,
+        mac,
+    })
+}
+
+/// Device used when the server assigns none. Configured sources always
+/// stand in for a device, so the stream can start.
+fn local_capture_device(config: &config::Config) -> Option<String> {
+    if config.sources.is_empty() {
+        config.capture_device.clone()
+    } else {
+        Some(config.sources.label())
+    }
+}
+
+/// What the config file adds to each server config.
+#[derive(Debug, Clone)]
+struct LocalSettings {
+    device: Option<String>,
+    overrides: overrides::Overrides,
+}
+
+impl LocalSettings {
+    fn new(config: &config::Config) -> Self {
+        Self {
+            device: local_capture_device(config),
+            overrides: config.overrides.clone(),
+        }
+    }
+
+    fn apply(&self, response: models::BridgeConfigResponse) -> models::BridgeConfigResponse {
+        self.overrides
+            .apply(with_local_device(response, self.device.as_deref()))
+    }
+}
+
+/// Falls back to the capture device from the local config when the server
+/// has not assigned one.
+fn with_local_device(
+    mut response: models::BridgeConfigResponse,
+    device: Option<&str>,
+) -> models::BridgeConfigResponse {
+    if response.capture_device.clone().flatten().is_none() {
+        response.capture_device = Some(device.map(str::to_string));
+    }
+    response
+}
+
+fn tee_path(config: &config::Config, runtime: &RuntimeConfig) -> Option<std::path::PathBuf> {
+    if !config.tee_pipe {
+        return None;
+    }
+    let dir = config
+        .tee_pipe_dir
+        .clone()
+        .unwrap_or_else(|| tee::DEFAULT_TEE_DIR.to_string());
+    let input_id = runtime.assigned_input_id.as_deref()?;
+    Some(tee::pipe_path(std::path::Path::new(&dir), input_id))
 }
 
 fn parse_resampler(value: Option<&str>) -> audio::ResamplerMode {
@@ -486,6 +2207,121 @@ fn parse_resampler(value: Option<&str>) -> audio::ResamplerMode {
         .unwrap_or(audio::ResamplerMode::SincQuality)
 }
 
+fn parse_framing(value: Option<&str>) -> framing::IngestFraming {
+    value
+        .and_then(framing::IngestFraming::parse)
+        .unwrap_or_default()
+}
+
+fn socket_options(response: &models::BridgeConfigResponse) -> sink::SocketOptions {
+    let defaults = sink::SocketOptions::default();
+    sink::SocketOptions {
+        keepalive_idle: response
+            .ingest_keepalive_idle_secs
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.keepalive_idle),
+        keepalive_interval: response
+            .ingest_keepalive_interval_secs
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.keepalive_interval),
+        keepalive_count: response
+            .ingest_keepalive_count
+            .unwrap_or(defaults.keepalive_count),
+        send_timeout: response
+            .ingest_send_timeout_ms
+            .map(send_timeout)
+            .unwrap_or(defaults.send_timeout),
+        local: defaults.local,
+    }
+}
+
+fn packet_ms(value: Option<u64>) -> u64 {
+    value.unwrap_or(DEFAULT_PACKET_MS).clamp(5, 200)
+}
+
+fn status_interval(secs: Option<u64>) -> Duration {
+    Duration::from_secs(secs.unwrap_or(DEFAULT_STATUS_INTERVAL_SECS).clamp(1, 300))
+}
+
+/// Gap before the next status post: the interval, doubled per failed post
+/// (at most 4x, since the bridge fails over after three), with ±10% jitter
+/// so a fleet of bridges doesn't post in lockstep.
+fn status_delay(interval: Duration, failures: u32) -> Duration {
+    let base = interval.saturating_mul(1 << failures.min(FAILOVER_AFTER_FAILURES - 1));
+    let jitter = 0.1 * (2.0 * random_unit() - 1.0);
+    base.mul_f64(1.0 + jitter)
+}
+
+/// Uniform in `[0, 1]`.
+fn random_unit() -> f64 {
+    use ::ring::rand::{SecureRandom, SystemRandom};
+    let mut bytes = [0u8; 8];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        return 0.5;
+    }
+    u64::from_le_bytes(bytes) as f64 / u64::MAX as f64
+}
+
+/// Send timeouts are kept well above the longest pacing interval
+/// (`ingest_packet_ms` goes up to 200).
+fn send_timeout(ms: u64) -> Duration {
+    Duration::from_millis(ms.max(500))
+}
+
+/// Reads `ingest_targets`, skipping entries this bridge doesn't understand.
+fn parse_ingest_targets(values: Option<Vec<serde_json::Value>>) -> Vec<models::IngestTargetConfig> {
+    values
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|value| match serde_json::from_value(value.clone()) {
+            Ok(target) => Some(target),
+            Err(err) => {
+                warn!("ignoring ingest target {}: {}", value, err);
+                None
+            }
+        })
+        .collect()
+}
+
+fn parse_record_format(value: Option<&str>) -> recorder::RecordFormat {
+    value
+        .and_then(recorder::RecordFormat::parse)
+        .unwrap_or(recorder::RecordFormat::Wav)
+}
+
+fn parse_vad_mode(value: Option<&str>) -> vad::VadMode {
+    value
+        .and_then(vad::VadMode::parse)
+        .unwrap_or(vad::VadMode::Rms)
+}
+
+fn parse_track_detect(value: Option<&str>) -> track::TrackDetectMode {
+    value
+        .and_then(track::TrackDetectMode::parse)
+        .unwrap_or(track::TrackDetectMode::Gap)
+}
+
+/// Random SSRC per input, as RFC 3550 asks for. It is kept for the life of
+/// the process, so the server can tell a reconnect from a new sender; a
+/// restart starts a new source.
+fn default_ssrc(input_id: Option<&str>) -> u32 {
+    use ::ring::rand::{SecureRandom, SystemRandom};
+    static CHOSEN: std::sync::Mutex<Vec<(Option<String>, u32)>> = std::sync::Mutex::new(Vec::new());
+    let mut chosen = CHOSEN.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some((_, ssrc)) = chosen.iter().find(|(id, _)| id.as_deref() == input_id) {
+        return *ssrc;
+    }
+    let mut bytes = [0u8; 4];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        bytes = uuid::Uuid::new_v4().as_bytes()[..4]
+            .try_into()
+            .expect("4 bytes");
+    }
+    let ssrc = u32::from_be_bytes(bytes);
+    chosen.push((input_id.map(str::to_string), ssrc));
+    ssrc
+}
+
 fn hash_capture_devices(devices: &[models::CaptureDeviceInfo]) -> u64 {
     use std::hash::{Hash, Hasher};
     let mut hasher = std::collections::hash_map::DefaultHasher::new();
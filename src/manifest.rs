@@ -0,0 +1,188 @@
+//! Manifest mode: several logical bridges in one process, for a machine
+//! serving many inputs. Every bridge keeps its own identity, device, server,
+//! status and health reporters, and runs on its own thread and runtime; a
+//! bridge that fails is restarted on a fresh runtime without touching the
+//! others. The combined health is reported separately.
+
+use crate::config::Config;
+use crate::discovery::DiscoveryLimits;
+use crate::health::{self, HealthConfig};
+use crate::server_api::ApiMode;
+use crate::shutdown::Shutdown;
+use crate::stream::StatusHandle;
+use crate::{faults, sd_notify, secrets, BridgeSetup};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn, Instrument};
+
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Deserialize)]
+pub struct Manifest {
+    /// Reporters for the combined health of all bridges.
+    #[serde(default)]
+    pub health: HealthConfig,
+    pub bridges: Vec<ManifestBridge>,
+}
+
+/// One bridge: a name plus the same settings as a standalone config file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManifestBridge {
+    pub name: String,
+    #[serde(flatten)]
+    pub config: Config,
+}
+
+pub fn load(path: &Path) -> Result<Manifest> {
+    let data = std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    let mut manifest: Manifest =
+        toml::from_str(&data).with_context(|| format!("parse {}", path.display()))?;
+    // The key is looked up next to the manifest, as for a config file.
+    for bridge in &mut manifest.bridges {
+        secrets::decrypt(&mut bridge.config, path)
+            .with_context(|| format!("bridge {} in {}", bridge.name, path.display()))?;
+    }
+    validate(&manifest).with_context(|| format!("invalid manifest {}", path.display()))?;
+    Ok(manifest)
+}
+
+fn validate(manifest: &Manifest) -> Result<()> {
+    if manifest.bridges.is_empty() {
+        anyhow::bail!("no bridges defined");
+    }
+    let mut names = HashSet::new();
+    let mut ids = HashSet::new();
+    for bridge in &manifest.bridges {
+        let valid_name = !bridge.name.is_empty()
+            && bridge
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            anyhow::bail!(
+                "bridge name {:?} must be letters, digits, '-' or '_'",
+                bridge.name
+            );
+        }
+        if !names.insert(bridge.name.as_str()) {
+            anyhow::bail!("bridge name {} is used twice", bridge.name);
+        }
+        if !ids.insert(bridge.config.bridge_id.as_str()) {
+            anyhow::bail!(
+                "bridge_id {} is used by more than one bridge",
+                bridge.config.bridge_id
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Health file of one bridge when its config doesn't name one, so bridges
+/// don't overwrite each other.
+fn bridge_health_path(name: &str) -> String {
+    format!("/tmp/lox-linein-bridge.{}.status.json", name)
+}
+
+pub async fn run(
+    path: &Path,
+    limits: DiscoveryLimits,
+    api_mode: ApiMode,
+    shutdown: Shutdown,
+) -> Result<()> {
+    if api_mode != ApiMode::Live {
+        anyhow::bail!("--record-api and --replay-api can't be combined with --manifest");
+    }
+    if limits.timeout.is_some() || limits.max_attempts.is_some() {
+        anyhow::bail!("discovery limits can't be combined with --manifest");
+    }
+    let manifest = load(path)?;
+    info!(
+        "loaded manifest {} with {} bridges",
+        path.display(),
+        manifest.bridges.len()
+    );
+    faults::spawn_control();
+
+    let mut statuses = Vec::new();
+    let mut threads = Vec::new();
+    for bridge in manifest.bridges {
+        let status = StatusHandle::new("", "");
+        let mut health = bridge.config.health.clone();
+        if health.file.path.is_none() {
+            health.file.path = Some(bridge_health_path(&bridge.name));
+        }
+        health::spawn(status.clone(), &health, &bridge.config.bridge_id);
+        statuses.push((bridge.name.clone(), status.clone()));
+        let manifest_path = path.to_path_buf();
+        let shutdown = shutdown.clone();
+        let thread = std::thread::Builder::new()
+            .name(format!("bridge-{}", bridge.name))
+            .spawn(move || supervise(bridge, status, manifest_path, shutdown))
+            .context("spawn bridge thread")?;
+        threads.push(thread);
+    }
+    let host = hostname::get()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "manifest".to_string());
+    sd_notify::spawn_watchdog(statuses.clone());
+    health::spawn_aggregate(statuses, &manifest.health, &host);
+
+    for thread in threads {
+        let _ = tokio::task::spawn_blocking(move || thread.join()).await;
+    }
+    Ok(())
+}
+
+/// Runs one bridge, restarting it whenever it stops, until a shutdown is
+/// requested. Capture streams aren't `Send`, so each bridge drives its own
+/// single-threaded runtime; dropping it on restart also ends every task the
+/// bridge left behind.
+fn supervise(
+    bridge: ManifestBridge,
+    status: StatusHandle,
+    manifest_path: PathBuf,
+    shutdown: Shutdown,
+) {
+    let span = tracing::info_span!("bridge", name = %bridge.name);
+    loop {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(err) => {
+                warn!("bridge {} runtime failed: {}", bridge.name, err);
+                std::thread::sleep(RESTART_DELAY);
+                continue;
+            }
+        };
+        let setup = BridgeSetup {
+            config: bridge.config.clone(),
+            path: manifest_path.clone(),
+            status: status.clone(),
+            log: None,
+            limits: DiscoveryLimits::default(),
+            api_mode: ApiMode::Live,
+            offer_wizard: false,
+            writable_config: false,
+            shutdown: shutdown.clone(),
+        };
+        match runtime.block_on(crate::run_bridge(setup).instrument(span.clone())) {
+            Ok(()) if shutdown.is_requested() => {
+                info!("bridge {} stopped", bridge.name);
+                return;
+            }
+            Ok(()) => warn!("bridge {} stopped, restarting", bridge.name),
+            Err(err) => {
+                status.set_last_error(Some(format!("{:#}", err)));
+                warn!("bridge {} failed: {:#}, restarting", bridge.name, err);
+            }
+        }
+        drop(runtime);
+        status.set_state("ERROR");
+        std::thread::sleep(RESTART_DELAY);
+    }
+}
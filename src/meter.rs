@@ -0,0 +1,112 @@
+use crate::audio::TARGET_CHANNELS;
+use crate::simd;
+
+const SHORT_WINDOW_SECS: f64 = 0.3;
+const LONG_WINDOW_SECS: f64 = 3.0;
+const FLOOR_DB: f32 = -100.0;
+/// Interpolation points between samples used for the true-peak estimate.
+const TRUE_PEAK_PHASES: [f32; 3] = [0.25, 0.5, 0.75];
+
+#[derive(Debug, Clone, Copy)]
+pub struct Levels {
+    /// RMS of the last chunk, used by the VAD gate.
+    pub rms_db: f32,
+    pub peak_db: f32,
+    pub true_peak_db: f32,
+    pub rms_short_db: f32,
+    pub rms_long_db: f32,
+}
+
+/// Level meter over interleaved stereo s16le chunks, shared by all stream
+/// paths. Short/long RMS are exponentially weighted windows.
+pub struct LevelMeter {
+    rate: u32,
+    short_ms: f64,
+    long_ms: f64,
+    primed: bool,
+}
+
+impl LevelMeter {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate: rate.max(1),
+            short_ms: 0.0,
+            long_ms: 0.0,
+            primed: false,
+        }
+    }
+
+    pub fn process(&mut self, bytes: &[u8]) -> Option<Levels> {
+        let mut samples = Vec::new();
+        simd::i16_le_to_f32(bytes, &mut samples);
+        if samples.is_empty() {
+            return None;
+        }
+        let (sum, peak) = simd::sum_squares_peak(&samples);
+        let mean_square = sum / samples.len() as f64;
+        let true_peak = peak.max(inter_sample_peak(&samples));
+
+        let frames = samples.len() / TARGET_CHANNELS as usize;
+        let duration = frames as f64 / self.rate as f64;
+        if self.primed {
+            self.short_ms = smooth(self.short_ms, mean_square, duration, SHORT_WINDOW_SECS);
+            self.long_ms = smooth(self.long_ms, mean_square, duration, LONG_WINDOW_SECS);
+        } else {
+            self.short_ms = mean_square;
+            self.long_ms = mean_square;
+            self.primed = true;
+        }
+
+        Some(Levels {
+            rms_db: power_to_db(mean_square),
+            peak_db: amplitude_to_db(peak),
+            true_peak_db: amplitude_to_db(true_peak),
+            rms_short_db: power_to_db(self.short_ms),
+            rms_long_db: power_to_db(self.long_ms),
+        })
+    }
+}
+
+fn smooth(current: f64, value: f64, duration: f64, window: f64) -> f64 {
+    let alpha = 1.0 - (-duration / window).exp();
+    current + (value - current) * alpha
+}
+
+/// Estimates the largest inter-sample peak per channel with Catmull-Rom
+/// interpolation, a cheap stand-in for 4x oversampled true-peak metering.
+fn inter_sample_peak(samples: &[f32]) -> f32 {
+    let channels = TARGET_CHANNELS as usize;
+    let frames = samples.len() / channels;
+    let mut peak = 0f32;
+    for channel in 0..channels {
+        let at = |frame: usize| samples[frame * channels + channel];
+        for frame in 1..frames.saturating_sub(2) {
+            let (p0, p1, p2, p3) = (at(frame - 1), at(frame), at(frame + 1), at(frame + 2));
+            for t in TRUE_PEAK_PHASES {
+                let value = 0.5
+                    * ((2.0 * p1)
+                        + (-p0 + p2) * t
+                        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t
+                        + (-p0 + 3.0 * p1 - 3.0 * p2 + p3) * t * t * t);
+                peak = peak.max(value.abs());
+            }
+        }
+    }
+    peak
+}
+
+pub fn power_to_db(mean_square: f64) -> f32 {
+    if mean_square <= 0.0 {
+        FLOOR_DB
+    } else {
+        (10.0 * mean_square.log10()) as f32
+    }
+}
+
+pub fn amplitude_to_db(amplitude: f32) -> f32 {
+    if amplitude <= 0.0 {
+        FLOOR_DB
+    } else {
+        20.0 * amplitude.log10()
+    }
+}
@@ -1,3 +1,4 @@
+use crate::redact::RedactionConfig;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Hash)]
@@ -6,6 +7,18 @@ pub struct CaptureDeviceInfo {
     pub name: String,
     pub channels: u16,
     pub sample_rates: Vec<u32>,
+    #[serde(default)]
+    pub is_monitor: bool,
+}
+
+/// Result of the last `analyze` run of the capture chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureReportSummary {
+    pub created: String,
+    pub device: String,
+    pub noise_floor_db: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_deviation_db: Option<f32>,
 }
 
 #[derive(Debug, Serialize)]
@@ -13,9 +26,67 @@ pub struct BridgeRegisterRequest {
     pub bridge_id: String,
     pub hostname: String,
     pub version: String,
+    /// Primary address, IPv4 when there is one.
     pub ip: String,
+    /// Every routable address, IPv4 and IPv6.
+    pub ips: Vec<String>,
     pub mac: String,
     pub capture_devices: Vec<CaptureDeviceInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture_report: Option<CaptureReportSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redaction: Option<RedactionConfig>,
+    /// Newest bridge API version this build speaks.
+    pub protocol_version: u32,
+    pub capabilities: BridgeCapabilities,
+}
+
+/// What the bridge can do, so the server only asks for supported features.
+#[derive(Debug, Clone, Serialize)]
+pub struct BridgeCapabilities {
+    pub codecs: Vec<&'static str>,
+    pub transports: Vec<&'static str>,
+    pub framings: Vec<&'static str>,
+    pub vad_modes: Vec<&'static str>,
+    /// Inputs one registration can feed.
+    pub max_inputs: u32,
+}
+
+/// Progress and result of a noise floor calibration.
+#[derive(Debug, Clone, Serialize)]
+pub struct CalibrationStatus {
+    pub state: String,
+    pub noise_floor_db: Option<f32>,
+    pub proposed_threshold_db: Option<f32>,
+    pub applied: bool,
+}
+
+/// Progress of an update the server asked for with `desired_version`:
+/// `downloading`, `installing`, `restarting` or `failed`.
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateStatus {
+    pub version: String,
+    pub state: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// An entry of the recent-error list. Repeats of the newest error only
+/// update its time and count.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentError {
+    pub ts: String,
+    pub message: String,
+    pub count: u64,
+}
+
+/// Server request to measure the noise floor. A new `id` starts a new run.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CalibrationCommand {
+    pub id: String,
+    pub seconds: Option<u64>,
+    /// Use the proposed threshold locally until the server sends a new one.
+    pub apply: Option<bool>,
 }
 
 #[derive(Debug, Serialize)]
@@ -26,23 +97,288 @@ pub struct BridgeStatusRequest {
     pub channels: Option<u16>,
     pub format: Option<String>,
     pub observed_rate: Option<u32>,
+    /// NaN/Inf samples replaced with silence since the bridge started.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub non_finite_samples: Option<u64>,
+    /// Set once the capture device delivered non-finite samples.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_suspect: Option<bool>,
+    /// Audio lost since the bridge started: frames dropped because capture
+    /// processing fell behind, chunks dropped because the send buffer was
+    /// full, and failed resampler blocks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture_overrun_frames: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dropped_chunks: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resampler_errors: Option<u64>,
+    /// Ingest sends that stalled past the send timeout.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_timeouts: Option<u64>,
+    /// Audio held for disconnected ingest targets right now, and dropped
+    /// from their full reconnect buffers since the bridge started.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffered_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dropped_ms: Option<u64>,
     pub rms_db: Option<f32>,
+    pub rms_short_db: Option<f32>,
+    pub rms_long_db: Option<f32>,
+    pub peak_db: Option<f32>,
+    pub true_peak_db: Option<f32>,
     pub last_error: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub track_change: Option<bool>,
+    /// 0..1, only with detectors that estimate it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track_change_confidence: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ceiling_interventions: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub calibration: Option<CalibrationStatus>,
+    /// Server clock minus bridge clock, and how fast that offset changes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_offset_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_drift_ppm: Option<f64>,
+    /// Source currently streamed when several local sources are configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_source: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub capture_devices: Option<Vec<CaptureDeviceInfo>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ips: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redaction: Option<RedactionConfig>,
+    /// `[overrides]` values that replace the server's; see `overrides.rs`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overrides: Option<serde_json::Value>,
+    /// Process and host telemetry; see `telemetry.rs`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_percent: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rss_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub soc_temp_c: Option<f32>,
+    /// Raspberry Pi firmware reports throttling or a capped clock.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub throttled: Option<bool>,
+    /// Sent once after the server asked for it with `report_errors`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recent_errors: Option<Vec<RecentError>>,
+    /// Revision of the last config received; also sent as `If-None-Match`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_revision: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update: Option<UpdateStatus>,
 }
 
+/// A status that couldn't be posted, with the time it was taken.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpooledStatus {
+    pub ts: String,
+    pub status: serde_json::Value,
+}
+
+/// Backlog upload after an outage, oldest first.
+#[derive(Debug, Serialize)]
+pub struct BridgeStatusBatch<'a> {
+    pub statuses: &'a [SpooledStatus],
+}
+
+/// Additional ingest target from `ingest_targets`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum IngestTargetConfig {
+    Tcp {
+        host: String,
+        port: u16,
+        #[serde(default)]
+        wav_header: bool,
+        /// Uses the `ingest_tls_*` settings.
+        #[serde(default)]
+        tls: bool,
+    },
+    Ws {
+        url: String,
+    },
+    Rtp {
+        addr: String,
+        payload_type: Option<u8>,
+        ssrc: Option<u32>,
+    },
+}
+
+/// Server config, from the registration, status replies and control
+/// pushes. A missing key keeps the current value. The `Option<Option<_>>`
+/// fields can also be cleared: `None` when the key is missing, `Some(None)`
+/// for an explicit `null`.
 #[derive(Debug, Deserialize, Clone)]
 pub struct BridgeConfigResponse {
-    pub assigned_input_id: Option<String>,
-    pub ingest_ws_url: Option<String>,
-    pub ingest_tcp_host: Option<String>,
-    pub ingest_tcp_port: Option<u16>,
-    pub capture_device: Option<String>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub assigned_input_id: Option<Option<String>>,
+    /// Display names of the assigned input and its zone, for operators.
+    #[serde(default, deserialize_with = "clearable")]
+    pub assigned_input_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub assigned_zone_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub ingest_ws_url: Option<Option<String>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub ingest_tcp_host: Option<Option<String>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub ingest_tcp_port: Option<Option<u16>>,
+    pub ingest_wav_header: Option<bool>,
+    /// TLS for the TCP ingest. The `ingest_tls_*` values below also apply
+    /// to `wss://` URLs; each is inline PEM or a path on the bridge.
+    pub ingest_tls: Option<bool>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub ingest_tls_ca: Option<Option<String>>,
+    /// SHA-256 of a self-signed server certificate to accept.
+    #[serde(default, deserialize_with = "clearable")]
+    pub ingest_tls_pin_sha256: Option<Option<String>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub ingest_tls_client_cert: Option<Option<String>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub ingest_tls_client_key: Option<Option<String>>,
+    /// TCP keepalive for ingest connections; `0` idle seconds turns it off.
+    pub ingest_keepalive_idle_secs: Option<u64>,
+    pub ingest_keepalive_interval_secs: Option<u64>,
+    pub ingest_keepalive_count: Option<u32>,
+    /// Reconnect when a payload can't be sent within this time.
+    pub ingest_send_timeout_ms: Option<u64>,
+    /// Audio kept per target while it reconnects; `0` disables buffering.
+    pub ingest_reconnect_buffer_ms: Option<u64>,
+    /// Duration of each paced ingest payload (5-200 ms, default 40).
+    pub ingest_packet_ms: Option<u64>,
+    /// `raw` (default) or `timestamped`; see `framing.rs`.
+    pub ingest_framing: Option<String>,
+    /// RTP destination `host:port`, unicast or multicast.
+    #[serde(default, deserialize_with = "clearable")]
+    pub ingest_rtp_addr: Option<Option<String>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub ingest_rtp_payload_type: Option<Option<u8>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub ingest_rtp_ssrc: Option<Option<u32>>,
+    /// Further targets that get the same audio as the primary ingest;
+    /// entries are read leniently as [`IngestTargetConfig`].
+    pub ingest_targets: Option<Vec<serde_json::Value>>,
+    /// Local recording of the gated stream; see `recorder.rs`.
+    pub record_enabled: Option<bool>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub record_dir: Option<Option<String>>,
+    /// `wav` (default) or `flac`.
+    pub record_format: Option<String>,
+    /// Rotation; `0` turns the limit off.
+    pub record_max_secs: Option<u64>,
+    pub record_max_mb: Option<u64>,
+    /// Retention; `0` turns the limit off.
+    pub record_keep_files: Option<usize>,
+    pub record_keep_days: Option<u64>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub capture_device: Option<Option<String>>,
+    pub vad_enabled: Option<bool>,
+    pub vad_mode: Option<String>,
     pub vad_threshold_db: Option<f32>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub vad_threshold_open_db: Option<Option<f32>>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub vad_threshold_close_db: Option<Option<f32>>,
+    pub vad_attack_ms: Option<u64>,
     pub vad_hold_ms: Option<u64>,
+    pub vad_preroll_ms: Option<u64>,
+    pub vad_silence_fill: Option<bool>,
+    pub track_detect: Option<String>,
+    pub vad_calibrate: Option<CalibrationCommand>,
+    /// A new id dumps the black box to files on the bridge.
+    pub black_box_dump: Option<String>,
+    /// A new id asks for the recent errors with the next status post.
+    pub report_errors: Option<String>,
     pub ingest_sample_rate: Option<u32>,
     pub ingest_resampler: Option<String>,
+    pub input_gain_db: Option<f32>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub output_ceiling_db: Option<Option<f32>>,
+    pub capture_exclusive: Option<bool>,
+    #[serde(default, deserialize_with = "clearable")]
+    pub capture_period_frames: Option<Option<u32>>,
+    pub log_level: Option<String>,
+    pub log_target: Option<String>,
+    pub log_file: Option<String>,
+    /// Seconds between status posts, 1 to 300; default 5.
+    pub status_interval_secs: Option<u64>,
+    /// Bridge API version the server accepted; servers from before the
+    /// negotiation leave it out and are treated as version 1.
+    pub protocol_version: Option<u32>,
+    /// Id the server wants the bridge to use from now on, e.g. after a
+    /// conflict with another bridge.
+    pub bridge_id: Option<String>,
+    /// Identifies this config; taken from the `ETag` header when the body
+    /// has none.
+    pub config_revision: Option<String>,
+    /// Bridge version the server wants; a newer one is downloaded from
+    /// `update_url`, checked against `update_sha256` and the signature at
+    /// `update_signature_url`, and installed; refused without
+    /// `update_public_key`.
+    pub desired_version: Option<String>,
+    pub update_url: Option<String>,
+    pub update_sha256: Option<String>,
+    pub update_signature_url: Option<String>,
+    /// Changes whenever the server restarts.
+    pub server_boot_id: Option<String>,
+    /// RFC 3339 server time when the response was created, with ms.
+    pub server_time: Option<String>,
+}
+
+impl BridgeConfigResponse {
+    /// Fills the clearable fields the server left out from `previous`, or
+    /// as cleared when there is none, so the result describes the whole
+    /// config.
+    pub fn keep_missing(&mut self, previous: Option<&Self>) {
+        macro_rules! keep {
+            ($($field:ident),* $(,)?) => {$(
+                if self.$field.is_none() {
+                    self.$field = Some(previous.and_then(|previous| previous.$field.clone().flatten()));
+                }
+            )*};
+        }
+        keep!(
+            assigned_input_id,
+            assigned_input_name,
+            assigned_zone_name,
+            ingest_ws_url,
+            ingest_tcp_host,
+            ingest_tcp_port,
+            ingest_tls_ca,
+            ingest_tls_pin_sha256,
+            ingest_tls_client_cert,
+            ingest_tls_client_key,
+            ingest_rtp_addr,
+            ingest_rtp_payload_type,
+            ingest_rtp_ssrc,
+            record_dir,
+            capture_device,
+            output_ceiling_db,
+            capture_period_frames,
+            vad_threshold_open_db,
+            vad_threshold_close_db,
+        );
+    }
+}
+
+/// Tells a `null` (`Some(None)`) from a missing key (`None`, by `default`).
+fn clearable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
 }
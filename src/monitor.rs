@@ -0,0 +1,136 @@
+//! Local monitor output: plays the processed stream on an ALSA output
+//! device, e.g. headphones on the Pi, to check what the bridge sends while
+//! it streams. Playback runs on its own thread with its own volume and
+//! never holds up the stream; the device is opened at the stream rate when
+//! it supports it and fed through a resampler otherwise.
+
+use crate::audio::{self, Resampler, ResamplerMode};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Paced payloads waiting for the monitor thread before new ones are
+/// dropped.
+const QUEUE_PAYLOADS: usize = 25;
+/// Audio between the monitor thread and the playback callback; bounds the
+/// added latency when the device clock runs slower than the capture.
+const PLAYBACK_BUFFER: Duration = Duration::from_millis(200);
+/// Wait before reopening a device that failed.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MonitorConfig {
+    /// Output device name as listed by `aplay -L`; the default output when
+    /// unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Monitor volume, independent of the stream.
+    #[serde(default)]
+    pub volume_db: f32,
+}
+
+/// Copy of the paced output for the monitor. Never blocks the stream: when
+/// playback falls behind, payloads are dropped.
+#[derive(Clone)]
+pub struct MonitorFeed {
+    tx: mpsc::SyncSender<(u32, Vec<u8>)>,
+}
+
+impl MonitorFeed {
+    /// `pcm` is interleaved s16le stereo at `rate`.
+    pub fn write(&self, pcm: &[u8], rate: u32) {
+        let _ = self.tx.try_send((rate, pcm.to_vec()));
+    }
+}
+
+pub fn start(config: &MonitorConfig) -> Result<MonitorFeed> {
+    let (tx, rx) = mpsc::sync_channel(QUEUE_PAYLOADS);
+    let device = config.device.clone();
+    let gain = 10f32.powf(config.volume_db / 20.0);
+    std::thread::Builder::new()
+        .name("monitor".to_string())
+        .spawn(move || run(rx, device, gain))
+        .context("spawn monitor")?;
+    info!(
+        "monitor output on {} ({:+.1} dB)",
+        config.device.as_deref().unwrap_or("default output"),
+        config.volume_db
+    );
+    Ok(MonitorFeed { tx })
+}
+
+struct Output {
+    /// Stream rate the output was opened for.
+    rate: u32,
+    playback: audio::Playback,
+    resampler: Option<Resampler>,
+}
+
+fn run(rx: mpsc::Receiver<(u32, Vec<u8>)>, device: Option<String>, gain: f32) {
+    let mut output: Option<Output> = None;
+    let mut failed_at: Option<std::time::Instant> = None;
+    let mut samples: Vec<f32> = Vec::new();
+    while let Ok((rate, pcm)) = rx.recv() {
+        if output.as_ref().is_some_and(|output| output.rate != rate) {
+            output = None;
+        }
+        if output.is_none() {
+            if failed_at.is_some_and(|at| at.elapsed() < RETRY_INTERVAL) {
+                continue;
+            }
+            match open(device.as_deref(), rate) {
+                Ok(opened) => {
+                    failed_at = None;
+                    output = Some(opened);
+                }
+                Err(err) => {
+                    warn!("monitor output unavailable: {:#}", err);
+                    failed_at = Some(std::time::Instant::now());
+                    continue;
+                }
+            }
+        }
+        let Some(output) = output.as_mut() else {
+            continue;
+        };
+        samples.clear();
+        samples.extend(
+            pcm.chunks_exact(2)
+                .map(|sample| i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0),
+        );
+        if let Some(resampler) = output.resampler.as_mut() {
+            let resampled = resampler.convert(&samples, 2);
+            samples.clear();
+            samples.extend(resampled.iter().map(|sample| *sample as f32 / 32768.0));
+        }
+        for sample in samples.iter_mut() {
+            *sample = (*sample * gain).clamp(-1.0, 1.0);
+        }
+        output.playback.producer.push(&samples);
+    }
+}
+
+fn open(device: Option<&str>, rate: u32) -> Result<Output> {
+    let playback = audio::start_playback(device, rate, PLAYBACK_BUFFER)?;
+    let resampler = if playback.sample_rate == rate {
+        None
+    } else {
+        info!(
+            "monitor device runs at {} Hz, resampling from {} Hz",
+            playback.sample_rate, rate
+        );
+        Some(Resampler::offline(
+            rate,
+            2,
+            playback.sample_rate,
+            ResamplerMode::SincFast,
+        )?)
+    };
+    Ok(Output {
+        rate,
+        playback,
+        resampler,
+    })
+}
@@ -0,0 +1,97 @@
+//! Minimal MQTT 3.1.1 publisher: connect, then QoS 0 publishes. Enough for
+//! pushing health snapshots to a broker without pulling in a client crate.
+
+use anyhow::{bail, Context, Result};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct MqttClient {
+    stream: TcpStream,
+}
+
+impl MqttClient {
+    /// Connects to `broker` (`host:port`) with a clean session and no
+    /// keep-alive, so an idle connection between publishes stays valid.
+    pub async fn connect(
+        broker: &str,
+        client_id: &str,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<Self> {
+        let mut stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(broker))
+            .await
+            .with_context(|| format!("connect {}: timed out", broker))?
+            .with_context(|| format!("connect {}", broker))?;
+
+        let mut body = Vec::new();
+        put_str(&mut body, "MQTT");
+        body.push(4); // protocol level 3.1.1
+        let mut flags = 0x02; // clean session
+        if credentials.is_some() {
+            flags |= 0xc0;
+        }
+        body.push(flags);
+        body.extend_from_slice(&0u16.to_be_bytes());
+        put_str(&mut body, client_id);
+        if let Some((username, password)) = credentials {
+            put_str(&mut body, username);
+            put_str(&mut body, password);
+        }
+        stream
+            .write_all(&packet(0x10, &body))
+            .await
+            .context("send CONNECT")?;
+
+        let mut connack = [0u8; 4];
+        tokio::time::timeout(CONNECT_TIMEOUT, stream.read_exact(&mut connack))
+            .await
+            .context("read CONNACK: timed out")?
+            .context("read CONNACK")?;
+        if connack[0] != 0x20 || connack[1] != 2 {
+            bail!("unexpected reply to CONNECT");
+        }
+        if connack[3] != 0 {
+            bail!("broker refused connection (code {})", connack[3]);
+        }
+        Ok(Self { stream })
+    }
+
+    pub async fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> Result<()> {
+        let mut body = Vec::with_capacity(topic.len() + payload.len() + 2);
+        put_str(&mut body, topic);
+        body.extend_from_slice(payload);
+        let header = if retain { 0x31 } else { 0x30 };
+        tokio::time::timeout(WRITE_TIMEOUT, self.stream.write_all(&packet(header, &body)))
+            .await
+            .context("send PUBLISH: timed out")?
+            .context("send PUBLISH")
+    }
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 5);
+    out.push(header);
+    let mut remaining = body.len();
+    loop {
+        let mut byte = (remaining % 128) as u8;
+        remaining /= 128;
+        if remaining > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if remaining == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(body);
+    out
+}
+
+fn put_str(out: &mut Vec<u8>, value: &str) {
+    let bytes = &value.as_bytes()[..value.len().min(u16::MAX as usize)];
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
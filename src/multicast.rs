@@ -0,0 +1,143 @@
+//! UDP multicast output of the processed stream for LAN listeners that
+//! don't go through a server. Every datagram carries the same amount of
+//! audio behind a 16 byte header:
+//!
+//! | bytes | content |
+//! | --- | --- |
+//! | 0..4 | sequence number, u32 big-endian, +1 per datagram |
+//! | 4..12 | capture time of the first frame, Unix µs, u64 big-endian |
+//! | 12..16 | sample rate, u32 big-endian |
+//! | 16.. | s16le stereo PCM |
+//!
+//! Receivers spot loss from sequence gaps and can line up several
+//! listeners on the capture time.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::AsRawFd;
+use tokio::net::UdpSocket;
+
+const HEADER_LEN: usize = 16;
+const FRAME_BYTES: usize = 4;
+/// Keeps datagrams below a typical 1500 byte MTU.
+const MAX_PAYLOAD: usize = 1400;
+/// Datagram duration: 5 ms, unless that exceeds `MAX_PAYLOAD`.
+const PACKETS_PER_SECOND: u32 = 200;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MulticastConfig {
+    /// Group and port, e.g. `239.255.77.1:5004` or `[ff15::77]:5004`.
+    pub group: String,
+    /// Router hops; 1 keeps the stream on the local subnet.
+    #[serde(default = "default_ttl")]
+    pub ttl: u32,
+    /// Send only to the group instead of the server-assigned ingest.
+    #[serde(default)]
+    pub replace_ingest: bool,
+}
+
+fn default_ttl() -> u32 {
+    1
+}
+
+/// Parses `host:port` and checks that the host is a multicast group.
+pub fn parse_group(value: &str) -> Result<SocketAddr> {
+    let addr: SocketAddr = value
+        .parse()
+        .with_context(|| format!("multicast group {:?} must be ip:port", value))?;
+    if !addr.ip().is_multicast() {
+        anyhow::bail!("{} is not a multicast address", addr.ip());
+    }
+    Ok(addr)
+}
+
+pub struct MulticastSender {
+    socket: UdpSocket,
+    group: SocketAddr,
+    rate: u32,
+    sequence: u32,
+    packet_bytes: usize,
+    /// Audio short of a full datagram, sent with the next payload.
+    pending: Vec<u8>,
+    packet: Vec<u8>,
+}
+
+impl MulticastSender {
+    pub async fn connect(group: SocketAddr, ttl: u32, rate: u32) -> Result<Self> {
+        let bind: SocketAddr = match group.ip() {
+            IpAddr::V4(_) => "0.0.0.0:0".parse().expect("valid address"),
+            IpAddr::V6(_) => "[::]:0".parse().expect("valid address"),
+        };
+        let socket = UdpSocket::bind(bind)
+            .await
+            .with_context(|| format!("bind udp socket for {}", group))?;
+        match group.ip() {
+            IpAddr::V4(_) => socket
+                .set_multicast_ttl_v4(ttl)
+                .context("set multicast ttl")?,
+            IpAddr::V6(_) => set_hops_v6(&socket, ttl)?,
+        }
+        let frames_per_packet =
+            ((rate / PACKETS_PER_SECOND) as usize).clamp(1, MAX_PAYLOAD / FRAME_BYTES);
+        Ok(Self {
+            socket,
+            group,
+            rate: rate.max(1),
+            sequence: 0,
+            packet_bytes: frames_per_packet * FRAME_BYTES,
+            pending: Vec::new(),
+            packet: Vec::with_capacity(HEADER_LEN + MAX_PAYLOAD),
+        })
+    }
+
+    /// Sends interleaved s16le stereo PCM captured at `captured_at_us` as
+    /// full datagrams; a remainder waits for the next call. Times are taken
+    /// from each payload's capture time, with the leftover frames just
+    /// before it, so gaps in the capture don't skew them.
+    pub async fn send(&mut self, pcm: &[u8], captured_at_us: u64) -> Result<()> {
+        let carried = (self.pending.len() / FRAME_BYTES) as u64;
+        let start_us = captured_at_us.saturating_sub(self.frames_us(carried));
+        self.pending.extend_from_slice(pcm);
+        let mut offset = 0;
+        while self.pending.len() - offset >= self.packet_bytes {
+            let frames = &self.pending[offset..offset + self.packet_bytes];
+            let at_us = start_us + self.frames_us((offset / FRAME_BYTES) as u64);
+            self.packet.clear();
+            self.packet.extend_from_slice(&self.sequence.to_be_bytes());
+            self.packet.extend_from_slice(&at_us.to_be_bytes());
+            self.packet.extend_from_slice(&self.rate.to_be_bytes());
+            self.packet.extend_from_slice(frames);
+            self.socket
+                .send_to(&self.packet, self.group)
+                .await
+                .with_context(|| format!("send to multicast {}", self.group))?;
+            self.sequence = self.sequence.wrapping_add(1);
+            offset += self.packet_bytes;
+        }
+        self.pending.drain(..offset);
+        Ok(())
+    }
+
+    fn frames_us(&self, frames: u64) -> u64 {
+        frames * 1_000_000 / self.rate as u64
+    }
+}
+
+/// Tokio only exposes the IPv4 TTL; IPv6 uses the hop limit.
+fn set_hops_v6(socket: &UdpSocket, hops: u32) -> Result<()> {
+    let value = hops as libc::c_int;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MULTICAST_HOPS,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error()).context("set multicast hops");
+    }
+    Ok(())
+}
@@ -0,0 +1,170 @@
+//! Address helpers shared by discovery, identity and ingest, so IPv6
+//! literals and interface binding are handled the same way everywhere.
+
+use anyhow::{Context, Result};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpListener, TcpSocket, TcpStream};
+use tracing::warn;
+
+/// Pause after a failed accept, e.g. out of file descriptors.
+const ACCEPT_RETRY: Duration = Duration::from_secs(1);
+
+/// `host:port`, with IPv6 literals in brackets. `host` may already be
+/// bracketed.
+pub fn host_port(host: &str, port: u16) -> String {
+    let bare = host.trim_start_matches('[').trim_end_matches(']');
+    if bare.parse::<Ipv6Addr>().is_ok() {
+        format!("[{}]:{}", bare, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// The next connection on `listener`. A failing accept (EMFILE, ENFILE)
+/// fails again right away, so it is logged and retried after a pause
+/// instead of spinning.
+pub async fn accept(listener: &TcpListener, what: &str) -> TcpStream {
+    loop {
+        match listener.accept().await {
+            Ok((socket, _)) => return socket,
+            Err(err) => {
+                warn!("{}: accept failed: {}", what, err);
+                tokio::time::sleep(ACCEPT_RETRY).await;
+            }
+        }
+    }
+}
+
+/// fe80::/10 addresses only work together with an interface scope, which
+/// neither URLs nor the server can use.
+pub fn is_link_local(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => addr.is_link_local(),
+        IpAddr::V6(addr) => addr.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+/// `bind_interface` / `bind_address` from the config: which NIC discovery,
+/// the reported identity and ingest connections use on multi-homed hosts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Binding {
+    pub interface: Option<String>,
+    pub address: Option<IpAddr>,
+}
+
+impl Binding {
+    pub fn new(interface: Option<&str>, address: Option<&str>) -> Result<Self> {
+        let address = address
+            .map(|address| {
+                address
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .parse::<IpAddr>()
+                    .with_context(|| format!("invalid bind_address {}", address))
+            })
+            .transpose()?;
+        Ok(Self {
+            interface: interface.map(str::to_string),
+            address,
+        })
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.interface.is_some() || self.address.is_some()
+    }
+
+    /// Non-loopback interfaces with their addresses, limited to the bound
+    /// interface or address.
+    pub fn interfaces(&self) -> Vec<(String, IpAddr)> {
+        get_if_addrs::get_if_addrs()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|iface| !iface.is_loopback())
+            .filter(|iface| {
+                self.interface
+                    .as_ref()
+                    .is_none_or(|name| *name == iface.name)
+            })
+            .filter(|iface| self.address.is_none_or(|address| address == iface.ip()))
+            .map(|iface| (iface.name.clone(), iface.ip()))
+            .collect()
+    }
+
+    /// Interface whose MAC is reported: the bound one, or the one that
+    /// carries the bound address.
+    pub fn interface_name(&self) -> Option<String> {
+        self.interface.clone().or_else(|| {
+            self.address?;
+            self.interfaces().into_iter().next().map(|(name, _)| name)
+        })
+    }
+
+    /// Local addresses for outgoing connections; link-local ones can't be
+    /// used without a scope.
+    pub fn local_addrs(&self) -> LocalAddrs {
+        if !self.is_set() {
+            return LocalAddrs::default();
+        }
+        let addresses: Vec<IpAddr> = match self.address {
+            Some(address) => vec![address],
+            None => self
+                .interfaces()
+                .into_iter()
+                .map(|(_, addr)| addr)
+                .filter(|addr| !is_link_local(addr))
+                .collect(),
+        };
+        LocalAddrs {
+            bound: true,
+            v4: addresses.iter().find_map(|addr| match addr {
+                IpAddr::V4(addr) => Some(*addr),
+                IpAddr::V6(_) => None,
+            }),
+            v6: addresses.iter().find_map(|addr| match addr {
+                IpAddr::V6(addr) => Some(*addr),
+                IpAddr::V4(_) => None,
+            }),
+        }
+    }
+}
+
+/// Source addresses for outgoing TCP, one per family. While `bound`, a
+/// family without an address can't be reached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LocalAddrs {
+    pub bound: bool,
+    pub v4: Option<Ipv4Addr>,
+    pub v6: Option<Ipv6Addr>,
+}
+
+/// Connects to `host:port`, from the bound source address if there is one.
+pub async fn connect(host: &str, port: u16, local: &LocalAddrs) -> io::Result<TcpStream> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if !local.bound {
+        return TcpStream::connect((host, port)).await;
+    }
+    let mut last_err = None;
+    for remote in lookup_host((host, port)).await? {
+        let (socket, source) = match remote {
+            SocketAddr::V4(_) => (TcpSocket::new_v4()?, local.v4.map(IpAddr::V4)),
+            SocketAddr::V6(_) => (TcpSocket::new_v6()?, local.v6.map(IpAddr::V6)),
+        };
+        let Some(source) = source else {
+            last_err = Some(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("no bound address for {}", remote),
+            ));
+            continue;
+        };
+        socket.bind(SocketAddr::new(source, 0))?;
+        match socket.connect(remote).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", host))
+    }))
+}
@@ -0,0 +1,94 @@
+//! OTLP export of the bridge's lifecycle spans (discovery, registration,
+//! capture sessions, ingest connections), so a flapping bridge can be lined
+//! up with server-side traces. Only in builds with the `otel` feature; the
+//! spans themselves always exist and show up as context in log lines.
+//!
+//! Export is on when `--otlp-endpoint` or the standard
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` / `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`
+//! variables are set. Spans go out over OTLP/HTTP (protobuf).
+
+#[cfg(feature = "otel")]
+mod enabled {
+    use anyhow::{Context, Result};
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+    use opentelemetry_sdk::Resource;
+    use std::sync::OnceLock;
+    use tracing::Subscriber;
+    use tracing_subscriber::filter::{Filtered, Targets};
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::Layer;
+
+    const SERVICE_NAME: &str = "lox-linein-bridge";
+
+    static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+    pub type OtelLayer<S> =
+        Filtered<tracing_opentelemetry::OpenTelemetryLayer<S, SdkTracer>, Targets, S>;
+
+    /// The export layer, or `None` when no endpoint is configured.
+    pub fn layer<S>(endpoint: Option<&str>) -> Result<Option<OtelLayer<S>>>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let from_env = [
+            "OTEL_EXPORTER_OTLP_ENDPOINT",
+            "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+        ]
+        .iter()
+        .any(|name| std::env::var_os(name).is_some());
+        if endpoint.is_none() && !from_env {
+            return Ok(None);
+        }
+        let mut exporter = opentelemetry_otlp::SpanExporter::builder().with_http();
+        if let Some(endpoint) = endpoint {
+            exporter = exporter.with_endpoint(endpoint);
+        }
+        let exporter = exporter.build().context("create otlp exporter")?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+            .build();
+        let tracer = provider.tracer(SERVICE_NAME);
+        let _ = PROVIDER.set(provider);
+        // Only the bridge's own spans; HTTP client spans would include the
+        // export requests themselves.
+        let targets = Targets::new().with_target("lox_linein_bridge", tracing::Level::INFO);
+        Ok(Some(
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(targets),
+        ))
+    }
+
+    /// Sends the spans still queued; call before the process exits.
+    pub fn shutdown() {
+        if let Some(provider) = PROVIDER.get() {
+            if let Err(err) = provider.shutdown() {
+                eprintln!("otlp export shutdown failed: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "otel")]
+pub use enabled::{layer, shutdown};
+
+#[cfg(not(feature = "otel"))]
+mod disabled {
+    use anyhow::Result;
+    use tracing_subscriber::layer::Identity;
+
+    pub fn layer(endpoint: Option<&str>) -> Result<Option<Identity>> {
+        if endpoint.is_some() {
+            eprintln!("--otlp-endpoint ignored: this build has no otel support");
+        }
+        Ok(None)
+    }
+
+    pub fn shutdown() {}
+}
+
+#[cfg(not(feature = "otel"))]
+pub use disabled::{layer, shutdown};
@@ -0,0 +1,118 @@
+//! `[overrides]` in `config.toml`: values that win over what the server
+//! sends, e.g. to pin a VAD threshold or resampler while debugging. Unlike
+//! presets, which only fill in what the server left unset, every value set
+//! here replaces the server's. The active overrides go out with each status
+//! post, so the server can show why its settings don't apply.
+
+use crate::models::BridgeConfigResponse;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Overrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_device: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_exclusive: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_period_frames: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingest_sample_rate: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingest_resampler: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingest_packet_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingest_reconnect_buffer_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_gain_db: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_ceiling_db: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vad_enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vad_mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vad_threshold_db: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vad_threshold_open_db: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vad_threshold_close_db: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vad_attack_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vad_hold_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vad_preroll_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vad_silence_fill: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_detect: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_interval_secs: Option<u64>,
+}
+
+impl Overrides {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The server config with every overridden field replaced.
+    pub fn apply(&self, mut response: BridgeConfigResponse) -> BridgeConfigResponse {
+        response.capture_device = self
+            .capture_device
+            .clone()
+            .map(Some)
+            .or(response.capture_device);
+        response.capture_exclusive = self.capture_exclusive.or(response.capture_exclusive);
+        response.capture_period_frames = self
+            .capture_period_frames
+            .map(Some)
+            .or(response.capture_period_frames);
+        response.ingest_sample_rate = self.ingest_sample_rate.or(response.ingest_sample_rate);
+        response.ingest_resampler = self.ingest_resampler.clone().or(response.ingest_resampler);
+        response.ingest_packet_ms = self.ingest_packet_ms.or(response.ingest_packet_ms);
+        response.ingest_reconnect_buffer_ms = self
+            .ingest_reconnect_buffer_ms
+            .or(response.ingest_reconnect_buffer_ms);
+        response.input_gain_db = self.input_gain_db.or(response.input_gain_db);
+        response.output_ceiling_db = self
+            .output_ceiling_db
+            .map(Some)
+            .or(response.output_ceiling_db);
+        response.vad_enabled = self.vad_enabled.or(response.vad_enabled);
+        response.vad_mode = self.vad_mode.clone().or(response.vad_mode);
+        response.vad_threshold_db = self.vad_threshold_db.or(response.vad_threshold_db);
+        response.vad_threshold_open_db = self
+            .vad_threshold_open_db
+            .map(Some)
+            .or(response.vad_threshold_open_db);
+        response.vad_threshold_close_db = self
+            .vad_threshold_close_db
+            .map(Some)
+            .or(response.vad_threshold_close_db);
+        response.vad_attack_ms = self.vad_attack_ms.or(response.vad_attack_ms);
+        response.vad_hold_ms = self.vad_hold_ms.or(response.vad_hold_ms);
+        response.vad_preroll_ms = self.vad_preroll_ms.or(response.vad_preroll_ms);
+        response.vad_silence_fill = self.vad_silence_fill.or(response.vad_silence_fill);
+        response.track_detect = self.track_detect.clone().or(response.track_detect);
+        response.log_level = self.log_level.clone().or(response.log_level);
+        response.log_target = self.log_target.clone().or(response.log_target);
+        response.log_file = self.log_file.clone().or(response.log_file);
+        response.status_interval_secs = self.status_interval_secs.or(response.status_interval_secs);
+        response
+    }
+
+    /// The `overrides` field of status posts: the set values by name.
+    pub fn report(&self) -> Option<serde_json::Value> {
+        if self.is_empty() {
+            return None;
+        }
+        serde_json::to_value(self).ok()
+    }
+}
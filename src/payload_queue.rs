@@ -0,0 +1,74 @@
+//! Hand-off of paced payloads to a writer thread (recorder, HLS encoder).
+//! The queue is bounded by the audio it holds rather than by a payload
+//! count, so it covers the same time whatever `ingest_packet_ms` is.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+/// Interleaved s16le stereo.
+const FRAME_BYTES: usize = 4;
+
+#[derive(Clone)]
+pub struct PayloadSender {
+    tx: mpsc::Sender<(u32, Vec<u8>)>,
+    queued_us: Arc<AtomicU64>,
+    max_us: u64,
+}
+
+pub struct PayloadReceiver {
+    rx: mpsc::Receiver<(u32, Vec<u8>)>,
+    queued_us: Arc<AtomicU64>,
+}
+
+pub fn channel(max: Duration) -> (PayloadSender, PayloadReceiver) {
+    let (tx, rx) = mpsc::channel();
+    let queued_us = Arc::new(AtomicU64::new(0));
+    (
+        PayloadSender {
+            tx,
+            queued_us: Arc::clone(&queued_us),
+            max_us: max.as_micros() as u64,
+        },
+        PayloadReceiver { rx, queued_us },
+    )
+}
+
+impl PayloadSender {
+    /// Queues a copy of `pcm` (s16le stereo at `rate`). Dropped, returning
+    /// false, when the writer is that far behind or gone.
+    pub fn try_send(&self, pcm: &[u8], rate: u32) -> bool {
+        let us = payload_us(pcm, rate);
+        let queued = self.queued_us.fetch_add(us, Ordering::Relaxed);
+        if queued + us > self.max_us || self.tx.send((rate, pcm.to_vec())).is_err() {
+            self.queued_us.fetch_sub(us, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+}
+
+impl PayloadReceiver {
+    #[cfg_attr(not(feature = "hls"), allow(dead_code))]
+    pub fn recv(&self) -> Result<(u32, Vec<u8>), mpsc::RecvError> {
+        self.rx.recv().inspect(|payload| self.taken(payload))
+    }
+
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<(u32, Vec<u8>), mpsc::RecvTimeoutError> {
+        self.rx
+            .recv_timeout(timeout)
+            .inspect(|payload| self.taken(payload))
+    }
+
+    fn taken(&self, (rate, pcm): &(u32, Vec<u8>)) {
+        self.queued_us
+            .fetch_sub(payload_us(pcm, *rate), Ordering::Relaxed);
+    }
+}
+
+fn payload_us(pcm: &[u8], rate: u32) -> u64 {
+    (pcm.len() / FRAME_BYTES) as u64 * 1_000_000 / u64::from(rate.max(1))
+}
@@ -0,0 +1,93 @@
+//! `pipe` command: captures locally and writes the processed stream as bare
+//! s16le stereo to stdout or a named pipe, without a server. Meant for
+//! piping the bridge into ffmpeg, aplay or custom tooling while testing.
+
+use crate::audio::{self, CaptureOptions, ResamplerMode};
+use crate::cli::DeviceArgs;
+use crate::framing::IngestFraming;
+use crate::i18n::t;
+use crate::limiter::OutputSettings;
+use crate::sink::SocketOptions;
+use crate::stream::{self, IngestTarget, StatusHandle, StreamParams, VadSettings};
+use crate::track::TrackDetectMode;
+use crate::vad::VadMode;
+use anyhow::Result;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const DEFAULT_RATE: u32 = 48_000;
+
+#[derive(Debug, clap::Args)]
+pub struct PipeOptions {
+    #[command(flatten)]
+    capture: DeviceArgs,
+    #[arg(long, default_value_t = DEFAULT_RATE)]
+    rate: u32,
+    /// Named pipe to write to instead of stdout.
+    #[arg(long, value_name = "PATH")]
+    fifo: Option<PathBuf>,
+    /// Gate threshold; without it audio flows continuously.
+    #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+    vad_threshold_db: Option<f32>,
+}
+
+pub async fn run_pipe(options: PipeOptions) -> Result<()> {
+    let device = audio::default_capture_device(options.capture.device)?;
+    let target = match options.fifo {
+        Some(path) => IngestTarget::Fifo(path),
+        None => IngestTarget::Stdout,
+    };
+    // Stdout may carry the audio, so this goes to stderr.
+    eprintln!(
+        "{}",
+        t!(
+            "pipe-started",
+            device = device.as_str(),
+            rate = options.rate,
+            target = target.label(),
+        )
+    );
+    let session = audio::start_capture(
+        &device,
+        options.rate,
+        ResamplerMode::SincQuality,
+        CaptureOptions::default(),
+        OutputSettings::default(),
+    )?;
+    let audio::CaptureSession {
+        receiver,
+        error_receiver,
+        streams,
+        ..
+    } = session;
+    let _capture_guard = streams;
+    let vad = VadSettings {
+        enabled: options.vad_threshold_db.is_some(),
+        mode: VadMode::Rms,
+        open_db: options.vad_threshold_db.unwrap_or(-45.0),
+        close_db: options.vad_threshold_db.unwrap_or(-45.0) - 3.0,
+        attack: Duration::ZERO,
+        hold: Duration::from_secs(2),
+        preroll: Duration::from_millis(500),
+        silence_fill: false,
+        track_detect: TrackDetectMode::Gap,
+    };
+    stream::stream_audio(StreamParams {
+        ingest: vec![target],
+        rx: receiver,
+        err_rx: error_receiver,
+        vad,
+        vad_updates: None,
+        status: StatusHandle::new("", ""),
+        output_rate: options.rate,
+        tee_path: None,
+        framing: IngestFraming::Raw,
+        socket: SocketOptions::default(),
+        reconnect_buffer: Duration::ZERO,
+        packet: Duration::from_millis(crate::DEFAULT_PACKET_MS),
+        hls: None,
+        monitor: None,
+        recorder: None,
+    })
+    .await
+}
@@ -0,0 +1,94 @@
+//! Byte buffers for capture chunks. A chunk returns its allocation to the
+//! pool when the consumer drops it, so once the pool is warm the capture
+//! callback hands out recycled buffers instead of allocating.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+/// Buffers kept for reuse; further returned buffers are freed.
+const MAX_POOLED: usize = 64;
+
+#[derive(Clone)]
+pub struct ChunkPool {
+    free: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl Default for ChunkPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChunkPool {
+    pub fn new() -> Self {
+        Self {
+            free: Arc::new(Mutex::new(Vec::with_capacity(MAX_POOLED))),
+        }
+    }
+
+    /// Empty chunk with room for `capacity` bytes. Never blocks: when the
+    /// consumer holds the lock a fresh buffer is allocated instead.
+    pub fn take(&self, capacity: usize) -> Chunk {
+        let mut data = self
+            .free
+            .try_lock()
+            .ok()
+            .and_then(|mut free| free.pop())
+            .unwrap_or_default();
+        data.clear();
+        data.reserve(capacity);
+        Chunk {
+            data,
+            captured_at_us: 0,
+            pool: Some(self.clone()),
+        }
+    }
+
+    fn recycle(&self, data: Vec<u8>) {
+        if let Ok(mut free) = self.free.lock() {
+            if free.len() < MAX_POOLED {
+                free.push(data);
+            }
+        }
+    }
+}
+
+/// Interleaved stereo s16le audio as sent from capture to the stream.
+pub struct Chunk {
+    data: Vec<u8>,
+    /// Unix µs when the last frame was captured, 0 when unknown.
+    pub captured_at_us: u64,
+    pool: Option<ChunkPool>,
+}
+
+impl From<Vec<u8>> for Chunk {
+    fn from(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            captured_at_us: 0,
+            pool: None,
+        }
+    }
+}
+
+impl Deref for Chunk {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.data
+    }
+}
+
+impl DerefMut for Chunk {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.recycle(std::mem::take(&mut self.data));
+        }
+    }
+}
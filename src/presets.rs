@@ -0,0 +1,117 @@
+use crate::models::BridgeConfigResponse;
+
+/// Recommended settings for a known capture device. Every field only fills
+/// in values the server left unset.
+#[derive(Debug)]
+pub struct Preset {
+    pub name: &'static str,
+    /// USB `vendor:product` ids of the card, as in `/proc/asound/<card>/usbid`.
+    usb_ids: &'static [&'static str],
+    /// Substrings of the ALSA device name that identify the device, for
+    /// cards that aren't on USB.
+    matches: &'static [&'static str],
+    sample_rate: Option<u32>,
+    resampler: Option<&'static str>,
+    period_frames: Option<u32>,
+    input_gain_db: Option<f32>,
+    vad_threshold_db: Option<f32>,
+    vad_hold_ms: Option<u64>,
+}
+
+const PRESETS: &[Preset] = &[
+    Preset {
+        // Low line-level input, clean converter.
+        name: "Behringer UCA202/UCA222",
+        // TI PCM2902 converter.
+        usb_ids: &["08bb:2902"],
+        matches: &[],
+        sample_rate: Some(48_000),
+        resampler: Some("sinc"),
+        period_frames: Some(1024),
+        input_gain_db: Some(6.0),
+        vad_threshold_db: Some(-50.0),
+        vad_hold_ms: None,
+    },
+    Preset {
+        // Cheap mono mic dongles with a noisy front end.
+        name: "C-Media CM108",
+        usb_ids: &["0d8c:000c", "0d8c:0012", "0d8c:0014", "0d8c:013c"],
+        matches: &[],
+        sample_rate: Some(48_000),
+        resampler: Some("sinc-fast"),
+        period_frames: Some(1024),
+        input_gain_db: None,
+        vad_threshold_db: Some(-40.0),
+        vad_hold_ms: Some(3000),
+    },
+    Preset {
+        name: "HiFiBerry DAC+ADC",
+        usb_ids: &[],
+        matches: &["sndrpihifiberry", "HiFiBerry"],
+        sample_rate: Some(48_000),
+        resampler: Some("sinc"),
+        period_frames: Some(512),
+        input_gain_db: None,
+        vad_threshold_db: Some(-55.0),
+        vad_hold_ms: None,
+    },
+];
+
+pub fn for_device(device: &str) -> Option<&'static Preset> {
+    let usb_id = usb_id(device);
+    PRESETS.iter().find(|preset| {
+        usb_id
+            .as_deref()
+            .is_some_and(|id| preset.usb_ids.contains(&id))
+            || preset
+                .matches
+                .iter()
+                .any(|pattern| device.contains(pattern))
+    })
+}
+
+/// USB id of the card behind an ALSA PCM name such as
+/// `plughw:CARD=Device,DEV=0` or `hw:1,0`. Card names like `Device` or
+/// `CODEC` are shared by many unrelated adapters; the id is not.
+fn usb_id(device: &str) -> Option<String> {
+    let (_, args) = device.split_once(':')?;
+    let card = args.split(',').enumerate().find_map(|(idx, part)| {
+        part.strip_prefix("CARD=")
+            .or((idx == 0).then_some(part))
+            .filter(|card| !card.is_empty() && !card.contains(['/', '=']))
+    })?;
+    let card_dir = if card.chars().all(|c| c.is_ascii_digit()) {
+        format!("card{}", card)
+    } else {
+        card.to_string()
+    };
+    let id = std::fs::read_to_string(format!("/proc/asound/{}/usbid", card_dir)).ok()?;
+    Some(id.trim().to_ascii_lowercase())
+}
+
+/// Fills unset fields of a server config from the preset of its capture
+/// device, so server values always win.
+pub fn apply(mut response: BridgeConfigResponse) -> BridgeConfigResponse {
+    let Some(preset) = response
+        .capture_device
+        .clone()
+        .flatten()
+        .and_then(|device| for_device(&device))
+    else {
+        return response;
+    };
+    response.ingest_sample_rate = response.ingest_sample_rate.or(preset.sample_rate);
+    response.ingest_resampler = response
+        .ingest_resampler
+        .or_else(|| preset.resampler.map(str::to_string));
+    response.capture_period_frames = Some(
+        response
+            .capture_period_frames
+            .flatten()
+            .or(preset.period_frames),
+    );
+    response.input_gain_db = response.input_gain_db.or(preset.input_gain_db);
+    response.vad_threshold_db = response.vad_threshold_db.or(preset.vad_threshold_db);
+    response.vad_hold_ms = response.vad_hold_ms.or(preset.vad_hold_ms);
+    response
+}
@@ -0,0 +1,153 @@
+//! `record` command: captures through the same capture, resample, gain and
+//! ceiling stages the bridge streams with and writes the result to a local
+//! WAV file, to hear what the server receives when chasing quality
+//! complaints. Settings the server would send are given as flags; device
+//! presets fill in the rest exactly as during streaming.
+
+use crate::audio;
+use crate::config;
+use crate::i18n::t;
+use crate::models::BridgeConfigResponse;
+use crate::shutdown::Shutdown;
+use crate::wav;
+use crate::RuntimeConfig;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+const DEFAULT_SECONDS: u64 = 30;
+
+#[derive(Debug, clap::Args)]
+pub struct RecordOptions {
+    /// Capture device; defaults to `capture_device`, then the first one
+    /// that isn't a monitor.
+    #[arg(long)]
+    device: Option<String>,
+    #[arg(long, default_value_t = DEFAULT_SECONDS)]
+    seconds: u64,
+    /// WAV file to write.
+    #[arg(long, value_name = "PATH")]
+    out: PathBuf,
+    /// `ingest_sample_rate` the server would send.
+    #[arg(long)]
+    rate: Option<u32>,
+    /// `ingest_resampler` the server would send.
+    #[arg(long, value_name = "MODE", value_parser = parse_resampler)]
+    resampler: Option<String>,
+    /// `input_gain_db` the server would send.
+    #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+    gain_db: Option<f32>,
+    /// `output_ceiling_db` the server would send.
+    #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+    ceiling_db: Option<f32>,
+    /// Opens the raw `hw:` device, like `capture_exclusive`.
+    #[arg(long)]
+    exclusive: bool,
+}
+
+fn parse_resampler(mode: &str) -> Result<String, String> {
+    match audio::ResamplerMode::parse(mode) {
+        Some(_) => Ok(mode.to_string()),
+        None => Err(format!("unknown resampler: {}", mode)),
+    }
+}
+
+pub async fn run_record(options: RecordOptions) -> Result<()> {
+    // A test recording shouldn't create a config or move a broken one aside.
+    let configured = config::load_existing_config().and_then(|config| config.capture_device);
+    let device = audio::default_capture_device(options.device.or(configured))?;
+    // Goes through the same defaults and presets as a server response.
+    let response: BridgeConfigResponse = serde_json::from_value(serde_json::json!({
+        "capture_device": device,
+        "ingest_sample_rate": options.rate,
+        "ingest_resampler": options.resampler,
+        "input_gain_db": options.gain_db,
+        "output_ceiling_db": options.ceiling_db,
+        "capture_exclusive": options.exclusive,
+    }))
+    .context("build capture settings")?;
+    let runtime = RuntimeConfig::from_response(response);
+
+    let session = audio::start_capture(
+        &device,
+        runtime.target_rate,
+        runtime.resampler,
+        runtime.capture_options,
+        runtime.output_settings(),
+    )?;
+    let audio::CaptureSession {
+        mut receiver,
+        mut error_receiver,
+        streams,
+        ..
+    } = session;
+    let _capture_guard = streams;
+    let rate = runtime.target_rate;
+    let frame_bytes = audio::TARGET_CHANNELS as u64 * 2;
+    // One WAV file holds just under 4 GiB; stop there rather than write
+    // sizes that wrap.
+    let max_bytes = u64::from(wav::MAX_DATA_LEN) / frame_bytes * frame_bytes;
+    let wanted_bytes = options
+        .seconds
+        .saturating_mul(u64::from(rate))
+        .saturating_mul(frame_bytes);
+    if wanted_bytes > max_bytes {
+        println!(
+            "{}",
+            t!(
+                "record-capped",
+                seconds = max_bytes / frame_bytes / u64::from(rate)
+            )
+        );
+    }
+    let wanted_bytes = wanted_bytes.min(max_bytes);
+    println!(
+        "{}",
+        t!(
+            "record-started",
+            device = device.as_str(),
+            rate = rate,
+            resampler = runtime.resampler.label(),
+            seconds = options.seconds,
+            path = options.out.display().to_string(),
+        )
+    );
+
+    let file =
+        File::create(&options.out).with_context(|| format!("create {}", options.out.display()))?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(&wav::header(rate, None))?;
+    let mut shutdown = Shutdown::install();
+    let mut written = 0u64;
+    while written < wanted_bytes && !shutdown.is_requested() {
+        let chunk = tokio::select! {
+            chunk = receiver.recv() => chunk.context("capture stopped")?,
+            Some(err) = error_receiver.recv() => anyhow::bail!("capture failed: {}", err),
+            _ = shutdown.requested() => break,
+            _ = tokio::time::sleep(Duration::from_secs(5)) => anyhow::bail!("capture stalled"),
+        };
+        let take = (chunk.len() as u64).min(wanted_bytes - written) as usize;
+        writer
+            .write_all(&chunk[..take])
+            .with_context(|| format!("write {}", options.out.display()))?;
+        written += take as u64;
+    }
+    // Now that the length is known, replace the streaming header.
+    let data_len = u32::try_from(written).unwrap_or(wav::MAX_DATA_LEN);
+    writer.seek(SeekFrom::Start(0))?;
+    writer.write_all(&wav::header(rate, Some(data_len)))?;
+    writer
+        .flush()
+        .with_context(|| format!("write {}", options.out.display()))?;
+    println!(
+        "{}",
+        t!(
+            "record-written",
+            seconds = format!("{:.1}", (written / frame_bytes) as f64 / rate as f64),
+            path = options.out.display().to_string(),
+        )
+    );
+    Ok(())
+}
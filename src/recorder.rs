@@ -0,0 +1,429 @@
+//! Local recording of the gated stream to timestamped WAV or FLAC files.
+//! Files rotate by duration or size, old ones are pruned by count and age,
+//! and the server can change all of it at runtime through the
+//! `record_*` config fields. Writing happens on its own thread so a slow
+//! disk never holds up the stream.
+
+use crate::flac::{self, FlacEncoder};
+use crate::payload_queue::{self, PayloadReceiver, PayloadSender};
+use crate::timestamp;
+use crate::wav;
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+pub const DEFAULT_DIR: &str = "/var/lib/lox-linein-bridge/recordings";
+/// Audio waiting for the writer before new payloads are dropped.
+const QUEUE: Duration = Duration::from_secs(4);
+/// A file is closed once the gate stays shut this long, so the next audio
+/// starts a new recording.
+const IDLE_CLOSE: Duration = Duration::from_secs(60);
+/// Wait after a file can't be created or written, doubling up to
+/// `RETRY_MAX` while it keeps failing.
+const RETRY_MIN: Duration = Duration::from_secs(5);
+const RETRY_MAX: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    Wav,
+    Flac,
+}
+
+impl RecordFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "wav" => Some(Self::Wav),
+            "flac" => Some(Self::Flac),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Flac => "flac",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordSettings {
+    pub dir: PathBuf,
+    /// File name prefix, usually the assigned input id.
+    pub prefix: String,
+    pub format: RecordFormat,
+    /// Start a new file after this long; zero never rotates by time.
+    pub max_duration: Duration,
+    /// Start a new file past this size; zero never rotates by size.
+    pub max_bytes: u64,
+    /// Recordings kept in `dir`; zero keeps any number.
+    pub keep_files: usize,
+    /// Recordings older than this are deleted; zero keeps them forever.
+    pub keep_age: Duration,
+}
+
+/// Cloneable handle to the recording thread. The stream writes every
+/// payload it sends; the status loop applies server changes.
+#[derive(Clone)]
+pub struct Recorder {
+    tx: PayloadSender,
+    settings: Arc<Mutex<Option<RecordSettings>>>,
+    enabled: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    thread: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl Recorder {
+    pub fn start() -> Result<Self> {
+        let (tx, rx) = payload_queue::channel(QUEUE);
+        let settings = Arc::new(Mutex::new(None));
+        let shared = Arc::clone(&settings);
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let thread = std::thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || run(rx, shared, stopped))
+            .context("spawn recorder")?;
+        Ok(Self {
+            tx,
+            settings,
+            enabled: Arc::new(AtomicBool::new(false)),
+            stop,
+            thread: Arc::new(Mutex::new(Some(thread))),
+        })
+    }
+
+    /// Closes the open file with its final header and ends the thread.
+    /// Anything written afterwards is dropped.
+    pub fn finish(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+        self.stop.store(true, Ordering::Relaxed);
+        let thread = self.thread.lock().ok().and_then(|mut thread| thread.take());
+        if let Some(thread) = thread {
+            let _ = thread.join();
+        }
+    }
+
+    /// Calls `finish` when dropped, so every way out of the bridge leaves
+    /// complete files.
+    pub fn finish_on_drop(&self) -> FinishOnDrop {
+        FinishOnDrop(self.clone())
+    }
+
+    /// Starts, changes or (with `None`) stops recording. The open file is
+    /// closed and a new one started when the settings change.
+    pub fn configure(&self, settings: Option<RecordSettings>) {
+        self.enabled.store(settings.is_some(), Ordering::Relaxed);
+        if let Ok(mut current) = self.settings.lock() {
+            *current = settings;
+        }
+    }
+
+    /// `pcm` is interleaved s16le stereo at `rate`. Dropped when the writer
+    /// falls behind.
+    pub fn write(&self, pcm: &[u8], rate: u32) {
+        if self.enabled.load(Ordering::Relaxed) {
+            self.tx.try_send(pcm, rate);
+        }
+    }
+}
+
+pub struct FinishOnDrop(Recorder);
+
+impl Drop for FinishOnDrop {
+    fn drop(&mut self) {
+        self.0.finish();
+    }
+}
+
+fn run(rx: PayloadReceiver, shared: Arc<Mutex<Option<RecordSettings>>>, stop: Arc<AtomicBool>) {
+    let mut settings: Option<RecordSettings> = None;
+    let mut recording: Option<Recording> = None;
+    let mut retry = Retry::default();
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        let next = match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(next) => Some(next),
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        let latest = shared.lock().map(|value| value.clone()).unwrap_or(None);
+        if latest != settings {
+            close(recording.take(), settings.as_ref());
+            match &latest {
+                Some(latest) => info!(
+                    "recording {} to {}",
+                    latest.format.extension(),
+                    latest.dir.display()
+                ),
+                None if settings.is_some() => info!("recording stopped"),
+                None => {}
+            }
+            settings = latest;
+            retry = Retry::default();
+        }
+        let Some(current) = settings.as_ref() else {
+            continue;
+        };
+        let Some((rate, pcm)) = next else {
+            if recording
+                .as_ref()
+                .is_some_and(|recording| recording.last_write.elapsed() >= IDLE_CLOSE)
+            {
+                close(recording.take(), Some(current));
+            }
+            continue;
+        };
+        if recording.as_ref().is_some_and(|recording| {
+            recording.rate != rate || recording.is_full(current, pcm.len())
+        }) {
+            close(recording.take(), Some(current));
+        }
+        if recording.is_none() {
+            if retry.waiting() {
+                continue;
+            }
+            match Recording::create(current, rate) {
+                Ok(created) => {
+                    info!("recording to {}", created.path.display());
+                    recording = Some(created);
+                    prune(current);
+                }
+                Err(err) => {
+                    retry.failed(&err);
+                    continue;
+                }
+            }
+        }
+        if let Some(active) = recording.as_mut() {
+            match active.write(&pcm) {
+                Ok(()) => retry = Retry::default(),
+                Err(err) => {
+                    retry.failed(&err);
+                    close(recording.take(), Some(current));
+                }
+            }
+        }
+    }
+    close(recording, settings.as_ref());
+}
+
+/// Backoff for a recording that keeps failing, so a full or read-only disk
+/// isn't retried, and logged, for every payload.
+#[derive(Default)]
+struct Retry {
+    delay: Option<Duration>,
+    at: Option<Instant>,
+}
+
+impl Retry {
+    fn waiting(&self) -> bool {
+        self.at.is_some_and(|at| Instant::now() < at)
+    }
+
+    fn failed(&mut self, err: &anyhow::Error) {
+        let delay = self
+            .delay
+            .map_or(RETRY_MIN, |delay| (delay * 2).min(RETRY_MAX));
+        warn!(
+            "recording failed, retrying in {}s: {:#}",
+            delay.as_secs(),
+            err
+        );
+        self.delay = Some(delay);
+        self.at = Some(Instant::now() + delay);
+    }
+}
+
+fn close(recording: Option<Recording>, settings: Option<&RecordSettings>) {
+    let Some(recording) = recording else {
+        return;
+    };
+    let path = recording.path.clone();
+    if let Err(err) = recording.finish() {
+        warn!("closing {} failed: {:#}", path.display(), err);
+    }
+    if let Some(settings) = settings {
+        prune(settings);
+    }
+}
+
+struct Recording {
+    path: PathBuf,
+    file: File,
+    rate: u32,
+    flac: Option<FlacEncoder>,
+    /// Audio bytes received, as PCM.
+    pcm_bytes: u64,
+    /// Bytes written to the file.
+    file_bytes: u64,
+    last_write: Instant,
+}
+
+impl Recording {
+    fn create(settings: &RecordSettings, rate: u32) -> Result<Self> {
+        std::fs::create_dir_all(&settings.dir)
+            .with_context(|| format!("create {}", settings.dir.display()))?;
+        let path = settings.dir.join(format!(
+            "{}-{}.{}",
+            settings.prefix,
+            timestamp::file_stamp(),
+            settings.format.extension()
+        ));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .with_context(|| format!("create {}", path.display()))?;
+        let (flac, header) = match settings.format {
+            // Sizes are patched on close; until then players read to the end.
+            RecordFormat::Wav => (None, wav::header(rate, None).to_vec()),
+            RecordFormat::Flac => {
+                let encoder = FlacEncoder::new(rate);
+                let header = encoder.header();
+                (Some(encoder), header)
+            }
+        };
+        file.write_all(&header)
+            .with_context(|| format!("write {}", path.display()))?;
+        Ok(Self {
+            path,
+            file,
+            rate,
+            flac,
+            pcm_bytes: 0,
+            file_bytes: header.len() as u64,
+            last_write: Instant::now(),
+        })
+    }
+
+    fn write(&mut self, pcm: &[u8]) -> Result<()> {
+        let encoded;
+        let data = match self.flac.as_mut() {
+            Some(encoder) => {
+                let samples: Vec<i16> = pcm
+                    .chunks_exact(2)
+                    .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+                    .collect();
+                encoded = encoder.push(&samples);
+                encoded.as_slice()
+            }
+            None => pcm,
+        };
+        self.file
+            .write_all(data)
+            .with_context(|| format!("write {}", self.path.display()))?;
+        self.pcm_bytes += pcm.len() as u64;
+        self.file_bytes += data.len() as u64;
+        self.last_write = Instant::now();
+        Ok(())
+    }
+
+    /// Whether the next `pending` PCM bytes belong in a new file. A WAV
+    /// file is closed before it outgrows its 32-bit sizes, whatever the
+    /// configured limits.
+    fn is_full(&self, settings: &RecordSettings, pending: usize) -> bool {
+        let byte_rate = self.rate as u64 * 4;
+        let duration = Duration::from_millis(self.pcm_bytes * 1000 / byte_rate.max(1));
+        let wav_full =
+            self.flac.is_none() && self.pcm_bytes + pending as u64 > u64::from(wav::MAX_DATA_LEN);
+        wav_full
+            || (!settings.max_duration.is_zero() && duration >= settings.max_duration)
+            || (settings.max_bytes > 0 && self.file_bytes >= settings.max_bytes)
+    }
+
+    /// Writes the final header so the file carries its real length.
+    fn finish(mut self) -> Result<()> {
+        let (offset, header) = match self.flac.as_mut() {
+            Some(encoder) => {
+                let tail = encoder.finish();
+                self.file.write_all(&tail)?;
+                (flac::STREAMINFO_OFFSET, encoder.header()[8..].to_vec())
+            }
+            None => {
+                let data_len = self.pcm_bytes.min(u64::from(wav::MAX_DATA_LEN)) as u32;
+                (0, wav::header(self.rate, Some(data_len)).to_vec())
+            }
+        };
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(&header)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Deletes the oldest recordings beyond `keep_files` and those older than
+/// `keep_age`. Only files named like this recorder's output are touched.
+fn prune(settings: &RecordSettings) {
+    let Ok(entries) = std::fs::read_dir(&settings.dir) else {
+        return;
+    };
+    let mut recordings: Vec<(PathBuf, SystemTime)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| is_recording(&entry.path(), &settings.prefix))
+        .filter_map(|entry| {
+            let modified = entry.metadata().ok()?.modified().ok()?;
+            Some((entry.path(), modified))
+        })
+        .collect();
+    // Timestamped names sort oldest first.
+    recordings.sort();
+    let excess = match settings.keep_files {
+        0 => 0,
+        keep => recordings.len().saturating_sub(keep),
+    };
+    let now = SystemTime::now();
+    for (index, (path, modified)) in recordings.iter().enumerate() {
+        let expired = !settings.keep_age.is_zero()
+            && now
+                .duration_since(*modified)
+                .is_ok_and(|age| age > settings.keep_age);
+        if index < excess || expired {
+            match std::fs::remove_file(path) {
+                Ok(()) => info!("deleted old recording {}", path.display()),
+                Err(err) => warn!("deleting {} failed: {}", path.display(), err),
+            }
+        }
+    }
+}
+
+fn is_recording(path: &Path, prefix: &str) -> bool {
+    let Some(rest) = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix(prefix))
+        .and_then(|rest| rest.strip_prefix('-'))
+    else {
+        return false;
+    };
+    let Some((stamp, extension)) = rest.split_once('.') else {
+        return false;
+    };
+    let digits = stamp.bytes().filter(u8::is_ascii_digit).count();
+    stamp.len() == 15
+        && digits == 14
+        && stamp.as_bytes()[8] == b'-'
+        && RecordFormat::parse(extension).is_some()
+}
+
+/// File name prefix for an input id, limited to safe characters.
+pub fn file_prefix(input_id: Option<&str>) -> String {
+    let id = input_id.unwrap_or("linein");
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,200 @@
+use crate::hex::from_hex;
+use crate::hex::to_hex;
+use crate::models::CaptureDeviceInfo;
+use anyhow::{anyhow, Context, Result};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::Path;
+use tracing::warn;
+
+const REDACTED: &str = "redacted";
+/// HMAC key for `hash`, next to the config. It never leaves the device, so
+/// the server can't test guesses against the hashes.
+const HASH_KEY_FILE: &str = "redact.key";
+const KEY_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactMode {
+    #[default]
+    None,
+    /// Replace with a stable keyed hash, so the server can still tell
+    /// bridges and devices apart.
+    Hash,
+    /// Replace with a fixed placeholder.
+    Redact,
+}
+
+/// Which identity fields leave the device in clear text. Sent along with
+/// registration and status payloads whenever any field is redacted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedactionConfig {
+    #[serde(default)]
+    pub hostname: RedactMode,
+    #[serde(default)]
+    pub ip: RedactMode,
+    #[serde(default)]
+    pub mac: RedactMode,
+    #[serde(default)]
+    pub devices: RedactMode,
+}
+
+impl RedactionConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    policy: RedactionConfig,
+    bridge_id: String,
+    key: hmac::Key,
+}
+
+impl Redactor {
+    /// The hash key is read from, or created next to, `config_path`.
+    pub fn new(policy: RedactionConfig, bridge_id: &str, config_path: &Path) -> Self {
+        let hashes = policy.devices != RedactMode::None
+            || [policy.hostname, policy.ip, policy.mac].contains(&RedactMode::Hash);
+        let secret = if hashes {
+            hash_key(&config_path.with_file_name(HASH_KEY_FILE), config_path)
+        } else {
+            [0; KEY_LEN]
+        };
+        Self {
+            policy,
+            bridge_id: bridge_id.to_string(),
+            key: hmac::Key::new(hmac::HMAC_SHA256, &secret),
+        }
+    }
+
+    /// Policy to attach to outgoing payloads, if anything is redacted.
+    pub fn policy(&self) -> Option<RedactionConfig> {
+        (!self.policy.is_default()).then(|| self.policy.clone())
+    }
+
+    pub fn hostname(&self, hostname: &str) -> String {
+        self.apply(self.policy.hostname, hostname, REDACTED)
+    }
+
+    pub fn ip(&self, ip: &str) -> String {
+        let placeholder = if ip.contains(':') { "::" } else { "0.0.0.0" };
+        self.apply(self.policy.ip, ip, placeholder)
+    }
+
+    pub fn mac(&self, mac: &str) -> String {
+        self.apply(self.policy.mac, mac, "00:00:00:00:00:00")
+    }
+
+    /// Device ids are always hashed rather than blanked when devices are
+    /// redacted, so the server can still assign one and we can map it back.
+    pub fn device_id(&self, id: &str) -> String {
+        match self.policy.devices {
+            RedactMode::None => id.to_string(),
+            RedactMode::Hash | RedactMode::Redact => self.hash(id),
+        }
+    }
+
+    pub fn devices(&self, devices: &[CaptureDeviceInfo]) -> Vec<CaptureDeviceInfo> {
+        devices
+            .iter()
+            .enumerate()
+            .map(|(idx, device)| CaptureDeviceInfo {
+                id: self.device_id(&device.id),
+                name: match self.policy.devices {
+                    RedactMode::None => device.name.clone(),
+                    RedactMode::Hash => self.hash(&device.name),
+                    RedactMode::Redact => format!("Capture device {}", idx + 1),
+                },
+                ..device.clone()
+            })
+            .collect()
+    }
+
+    /// Maps a device id received from the server back to the local device.
+    pub fn resolve_device(&self, id: &str, devices: &[CaptureDeviceInfo]) -> String {
+        if self.policy.devices == RedactMode::None {
+            return id.to_string();
+        }
+        devices
+            .iter()
+            .find(|device| self.device_id(&device.id) == id)
+            .map(|device| device.id.clone())
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    fn apply(&self, mode: RedactMode, value: &str, placeholder: &str) -> String {
+        match mode {
+            RedactMode::None => value.to_string(),
+            RedactMode::Hash => self.hash(value),
+            RedactMode::Redact => placeholder.to_string(),
+        }
+    }
+
+    /// HMAC-SHA256 over the bridge id and the value, cut to 64 bits.
+    fn hash(&self, value: &str) -> String {
+        let mut context = hmac::Context::with_key(&self.key);
+        context.update(self.bridge_id.as_bytes());
+        context.update(&[0]);
+        context.update(value.as_bytes());
+        let tag = context.sign();
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&tag.as_ref()[..8]);
+        format!("h:{:016x}", u64::from_be_bytes(bytes))
+    }
+}
+
+/// The key in `path`, created on first use. Without a usable file the
+/// hashes still hide the values, but change with every start.
+fn hash_key(path: &Path, config_path: &Path) -> [u8; KEY_LEN] {
+    let stored = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|text| from_hex(text.trim()))
+        .and_then(|bytes| bytes.try_into().ok());
+    if let Some(key) = stored {
+        return key;
+    }
+    if !path.exists() {
+        match create_key(path, config_path) {
+            Ok(key) => return key,
+            Err(err) => warn!("redaction hash key not stored: {:#}", err),
+        }
+    } else {
+        warn!(
+            "{} is not a valid key, using a temporary one",
+            path.display()
+        );
+    }
+    let mut key = [0; KEY_LEN];
+    let _ = SystemRandom::new().fill(&mut key);
+    key
+}
+
+/// Writes a new random key as hex to a 0600 file. It gets the owner of
+/// `config_path`, so a bridge running as that user can read a key that
+/// root created.
+fn create_key(path: &Path, config_path: &Path) -> Result<[u8; KEY_LEN]> {
+    let mut key = [0u8; KEY_LEN];
+    SystemRandom::new()
+        .fill(&mut key)
+        .map_err(|_| anyhow!("no random numbers for the hash key"))?;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("create {}", path.display()))?;
+    if let Ok(config) = fs::metadata(config_path) {
+        std::os::unix::fs::fchown(&file, Some(config.uid()), Some(config.gid()))
+            .with_context(|| format!("chown {}", path.display()))?;
+    }
+    writeln!(file, "{}", to_hex(&key)).with_context(|| format!("write {}", path.display()))?;
+    file.sync_all()
+        .with_context(|| format!("write {}", path.display()))?;
+    Ok(key)
+}
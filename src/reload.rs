@@ -0,0 +1,211 @@
+//! Config hot-reload. On `SIGHUP`, or when the file's modification time
+//! changes, the config is read again and handed to `run_bridge`:
+//! - `capture_device` and `[overrides]` are applied to the last server
+//!   config again, like a config the server pushed
+//! - server selection, identity and the other per-session settings end the
+//!   session; the bridge discovers or picks its server again and registers
+//! - everything set up once per process (outputs, local API, health) only
+//!   takes effect after a restart, which is logged
+//!
+//! A file that doesn't parse is reported and ignored; the bridge keeps the
+//! config it has.
+
+use crate::config::{self, Config};
+use std::future::pending;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Counts hangups; every `Reloads` subscribes.
+static HANGUP: OnceLock<watch::Sender<u64>> = OnceLock::new();
+
+/// Applied to the runtime config of the running session.
+const LOCAL_KEYS: &[&str] = &["capture_device", "overrides"];
+/// Applied by ending the session and registering again.
+const SESSION_KEYS: &[&str] = &[
+    "bridge_id",
+    "preferred_server_name",
+    "preferred_server_mac",
+    "server_url",
+    "register_path",
+    "status_path",
+    "fallback_servers",
+    "dns_sd_domain",
+    "dns_sd_server",
+    "server_tls",
+    "server_token",
+    "update_public_key",
+    "redact",
+    "sources",
+    "tee_pipe",
+    "tee_pipe_dir",
+];
+/// Read again whenever they are used.
+const ON_USE_KEYS: &[&str] = &["update_url"];
+
+/// Takes over `SIGHUP` for the whole process. Called before any command
+/// runs, since the default action ends the process, e.g. on `systemctl
+/// reload` in manifest mode. Hangups while nothing reloads are logged.
+pub fn install_hangup_handler() {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            warn!("SIGHUP handler unavailable: {}", err);
+            return;
+        }
+    };
+    let tx = HANGUP.get_or_init(|| watch::channel(0).0);
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            if tx.receiver_count() == 0 {
+                info!("SIGHUP ignored, config reloading is off");
+            }
+            tx.send_modify(|count| *count += 1);
+        }
+    });
+}
+
+/// Receives reloaded configs; never fires when reloading is off.
+#[derive(Clone)]
+pub struct Reloads {
+    rx: Option<watch::Receiver<Config>>,
+}
+
+impl Reloads {
+    /// For bridges that don't own their config file, e.g. from a manifest.
+    pub fn disabled() -> Self {
+        Self { rx: None }
+    }
+
+    /// Starts watching `path`; `config` is what the bridge runs with now.
+    pub fn spawn(path: PathBuf, config: Config) -> Self {
+        let (tx, rx) = watch::channel(config);
+        let mut hangup = HANGUP.get().map(watch::Sender::subscribe);
+        tokio::spawn(async move {
+            let mut last_modified = modified(&path);
+            let mut poll = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                let trigger = tokio::select! {
+                    _ = poll.tick() => {
+                        let now = modified(&path);
+                        if now == last_modified {
+                            continue;
+                        }
+                        last_modified = now;
+                        "file change"
+                    }
+                    Ok(()) = async {
+                        match hangup.as_mut() {
+                            Some(hangup) => hangup.changed().await,
+                            None => pending().await,
+                        }
+                    } => "SIGHUP",
+                    _ = tx.closed() => return,
+                };
+                let fresh = match config::reload_config(&path) {
+                    Ok(fresh) => fresh,
+                    Err(err) => {
+                        warn!(
+                            "config reload ({}) failed, keeping the current config: {:#}",
+                            trigger, err
+                        );
+                        continue;
+                    }
+                };
+                let changes = Changes::between(&tx.borrow(), &fresh);
+                if changes.is_empty() {
+                    continue;
+                }
+                info!(
+                    "config reloaded ({}), changed: {}",
+                    trigger,
+                    changes.keys.join(", ")
+                );
+                let _ = tx.send(fresh);
+            }
+        });
+        Self { rx: Some(rx) }
+    }
+
+    /// Waits for the next reload.
+    pub async fn next(&mut self) -> Config {
+        if let Some(rx) = self.rx.as_mut() {
+            if rx.changed().await.is_ok() {
+                return rx.borrow_and_update().clone();
+            }
+        }
+        pending().await
+    }
+
+    /// The reloaded config, if there was a reload since the last call.
+    pub fn take(&mut self) -> Option<Config> {
+        let rx = self.rx.as_mut()?;
+        rx.has_changed()
+            .unwrap_or(false)
+            .then(|| rx.borrow_and_update().clone())
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// The top-level keys that differ between two configs.
+pub struct Changes {
+    keys: Vec<String>,
+}
+
+impl Changes {
+    pub fn between(old: &Config, new: &Config) -> Self {
+        let table = |config: &Config| match toml::Value::try_from(config) {
+            Ok(toml::Value::Table(table)) => table,
+            _ => toml::Table::new(),
+        };
+        let (old, new) = (table(old), table(new));
+        let mut keys: Vec<String> = old
+            .keys()
+            .chain(new.keys())
+            .filter(|key| old.get(*key) != new.get(*key))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.dedup();
+        Self { keys }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn local(&self) -> bool {
+        self.any_of(LOCAL_KEYS)
+    }
+
+    pub fn session(&self) -> bool {
+        self.any_of(SESSION_KEYS)
+    }
+
+    /// Changes that need a restart of the process.
+    pub fn restart(&self) -> Vec<&str> {
+        self.keys
+            .iter()
+            .map(String::as_str)
+            .filter(|key| {
+                ![LOCAL_KEYS, SESSION_KEYS, ON_USE_KEYS]
+                    .iter()
+                    .any(|keys| keys.contains(key))
+            })
+            .collect()
+    }
+
+    fn any_of(&self, keys: &[&str]) -> bool {
+        self.keys.iter().any(|key| keys.contains(&key.as_str()))
+    }
+}
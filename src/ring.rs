@@ -0,0 +1,154 @@
+//! Single-producer single-consumer ring of interleaved samples, handing
+//! capture data from the audio callback to the processing thread (and
+//! monitor audio the other way, to a playback callback). Neither side
+//! locks or allocates. When the consumer falls behind, incoming frames are
+//! dropped whole and counted instead of blocking the callback.
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread::Thread;
+
+struct Shared {
+    slots: Box<[UnsafeCell<f32>]>,
+    mask: usize,
+    /// Total samples written and read; slot indices are these modulo the
+    /// (power of two) capacity.
+    written: AtomicUsize,
+    read: AtomicUsize,
+    dropped_frames: AtomicU64,
+    closed: AtomicBool,
+    /// Woken after each push.
+    consumer_thread: OnceLock<Thread>,
+}
+
+// The producer only writes slots outside `read..written` and the consumer
+// only reads slots inside it; the index stores publish the slot contents.
+unsafe impl Sync for Shared {}
+
+/// Creates a ring holding at least `frames` frames of `channels` samples.
+pub fn ring(frames: usize, channels: u16) -> (Producer, Consumer) {
+    let channels = channels.max(1) as usize;
+    let capacity = (frames.max(1) * channels).next_power_of_two();
+    let shared = Arc::new(Shared {
+        slots: (0..capacity).map(|_| UnsafeCell::new(0.0)).collect(),
+        mask: capacity - 1,
+        written: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+        dropped_frames: AtomicU64::new(0),
+        closed: AtomicBool::new(false),
+        consumer_thread: OnceLock::new(),
+    });
+    (
+        Producer {
+            shared: Arc::clone(&shared),
+            channels,
+        },
+        Consumer { shared },
+    )
+}
+
+pub struct Producer {
+    shared: Arc<Shared>,
+    channels: usize,
+}
+
+impl Producer {
+    pub fn push(&mut self, data: &[f32]) {
+        self.push_map(data, |sample| sample);
+    }
+
+    /// Converts and writes as many whole frames as fit; the rest of `data`
+    /// is dropped and counted.
+    pub fn push_map<T: Copy>(&mut self, data: &[T], convert: impl Fn(T) -> f32) {
+        let shared = &*self.shared;
+        let written = shared.written.load(Ordering::Relaxed);
+        let read = shared.read.load(Ordering::Acquire);
+        let free = shared.slots.len() - written.wrapping_sub(read);
+        let fit = free.min(data.len()) / self.channels * self.channels;
+        for (offset, sample) in data[..fit].iter().enumerate() {
+            let slot = &shared.slots[written.wrapping_add(offset) & shared.mask];
+            unsafe { *slot.get() = convert(*sample) };
+        }
+        shared
+            .written
+            .store(written.wrapping_add(fit), Ordering::Release);
+        let dropped = (data.len() - fit) / self.channels;
+        if dropped > 0 {
+            shared
+                .dropped_frames
+                .fetch_add(dropped as u64, Ordering::Relaxed);
+        }
+        self.wake();
+    }
+
+    fn wake(&self) {
+        if let Some(thread) = self.shared.consumer_thread.get() {
+            thread.unpark();
+        }
+    }
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.wake();
+    }
+}
+
+pub struct Consumer {
+    shared: Arc<Shared>,
+}
+
+impl Consumer {
+    /// Makes each push wake the calling thread, which can then park while
+    /// the ring is empty. Only the first call counts.
+    pub fn wake_current_thread(&self) {
+        let _ = self.shared.consumer_thread.set(std::thread::current());
+    }
+
+    /// Appends every available sample to `out` and returns how many.
+    pub fn pop_into(&mut self, out: &mut Vec<f32>) -> usize {
+        let shared = &*self.shared;
+        let read = shared.read.load(Ordering::Relaxed);
+        let written = shared.written.load(Ordering::Acquire);
+        let available = written.wrapping_sub(read);
+        out.reserve(available);
+        for offset in 0..available {
+            let slot = &shared.slots[read.wrapping_add(offset) & shared.mask];
+            out.push(unsafe { *slot.get() });
+        }
+        shared
+            .read
+            .store(read.wrapping_add(available), Ordering::Release);
+        available
+    }
+
+    /// Fills the front of `out` with as many samples as are available and
+    /// returns how many; for playback callbacks, which must not allocate.
+    pub fn pop_slice(&mut self, out: &mut [f32]) -> usize {
+        let shared = &*self.shared;
+        let read = shared.read.load(Ordering::Relaxed);
+        let written = shared.written.load(Ordering::Acquire);
+        let count = written.wrapping_sub(read).min(out.len());
+        for (offset, sample) in out[..count].iter_mut().enumerate() {
+            let slot = &shared.slots[read.wrapping_add(offset) & shared.mask];
+            *sample = unsafe { *slot.get() };
+        }
+        shared
+            .read
+            .store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// True once the producer is gone. Samples pushed before that are still
+    /// returned by the next `pop_into`.
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+
+    /// Frames dropped because the ring was full.
+    pub fn dropped_frames(&self) -> u64 {
+        self.shared.dropped_frames.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,121 @@
+//! RTP sender for L16 audio (RFC 3551): big-endian 16-bit stereo, one RTP
+//! timestamp tick per frame. Destinations may be unicast or multicast;
+//! multicast goes out with the system default TTL of 1, so it stays on the
+//! local subnet. Time the bridge doesn't send, such as audio held back by
+//! the VAD gate, still advances the RTP timestamp, and the first packet
+//! after such a gap carries the marker bit.
+
+use anyhow::{Context, Result};
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::UdpSocket;
+
+const HEADER_LEN: usize = 12;
+const FRAME_BYTES: usize = 4;
+/// Keeps packets below a typical 1500 byte MTU.
+const MAX_PAYLOAD: usize = 1400;
+/// Packet duration: 5 ms, unless that exceeds `MAX_PAYLOAD`.
+const PACKETS_PER_SECOND: u32 = 200;
+/// RFC 3551 static payload type for L16 stereo at 44.1 kHz; other rates
+/// need a dynamic type negotiated out of band.
+const PT_L16_STEREO_44K1: u8 = 10;
+const PT_DYNAMIC: u8 = 96;
+const MARKER: u8 = 0x80;
+
+/// Payload type used when the server doesn't pick one.
+pub fn default_payload_type(rate: u32) -> u8 {
+    if rate == 44_100 {
+        PT_L16_STEREO_44K1
+    } else {
+        PT_DYNAMIC
+    }
+}
+
+pub struct RtpSender {
+    socket: UdpSocket,
+    dest: SocketAddr,
+    payload_type: u8,
+    ssrc: u32,
+    sequence: u16,
+    timestamp: u32,
+    rate: u32,
+    /// Capture time the next payload starts at if nothing was left out.
+    next_capture_us: Option<u64>,
+    frames_per_packet: usize,
+    packet: Vec<u8>,
+}
+
+impl RtpSender {
+    pub async fn connect(addr: &str, payload_type: u8, ssrc: u32, rate: u32) -> Result<Self> {
+        let dest = tokio::net::lookup_host(addr)
+            .await
+            .with_context(|| format!("resolve {}", addr))?
+            .next()
+            .with_context(|| format!("resolve {}: no address", addr))?;
+        let bind: SocketAddr = match dest.ip() {
+            IpAddr::V4(_) => "0.0.0.0:0".parse().expect("valid address"),
+            IpAddr::V6(_) => "[::]:0".parse().expect("valid address"),
+        };
+        let socket = UdpSocket::bind(bind)
+            .await
+            .with_context(|| format!("bind udp socket for {}", addr))?;
+        // Random starting points, as RFC 3550 recommends.
+        let seed = uuid::Uuid::new_v4().as_u128();
+        Ok(Self {
+            socket,
+            dest,
+            payload_type: payload_type & 0x7f,
+            ssrc,
+            sequence: seed as u16,
+            timestamp: (seed >> 16) as u32,
+            rate: rate.max(1),
+            next_capture_us: None,
+            frames_per_packet: ((rate / PACKETS_PER_SECOND) as usize)
+                .clamp(1, MAX_PAYLOAD / FRAME_BYTES),
+            packet: Vec::with_capacity(HEADER_LEN + MAX_PAYLOAD),
+        })
+    }
+
+    /// Sends interleaved s16le stereo PCM, captured at `captured_at_us`, as
+    /// consecutive packets.
+    pub async fn send(&mut self, pcm: &[u8], captured_at_us: u64) -> Result<()> {
+        let packet_us = self.frames_us(self.frames_per_packet as u64);
+        let (start_us, mut marker) = match self.next_capture_us {
+            // Less than a packet of drift is capture jitter, not a gap.
+            Some(expected) if captured_at_us > expected + packet_us => {
+                let skipped = (captured_at_us - expected) * self.rate as u64 / 1_000_000;
+                self.timestamp = self.timestamp.wrapping_add(skipped as u32);
+                (captured_at_us, true)
+            }
+            Some(expected) => (expected, false),
+            None => (captured_at_us, true),
+        };
+        let frames = (pcm.len() / FRAME_BYTES) as u64;
+        self.next_capture_us = Some(start_us + self.frames_us(frames));
+        for frames in pcm.chunks(self.frames_per_packet * FRAME_BYTES) {
+            self.packet.clear();
+            self.packet.push(0x80); // version 2, no padding/extension/CSRC
+            self.packet
+                .push(self.payload_type | if marker { MARKER } else { 0 });
+            marker = false;
+            self.packet.extend_from_slice(&self.sequence.to_be_bytes());
+            self.packet.extend_from_slice(&self.timestamp.to_be_bytes());
+            self.packet.extend_from_slice(&self.ssrc.to_be_bytes());
+            for sample in frames.chunks_exact(2) {
+                self.packet.extend_from_slice(&[sample[1], sample[0]]);
+            }
+            self.socket
+                .send_to(&self.packet, self.dest)
+                .await
+                .with_context(|| format!("send rtp to {}", self.dest))?;
+            self.sequence = self.sequence.wrapping_add(1);
+            self.timestamp = self
+                .timestamp
+                .wrapping_add((frames.len() / FRAME_BYTES) as u32);
+        }
+        Ok(())
+    }
+
+    fn frames_us(&self, frames: u64) -> u64 {
+        frames * 1_000_000 / self.rate as u64
+    }
+}
@@ -0,0 +1,102 @@
+//! systemd notification protocol (`Type=notify`): `READY=1` once the bridge
+//! registered, `WATCHDOG=1` heartbeats while it runs and `STOPPING=1` on
+//! shutdown. Without `NOTIFY_SOCKET`, e.g. when started by hand, every call
+//! does nothing.
+//!
+//! The heartbeat has its own timer, so long status intervals or a slow
+//! registration don't starve it. It is only sent while every bridge's
+//! runtime still runs its `beat` task, so a hung bridge is restarted.
+
+use crate::stream::StatusHandle;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// How often a bridge's runtime marks itself alive.
+const BEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(err) = send(&path, state) {
+        debug!("sd_notify {} failed: {}", state, err);
+    }
+}
+
+fn send(path: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    let bytes = path.as_encoded_bytes();
+    // A leading '@' names a socket in the abstract namespace.
+    if let Some(name) = bytes.strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        socket.send_to_addr(state.as_bytes(), &addr)?;
+    } else {
+        socket.send_to(state.as_bytes(), path)?;
+    }
+    Ok(())
+}
+
+pub fn ready() {
+    notify("READY=1");
+}
+
+fn watchdog() {
+    notify("WATCHDOG=1");
+}
+
+/// Sends `WATCHDOG=1` every half `WatchdogSec` while every named status in
+/// `statuses` got a recent `beat`.
+pub fn spawn_watchdog(statuses: Vec<(String, StatusHandle)>) {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    info!("systemd watchdog expects a heartbeat every {:?}", interval);
+    let period = interval / 2;
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(period);
+        loop {
+            ticks.tick().await;
+            let stalled = statuses
+                .iter()
+                .find_map(|(name, status)| match status.beat_age() {
+                    Some(age) if age < period => None,
+                    age => Some((name, age)),
+                });
+            match stalled {
+                None => watchdog(),
+                Some((label, age)) => warn!(
+                    "bridge {} stalled (last beat {:?} ago), holding back the watchdog",
+                    label, age
+                ),
+            }
+        }
+    });
+}
+
+/// Keeps `status` beating while the calling runtime runs tasks.
+pub fn spawn_beat(status: StatusHandle) {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(BEAT_INTERVAL);
+        loop {
+            ticks.tick().await;
+            status.beat();
+        }
+    });
+}
+
+pub fn stopping() {
+    notify("STOPPING=1");
+}
+
+/// `WatchdogSec` of the unit, when systemd expects heartbeats from this
+/// process.
+pub fn watchdog_interval() -> Option<Duration> {
+    let pid = std::env::var("WATCHDOG_PID").ok();
+    if pid.is_some_and(|pid| pid.parse() != Ok(std::process::id())) {
+        return None;
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
@@ -0,0 +1,226 @@
+//! Secrets at rest. With a key, `server_token` and the MQTT password are
+//! stored in `config.toml` as `enc:v1:<hex>` (AES-256-GCM) and decrypted
+//! when the config is loaded, so a copied config or a backup doesn't carry
+//! the tokens in the clear. The key comes from the systemd credential
+//! `secret-key`, or else from `secret.key` next to the config file; both
+//! hold 32 bytes, raw or as hex. Whenever a key is found the bridge writes
+//! these fields encrypted.
+
+use crate::config::{self, Config};
+use crate::hex::{from_hex, to_hex};
+use crate::i18n::t;
+use anyhow::{anyhow, bail, Context, Result};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+const PREFIX: &str = "enc:v1:";
+const KEY_LEN: usize = 32;
+/// Name for `LoadCredential=` / `LoadCredentialEncrypted=`.
+const CREDENTIAL: &str = "secret-key";
+const KEY_FILE: &str = "secret.key";
+
+type SecretField = fn(&mut Config) -> Option<&mut String>;
+
+const SECRET_FIELDS: &[(&str, SecretField)] = &[
+    ("server_token", |config| config.server_token.as_mut()),
+    ("health.mqtt.password", |config| {
+        config.health.mqtt.as_mut()?.password.as_mut()
+    }),
+];
+
+#[derive(Debug, clap::Args)]
+pub struct EncryptSecretsOptions {
+    /// Config file to encrypt instead of the one the bridge would load.
+    #[arg(long, value_name = "PATH")]
+    file: Option<PathBuf>,
+}
+
+pub fn run_encrypt_secrets(options: EncryptSecretsOptions) -> Result<()> {
+    let Some(path) = options.file.or_else(config::existing_config_path) else {
+        bail!("{}", t!("secrets-no-config"));
+    };
+    if find_key(&path)?.is_none() {
+        let key_path = key_file(&path);
+        create_key(&key_path, &path)?;
+        println!(
+            "{}",
+            t!("secrets-key-created", path = key_path.display().to_string())
+        );
+    }
+    let (mut config, _) = config::read_config_file(&path)?;
+    decrypt(&mut config, &path)?;
+    let count = SECRET_FIELDS
+        .iter()
+        .filter(|(_, field)| field(&mut config).is_some())
+        .count();
+    config::save_config(&config, &path)?;
+    println!(
+        "{}",
+        t!(
+            "secrets-encrypted",
+            count = count,
+            path = path.display().to_string()
+        )
+    );
+    Ok(())
+}
+
+/// Replaces every `enc:` value with its plain text. Fails when one can't be
+/// decrypted, rather than running without the token.
+pub fn decrypt(config: &mut Config, path: &Path) -> Result<()> {
+    match open_all(config, path).into_iter().next() {
+        Some((name, err)) => Err(err.context(format!("decrypt {}", name))),
+        None => Ok(()),
+    }
+}
+
+/// Encrypts the plain secrets when there is a key for `path`.
+pub fn encrypt(config: &mut Config, path: &Path) -> Result<()> {
+    let Some(key) = find_key(path)? else {
+        return Ok(());
+    };
+    for (name, field) in SECRET_FIELDS {
+        if let Some(value) = field(config) {
+            if !value.starts_with(PREFIX) {
+                *value = seal(&key, name, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The encrypted fields that can't be decrypted, for `validate-config`.
+pub fn check(config: &Config, path: &Path) -> Vec<(&'static str, anyhow::Error)> {
+    open_all(&mut config.clone(), path)
+}
+
+/// Decrypts the `enc:` values in place; the key is only looked up when
+/// there is one.
+fn open_all(config: &mut Config, path: &Path) -> Vec<(&'static str, anyhow::Error)> {
+    let mut key = None;
+    let mut errors = Vec::new();
+    for (name, field) in SECRET_FIELDS {
+        let Some(value) = field(config) else {
+            continue;
+        };
+        let Some(sealed) = value.strip_prefix(PREFIX) else {
+            continue;
+        };
+        if key.is_none() {
+            match find_key(path) {
+                Ok(found) => key = found,
+                Err(err) => {
+                    errors.push((*name, err));
+                    continue;
+                }
+            }
+        }
+        let Some(key) = &key else {
+            errors.push((
+                *name,
+                anyhow!(
+                    "encrypted, but there is no secret key (credential {} or {})",
+                    CREDENTIAL,
+                    key_file(path).display()
+                ),
+            ));
+            continue;
+        };
+        match open(key, name, sealed) {
+            Ok(plain) => *value = plain,
+            Err(err) => errors.push((*name, err)),
+        }
+    }
+    errors
+}
+
+fn key_file(config_path: &Path) -> PathBuf {
+    config_path.with_file_name(KEY_FILE)
+}
+
+/// The systemd credential wins over the key file.
+fn find_key(config_path: &Path) -> Result<Option<LessSafeKey>> {
+    let credential = std::env::var_os("CREDENTIALS_DIRECTORY")
+        .map(|dir| PathBuf::from(dir).join(CREDENTIAL))
+        .filter(|path| path.exists());
+    let Some(path) = credential.or_else(|| Some(key_file(config_path)).filter(|p| p.exists()))
+    else {
+        return Ok(None);
+    };
+    let data = fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+    // Either 32 raw bytes or their hex text.
+    let bytes = if data.len() == KEY_LEN {
+        data
+    } else {
+        std::str::from_utf8(&data)
+            .ok()
+            .and_then(|text| from_hex(text.trim()))
+            .filter(|bytes| bytes.len() == KEY_LEN)
+            .with_context(|| format!("{} must hold 32 bytes, raw or hex", path.display()))?
+    };
+    let key = UnboundKey::new(&AES_256_GCM, &bytes).map_err(|_| anyhow!("invalid secret key"))?;
+    Ok(Some(LessSafeKey::new(key)))
+}
+
+/// Writes 32 random bytes as hex to a new 0600 file. The file gets the
+/// owner of `config_path`, so a bridge running as that user
+/// (`install --service-user`) can read a key created by root.
+fn create_key(path: &Path, config_path: &Path) -> Result<()> {
+    let mut bytes = [0u8; KEY_LEN];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| anyhow!("no random numbers for the secret key"))?;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("create {}", path.display()))?;
+    if let Ok(config) = fs::metadata(config_path) {
+        let created = file
+            .metadata()
+            .with_context(|| format!("stat {}", path.display()))?;
+        if (created.uid(), created.gid()) != (config.uid(), config.gid()) {
+            std::os::unix::fs::fchown(&file, Some(config.uid()), Some(config.gid()))
+                .with_context(|| format!("chown {}", path.display()))?;
+        }
+    }
+    writeln!(file, "{}", to_hex(&bytes)).with_context(|| format!("write {}", path.display()))?;
+    file.sync_all()
+        .with_context(|| format!("write {}", path.display()))
+}
+
+/// The field name is authenticated too, so a value can't be moved to
+/// another field.
+fn seal(key: &LessSafeKey, name: &str, plain: &str) -> Result<String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| anyhow!("no random numbers for the nonce"))?;
+    let mut data = plain.as_bytes().to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(name.as_bytes()),
+        &mut data,
+    )
+    .map_err(|_| anyhow!("encrypt {} failed", name))?;
+    Ok(format!("{}{}{}", PREFIX, to_hex(&nonce), to_hex(&data)))
+}
+
+fn open(key: &LessSafeKey, name: &str, sealed: &str) -> Result<String> {
+    let data = from_hex(sealed).context("not hex")?;
+    if data.len() < NONCE_LEN {
+        bail!("too short");
+    }
+    let (nonce, data) = data.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| anyhow!("bad nonce"))?;
+    let mut data = data.to_vec();
+    let plain = key
+        .open_in_place(nonce, Aad::from(name.as_bytes()), &mut data)
+        .map_err(|_| anyhow!("wrong secret key or damaged value"))?;
+    String::from_utf8(plain.to_vec()).context("not UTF-8")
+}
@@ -1,69 +1,598 @@
-use crate::models::{BridgeConfigResponse, BridgeRegisterRequest, BridgeStatusRequest};
+use crate::discovery::DiscoveredServer;
+use crate::models::{
+    BridgeConfigResponse, BridgeRegisterRequest, BridgeStatusBatch, BridgeStatusRequest,
+    SpooledStatus,
+};
+use crate::net::{self, LocalAddrs};
+use crate::tls::{self, TlsSettings};
 use anyhow::{Context, Result};
-use reqwest::Client;
+use futures_util::StreamExt;
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tracing::{debug, info, warn};
+
+/// Without timeouts a server that accepts the connection but never answers
+/// would stall registration for good.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Retry delay after the first failed attempt, doubled per attempt.
+const RETRY_FIRST: Duration = Duration::from_millis(500);
+const RETRY_MAX: Duration = Duration::from_secs(8);
+/// Attempts per request, including the first. Status posts repeat anyway
+/// and the status loop backs off on its own; the goodbye has to be quick.
+const REGISTER_ATTEMPTS: u32 = 5;
+const STATUS_ATTEMPTS: u32 = 2;
+const UNREGISTER_ATTEMPTS: u32 = 1;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bridge API versions: 1 is register, status and goodbye; 2 adds the
+/// control channel.
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const CONTROL_CHANNEL_VERSION: u32 = 2;
+
+/// Reconnect delays of the control channel.
+const CONTROL_RETRY_MIN: Duration = Duration::from_secs(2);
+const CONTROL_RETRY_MAX: Duration = Duration::from_secs(60);
+
+/// Where `ServerApi` gets its responses from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ApiMode {
+    #[default]
+    Live,
+    /// Talks to the server and also writes every exchange to the directory.
+    Record(PathBuf),
+    /// Answers from a recorded directory without contacting a server.
+    Replay(PathBuf),
+}
 
 #[derive(Clone)]
 pub struct ServerApi {
     base_url: String,
     register_path: String,
     status_path: String,
+    unregister_path: String,
+    control_path: String,
+    status_batch_path: String,
+    tls: TlsSettings,
+    token: Option<String>,
     client: Client,
+    exchanges: Option<Arc<Exchanges>>,
 }
 
 impl ServerApi {
-    pub fn new(base_url: &str, register_path: &str, status_path: &str) -> Result<Self> {
+    pub fn new(
+        server: &DiscoveredServer,
+        tls: &TlsSettings,
+        token: Option<&str>,
+        mode: &ApiMode,
+    ) -> Result<Self> {
+        let exchanges = match mode {
+            ApiMode::Live => None,
+            ApiMode::Record(dir) => {
+                fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+                Some(Exchanges::new(dir, false))
+            }
+            ApiMode::Replay(dir) => {
+                if !Exchanges::path(dir, "register", 1).exists() {
+                    anyhow::bail!("no recorded registration in {}", dir.display());
+                }
+                Some(Exchanges::new(dir, true))
+            }
+        };
+        // Anyone on the LAN can announce a server, so a discovered one only
+        // gets the token over TLS.
+        let token = match token {
+            Some(_) if server.instance.is_some() && server.base_url.starts_with("http://") => {
+                warn!(
+                    "not sending server_token over plain http to the discovered server {}",
+                    server.base_url
+                );
+                None
+            }
+            token => token,
+        };
         Ok(Self {
-            base_url: base_url.trim_end_matches('/').to_string(),
-            register_path: register_path.to_string(),
-            status_path: status_path.to_string(),
-            client: Client::new(),
+            base_url: server.base_url.trim_end_matches('/').to_string(),
+            register_path: server.register_path.clone(),
+            status_path: server.status_path.clone(),
+            unregister_path: server.unregister_path.clone(),
+            control_path: server.control_path.clone(),
+            status_batch_path: server.status_batch_path.clone(),
+            tls: tls.clone(),
+            token: token.map(str::to_string),
+            client: http_client(tls)?,
+            exchanges: exchanges.map(Arc::new),
         })
     }
 
+    /// Recorded server times are stale, so clock checks skip replays.
+    pub fn is_replay(&self) -> bool {
+        self.replaying().is_some()
+    }
+
+    fn replaying(&self) -> Option<&Exchanges> {
+        self.exchanges
+            .as_deref()
+            .filter(|exchanges| exchanges.replay)
+    }
+
+    fn record(&self, kind: &str, request: &impl Serialize, response: &Value) {
+        if let Some(exchanges) = self.exchanges.as_deref() {
+            if let Err(err) = exchanges.record(kind, request, response) {
+                warn!("recording {} exchange failed: {:#}", kind, err);
+            }
+        }
+    }
+
     pub async fn register_bridge(
         &self,
         request: &BridgeRegisterRequest,
     ) -> Result<BridgeConfigResponse> {
+        self.register(request, REGISTER_ATTEMPTS).await
+    }
+
+    /// A single attempt, for a cached server that may be long gone; the
+    /// bridge discovers again instead of retrying it.
+    pub async fn register_bridge_once(
+        &self,
+        request: &BridgeRegisterRequest,
+    ) -> Result<BridgeConfigResponse> {
+        self.register(request, 1).await
+    }
+
+    async fn register(
+        &self,
+        request: &BridgeRegisterRequest,
+        attempts: u32,
+    ) -> Result<BridgeConfigResponse> {
+        if let Some(exchanges) = self.replaying() {
+            return exchanges.replay("register");
+        }
         let url = format!("{}{}", self.base_url, self.register_path);
         let response = self
-            .client
-            .post(url)
-            .json(request)
-            .send()
+            .send(attempts, || self.client.post(&url).json(request))
             .await
             .context("register bridge")?
             .error_for_status()
             .context("register response status")?;
-        let config = response
-            .json::<BridgeConfigResponse>()
+        let body = response
+            .json::<Value>()
             .await
             .context("parse register response")?;
-        Ok(config)
+        self.record("register", request, &body);
+        serde_json::from_value(body).context("parse register response")
     }
 
+    /// Posts the status. `None` means the server answered 304: the config
+    /// still matches `status.config_revision`.
     pub async fn post_status(
         &self,
         bridge_id: &str,
         status: &BridgeStatusRequest,
-    ) -> Result<BridgeConfigResponse> {
+    ) -> Result<Option<BridgeConfigResponse>> {
+        if let Some(exchanges) = self.replaying() {
+            return exchanges.replay("status").map(Some);
+        }
         let url = format!(
             "{}{}",
             self.base_url,
             self.status_path.replace("{bridge_id}", bridge_id)
         );
         let response = self
-            .client
-            .post(url)
-            .json(status)
-            .send()
+            .send(STATUS_ATTEMPTS, || {
+                let request = self.client.post(&url).json(status);
+                match status.config_revision.as_deref() {
+                    Some(revision) => request.header(IF_NONE_MATCH, format!("\"{}\"", revision)),
+                    None => request,
+                }
+            })
             .await
-            .context("post status")?
+            .context("post status")?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = response
             .error_for_status()
             .context("status response status")?;
-        let config = response
-            .json::<BridgeConfigResponse>()
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim_start_matches("W/").trim_matches('"').to_string());
+        let mut body = response
+            .json::<Value>()
             .await
             .context("parse status response")?;
-        Ok(config)
+        if let (Some(etag), Some(fields)) = (etag, body.as_object_mut()) {
+            fields
+                .entry("config_revision")
+                .or_insert(Value::String(etag));
+        }
+        self.record("status", status, &body);
+        serde_json::from_value(body)
+            .map(Some)
+            .context("parse status response")
+    }
+
+    /// Uploads statuses spooled during an outage. A server without the
+    /// batch endpoint answers 404, 405 or 501; the backlog is then dropped,
+    /// as the server has no use for it. Replays have no server to take the
+    /// batch and skip it; recording sends it to the real server.
+    pub async fn post_status_batch(
+        &self,
+        bridge_id: &str,
+        statuses: &[SpooledStatus],
+    ) -> Result<()> {
+        if self.replaying().is_some() {
+            return Ok(());
+        }
+        let url = format!(
+            "{}{}",
+            self.base_url,
+            self.status_batch_path.replace("{bridge_id}", bridge_id)
+        );
+        let batch = BridgeStatusBatch { statuses };
+        let response = self
+            .send(STATUS_ATTEMPTS, || self.client.post(&url).json(&batch))
+            .await
+            .context("post status batch")?;
+        if matches!(
+            response.status(),
+            StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+        ) {
+            debug!("server has no status batch endpoint");
+            return Ok(());
+        }
+        response
+            .error_for_status()
+            .context("status batch response status")?;
+        Ok(())
+    }
+
+    /// Goodbye on shutdown, so the server drops the bridge from its list
+    /// right away. Servers without the endpoint answer 404 or 405, which is
+    /// not an error; they still see the final `OFFLINE` status.
+    pub async fn unregister_bridge(&self, bridge_id: &str) -> Result<()> {
+        if self.replaying().is_some() {
+            return Ok(());
+        }
+        let url = format!(
+            "{}{}",
+            self.base_url,
+            self.unregister_path.replace("{bridge_id}", bridge_id)
+        );
+        let response = self
+            .send(UNREGISTER_ATTEMPTS, || self.client.delete(&url))
+            .await
+            .context("unregister bridge")?;
+        if matches!(
+            response.status(),
+            StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED
+        ) {
+            debug!("server has no unregister endpoint");
+            return Ok(());
+        }
+        response
+            .error_for_status()
+            .context("unregister response status")?;
+        Ok(())
+    }
+
+    /// Sends the request built by `request` with the bearer token, retrying
+    /// transient failures with backoff and jitter: connection errors,
+    /// timeouts, and 408, 429 and 5xx answers. Other statuses are returned
+    /// as they are.
+    async fn send(&self, attempts: u32, request: impl Fn() -> RequestBuilder) -> Result<Response> {
+        let mut delay = RETRY_FIRST;
+        let mut attempt = 1;
+        loop {
+            let mut builder = request();
+            if let Some(token) = self.token.as_deref() {
+                builder = builder.bearer_auth(token);
+            }
+            let result = builder.send().await;
+            let failure = match &result {
+                Ok(response) if is_retryable(response.status()) => {
+                    Some(response.status().to_string())
+                }
+                Ok(_) => None,
+                Err(err) if err.is_connect() || err.is_timeout() || err.is_request() => {
+                    Some(err.to_string())
+                }
+                Err(_) => None,
+            };
+            match failure {
+                Some(reason) if attempt < attempts => {
+                    debug!(
+                        "attempt {} failed ({}), retrying in {:?}",
+                        attempt, reason, delay
+                    );
+                }
+                _ => return Ok(result?),
+            }
+            tokio::time::sleep(delay.mul_f64(0.8 + 0.4 * crate::random_unit())).await;
+            delay = (delay * 2).min(RETRY_MAX);
+            attempt += 1;
+        }
+    }
+
+    /// Receives config pushes over a WebSocket at the control path and
+    /// forwards them to `updates`; each text message is a config response
+    /// as in status replies. Reconnects with backoff and returns once
+    /// `updates` closes, or right away when the server has no control
+    /// endpoint, leaving changes to status polling. Recording and replay
+    /// cover HTTP exchanges only, so they skip the channel. The socket is
+    /// opened from the bound source address, like the ingest connections.
+    pub async fn follow_control(
+        &self,
+        bridge_id: &str,
+        local: &LocalAddrs,
+        updates: mpsc::Sender<BridgeConfigResponse>,
+    ) {
+        if self.exchanges.is_some() {
+            return;
+        }
+        let url = control_url(
+            &self.base_url,
+            &self.control_path.replace("{bridge_id}", bridge_id),
+        );
+        let mut delay = CONTROL_RETRY_MIN;
+        loop {
+            let result = tokio::select! {
+                result = read_control(&url, self, local, &updates, &mut delay) => result,
+                _ = updates.closed() => return,
+            };
+            match result {
+                Ok(()) => debug!("control channel closed by the server"),
+                Err(err) if is_unsupported(&err) => {
+                    debug!("server has no control channel, relying on status polling");
+                    return;
+                }
+                Err(err) => debug!("control channel: {:#}", err),
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = updates.closed() => return,
+            }
+            delay = (delay * 2).min(CONTROL_RETRY_MAX);
+        }
+    }
+}
+
+pub fn http_client(tls: &TlsSettings) -> Result<Client> {
+    client_builder(tls)?.build().context("create http client")
+}
+
+fn client_builder(tls: &TlsSettings) -> Result<reqwest::ClientBuilder> {
+    let mut builder = Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT);
+    if tls.skip_verify {
+        warn!("server certificates are not verified (insecure_skip_verify)");
+    }
+    if *tls != TlsSettings::default() {
+        builder = builder.use_preconfigured_tls(tls::client_config(tls)?);
+    }
+    Ok(builder)
+}
+
+/// Whether the server answers a request to its register path from the
+/// bound source address; used to fail back to the primary. The server
+/// rejects a `GET` there, so any answer counts except the 502, 503 or 504
+/// of a proxy in front of a stopped server.
+pub async fn probe(server: &DiscoveredServer, tls: &TlsSettings, local: &LocalAddrs) -> bool {
+    let Ok(url) = url::Url::parse(&server.base_url) else {
+        return false;
+    };
+    let source = match url.host() {
+        Some(url::Host::Ipv6(_)) => local.v6.map(std::net::IpAddr::V6),
+        _ => local.v4.map(std::net::IpAddr::V4),
+    };
+    if local.bound && source.is_none() {
+        return false;
+    }
+    let client = match client_builder(tls) {
+        Ok(builder) => builder.local_address(source).timeout(PROBE_TIMEOUT).build(),
+        Err(_) => return false,
+    };
+    let Ok(client) = client else {
+        return false;
+    };
+    let url = format!(
+        "{}{}",
+        server.base_url.trim_end_matches('/'),
+        server.register_path
+    );
+    match client.get(&url).send().await {
+        Ok(response) => !matches!(
+            response.status(),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+        ),
+        Err(err) => {
+            debug!("probing {} failed: {}", server.base_url, err);
+            false
+        }
+    }
+}
+
+/// Version to speak after registration. A server may only pick a version up
+/// to ours; anything else is logged and clamped.
+pub fn accepted_protocol(accepted: Option<u32>) -> u32 {
+    match accepted {
+        None => 1,
+        Some(version @ 1..=PROTOCOL_VERSION) => version,
+        Some(version) => {
+            warn!(
+                "server accepted unknown protocol version {}, using {}",
+                version, PROTOCOL_VERSION
+            );
+            version.clamp(1, PROTOCOL_VERSION)
+        }
+    }
+}
+
+/// The status the server answered with when it no longer knows the bridge
+/// (404, 410, e.g. after a database reset) or its session (401).
+pub fn registration_lost(err: &anyhow::Error) -> Option<StatusCode> {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<reqwest::Error>()?.status())
+        .find(|status| {
+            matches!(
+                *status,
+                StatusCode::UNAUTHORIZED | StatusCode::NOT_FOUND | StatusCode::GONE
+            )
+        })
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS
+    ) || status.is_server_error()
+}
+
+/// `ws://` or `wss://` URL of the control channel on the API server.
+fn control_url(base_url: &str, path: &str) -> String {
+    let base = match base_url.split_once("://") {
+        Some(("https", rest)) => format!("wss://{}", rest),
+        Some((_, rest)) => format!("ws://{}", rest),
+        None => format!("ws://{}", base_url),
+    };
+    format!("{}{}", base, path)
+}
+
+/// Reads pushes until the connection ends. `delay` is reset once connected.
+async fn read_control(
+    url: &str,
+    api: &ServerApi,
+    local: &LocalAddrs,
+    updates: &mpsc::Sender<BridgeConfigResponse>,
+    delay: &mut Duration,
+) -> Result<()> {
+    let parsed = url::Url::parse(url).with_context(|| format!("invalid control url {}", url))?;
+    let host = parsed
+        .host_str()
+        .with_context(|| format!("control url {} has no host", url))?;
+    let port = parsed.port_or_known_default().unwrap_or(80);
+    let stream = net::connect(host, port, local)
+        .await
+        .with_context(|| format!("connect {}", url))?;
+    let stream: tls::Stream = if parsed.scheme() == "wss" {
+        tls::connect(stream, host, &api.tls).await?
+    } else {
+        Box::new(stream)
+    };
+    let mut request = url
+        .into_client_request()
+        .with_context(|| format!("invalid control url {}", url))?;
+    if let Some(token) = api.token.as_deref() {
+        let value = format!("Bearer {}", token)
+            .parse()
+            .context("server token is not a valid header value")?;
+        request.headers_mut().insert("authorization", value);
+    }
+    let (mut ws, _) = tokio_tungstenite::client_async(request, stream)
+        .await
+        .with_context(|| format!("connect {}", url))?;
+    info!("control channel connected");
+    *delay = CONTROL_RETRY_MIN;
+    while let Some(message) = ws.next().await {
+        let text = match message.context("read control message")? {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        match serde_json::from_str::<BridgeConfigResponse>(&text) {
+            Ok(update) => {
+                debug!("config pushed over the control channel");
+                if updates.send(update).await.is_err() {
+                    break;
+                }
+            }
+            Err(err) => warn!("ignoring control message: {}", err),
+        }
+    }
+    Ok(())
+}
+
+/// Handshake answered with 404, 405 or 501: the server predates the
+/// control channel.
+fn is_unsupported(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<tungstenite::Error>(),
+            Some(tungstenite::Error::Http(response))
+                if matches!(response.status().as_u16(), 404 | 405 | 501)
+        )
+    })
+}
+
+/// Recorded exchanges, one file per response: `register-0001.json`,
+/// `status-0001.json`, ... with the request next to the response. Replay
+/// walks them in order and repeats the last one once they run out.
+struct Exchanges {
+    dir: PathBuf,
+    replay: bool,
+    register: AtomicUsize,
+    status: AtomicUsize,
+}
+
+impl Exchanges {
+    fn new(dir: &Path, replay: bool) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            replay,
+            register: AtomicUsize::new(0),
+            status: AtomicUsize::new(0),
+        }
+    }
+
+    fn path(dir: &Path, kind: &str, index: usize) -> PathBuf {
+        dir.join(format!("{}-{:04}.json", kind, index))
+    }
+
+    fn counter(&self, kind: &str) -> &AtomicUsize {
+        match kind {
+            "register" => &self.register,
+            _ => &self.status,
+        }
+    }
+
+    fn record(&self, kind: &str, request: &impl Serialize, response: &Value) -> Result<()> {
+        let index = self.counter(kind).fetch_add(1, Ordering::Relaxed) + 1;
+        let path = Self::path(&self.dir, kind, index);
+        let exchange = serde_json::json!({ "request": request, "response": response });
+        let contents = serde_json::to_string_pretty(&exchange).context("serialize exchange")?;
+        fs::write(&path, contents).with_context(|| format!("write {}", path.display()))
+    }
+
+    fn replay(&self, kind: &str) -> Result<BridgeConfigResponse> {
+        let counter = self.counter(kind);
+        let last = counter.load(Ordering::Relaxed);
+        let next = Self::path(&self.dir, kind, last + 1);
+        let path = if next.exists() {
+            counter.store(last + 1, Ordering::Relaxed);
+            next
+        } else if last > 0 {
+            Self::path(&self.dir, kind, last)
+        } else {
+            // Without recorded status posts, answer like the registration.
+            Self::path(&self.dir, "register", 1)
+        };
+        let data = fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+        let exchange: Value =
+            serde_json::from_str(&data).with_context(|| format!("parse {}", path.display()))?;
+        let response = exchange.get("response").cloned().unwrap_or(exchange);
+        serde_json::from_value(response).with_context(|| format!("parse {}", path.display()))
     }
 }
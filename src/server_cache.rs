@@ -0,0 +1,62 @@
+//! The servers found by the last discovery, kept next to the config. At
+//! startup the bridge registers with them right away while a discovery
+//! runs in the background, so a reboot resumes streaming without waiting
+//! for an mDNS round and even while multicast is flaky. The background
+//! discovery and servers the mDNS monitor saw move refresh the cache; when
+//! none of the cached servers accepts the registration the bridge falls
+//! back to a fresh discovery.
+
+use crate::discovery::DiscoveredServer;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+fn cache_path(config_path: &Path, bridge_id: &str) -> PathBuf {
+    config_path.with_file_name(format!("servers-{}.json", bridge_id))
+}
+
+/// The cached candidates, best first; `None` without a usable cache.
+pub fn load(config_path: &Path, bridge_id: &str) -> Option<Vec<DiscoveredServer>> {
+    let path = cache_path(config_path, bridge_id);
+    let data = fs::read_to_string(&path).ok()?;
+    match serde_json::from_str::<Vec<DiscoveredServer>>(&data) {
+        Ok(servers) if !servers.is_empty() => Some(servers),
+        Ok(_) => None,
+        Err(err) => {
+            debug!("ignoring {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// Carries the cache over to a bridge id the server assigned.
+pub fn rename(config_path: &Path, from: &str, to: &str) {
+    let (old, new) = (cache_path(config_path, from), cache_path(config_path, to));
+    match fs::rename(&old, &new) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            warn!("moving {} failed: {}", old.display(), err)
+        }
+        _ => {}
+    }
+}
+
+/// Remembers the candidates of a discovery that led to a registration.
+pub fn store(config_path: &Path, bridge_id: &str, servers: &[DiscoveredServer]) {
+    let path = cache_path(config_path, bridge_id);
+    let result = serde_json::to_string_pretty(servers)
+        .map_err(anyhow::Error::from)
+        .and_then(|data| {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, data)?;
+            Ok(())
+        });
+    if let Err(err) = result {
+        warn!(
+            "caching the discovered servers in {} failed: {:#}",
+            path.display(),
+            err
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! Graceful stop on `SIGTERM` (systemd stop) and `SIGINT` (Ctrl-C). The
+//! bridge stops capturing, sends the resampler tail and whatever is queued,
+//! reports `OFFLINE` to the server and returns. A second signal, or a stop
+//! that takes longer than `FORCE_EXIT_AFTER`, exits right away.
+//!
+//! A restart after a remote update takes the same path, then `main` runs the
+//! new binary in place of the process.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// Upper bound for draining and the final status post.
+const FORCE_EXIT_AFTER: Duration = Duration::from_secs(10);
+/// Exit code of a forced stop, as after an unhandled `SIGTERM`.
+const EXIT_FORCED: i32 = 143;
+
+/// Cloneable view of whether a stop was requested.
+#[derive(Clone)]
+pub struct Shutdown {
+    rx: watch::Receiver<bool>,
+    tx: Arc<watch::Sender<bool>>,
+    restart: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    /// Installs the signal handlers. Without them the default action still
+    /// kills the process.
+    pub fn install() -> Self {
+        let (tx, rx) = watch::channel(false);
+        let tx = Arc::new(tx);
+        let handler_tx = tx.clone();
+        let signals = signal(SignalKind::terminate())
+            .and_then(|term| Ok((term, signal(SignalKind::interrupt())?)));
+        match signals {
+            Ok((mut term, mut interrupt)) => {
+                let tx = handler_tx;
+                tokio::spawn(async move {
+                    loop {
+                        let name = tokio::select! {
+                            _ = term.recv() => "SIGTERM",
+                            _ = interrupt.recv() => "SIGINT",
+                        };
+                        if *tx.borrow() {
+                            warn!("{} again, exiting now", name);
+                            std::process::exit(EXIT_FORCED);
+                        }
+                        info!("{} received, shutting down", name);
+                        crate::sd_notify::stopping();
+                        let _ = tx.send(true);
+                        spawn_force_exit();
+                    }
+                });
+            }
+            Err(err) => warn!("shutdown signal handlers unavailable: {}", err),
+        }
+        Self {
+            rx,
+            tx,
+            restart: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Stops like `SIGTERM` would, for `main` to start over afterwards.
+    pub fn request_restart(&self) {
+        if self.tx.send_replace(true) {
+            return;
+        }
+        self.restart.store(true, Ordering::SeqCst);
+        spawn_force_exit();
+    }
+
+    pub fn restart_requested(&self) -> bool {
+        self.restart.load(Ordering::SeqCst)
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Resolves once a stop was requested; right away if it already was.
+    pub async fn requested(&mut self) {
+        if self.rx.wait_for(|stop| *stop).await.is_err() {
+            // Handlers unavailable: never resolves.
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+fn spawn_force_exit() {
+    tokio::spawn(async {
+        tokio::time::sleep(FORCE_EXIT_AFTER).await;
+        warn!("shutdown took longer than {:?}, exiting", FORCE_EXIT_AFTER);
+        std::process::exit(EXIT_FORCED);
+    });
+}
@@ -0,0 +1,400 @@
+//! Vectorized sample conversion and level sums for the per-chunk hot paths.
+//! SSE2 on x86_64 and NEON on aarch64, both always available there, and
+//! NEON on 32-bit ARM when the CPU has it; other targets use the scalar
+//! versions. Conversions are bit-identical to the scalar code; the sum of
+//! squares is accumulated in f32 lanes.
+//!
+//! Self-contained so `benches/dsp.rs` can include it.
+
+const I16_SCALE: f32 = 1.0 / i16::MAX as f32;
+
+/// Decodes interleaved s16le bytes to f32 in -1..1, appending to `out`.
+pub fn i16_le_to_f32(bytes: &[u8], out: &mut Vec<f32>) {
+    out.reserve(bytes.len() / 2);
+    #[cfg(all(target_arch = "x86_64", target_endian = "little"))]
+    let done = unsafe { sse2::i16_le_to_f32(bytes, out) };
+    #[cfg(all(target_arch = "aarch64", target_endian = "little"))]
+    let done = unsafe { neon::i16_le_to_f32(bytes, out) };
+    #[cfg(all(target_arch = "arm", target_endian = "little"))]
+    let done = if arm_neon::available() {
+        unsafe { arm_neon::i16_le_to_f32(bytes, out) }
+    } else {
+        0
+    };
+    #[cfg(not(all(
+        any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm"),
+        target_endian = "little"
+    )))]
+    let done = 0;
+    i16_le_to_f32_scalar(&bytes[done..], out);
+}
+
+pub fn i16_le_to_f32_scalar(bytes: &[u8], out: &mut Vec<f32>) {
+    out.extend(
+        bytes
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]) as f32 * I16_SCALE),
+    );
+}
+
+/// Clamps to -1..1 and converts to i16, truncating like `as i16`.
+pub fn f32_to_i16(samples: &[f32], out: &mut Vec<i16>) {
+    out.reserve(samples.len());
+    #[cfg(target_arch = "x86_64")]
+    let done = unsafe { sse2::f32_to_i16(samples, out) };
+    #[cfg(target_arch = "aarch64")]
+    let done = unsafe { neon::f32_to_i16(samples, out) };
+    #[cfg(target_arch = "arm")]
+    let done = if arm_neon::available() {
+        unsafe { arm_neon::f32_to_i16(samples, out) }
+    } else {
+        0
+    };
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm")))]
+    let done = 0;
+    f32_to_i16_scalar(&samples[done..], out);
+}
+
+pub fn f32_to_i16_scalar(samples: &[f32], out: &mut Vec<i16>) {
+    out.extend(
+        samples
+            .iter()
+            .map(|sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16),
+    );
+}
+
+/// Sum of squares and absolute peak of `samples`.
+pub fn sum_squares_peak(samples: &[f32]) -> (f64, f32) {
+    #[cfg(target_arch = "x86_64")]
+    let (sum, peak, done) = unsafe { sse2::sum_squares_peak(samples) };
+    #[cfg(target_arch = "aarch64")]
+    let (sum, peak, done) = unsafe { neon::sum_squares_peak(samples) };
+    #[cfg(target_arch = "arm")]
+    let (sum, peak, done) = if arm_neon::available() {
+        unsafe { arm_neon::sum_squares_peak(samples) }
+    } else {
+        (0.0, 0.0, 0)
+    };
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm")))]
+    let (sum, peak, done) = (0.0, 0.0, 0);
+    let (tail_sum, tail_peak) = sum_squares_peak_scalar(&samples[done..]);
+    (sum + tail_sum, peak.max(tail_peak))
+}
+
+pub fn sum_squares_peak_scalar(samples: &[f32]) -> (f64, f32) {
+    let mut sum = 0f64;
+    let mut peak = 0f32;
+    for sample in samples {
+        sum += (*sample as f64) * (*sample as f64);
+        peak = peak.max(sample.abs());
+    }
+    (sum, peak)
+}
+
+/// Each function handles whole vectors and returns how many input elements
+/// it consumed; the caller finishes the tail with the scalar version.
+#[cfg(target_arch = "x86_64")]
+mod sse2 {
+    use super::I16_SCALE;
+    use std::arch::x86_64::*;
+
+    #[cfg(target_endian = "little")]
+    pub unsafe fn i16_le_to_f32(bytes: &[u8], out: &mut Vec<f32>) -> usize {
+        let blocks = bytes.len() / 16;
+        let scale = _mm_set1_ps(I16_SCALE);
+        let mut lanes = [0f32; 8];
+        for block in 0..blocks {
+            let raw = _mm_loadu_si128(bytes.as_ptr().add(block * 16) as *const __m128i);
+            // Duplicate each i16 into both halves of an i32, then shift the
+            // copy down arithmetically to sign-extend.
+            let low = _mm_srai_epi32(_mm_unpacklo_epi16(raw, raw), 16);
+            let high = _mm_srai_epi32(_mm_unpackhi_epi16(raw, raw), 16);
+            _mm_storeu_ps(lanes.as_mut_ptr(), _mm_mul_ps(_mm_cvtepi32_ps(low), scale));
+            _mm_storeu_ps(
+                lanes.as_mut_ptr().add(4),
+                _mm_mul_ps(_mm_cvtepi32_ps(high), scale),
+            );
+            out.extend_from_slice(&lanes);
+        }
+        blocks * 16
+    }
+
+    pub unsafe fn f32_to_i16(samples: &[f32], out: &mut Vec<i16>) -> usize {
+        let blocks = samples.len() / 8;
+        let min = _mm_set1_ps(-1.0);
+        let max = _mm_set1_ps(1.0);
+        let scale = _mm_set1_ps(i16::MAX as f32);
+        let mut lanes = [0i16; 8];
+        // NaN becomes 0 like with `as`; max/min would turn it into -1.
+        let scrub = |value: __m128| _mm_and_ps(value, _mm_cmpord_ps(value, value));
+        for block in 0..blocks {
+            let ptr = samples.as_ptr().add(block * 8);
+            let a = _mm_mul_ps(
+                _mm_min_ps(_mm_max_ps(scrub(_mm_loadu_ps(ptr)), min), max),
+                scale,
+            );
+            let b = _mm_mul_ps(
+                _mm_min_ps(_mm_max_ps(scrub(_mm_loadu_ps(ptr.add(4))), min), max),
+                scale,
+            );
+            let packed = _mm_packs_epi32(_mm_cvttps_epi32(a), _mm_cvttps_epi32(b));
+            _mm_storeu_si128(lanes.as_mut_ptr() as *mut __m128i, packed);
+            out.extend_from_slice(&lanes);
+        }
+        blocks * 8
+    }
+
+    pub unsafe fn sum_squares_peak(samples: &[f32]) -> (f64, f32, usize) {
+        let blocks = samples.len() / 4;
+        let sign = _mm_set1_ps(-0.0);
+        let mut sum = _mm_setzero_ps();
+        let mut peak = _mm_setzero_ps();
+        for block in 0..blocks {
+            let value = _mm_loadu_ps(samples.as_ptr().add(block * 4));
+            sum = _mm_add_ps(sum, _mm_mul_ps(value, value));
+            peak = _mm_max_ps(peak, _mm_andnot_ps(sign, value));
+        }
+        let mut sums = [0f32; 4];
+        let mut peaks = [0f32; 4];
+        _mm_storeu_ps(sums.as_mut_ptr(), sum);
+        _mm_storeu_ps(peaks.as_mut_ptr(), peak);
+        (
+            sums.iter().map(|value| *value as f64).sum(),
+            peaks.iter().fold(0f32, |acc, value| acc.max(*value)),
+            blocks * 4,
+        )
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::I16_SCALE;
+    use std::arch::aarch64::*;
+
+    #[cfg(target_endian = "little")]
+    pub unsafe fn i16_le_to_f32(bytes: &[u8], out: &mut Vec<f32>) -> usize {
+        let blocks = bytes.len() / 16;
+        let mut lanes = [0f32; 8];
+        for block in 0..blocks {
+            let raw = vreinterpretq_s16_u8(vld1q_u8(bytes.as_ptr().add(block * 16)));
+            let low = vcvtq_f32_s32(vmovl_s16(vget_low_s16(raw)));
+            let high = vcvtq_f32_s32(vmovl_high_s16(raw));
+            vst1q_f32(lanes.as_mut_ptr(), vmulq_n_f32(low, I16_SCALE));
+            vst1q_f32(lanes.as_mut_ptr().add(4), vmulq_n_f32(high, I16_SCALE));
+            out.extend_from_slice(&lanes);
+        }
+        blocks * 16
+    }
+
+    pub unsafe fn f32_to_i16(samples: &[f32], out: &mut Vec<i16>) -> usize {
+        let blocks = samples.len() / 8;
+        let min = vdupq_n_f32(-1.0);
+        let max = vdupq_n_f32(1.0);
+        let mut lanes = [0i16; 8];
+        for block in 0..blocks {
+            let ptr = samples.as_ptr().add(block * 8);
+            let a = vmulq_n_f32(
+                vminq_f32(vmaxq_f32(vld1q_f32(ptr), min), max),
+                i16::MAX as f32,
+            );
+            let b = vmulq_n_f32(
+                vminq_f32(vmaxq_f32(vld1q_f32(ptr.add(4)), min), max),
+                i16::MAX as f32,
+            );
+            // vcvtq truncates toward zero like `as`; the narrowing saturates.
+            let packed = vcombine_s16(vqmovn_s32(vcvtq_s32_f32(a)), vqmovn_s32(vcvtq_s32_f32(b)));
+            vst1q_s16(lanes.as_mut_ptr(), packed);
+            out.extend_from_slice(&lanes);
+        }
+        blocks * 8
+    }
+
+    pub unsafe fn sum_squares_peak(samples: &[f32]) -> (f64, f32, usize) {
+        let blocks = samples.len() / 4;
+        let mut sum = vdupq_n_f32(0.0);
+        let mut peak = vdupq_n_f32(0.0);
+        for block in 0..blocks {
+            let value = vld1q_f32(samples.as_ptr().add(block * 4));
+            sum = vfmaq_f32(sum, value, value);
+            peak = vmaxq_f32(peak, vabsq_f32(value));
+        }
+        (vaddvq_f32(sum) as f64, vmaxvq_f32(peak), blocks * 4)
+    }
+}
+
+/// NEON is optional on 32-bit ARM (the Pi 1 and Zero lack it) and its
+/// intrinsics aren't stable there, so this is inline assembly behind a
+/// HWCAP check. Only q0-q3 are used; they are d0-d7, which every asm block
+/// declares as clobbered.
+#[cfg(target_arch = "arm")]
+mod arm_neon {
+    use super::I16_SCALE;
+    use std::arch::asm;
+    use std::sync::OnceLock;
+
+    /// From the kernel's `asm/hwcap.h`.
+    const HWCAP_NEON: libc::c_ulong = 1 << 12;
+
+    pub fn available() -> bool {
+        static NEON: OnceLock<bool> = OnceLock::new();
+        *NEON.get_or_init(|| unsafe { libc::getauxval(libc::AT_HWCAP) } & HWCAP_NEON != 0)
+    }
+
+    #[cfg(target_endian = "little")]
+    pub unsafe fn i16_le_to_f32(bytes: &[u8], out: &mut Vec<f32>) -> usize {
+        let blocks = bytes.len() / 16;
+        if blocks == 0 {
+            return 0;
+        }
+        // The caller reserved room for all of `bytes`.
+        let len = out.len();
+        asm!(
+            ".fpu neon",
+            "vdup.32 q3, {scale}",
+            "2:",
+            "vld1.8 {{d0, d1}}, [{src}]!",
+            "vmovl.s16 q1, d0",
+            "vmovl.s16 q2, d1",
+            "vcvt.f32.s32 q1, q1",
+            "vcvt.f32.s32 q2, q2",
+            "vmul.f32 q1, q1, q3",
+            "vmul.f32 q2, q2, q3",
+            "vst1.32 {{d2, d3, d4, d5}}, [{dst}]!",
+            "subs {blocks}, {blocks}, #1",
+            "bne 2b",
+            src = inout(reg) bytes.as_ptr() => _,
+            dst = inout(reg) out.as_mut_ptr().add(len) => _,
+            blocks = inout(reg) blocks => _,
+            scale = in(reg) I16_SCALE.to_bits(),
+            out("d0") _, out("d1") _, out("d2") _, out("d3") _,
+            out("d4") _, out("d5") _, out("d6") _, out("d7") _,
+            options(nostack),
+        );
+        out.set_len(len + blocks * 8);
+        blocks * 16
+    }
+
+    pub unsafe fn f32_to_i16(samples: &[f32], out: &mut Vec<i16>) -> usize {
+        let blocks = samples.len() / 4;
+        if blocks == 0 {
+            return 0;
+        }
+        let len = out.len();
+        // ARMv7 NEON turns NaN into the default NaN in vmax/vmin and
+        // converts that to 0, like `as`. vcvt truncates toward zero and the
+        // narrowing saturates.
+        asm!(
+            ".fpu neon",
+            "vdup.32 q1, {min}",
+            "vdup.32 q2, {max}",
+            "vdup.32 q3, {scale}",
+            "2:",
+            "vld1.32 {{d0, d1}}, [{src}]!",
+            "vmax.f32 q0, q0, q1",
+            "vmin.f32 q0, q0, q2",
+            "vmul.f32 q0, q0, q3",
+            "vcvt.s32.f32 q0, q0",
+            "vqmovn.s32 d0, q0",
+            "vst1.16 {{d0}}, [{dst}]!",
+            "subs {blocks}, {blocks}, #1",
+            "bne 2b",
+            src = inout(reg) samples.as_ptr() => _,
+            dst = inout(reg) out.as_mut_ptr().add(len) => _,
+            blocks = inout(reg) blocks => _,
+            min = in(reg) (-1f32).to_bits(),
+            max = in(reg) 1f32.to_bits(),
+            scale = in(reg) (i16::MAX as f32).to_bits(),
+            out("d0") _, out("d1") _, out("d2") _, out("d3") _,
+            out("d4") _, out("d5") _, out("d6") _, out("d7") _,
+            options(nostack),
+        );
+        out.set_len(len + blocks * 4);
+        blocks * 4
+    }
+
+    pub unsafe fn sum_squares_peak(samples: &[f32]) -> (f64, f32, usize) {
+        let blocks = samples.len() / 4;
+        if blocks == 0 {
+            return (0.0, 0.0, 0);
+        }
+        let mut sums = [0f32; 4];
+        let mut peaks = [0f32; 4];
+        asm!(
+            ".fpu neon",
+            "vmov.i32 q0, #0",
+            "vmov.i32 q1, #0",
+            "2:",
+            "vld1.32 {{d4, d5}}, [{src}]!",
+            "vmla.f32 q0, q2, q2",
+            "vabs.f32 q2, q2",
+            "vmax.f32 q1, q1, q2",
+            "subs {blocks}, {blocks}, #1",
+            "bne 2b",
+            "vst1.32 {{d0, d1}}, [{sums}]",
+            "vst1.32 {{d2, d3}}, [{peaks}]",
+            src = inout(reg) samples.as_ptr() => _,
+            blocks = inout(reg) blocks => _,
+            sums = in(reg) sums.as_mut_ptr(),
+            peaks = in(reg) peaks.as_mut_ptr(),
+            out("d0") _, out("d1") _, out("d2") _, out("d3") _,
+            out("d4") _, out("d5") _,
+            options(nostack),
+        );
+        (
+            sums.iter().map(|value| *value as f64).sum(),
+            peaks.iter().fold(0f32, |acc, value| acc.max(*value)),
+            blocks * 4,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Paths instead of a glob import, which `benches/dsp.rs` would see as
+    // unused when it includes this file.
+
+    /// A sweep past both clamp edges, with a length that leaves a tail for
+    /// the scalar code after the vectors.
+    fn samples() -> Vec<f32> {
+        (0..1027)
+            .map(|idx| (idx as f32 * 0.37).sin() * 1.2)
+            .collect()
+    }
+
+    #[test]
+    fn i16_le_to_f32_matches_scalar() {
+        let bytes: Vec<u8> = (0..1027)
+            .map(|idx| (idx * 64 - 32768).clamp(i16::MIN as i32, i16::MAX as i32) as i16)
+            .chain([i16::MIN, i16::MAX, 0, -1])
+            .flat_map(i16::to_le_bytes)
+            .collect();
+        let (mut simd, mut scalar) = (Vec::new(), Vec::new());
+        super::i16_le_to_f32(&bytes, &mut simd);
+        super::i16_le_to_f32_scalar(&bytes, &mut scalar);
+        assert_eq!(simd, scalar);
+    }
+
+    #[test]
+    fn f32_to_i16_matches_scalar() {
+        let samples = samples();
+        let (mut simd, mut scalar) = (Vec::new(), Vec::new());
+        super::f32_to_i16(&samples, &mut simd);
+        super::f32_to_i16_scalar(&samples, &mut scalar);
+        assert_eq!(simd, scalar);
+    }
+
+    #[test]
+    fn sum_squares_peak_matches_scalar() {
+        let samples = samples();
+        let (sum, peak) = super::sum_squares_peak(&samples);
+        let (scalar_sum, scalar_peak) = super::sum_squares_peak_scalar(&samples);
+        assert_eq!(peak, scalar_peak);
+        // f32 lanes against an f64 sum.
+        assert!(
+            ((sum - scalar_sum) / scalar_sum).abs() < 1e-5,
+            "{} vs {}",
+            sum,
+            scalar_sum
+        );
+    }
+}
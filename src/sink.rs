@@ -0,0 +1,554 @@
+//! One ingest target fed by the stream loop. Each sink owns its
+//! connection, framing and reconnect backoff, so a slow or unreachable
+//! target never holds up the others.
+
+use crate::faults;
+use crate::framing::{Framer, IngestFraming};
+use crate::multicast::MulticastSender;
+use crate::net::{self, LocalAddrs};
+use crate::rtp::RtpSender;
+use crate::snapcast::{self, SnapcastTarget};
+use crate::stream::{IngestTarget, StatusHandle};
+use crate::tee::PipeTee;
+use crate::tls::{self, TlsSettings};
+use anyhow::{Context, Result};
+use futures_util::SinkExt;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{info, warn, Instrument};
+
+type WsStream = tokio_tungstenite::WebSocketStream<tls::Stream>;
+
+/// Bytes per interleaved stereo s16le frame.
+const FRAME_BYTES: usize = 4;
+
+/// TCP keepalive and write timeout for ingest connections. Without them a
+/// silently dropped route leaves a write blocked forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// Idle time before the first keepalive probe; zero turns keepalive off.
+    pub keepalive_idle: Duration,
+    pub keepalive_interval: Duration,
+    /// Unanswered probes before the kernel drops the connection.
+    pub keepalive_count: u32,
+    /// A payload that can't be sent within this time counts as a dead
+    /// connection.
+    pub send_timeout: Duration,
+    /// Source addresses from `bind_interface` / `bind_address`.
+    pub local: LocalAddrs,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            keepalive_idle: Duration::from_secs(10),
+            keepalive_interval: Duration::from_secs(5),
+            keepalive_count: 3,
+            send_timeout: Duration::from_secs(5),
+            local: LocalAddrs::default(),
+        }
+    }
+}
+
+/// Settings shared by all sinks of one stream.
+#[derive(Debug, Clone, Copy)]
+pub struct SinkSettings {
+    pub framing: IngestFraming,
+    pub output_rate: u32,
+    /// Payloads that may wait for a slow connection.
+    pub queue: usize,
+    pub socket: SocketOptions,
+    /// Audio kept while disconnected and sent first after reconnecting.
+    pub reconnect_buffer: Duration,
+}
+
+/// One paced payload of bare PCM, shared by all sinks.
+pub struct Paced {
+    pub pcm: Vec<u8>,
+    /// Unix µs when the first frame was captured.
+    pub captured_at_us: u64,
+}
+
+#[derive(Default)]
+struct SinkState {
+    connected: AtomicBool,
+    buffered_ms: AtomicU64,
+}
+
+pub struct SinkHandle {
+    tx: mpsc::Sender<Arc<Paced>>,
+    state: Arc<SinkState>,
+}
+
+impl SinkHandle {
+    pub fn is_connected(&self) -> bool {
+        self.state.connected.load(Ordering::Relaxed)
+    }
+
+    /// Audio held in the reconnect buffer.
+    pub fn buffered_ms(&self) -> u64 {
+        self.state.buffered_ms.load(Ordering::Relaxed)
+    }
+
+    /// Queues a payload. Returns false when a connected sink can't keep
+    /// up and the payload was dropped; disconnected sinks keep draining
+    /// into their reconnect buffer.
+    pub fn offer(&self, paced: &Arc<Paced>) -> bool {
+        self.tx.try_send(Arc::clone(paced)).is_ok() || !self.is_connected()
+    }
+}
+
+/// Starts a sink task on `tasks`.
+pub fn spawn(
+    target: IngestTarget,
+    settings: SinkSettings,
+    status: StatusHandle,
+    tasks: &mut JoinSet<()>,
+) -> SinkHandle {
+    let (tx, rx) = mpsc::channel(settings.queue.max(1));
+    let state = Arc::new(SinkState::default());
+    // RTP and multicast carry their own sequence numbers and timestamps;
+    // Snapcast reads bare PCM.
+    let framing = match target {
+        IngestTarget::Rtp { .. }
+        | IngestTarget::UdpMulticast { .. }
+        | IngestTarget::Snapcast(_)
+        | IngestTarget::Stdout
+        | IngestTarget::Fifo(_) => IngestFraming::Raw,
+        _ => settings.framing,
+    };
+    let settings = SinkSettings {
+        framing,
+        ..settings
+    };
+    let label = target.label();
+    let span = tracing::info_span!("ingest", target = %label);
+    let sink_state = Arc::clone(&state);
+    tasks.spawn(
+        async move {
+            run(target, settings, rx, sink_state, status.clone()).await;
+            status.remove_sink(&label);
+        }
+        .instrument(span),
+    );
+    SinkHandle { tx, state }
+}
+
+/// Connects, sends queued payloads, and reconnects with backoff after a
+/// failure. While disconnected, payloads go to the reconnect buffer. Once
+/// the connection is back it is sent first where the receiver can place it
+/// by capture time; bare PCM skips to the live edge instead, since the
+/// buffered audio would only add to its latency for good. Ends once the
+/// stream loop drops its handle and the queue is sent.
+async fn run(
+    target: IngestTarget,
+    settings: SinkSettings,
+    mut rx: mpsc::Receiver<Arc<Paced>>,
+    state: Arc<SinkState>,
+    status: StatusHandle,
+) {
+    let label = target.label();
+    let socket = settings.socket;
+    let mut framer = Framer::new(settings.framing);
+    let mut backoff = Backoff::new();
+    let mut backlog = Backlog::new(&settings, Arc::clone(&state), status.clone());
+    let mut was_connected = false;
+    let replay = settings.framing == IngestFraming::Timestamped
+        || matches!(
+            target,
+            IngestTarget::Rtp { .. } | IngestTarget::UdpMulticast { .. }
+        );
+    loop {
+        let connecting = connect(&target, settings.framing, settings.output_rate, &socket);
+        let Some(result) = park_until(connecting, &mut rx, &mut backlog).await else {
+            backlog.discard();
+            return;
+        };
+        let mut connection = match result {
+            Ok(connection) => {
+                info!("connected to {}", label);
+                state.connected.store(true, Ordering::Relaxed);
+                status.set_sink_error(&label, None);
+                status.record_connected(was_connected);
+                was_connected = true;
+                backoff.reset();
+                connection
+            }
+            Err(err) => {
+                status.set_sink_error(&label, Some(format!("{}: {:#}", label, err)));
+                let retry = tokio::time::sleep(backoff.next_delay());
+                if park_until(retry, &mut rx, &mut backlog).await.is_none() {
+                    backlog.discard();
+                    return;
+                }
+                continue;
+            }
+        };
+        // Open while connected; ends with the error that closed it.
+        let connection_span = tracing::info_span!(
+            "connection",
+            target = %label,
+            error = tracing::field::Empty,
+        );
+        if !backlog.is_empty() {
+            if replay {
+                info!("sending {} ms buffered for {}", backlog.buffered_ms, label);
+            } else {
+                info!(
+                    "dropping {} ms buffered for {}, resuming live",
+                    backlog.buffered_ms, label
+                );
+                backlog.discard();
+            }
+        }
+        loop {
+            let paced = match backlog.pop() {
+                Some(paced) => paced,
+                None => match rx.recv().await {
+                    Some(paced) => paced,
+                    // The stream loop is done and everything queued went out.
+                    None => {
+                        status.record_disconnected();
+                        return;
+                    }
+                },
+            };
+            let payload = framer.frame(paced.pcm.clone(), paced.captured_at_us);
+            let send = async {
+                if let Some(delay) = faults::send_delay() {
+                    tokio::time::sleep(delay).await;
+                }
+                connection.send(payload, paced.captured_at_us).await
+            };
+            let result = match tokio::time::timeout(socket.send_timeout, send).await {
+                Ok(result) => result,
+                Err(_) => {
+                    warn!(
+                        "send to {} stalled for {:?}, reconnecting",
+                        label, socket.send_timeout
+                    );
+                    status.record_send_timeout();
+                    Err(anyhow::anyhow!(
+                        "send timed out after {:?}",
+                        socket.send_timeout
+                    ))
+                }
+            };
+            if let Err(err) = result {
+                status.set_sink_error(&label, Some(format!("{}: {:#}", label, err)));
+                state.connected.store(false, Ordering::Relaxed);
+                status.record_disconnected();
+                status.record_dropped_ms(backlog.payload_ms(&paced));
+                connection_span.record("error", tracing::field::display(format!("{:#}", err)));
+                break;
+            }
+        }
+    }
+}
+
+/// Runs `task` while moving incoming payloads to the reconnect buffer, so
+/// the queue never fills up behind a dead connection. Returns `None` when
+/// the stream ended first.
+async fn park_until<F: Future>(
+    task: F,
+    rx: &mut mpsc::Receiver<Arc<Paced>>,
+    backlog: &mut Backlog,
+) -> Option<F::Output> {
+    tokio::pin!(task);
+    loop {
+        tokio::select! {
+            output = &mut task => return Some(output),
+            paced = rx.recv() => backlog.push(paced?),
+        }
+    }
+}
+
+/// Audio held while a sink is disconnected. When full, the oldest audio is
+/// dropped and counted, so a reconnect resumes close to live.
+struct Backlog {
+    payloads: VecDeque<Arc<Paced>>,
+    buffered_ms: u64,
+    max_ms: u64,
+    output_rate: u64,
+    state: Arc<SinkState>,
+    status: StatusHandle,
+}
+
+impl Backlog {
+    fn new(settings: &SinkSettings, state: Arc<SinkState>, status: StatusHandle) -> Self {
+        Self {
+            payloads: VecDeque::new(),
+            buffered_ms: 0,
+            max_ms: settings.reconnect_buffer.as_millis() as u64,
+            output_rate: settings.output_rate.max(1) as u64,
+            state,
+            status,
+        }
+    }
+
+    fn payload_ms(&self, paced: &Paced) -> u64 {
+        (paced.pcm.len() / FRAME_BYTES) as u64 * 1000 / self.output_rate
+    }
+
+    fn is_empty(&self) -> bool {
+        self.payloads.is_empty()
+    }
+
+    fn push(&mut self, paced: Arc<Paced>) {
+        self.buffered_ms += self.payload_ms(&paced);
+        self.payloads.push_back(paced);
+        while self.buffered_ms > self.max_ms {
+            let Some(oldest) = self.payloads.pop_front() else {
+                break;
+            };
+            let ms = self.payload_ms(&oldest);
+            self.buffered_ms -= ms;
+            self.status.record_dropped_ms(ms);
+        }
+        self.publish();
+    }
+
+    fn pop(&mut self) -> Option<Arc<Paced>> {
+        let paced = self.payloads.pop_front()?;
+        self.buffered_ms -= self.payload_ms(&paced);
+        self.publish();
+        Some(paced)
+    }
+
+    /// Drops what is left, counted as lost.
+    fn discard(&mut self) {
+        self.status.record_dropped_ms(self.buffered_ms);
+        self.payloads.clear();
+        self.buffered_ms = 0;
+        self.publish();
+    }
+
+    fn publish(&self) {
+        self.state
+            .buffered_ms
+            .store(self.buffered_ms, Ordering::Relaxed);
+    }
+}
+
+/// An open ingest connection.
+pub(crate) enum Connection {
+    Tcp(tls::Stream),
+    Ws(Box<WsStream>),
+    Rtp(RtpSender),
+    Multicast(MulticastSender),
+    Pipe(PipeTee),
+    Stdout(tokio::io::Stdout),
+}
+
+impl Connection {
+    async fn send(&mut self, payload: Vec<u8>, captured_at_us: u64) -> Result<()> {
+        match self {
+            Self::Tcp(stream) => {
+                stream.write_all(&payload).await?;
+                Ok(stream.flush().await?)
+            }
+            Self::Ws(stream) => Ok(stream.send(Message::Binary(payload)).await?),
+            Self::Rtp(sender) => sender.send(&payload, captured_at_us).await,
+            Self::Multicast(sender) => sender.send(&payload, captured_at_us).await,
+            Self::Pipe(pipe) => {
+                pipe.write(&payload);
+                Ok(())
+            }
+            Self::Stdout(stdout) => match stdout.write_all(&payload).await {
+                Ok(()) => Ok(stdout.flush().await?),
+                // Like any Unix filter, stop once the reader is gone.
+                Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => {
+                    info!("stdout closed, exiting");
+                    std::process::exit(0);
+                }
+                Err(err) => Err(err.into()),
+            },
+        }
+    }
+}
+
+async fn connect(
+    ingest: &IngestTarget,
+    framing: IngestFraming,
+    output_rate: u32,
+    socket: &SocketOptions,
+) -> Result<Connection> {
+    match ingest {
+        IngestTarget::Tcp {
+            host,
+            port,
+            header,
+            wav_header,
+            tls,
+        } => {
+            // A WAV header would not describe a framed stream.
+            let wav_rate = (*wav_header && framing == IngestFraming::Raw).then_some(output_rate);
+            connect_tcp(host, *port, tls.as_ref(), socket, header, wav_rate)
+                .await
+                .map(Connection::Tcp)
+        }
+        IngestTarget::Ws { url, tls } => connect_ws(url, tls, socket)
+            .await
+            .map(|stream| Connection::Ws(Box::new(stream))),
+        IngestTarget::Rtp {
+            addr,
+            payload_type,
+            ssrc,
+        } => {
+            if faults::take_connect_failure() {
+                anyhow::bail!("rtp {}: injected failure", addr);
+            }
+            RtpSender::connect(addr, *payload_type, *ssrc, output_rate)
+                .await
+                .map(Connection::Rtp)
+        }
+        IngestTarget::UdpMulticast { group, ttl } => {
+            MulticastSender::connect(*group, *ttl, output_rate)
+                .await
+                .map(Connection::Multicast)
+        }
+        IngestTarget::Snapcast(SnapcastTarget::Tcp(addr)) => {
+            let stream = snapcast::connect_tcp(addr).await?;
+            set_keepalive(&stream, socket)?;
+            Ok(Connection::Tcp(Box::new(stream)))
+        }
+        IngestTarget::Snapcast(SnapcastTarget::Pipe(path)) => {
+            PipeTee::named("snapcast", path.clone()).map(Connection::Pipe)
+        }
+        IngestTarget::Stdout => Ok(Connection::Stdout(tokio::io::stdout())),
+        IngestTarget::Fifo(path) => PipeTee::named("output", path.clone()).map(Connection::Pipe),
+    }
+}
+
+async fn connect_tcp(
+    host: &str,
+    port: u16,
+    tls: Option<&TlsSettings>,
+    socket: &SocketOptions,
+    header: &str,
+    wav_rate: Option<u32>,
+) -> Result<tls::Stream> {
+    let addr = net::host_port(host, port);
+    if faults::take_connect_failure() {
+        anyhow::bail!("connect to {}: injected failure", addr);
+    }
+    let stream = net::connect(host, port, &socket.local)
+        .await
+        .with_context(|| format!("connect to {}", addr))?;
+    stream.set_nodelay(true).context("set TCP nodelay")?;
+    set_keepalive(&stream, socket)?;
+    let mut stream: tls::Stream = match tls {
+        Some(settings) => tls::connect(stream, host, settings).await?,
+        None => Box::new(stream),
+    };
+    let header_line = format!("{}\n", header);
+    stream
+        .write_all(header_line.as_bytes())
+        .await
+        .context("send input id")?;
+    if let Some(rate) = wav_rate {
+        stream
+            .write_all(&crate::wav::header(rate, None))
+            .await
+            .context("send wav header")?;
+    }
+    stream.flush().await.context("send input id")?;
+    Ok(stream)
+}
+
+/// `tls` applies to `wss://` URLs only.
+async fn connect_ws(url: &str, tls: &TlsSettings, socket: &SocketOptions) -> Result<WsStream> {
+    if faults::take_connect_failure() {
+        anyhow::bail!("connect ws {}: injected failure", url);
+    }
+    let parsed = url::Url::parse(url).with_context(|| format!("invalid ws url {}", url))?;
+    let secure = match parsed.scheme() {
+        "ws" => false,
+        "wss" => true,
+        scheme => anyhow::bail!("unsupported ws url scheme {}", scheme),
+    };
+    let host = parsed
+        .host_str()
+        .with_context(|| format!("ws url {} has no host", url))?;
+    let port = parsed.port_or_known_default().unwrap_or(80);
+    let stream = net::connect(host, port, &socket.local)
+        .await
+        .with_context(|| format!("connect ws {}", url))?;
+    stream.set_nodelay(true).context("set TCP nodelay")?;
+    set_keepalive(&stream, socket)?;
+    let stream: tls::Stream = if secure {
+        tls::connect(stream, host, tls).await?
+    } else {
+        Box::new(stream)
+    };
+    let (stream, _) = tokio_tungstenite::client_async(url, stream)
+        .await
+        .with_context(|| format!("connect ws {}", url))?;
+    Ok(stream)
+}
+
+fn set_keepalive(stream: &TcpStream, options: &SocketOptions) -> Result<()> {
+    let fd = stream.as_raw_fd();
+    let idle = options.keepalive_idle.as_secs() as libc::c_int;
+    set_sockopt(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, (idle > 0).into())?;
+    if idle > 0 {
+        let interval = options.keepalive_interval.as_secs().max(1) as libc::c_int;
+        let count = options.keepalive_count.max(1) as libc::c_int;
+        set_sockopt(fd, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, idle)?;
+        set_sockopt(fd, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL, interval)?;
+        set_sockopt(fd, libc::IPPROTO_TCP, libc::TCP_KEEPCNT, count)?;
+    }
+    Ok(())
+}
+
+fn set_sockopt(
+    fd: libc::c_int,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error()).context("set TCP keepalive");
+    }
+    Ok(())
+}
+
+struct Backoff {
+    current: Duration,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Self {
+            current: Duration::from_secs(1),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.current = Duration::from_secs(1);
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = std::cmp::min(self.current * 2, Duration::from_secs(30));
+        delay
+    }
+}
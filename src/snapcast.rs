@@ -0,0 +1,55 @@
+//! Snapcast output. Snapserver reads bare PCM in the `sampleformat` of its
+//! stream source (`<rate>:16:2` here), either from a named pipe
+//! (`pipe:///tmp/snapfifo`) or from a TCP source it listens on
+//! (`tcp://host:4953`, snapserver's `mode=server`). The output runs next to
+//! the Loxone ingest, as one more sink, or replaces it.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::net::TcpStream;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapcastConfig {
+    /// `pipe:///path/to/fifo` or `tcp://host:port`.
+    pub target: String,
+    /// Send only to Snapcast instead of the server-assigned ingest.
+    #[serde(default)]
+    pub replace_ingest: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapcastTarget {
+    Pipe(PathBuf),
+    Tcp(String),
+}
+
+impl SnapcastTarget {
+    pub fn parse(value: &str) -> Result<Self> {
+        if let Some(path) = value.strip_prefix("pipe://") {
+            return Ok(Self::Pipe(PathBuf::from(path)));
+        }
+        if let Some(addr) = value.strip_prefix("tcp://") {
+            return Ok(Self::Tcp(addr.trim_end_matches('/').to_string()));
+        }
+        anyhow::bail!(
+            "snapcast target {:?} must start with pipe:// or tcp://",
+            value
+        )
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            Self::Pipe(path) => format!("snapcast pipe://{}", path.display()),
+            Self::Tcp(addr) => format!("snapcast tcp://{}", addr),
+        }
+    }
+}
+
+pub async fn connect_tcp(addr: &str) -> Result<TcpStream> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .with_context(|| format!("connect snapcast tcp://{}", addr))?;
+    stream.set_nodelay(true).context("set TCP nodelay")?;
+    Ok(stream)
+}
@@ -0,0 +1,361 @@
+//! Several local capture sources (line-in, a Bluetooth or AirPlay loopback,
+//! ...) feeding one assigned input. All sources are captured, and an
+//! arbitration policy decides which one reaches the ingest, so they never
+//! fight over the connection.
+
+use crate::audio::{
+    self, CaptureCounters, CaptureFlush, CaptureOptions, CaptureSession, ResamplerMode,
+};
+use crate::limiter::{OutputSettings, OutputStage};
+use crate::meter::LevelMeter;
+use crate::pool::Chunk;
+use crate::stream::StatusHandle;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourcePolicy {
+    /// The source that most recently started playing wins.
+    #[default]
+    LastActive,
+    /// The highest-priority playing source wins.
+    Priority,
+    /// Only `manual` is streamed.
+    Manual,
+}
+
+impl SourcePolicy {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::LastActive => "last-active",
+            Self::Priority => "priority",
+            Self::Manual => "manual",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceConfig {
+    pub name: String,
+    pub device: String,
+    /// Higher wins under the `priority` policy.
+    #[serde(default)]
+    pub priority: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SourcesConfig {
+    pub policy: SourcePolicy,
+    /// Source name streamed under the `manual` policy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manual: Option<String>,
+    /// Level above which a source counts as playing.
+    pub threshold_db: f32,
+    /// How long a source counts as playing after its level drops.
+    pub hold_ms: u64,
+    pub inputs: Vec<SourceConfig>,
+}
+
+impl Default for SourcesConfig {
+    fn default() -> Self {
+        Self {
+            policy: SourcePolicy::default(),
+            manual: None,
+            threshold_db: -50.0,
+            hold_ms: 3000,
+            inputs: Vec::new(),
+        }
+    }
+}
+
+impl SourcesConfig {
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// Device label for status and logs, e.g. `sources: line-in, airplay`.
+    pub fn label(&self) -> String {
+        let names = self
+            .inputs
+            .iter()
+            .map(|input| input.name.as_str())
+            .collect::<Vec<_>>();
+        format!("sources: {}", names.join(", "))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Activity {
+    /// Start of the current playing stretch.
+    active_since: Option<Instant>,
+    last_active: Option<Instant>,
+}
+
+/// Picks the source that reaches the ingest.
+struct Arbiter {
+    policy: SourcePolicy,
+    manual: Option<usize>,
+    priorities: Vec<i32>,
+    threshold_db: f32,
+    hold: Duration,
+    activity: Vec<Activity>,
+    current: usize,
+}
+
+impl Arbiter {
+    fn new(config: &SourcesConfig, sources: &[SourceConfig]) -> Self {
+        let priorities = sources
+            .iter()
+            .map(|source| source.priority)
+            .collect::<Vec<_>>();
+        let manual = config
+            .manual
+            .as_deref()
+            .and_then(|name| sources.iter().position(|source| source.name == name));
+        if config.policy == SourcePolicy::Manual && manual.is_none() {
+            warn!(
+                "manual source {:?} not available, using {}",
+                config.manual, sources[0].name
+            );
+        }
+        let current = manual.unwrap_or_else(|| highest_priority(&priorities, 0..sources.len()));
+        Self {
+            policy: config.policy,
+            manual,
+            priorities,
+            threshold_db: config.threshold_db,
+            hold: Duration::from_millis(config.hold_ms),
+            activity: vec![Activity::default(); sources.len()],
+            current,
+        }
+    }
+
+    fn observe(&mut self, idx: usize, playing: bool, now: Instant) {
+        let hold = self.hold;
+        let activity = &mut self.activity[idx];
+        if playing {
+            let held = activity
+                .last_active
+                .is_some_and(|last| now.duration_since(last) <= hold);
+            if !held {
+                activity.active_since = Some(now);
+            }
+            activity.last_active = Some(now);
+        }
+    }
+
+    fn is_active(&self, idx: usize, now: Instant) -> bool {
+        self.activity[idx]
+            .last_active
+            .is_some_and(|last| now.duration_since(last) <= self.hold)
+    }
+
+    /// Returns the selected source; with nothing playing the current one
+    /// stays selected.
+    fn select(&mut self, now: Instant) -> usize {
+        let active = (0..self.activity.len())
+            .filter(|idx| self.is_active(*idx, now))
+            .collect::<Vec<_>>();
+        let next = match self.policy {
+            SourcePolicy::Manual => self.manual.unwrap_or(self.current),
+            _ if active.is_empty() => self.current,
+            SourcePolicy::Priority => highest_priority(&self.priorities, active.iter().copied()),
+            SourcePolicy::LastActive => active
+                .iter()
+                .copied()
+                .max_by_key(|idx| (self.activity[*idx].active_since, usize::MAX - idx))
+                .unwrap_or(self.current),
+        };
+        self.current = next;
+        next
+    }
+}
+
+/// Highest priority among `candidates`, the first one on ties.
+fn highest_priority(priorities: &[i32], candidates: impl Iterator<Item = usize>) -> usize {
+    candidates
+        .max_by_key(|idx| (priorities[*idx], usize::MAX - idx))
+        .unwrap_or(0)
+}
+
+/// Starts every configured source and returns one session whose receiver
+/// carries only the selected source. Sources that fail to open are skipped.
+/// Gain and ceiling apply to the selected source after arbitration.
+pub fn start_capture(
+    config: &SourcesConfig,
+    target_rate: u32,
+    resampler: ResamplerMode,
+    options: CaptureOptions,
+    output: OutputSettings,
+    status: StatusHandle,
+) -> Result<CaptureSession> {
+    let mut sources = Vec::new();
+    let mut sessions = Vec::new();
+    for source in &config.inputs {
+        match audio::start_capture(
+            &source.device,
+            target_rate,
+            resampler,
+            options,
+            OutputSettings::default(),
+        ) {
+            Ok(session) => {
+                info!(
+                    "source {} opened: {} ({} Hz, {} channels)",
+                    source.name, source.device, session.sample_rate, session.channels
+                );
+                sources.push(source.clone());
+                sessions.push(session);
+            }
+            Err(err) => warn!("source {} unavailable: {:#}", source.name, err),
+        }
+    }
+    if sessions.is_empty() {
+        anyhow::bail!("no capture source could be opened");
+    }
+
+    let (tagged_tx, tagged_rx) = mpsc::unbounded_channel::<(usize, Chunk)>();
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (err_tx, err_rx) = mpsc::channel(4);
+    let first = &sessions[0];
+    let (sample_rate, channels, format) = (first.sample_rate, first.channels, first.format);
+    let observed_rate = first.observed_rate.clone();
+    let counters = Arc::new(CaptureCounters::default());
+    let mut source_counters = Vec::new();
+    let mut streams = Vec::new();
+    let mut flushes = Vec::new();
+    for (idx, session) in sessions.into_iter().enumerate() {
+        let CaptureSession {
+            mut receiver,
+            mut error_receiver,
+            streams: source_streams,
+            counters: source_counter,
+            flush,
+            ..
+        } = session;
+        streams.extend(source_streams);
+        source_counters.push(source_counter);
+        flushes.push(flush);
+        let tagged_tx = tagged_tx.clone();
+        tokio::spawn(async move {
+            while let Some(chunk) = receiver.recv().await {
+                if tagged_tx.send((idx, chunk)).is_err() {
+                    break;
+                }
+            }
+        });
+        let err_tx = err_tx.clone();
+        let name = sources[idx].name.clone();
+        tokio::spawn(async move {
+            while let Some(message) = error_receiver.recv().await {
+                let _ = err_tx.send(format!("source {}: {}", name, message)).await;
+            }
+        });
+    }
+    drop(tagged_tx);
+
+    let arbiter = Arbiter::new(config, &sources);
+    status.set_active_source(Some(&sources[arbiter.current].name));
+    let arbitration = Arbitration {
+        arbiter,
+        meters: sources
+            .iter()
+            .map(|_| LevelMeter::new(target_rate))
+            .collect(),
+        sources,
+        output: OutputStage::new(output, target_rate),
+        counters: Arc::clone(&counters),
+        source_counters,
+    };
+    tokio::spawn(arbitration.run(tagged_rx, tx, status));
+
+    Ok(CaptureSession {
+        receiver: rx,
+        error_receiver: err_rx,
+        streams,
+        sample_rate,
+        channels,
+        format,
+        observed_rate,
+        counters,
+        flush: CaptureFlush::merge(flushes),
+    })
+}
+
+struct Arbitration {
+    arbiter: Arbiter,
+    sources: Vec<SourceConfig>,
+    meters: Vec<LevelMeter>,
+    output: OutputStage,
+    /// Session totals: the sources' counters summed, plus the ceiling,
+    /// which only runs here.
+    counters: Arc<CaptureCounters>,
+    source_counters: Vec<Arc<CaptureCounters>>,
+}
+
+impl Arbitration {
+    fn update_counters(&self) {
+        let sum = |field: fn(&CaptureCounters) -> &AtomicU64| {
+            self.source_counters
+                .iter()
+                .map(|counters| field(counters).load(Ordering::Relaxed))
+                .sum::<u64>()
+        };
+        let totals = &self.counters;
+        totals
+            .non_finite_samples
+            .store(sum(|c| &c.non_finite_samples), Ordering::Relaxed);
+        totals
+            .overrun_frames
+            .store(sum(|c| &c.overrun_frames), Ordering::Relaxed);
+        totals
+            .resampler_errors
+            .store(sum(|c| &c.resampler_errors), Ordering::Relaxed);
+    }
+
+    /// Forwards chunks of the selected source. Ends once every source closed.
+    async fn run(
+        mut self,
+        mut tagged_rx: mpsc::UnboundedReceiver<(usize, Chunk)>,
+        tx: mpsc::UnboundedSender<Chunk>,
+        status: StatusHandle,
+    ) {
+        while let Some((idx, mut chunk)) = tagged_rx.recv().await {
+            self.update_counters();
+            let arbiter = &mut self.arbiter;
+            let now = Instant::now();
+            if let Some(levels) = self.meters[idx].process(&chunk) {
+                arbiter.observe(idx, levels.rms_db >= arbiter.threshold_db, now);
+            }
+            let previous = arbiter.current;
+            let selected = arbiter.select(now);
+            if selected != previous {
+                info!(
+                    "switching source: {} -> {} ({})",
+                    self.sources[previous].name,
+                    self.sources[selected].name,
+                    arbiter.policy.label()
+                );
+                status.set_active_source(Some(&self.sources[selected].name));
+            }
+            if idx != selected {
+                continue;
+            }
+            if self.output.process(&mut chunk) {
+                self.counters
+                    .ceiling_interventions
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            if tx.send(chunk).is_err() {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Status snapshots that couldn't be posted, kept on disk next to the
+//! config. Once the server answers again the backlog goes up in one batch,
+//! so track changes and errors from an outage aren't lost. The queue is
+//! bounded; when it is full, the oldest snapshots without a track change go
+//! first. File access runs on the blocking pool, off the status loop.
+
+use crate::models::{BridgeStatusRequest, SpooledStatus};
+use crate::timestamp;
+use anyhow::{Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tracing::{debug, warn};
+
+/// One hour of snapshots at the default status interval.
+const MAX_ENTRIES: usize = 720;
+/// Trimming rewrites the file, so it runs only once the queue is this far
+/// over the limit.
+const TRIM_SLACK: usize = MAX_ENTRIES / 10;
+
+/// Shared by the status loop and the snapshots taken between sessions.
+#[derive(Clone)]
+pub struct Spool {
+    inner: Arc<Mutex<SpoolFile>>,
+}
+
+struct SpoolFile {
+    path: PathBuf,
+    entries: Vec<SpooledStatus>,
+}
+
+impl Spool {
+    /// Opens the spool of `bridge_id` next to the config file, with what a
+    /// previous run left behind.
+    pub async fn open(config_path: &Path, bridge_id: &str) -> Self {
+        let path = spool_path(config_path, bridge_id);
+        let file = tokio::task::spawn_blocking(move || {
+            let entries = fs::read_to_string(&path)
+                .map(|data| {
+                    data.lines()
+                        .filter_map(|line| serde_json::from_str(line).ok())
+                        .collect()
+                })
+                .unwrap_or_default();
+            SpoolFile { path, entries }
+        })
+        .await
+        .expect("spool open panicked");
+        Self {
+            inner: Arc::new(Mutex::new(file)),
+        }
+    }
+
+    /// Moves the queue to the file of a bridge id the server assigned.
+    pub async fn rename(&self, config_path: &Path, bridge_id: &str) {
+        let inner = Arc::clone(&self.inner);
+        let path = spool_path(config_path, bridge_id);
+        let result = tokio::task::spawn_blocking(move || {
+            let mut file = lock(&inner);
+            let moved = match fs::rename(&file.path, &path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err)
+                    .with_context(|| format!("move {} to {}", file.path.display(), path.display())),
+                _ => Ok(()),
+            };
+            file.path = path;
+            moved
+        })
+        .await;
+        if let Ok(Err(err)) = result {
+            warn!("moving the status spool failed: {:#}", err);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().entries.is_empty()
+    }
+
+    /// A copy of the queue, oldest first.
+    pub fn entries(&self) -> Vec<SpooledStatus> {
+        self.lock().entries.clone()
+    }
+
+    pub async fn push(&self, status: &BridgeStatusRequest) {
+        let entry = SpooledStatus {
+            ts: timestamp::now_rfc3339(),
+            status: match serde_json::to_value(status) {
+                Ok(status) => status,
+                Err(err) => {
+                    warn!("spooling status failed: {}", err);
+                    return;
+                }
+            },
+        };
+        let inner = Arc::clone(&self.inner);
+        let result = tokio::task::spawn_blocking(move || lock(&inner).push(entry)).await;
+        if let Ok(Err(err)) = result {
+            warn!("writing the status spool failed: {:#}", err);
+        }
+    }
+
+    /// Drops the `count` oldest snapshots after they were uploaded.
+    pub async fn remove(&self, count: usize) {
+        let inner = Arc::clone(&self.inner);
+        let result = tokio::task::spawn_blocking(move || lock(&inner).remove(count)).await;
+        if let Ok(Err(err)) = result {
+            warn!("updating the status spool failed: {:#}", err);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SpoolFile> {
+        lock(&self.inner)
+    }
+}
+
+fn spool_path(config_path: &Path, bridge_id: &str) -> PathBuf {
+    config_path.with_file_name(format!("status-spool-{}.jsonl", bridge_id))
+}
+
+fn lock(inner: &Mutex<SpoolFile>) -> MutexGuard<'_, SpoolFile> {
+    match inner.lock() {
+        Ok(inner) => inner,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+impl SpoolFile {
+    fn push(&mut self, entry: SpooledStatus) -> Result<()> {
+        if self.entries.len() >= MAX_ENTRIES + TRIM_SLACK {
+            self.entries.push(entry);
+            self.trim();
+            self.rewrite()
+        } else {
+            let result = self.append(&entry);
+            self.entries.push(entry);
+            result
+        }
+    }
+
+    fn remove(&mut self, count: usize) -> Result<()> {
+        self.entries.drain(..count.min(self.entries.len()));
+        if !self.entries.is_empty() {
+            return self.rewrite();
+        }
+        match fs::remove_file(&self.path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("remove {}", self.path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn trim(&mut self) {
+        let excess = self.entries.len().saturating_sub(MAX_ENTRIES);
+        let mut dropped = 0;
+        self.entries.retain(|entry| {
+            if dropped < excess && entry.status.get("track_change") != Some(&true.into()) {
+                dropped += 1;
+                return false;
+            }
+            true
+        });
+        // Only track changes left; drop the oldest of those.
+        let excess = self.entries.len().saturating_sub(MAX_ENTRIES);
+        self.entries.drain(..excess);
+        debug!("status spool full, dropped {} snapshots", dropped + excess);
+    }
+
+    fn append(&self, entry: &SpooledStatus) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("open {}", self.path.display()))?;
+        let line = serde_json::to_string(entry).context("serialize spooled status")?;
+        writeln!(file, "{}", line).with_context(|| format!("write {}", self.path.display()))
+    }
+
+    fn rewrite(&self) -> Result<()> {
+        let mut data = String::new();
+        for entry in &self.entries {
+            data.push_str(&serde_json::to_string(entry).context("serialize spooled status")?);
+            data.push('\n');
+        }
+        fs::write(&self.path, data).with_context(|| format!("write {}", self.path.display()))
+    }
+}
@@ -0,0 +1,185 @@
+//! `status` command: a short summary of the running bridge from its health
+//! file, so nobody has to read the JSON by hand. Works for a single bridge
+//! and for the aggregate a manifest writes.
+
+use crate::cli::OutputArgs;
+use crate::config;
+use crate::health::{self, AggregateSnapshot, HealthSnapshot};
+use crate::i18n::t;
+use crate::timestamp;
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::path::PathBuf;
+
+#[derive(Debug, clap::Args)]
+pub struct StatusOptions {
+    /// Health file to read instead of the configured one.
+    #[arg(long, value_name = "PATH")]
+    file: Option<PathBuf>,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+pub fn run_status(options: StatusOptions) -> Result<()> {
+    let health = config::load_existing_config()
+        .map(|config| config.health)
+        .unwrap_or_default();
+    let path = options
+        .file
+        .unwrap_or_else(|| PathBuf::from(health::file_path(&health)));
+    let data = match std::fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!(
+                "{}",
+                t!("status-not-running", path = path.display().to_string())
+            );
+            std::process::exit(3);
+        }
+        Err(err) => return Err(err).with_context(|| format!("read {}", path.display())),
+    };
+    let value: Value =
+        serde_json::from_str(&data).with_context(|| format!("parse {}", path.display()))?;
+    let ts = value
+        .get("ts")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    if options.output.json {
+        let json = serde_json::to_string_pretty(&value).context("serialize health")?;
+        println!("{}", json);
+    } else if value.get("bridges").is_some() {
+        let aggregate: AggregateSnapshot =
+            serde_json::from_value(value).with_context(|| format!("parse {}", path.display()))?;
+        println!(
+            "{}",
+            t!(
+                "status-bridges",
+                streaming = aggregate.bridges_streaming,
+                total = aggregate.bridges_total
+            )
+        );
+        for (name, snapshot) in &aggregate.bridges {
+            println!();
+            println!("[{}]", name);
+            print_snapshot(snapshot);
+        }
+    } else {
+        let snapshot: HealthSnapshot =
+            serde_json::from_value(value).with_context(|| format!("parse {}", path.display()))?;
+        print_snapshot(&snapshot);
+    }
+
+    // The file stays behind when the bridge stops.
+    if let Some(written) = timestamp::parse_unix_ms(&ts) {
+        let age_secs = ((timestamp::now_unix_ms() - written) / 1000.0).max(0.0) as u64;
+        if age_secs > health.file.interval_secs.max(1) * 3 {
+            eprintln!();
+            eprintln!("{}", t!("status-stale", seconds = age_secs));
+            std::process::exit(3);
+        }
+    }
+    Ok(())
+}
+
+fn print_snapshot(snapshot: &HealthSnapshot) {
+    let mut lines = vec![
+        (t!("status-state"), snapshot.state.clone()),
+        (t!("status-device"), or_dash(&snapshot.device)),
+    ];
+    if let Some(input) = snapshot.input_name.as_ref().or(snapshot.input_id.as_ref()) {
+        let input = match &snapshot.zone_name {
+            Some(zone) => format!("{} ({})", input, zone),
+            None => input.clone(),
+        };
+        lines.push((t!("status-input"), input));
+    }
+    lines.push((t!("status-ingest"), or_dash(&snapshot.ingest)));
+    if let Some(rate) = snapshot.rate {
+        let rate = match snapshot.observed_rate {
+            Some(observed) if observed != rate => {
+                t!("status-rate-observed", rate = rate, observed = observed)
+            }
+            _ => format!("{} Hz", rate),
+        };
+        lines.push((t!("status-rate"), rate));
+    }
+    lines.push((
+        t!("status-throughput"),
+        t!(
+            "status-throughput-value",
+            rate = format_bytes(snapshot.bytes_per_sec),
+            total = format_bytes(snapshot.bytes_sent_total)
+        ),
+    ));
+    let connection = match snapshot.current_connection_uptime_secs {
+        Some(secs) => t!(
+            "status-connection-up",
+            uptime = format_duration(secs),
+            reconnects = snapshot.reconnect_count
+        ),
+        None => t!(
+            "status-connection-down",
+            reconnects = snapshot.reconnect_count
+        ),
+    };
+    lines.push((t!("status-connection"), connection));
+    if snapshot.dropped_chunks > 0 || snapshot.dropped_ms > 0 {
+        lines.push((
+            t!("status-dropped"),
+            t!(
+                "status-dropped-value",
+                chunks = snapshot.dropped_chunks,
+                ms = snapshot.dropped_ms
+            ),
+        ));
+    }
+    if let Some(error) = &snapshot.last_error {
+        lines.push((t!("status-last-error"), error.clone()));
+    }
+    let width = lines
+        .iter()
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or(0);
+    for (label, value) in lines {
+        println!(
+            "{:<width$}  {}",
+            format!("{}:", label),
+            value,
+            width = width + 1
+        );
+    }
+}
+
+fn or_dash(value: &str) -> String {
+    if value.is_empty() {
+        "-".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "kB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        3600..=86_399 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86_400, secs % 86_400 / 3600),
+    }
+}
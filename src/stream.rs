@@ -1,18 +1,35 @@
-use crate::models::BridgeStatusRequest;
-use anyhow::{Context, Result};
-use futures_util::SinkExt;
-use std::collections::VecDeque;
+use crate::audio::CounterSnapshot;
+use crate::calibration::Calibration;
+use crate::clock::ClockReport;
+use crate::faults;
+use crate::framing::{IngestFraming, PendingClock};
+use crate::hls::HlsFeed;
+use crate::meter::{LevelMeter, Levels};
+use crate::models::{BridgeStatusRequest, CalibrationStatus, RecentError, UpdateStatus};
+use crate::monitor::MonitorFeed;
+use crate::net;
+use crate::pool::Chunk;
+use crate::recorder::Recorder;
+use crate::sink::{self, Paced, SinkHandle, SinkSettings, SocketOptions};
+use crate::snapcast::SnapcastTarget;
+use crate::tee::PipeTee;
+use crate::tls::TlsSettings;
+use crate::track::{ProfileDetector, TrackDetectMode};
+use crate::vad::{VadDetector, VadMode};
+use anyhow::Result;
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
 use tokio::sync::mpsc;
-use tokio_tungstenite::connect_async;
-use tokio_tungstenite::tungstenite::Message;
+use tokio::task::JoinSet;
 use tracing::{info, warn};
-
-type WsStream = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>;
 const TRACK_GAP_MS: u64 = 2000;
+/// Errors kept for the health file and `report_errors`.
+const RECENT_ERRORS: usize = 20;
+/// Window over which the health snapshot's send rate is averaged.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(5);
 
 #[derive(Clone)]
 pub struct StatusHandle {
@@ -23,15 +40,47 @@ struct StatusState {
     state: String,
     device: String,
     ingest: String,
+    input_id: Option<String>,
+    input_name: Option<String>,
+    zone_name: Option<String>,
+    active_source: Option<String>,
     last_error: Option<String>,
+    /// Current error of each ingest target that is down, by label.
+    sink_errors: BTreeMap<String, String>,
+    recent_errors: VecDeque<RecentError>,
     rate: Option<u32>,
     channels: Option<u16>,
     format: Option<String>,
     observed_rate: Option<u32>,
+    non_finite_samples: u64,
+    device_suspect: bool,
+    loss: AudioLoss,
+    loss_reported: AudioLoss,
+    loss_warned_at: Option<Instant>,
+    send_timeouts: u64,
+    buffered_ms: u64,
+    clock: Option<ClockReport>,
     rms_db: Option<f32>,
+    rms_short_db: Option<f32>,
+    rms_long_db: Option<f32>,
+    peak_db: Option<f32>,
+    true_peak_db: Option<f32>,
     track_change: bool,
+    track_change_confidence: Option<f32>,
+    ceiling_interventions: Option<u64>,
+    calibration: Option<Calibration>,
+    calibration_status: Option<CalibrationStatus>,
+    update: Option<UpdateStatus>,
     bytes_sent_total: u64,
+    /// Start of the current throughput window and the total at that point.
+    throughput_mark: Option<(Instant, u64)>,
+    bytes_per_sec: u64,
     last_chunk_ts: Option<String>,
+    connection: ConnectionStats,
+    gate_opens: u64,
+    gate_closes: u64,
+    /// Last `beat` from the bridge's runtime, for the systemd watchdog.
+    last_beat: Instant,
 }
 
 impl StatusHandle {
@@ -41,33 +90,148 @@ impl StatusHandle {
                 state: "IDLE".to_string(),
                 device: device.to_string(),
                 ingest: ingest.to_string(),
+                input_id: None,
+                input_name: None,
+                zone_name: None,
+                active_source: None,
                 last_error: None,
+                sink_errors: BTreeMap::new(),
+                recent_errors: VecDeque::new(),
                 rate: None,
                 channels: None,
                 format: None,
                 observed_rate: None,
+                non_finite_samples: 0,
+                device_suspect: false,
+                loss: AudioLoss::default(),
+                loss_reported: AudioLoss::default(),
+                loss_warned_at: None,
+                send_timeouts: 0,
+                buffered_ms: 0,
+                clock: None,
                 rms_db: None,
+                rms_short_db: None,
+                rms_long_db: None,
+                peak_db: None,
+                true_peak_db: None,
                 track_change: false,
+                track_change_confidence: None,
+                ceiling_interventions: None,
+                calibration: None,
+                calibration_status: None,
+                update: None,
                 bytes_sent_total: 0,
+                throughput_mark: None,
+                bytes_per_sec: 0,
                 last_chunk_ts: None,
+                connection: ConnectionStats::default(),
+                gate_opens: 0,
+                gate_closes: 0,
+                last_beat: Instant::now(),
             })),
         }
     }
 
+    /// Called regularly by a task on the bridge's runtime.
+    pub fn beat(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.last_beat = Instant::now();
+        }
+    }
+
+    /// Time since the last `beat`; `None` once a thread panicked while
+    /// holding the status.
+    pub fn beat_age(&self) -> Option<Duration> {
+        self.inner
+            .lock()
+            .ok()
+            .map(|inner| inner.last_beat.elapsed())
+    }
+
     pub fn set_state(&self, state: &str) {
         if let Ok(mut inner) = self.inner.lock() {
             inner.state = state.to_string();
         }
     }
 
+    /// Sets the current error; errors are also added to the recent-error
+    /// list.
     pub fn set_last_error(&self, error: Option<String>) {
         if let Ok(mut inner) = self.inner.lock() {
+            if let Some(message) = error.as_deref() {
+                inner.push_recent_error(message);
+            }
             inner.last_error = error;
         }
     }
 
+    /// Sets or clears the error of one ingest target. Clearing it leaves
+    /// the errors of other targets that are still down in `last_error`, and
+    /// clears `last_error` once every target is back.
+    pub fn set_sink_error(&self, label: &str, error: Option<String>) {
+        if let Ok(mut inner) = self.inner.lock() {
+            match error {
+                Some(message) => {
+                    inner.push_recent_error(&message);
+                    inner.last_error = Some(message.clone());
+                    inner.sink_errors.insert(label.to_string(), message);
+                }
+                None => {
+                    let cleared = inner.sink_errors.remove(label);
+                    if inner.sink_errors.is_empty() {
+                        inner.last_error = None;
+                    } else if cleared.is_some() && inner.last_error == cleared {
+                        inner.last_error = inner.sink_errors.values().next().cloned();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forgets a target whose sink ended, keeping `last_error` as it is.
+    pub fn remove_sink(&self, label: &str) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.sink_errors.remove(label);
+        }
+    }
+
+    /// The recent errors, oldest first.
+    pub fn recent_errors(&self) -> Vec<RecentError> {
+        match self.inner.lock() {
+            Ok(inner) => inner.recent_errors.iter().cloned().collect(),
+            Err(poisoned) => poisoned
+                .into_inner()
+                .recent_errors
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn set_assignment(
+        &self,
+        input_id: Option<&str>,
+        input_name: Option<&str>,
+        zone_name: Option<&str>,
+    ) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.input_id = input_id.map(str::to_string);
+            inner.input_name = input_name.map(str::to_string);
+            inner.zone_name = zone_name.map(str::to_string);
+        }
+    }
+
+    pub fn set_active_source(&self, source: Option<&str>) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.active_source = source.map(str::to_string);
+        }
+    }
+
     pub fn set_device(&self, device: &str) {
         if let Ok(mut inner) = self.inner.lock() {
+            if inner.device != device {
+                inner.device_suspect = false;
+            }
             inner.device = device.to_string();
         }
     }
@@ -86,15 +250,144 @@ impl StatusHandle {
         }
     }
 
-    pub fn set_rms_db(&self, rms_db: Option<f32>) {
+    pub fn set_clock(&self, clock: Option<ClockReport>) {
         if let Ok(mut inner) = self.inner.lock() {
-            inner.rms_db = rms_db;
+            inner.clock = clock;
         }
     }
 
-    pub fn set_track_change(&self) {
+    /// Adds the capture counters' increase since the previous call. Non-finite
+    /// samples mark the device as suspect until a different one is selected.
+    pub fn record_capture(&self, delta: &CounterSnapshot) {
+        if let Ok(mut inner) = self.inner.lock() {
+            if delta.non_finite_samples > 0 {
+                inner.non_finite_samples = inner
+                    .non_finite_samples
+                    .saturating_add(delta.non_finite_samples);
+                if !inner.device_suspect {
+                    warn!(
+                        "capture device {} delivered non-finite samples, marking it suspect",
+                        inner.device
+                    );
+                }
+                inner.device_suspect = true;
+            }
+            if let Some(count) = inner.ceiling_interventions.as_mut() {
+                *count = count.saturating_add(delta.ceiling_interventions);
+            }
+            inner.loss.overrun_frames += delta.overrun_frames;
+            inner.loss.resampler_errors += delta.resampler_errors;
+            inner.warn_on_loss();
+        }
+    }
+
+    /// Counts a chunk's worth of audio discarded before sending.
+    pub fn record_dropped_chunk(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.loss.dropped_chunks += 1;
+            inner.warn_on_loss();
+        }
+    }
+
+    /// Counts audio dropped from a full reconnect buffer.
+    pub fn record_dropped_ms(&self, ms: u64) {
+        if ms == 0 {
+            return;
+        }
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.loss.dropped_ms += ms;
+            inner.warn_on_loss();
+        }
+    }
+
+    /// Audio currently held for disconnected targets (the largest buffer).
+    pub fn set_buffered_ms(&self, ms: u64) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.buffered_ms = ms;
+        }
+    }
+
+    /// Counts a send that stalled past the timeout and forced a reconnect.
+    pub fn record_send_timeout(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.send_timeouts += 1;
+        }
+    }
+
+    /// Stores the latest levels. Peaks are held until the next status post.
+    pub fn set_levels(&self, levels: Option<Levels>) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.rms_db = levels.map(|levels| levels.rms_db);
+            inner.rms_short_db = levels.map(|levels| levels.rms_short_db);
+            inner.rms_long_db = levels.map(|levels| levels.rms_long_db);
+            if let (Some(levels), Some(calibration)) = (levels, inner.calibration.as_mut()) {
+                calibration.push(levels.rms_db);
+            }
+            if let Some(levels) = levels {
+                inner.peak_db = Some(max_db(inner.peak_db, levels.peak_db));
+                inner.true_peak_db = Some(max_db(inner.true_peak_db, levels.true_peak_db));
+            }
+        }
+    }
+
+    /// Flags a track change. `confidence` is set by detectors that estimate
+    /// one.
+    pub fn set_track_change(&self, confidence: Option<f32>) {
         if let Ok(mut inner) = self.inner.lock() {
             inner.track_change = true;
+            inner.track_change_confidence = confidence;
+        }
+    }
+
+    /// Starts a noise floor measurement fed by the running stream's levels.
+    pub fn start_calibration(&self, duration: Duration, apply: bool) {
+        if let Ok(mut inner) = self.inner.lock() {
+            let calibration = Calibration::new(duration, apply);
+            inner.calibration_status = Some(calibration.running_status());
+            inner.calibration = Some(calibration);
+        }
+    }
+
+    /// Returns the result once when a calibration has finished, along with
+    /// whether it should be applied.
+    pub fn poll_calibration(&self) -> Option<(CalibrationStatus, bool)> {
+        let mut inner = self.inner.lock().ok()?;
+        if !inner.calibration.as_ref()?.is_done() {
+            return None;
+        }
+        let calibration = inner.calibration.take()?;
+        let apply = calibration.apply();
+        let result = calibration.finish();
+        inner.calibration_status = Some(result.clone());
+        Some((result, apply))
+    }
+
+    pub fn mark_calibration_applied(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            if let Some(status) = inner.calibration_status.as_mut() {
+                status.applied = true;
+            }
+        }
+    }
+
+    /// Progress of a server-requested update, kept across sessions.
+    pub fn set_update(&self, update: UpdateStatus) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.update = Some(update);
+        }
+    }
+
+    pub fn update(&self) -> Option<UpdateStatus> {
+        self.inner.lock().ok()?.update.clone()
+    }
+
+    /// Starts reporting the ceiling counter, or stops when disabled.
+    pub fn set_ceiling_enabled(&self, enabled: bool) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.ceiling_interventions = match (enabled, inner.ceiling_interventions) {
+                (true, count) => Some(count.unwrap_or(0)),
+                (false, _) => None,
+            };
         }
     }
 
@@ -105,19 +398,83 @@ impl StatusHandle {
         }
     }
 
+    /// Marks an ingest connection as established. `reconnect` is set when
+    /// the target had been connected before in this session.
+    pub fn record_connected(&self, reconnect: bool) {
+        if let Ok(mut inner) = self.inner.lock() {
+            if reconnect {
+                inner.connection.reconnects += 1;
+            }
+            inner.connection.last_connect_ts = Some(crate::timestamp::now_rfc3339());
+            inner.connection.connected_since = Some(Instant::now());
+        }
+    }
+
+    pub fn record_disconnected(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.connection.connected_since = None;
+        }
+    }
+
+    /// Counts a VAD gate transition.
+    pub fn record_gate(&self, open: bool) {
+        if let Ok(mut inner) = self.inner.lock() {
+            if open {
+                inner.gate_opens += 1;
+            } else {
+                inner.gate_closes += 1;
+            }
+        }
+    }
+
     pub fn health_snapshot(&self) -> crate::health::HealthSnapshot {
-        let inner = match self.inner.lock() {
+        let mut inner = match self.inner.lock() {
             Ok(inner) => inner,
             Err(poisoned) => poisoned.into_inner(),
         };
+        let total = inner.bytes_sent_total;
+        match inner.throughput_mark {
+            Some((since, sent)) if since.elapsed() >= THROUGHPUT_WINDOW => {
+                inner.bytes_per_sec =
+                    (total.saturating_sub(sent) as f64 / since.elapsed().as_secs_f64()) as u64;
+                inner.throughput_mark = Some((Instant::now(), total));
+            }
+            Some(_) => {}
+            None => inner.throughput_mark = Some((Instant::now(), total)),
+        }
         crate::health::HealthSnapshot {
             ts: crate::timestamp::now_rfc3339(),
             state: inner.state.clone(),
             device: inner.device.clone(),
             ingest: inner.ingest.clone(),
+            input_id: inner.input_id.clone(),
+            input_name: inner.input_name.clone(),
+            zone_name: inner.zone_name.clone(),
+            active_source: inner.active_source.clone(),
+            rate: inner.rate,
+            observed_rate: inner.observed_rate,
+            device_suspect: inner.device_suspect,
+            capture_overrun_frames: inner.loss.overrun_frames,
+            dropped_chunks: inner.loss.dropped_chunks,
+            resampler_errors: inner.loss.resampler_errors,
+            send_timeouts: inner.send_timeouts,
+            buffered_ms: inner.buffered_ms,
+            dropped_ms: inner.loss.dropped_ms,
+            clock_offset_ms: inner.clock.map(|clock| clock.offset_ms),
+            clock_drift_ppm: inner.clock.and_then(|clock| clock.drift_ppm),
             last_error: inner.last_error.clone(),
+            recent_errors: inner.recent_errors.iter().cloned().collect(),
             bytes_sent_total: inner.bytes_sent_total,
+            bytes_per_sec: inner.bytes_per_sec,
             last_chunk_ts: inner.last_chunk_ts.clone(),
+            reconnect_count: inner.connection.reconnects,
+            last_connect_ts: inner.connection.last_connect_ts.clone(),
+            current_connection_uptime_secs: inner
+                .connection
+                .connected_since
+                .map(|since| since.elapsed().as_secs()),
+            gate_opens: inner.gate_opens,
+            gate_closes: inner.gate_closes,
         }
     }
 
@@ -132,6 +489,7 @@ impl StatusHandle {
         } else {
             None
         };
+        let track_change_confidence = inner.track_change_confidence.take();
         BridgeStatusRequest {
             state: inner.state.clone(),
             device: if inner.device.is_empty() {
@@ -143,10 +501,42 @@ impl StatusHandle {
             channels: inner.channels,
             format: inner.format.clone(),
             observed_rate: inner.observed_rate,
+            non_finite_samples: nonzero(inner.non_finite_samples),
+            device_suspect: inner.device_suspect.then_some(true),
+            capture_overrun_frames: nonzero(inner.loss.overrun_frames),
+            dropped_chunks: nonzero(inner.loss.dropped_chunks),
+            resampler_errors: nonzero(inner.loss.resampler_errors),
+            send_timeouts: nonzero(inner.send_timeouts),
+            buffered_ms: nonzero(inner.buffered_ms),
+            dropped_ms: nonzero(inner.loss.dropped_ms),
             rms_db: inner.rms_db,
+            rms_short_db: inner.rms_short_db,
+            rms_long_db: inner.rms_long_db,
+            peak_db: inner.peak_db.take(),
+            true_peak_db: inner.true_peak_db.take(),
             last_error: inner.last_error.clone(),
             track_change,
+            track_change_confidence,
+            ceiling_interventions: inner.ceiling_interventions,
+            calibration: inner.calibration_status.clone(),
+            clock_offset_ms: inner.clock.map(|clock| clock.offset_ms),
+            clock_drift_ppm: inner.clock.and_then(|clock| clock.drift_ppm),
+            active_source: inner.active_source.clone(),
             capture_devices: None,
+            hostname: None,
+            ip: None,
+            ips: None,
+            mac: None,
+            redaction: None,
+            overrides: None,
+            uptime_secs: None,
+            cpu_percent: None,
+            rss_bytes: None,
+            soc_temp_c: None,
+            throttled: None,
+            recent_errors: None,
+            config_revision: None,
+            update: inner.update.clone(),
         }
     }
 
@@ -157,122 +547,280 @@ impl StatusHandle {
     }
 }
 
+/// Ingest connection history for the health snapshot. With several targets
+/// it follows whichever connected or dropped last.
+#[derive(Debug, Default)]
+struct ConnectionStats {
+    reconnects: u64,
+    last_connect_ts: Option<String>,
+    connected_since: Option<Instant>,
+}
+
+/// Audio lost between capture and the ingest, as running totals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct AudioLoss {
+    overrun_frames: u64,
+    dropped_chunks: u64,
+    resampler_errors: u64,
+    /// Audio dropped from full reconnect buffers.
+    dropped_ms: u64,
+}
+
+/// Loss is logged once it starts and then at most this often.
+const LOSS_WARN_INTERVAL: Duration = Duration::from_secs(30);
+
+impl StatusState {
+    fn push_recent_error(&mut self, message: &str) {
+        let ts = crate::timestamp::now_rfc3339();
+        if let Some(newest) = self.recent_errors.back_mut() {
+            if newest.message == message {
+                newest.ts = ts;
+                newest.count += 1;
+                return;
+            }
+        }
+        if self.recent_errors.len() == RECENT_ERRORS {
+            self.recent_errors.pop_front();
+        }
+        self.recent_errors.push_back(RecentError {
+            ts,
+            message: message.to_string(),
+            count: 1,
+        });
+    }
+
+    fn warn_on_loss(&mut self) {
+        if self.loss == self.loss_reported
+            || self
+                .loss_warned_at
+                .is_some_and(|at| at.elapsed() < LOSS_WARN_INTERVAL)
+        {
+            return;
+        }
+        warn!(
+            "audio lost: {} overrun frames, {} dropped chunks, {} resampler errors, {} ms while disconnected (totals {}/{}/{}/{} ms)",
+            self.loss.overrun_frames - self.loss_reported.overrun_frames,
+            self.loss.dropped_chunks - self.loss_reported.dropped_chunks,
+            self.loss.resampler_errors - self.loss_reported.resampler_errors,
+            self.loss.dropped_ms - self.loss_reported.dropped_ms,
+            self.loss.overrun_frames,
+            self.loss.dropped_chunks,
+            self.loss.resampler_errors,
+            self.loss.dropped_ms
+        );
+        self.loss_reported = self.loss;
+        self.loss_warned_at = Some(Instant::now());
+    }
+}
+
+fn nonzero(count: u64) -> Option<u64> {
+    (count > 0).then_some(count)
+}
+
 pub enum IngestTarget {
     Tcp {
         host: String,
         port: u16,
         header: String,
+        wav_header: bool,
+        /// TLS instead of plaintext when set.
+        tls: Option<TlsSettings>,
     },
     Ws {
         url: String,
+        tls: TlsSettings,
+    },
+    /// RTP/UDP with an L16 payload; `addr` may be a multicast group.
+    Rtp {
+        addr: String,
+        payload_type: u8,
+        ssrc: u32,
     },
+    /// Snapcast stream source.
+    Snapcast(SnapcastTarget),
+    /// Fixed-size datagrams to a multicast group; see `multicast.rs`.
+    UdpMulticast {
+        group: SocketAddr,
+        ttl: u32,
+    },
+    /// Bare PCM on stdout, for the `pipe` command.
+    Stdout,
+    /// Bare PCM into a named pipe; dropped while no reader is attached.
+    Fifo(PathBuf),
+}
+
+impl IngestTarget {
+    pub fn label(&self) -> String {
+        match self {
+            Self::Tcp {
+                host,
+                port,
+                tls: Some(_),
+                ..
+            } => format!("tls://{}", net::host_port(host, *port)),
+            Self::Tcp { host, port, .. } => net::host_port(host, *port),
+            Self::Ws { url, .. } => url.clone(),
+            Self::Rtp { addr, .. } => format!("rtp://{}", addr),
+            Self::Snapcast(target) => target.label(),
+            Self::UdpMulticast { group, .. } => format!("udp://{}", group),
+            Self::Stdout => "stdout".to_string(),
+            Self::Fifo(path) => format!("pipe://{}", path.display()),
+        }
+    }
+}
+
+/// Gate tuning that can change while a stream is running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VadSettings {
+    /// When false the gate stays open and audio streams continuously.
+    pub enabled: bool,
+    pub mode: VadMode,
+    /// Level the signal must reach to open the gate.
+    pub open_db: f32,
+    /// Level below which an open gate counts as silent. Lower than
+    /// `open_db` so the gate doesn't chatter around a single threshold.
+    pub close_db: f32,
+    /// How long activity must last before the gate opens.
+    pub attack: Duration,
+    pub hold: Duration,
+    /// Audio kept while the gate is closed and sent first when it opens.
+    pub preroll: Duration,
+    /// Send digital silence while the gate is closed instead of pausing,
+    /// for consumers that need a continuous sample clock.
+    pub silence_fill: bool,
+    pub track_detect: TrackDetectMode,
+}
+
+impl VadSettings {
+    pub(crate) fn threshold_db(&self, gate_active: bool) -> f32 {
+        if gate_active {
+            self.close_db
+        } else {
+            self.open_db
+        }
+    }
 }
 
 pub struct StreamParams {
-    pub ingest: IngestTarget,
-    pub rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    /// Every target receives the same audio; each connects and reconnects
+    /// on its own.
+    pub ingest: Vec<IngestTarget>,
+    pub rx: mpsc::UnboundedReceiver<Chunk>,
     pub err_rx: mpsc::Receiver<String>,
-    pub threshold_db: f32,
-    pub hold_duration: Duration,
-    pub vad_updates: Option<tokio::sync::watch::Receiver<(f32, Duration)>>,
+    pub vad: VadSettings,
+    pub vad_updates: Option<tokio::sync::watch::Receiver<VadSettings>>,
     pub status: StatusHandle,
     pub output_rate: u32,
+    /// Named pipe that receives a copy of everything sent to the ingest.
+    pub tee_path: Option<PathBuf>,
+    pub framing: IngestFraming,
+    pub socket: SocketOptions,
+    pub reconnect_buffer: Duration,
+    /// Duration of each paced payload.
+    pub packet: Duration,
+    /// Local HLS output; keeps getting silence while the gate is closed so
+    /// its timeline doesn't stall.
+    pub hls: Option<HlsFeed>,
+    /// Local playback of what is sent.
+    pub monitor: Option<MonitorFeed>,
+    /// Local recording of what is sent.
+    pub recorder: Option<Recorder>,
 }
 
 pub async fn stream_audio(mut params: StreamParams) -> Result<()> {
-    match &params.ingest {
-        IngestTarget::Tcp { .. } => stream_audio_tcp(&mut params).await,
-        IngestTarget::Ws { .. } => stream_audio_ws(&mut params).await,
-    }
-}
-
-async fn stream_audio_tcp(params: &mut StreamParams) -> Result<()> {
-    let mut backoff = Backoff::new();
-    let (host, port, header) = match &params.ingest {
-        IngestTarget::Tcp { host, port, header } => (host.clone(), *port, header.clone()),
-        IngestTarget::Ws { .. } => anyhow::bail!("invalid tcp ingest"),
-    };
-    let addr = format!("{}:{}", host, port);
-    let mut gate = VadGate::new();
-    let mut threshold_db = params.threshold_db;
-    let mut hold_duration = params.hold_duration;
-    let mut idle_since: Option<Instant> = None;
+    let params = &mut params;
+    let mut tee = open_tee(params.tee_path.take());
+    let mut vad = params.vad;
+    let mut stage = GateStage::new(vad.mode, params.output_rate);
+    let mut track = track_detector(vad.track_detect, params.output_rate);
     let mut last_rate_log = Instant::now();
     let mut bytes_since_log: u64 = 0;
-    let chunk_bytes = chunk_bytes_for_rate(params.output_rate);
-    let chunk_interval = chunk_interval();
+    let mut packetizer = Packetizer::new(params.output_rate, params.packet);
     let max_pending = max_buffer_bytes_for_rate(params.output_rate);
     let mut pending = VecDeque::with_capacity(max_pending);
-    let mut overrun_since = Instant::now();
+    let mut pending_clock = PendingClock::new(params.output_rate);
+    let mut preroll_bytes = preroll_bytes_for_rate(params.output_rate, vad.preroll, max_pending);
     let mut underrun_since = Instant::now();
     let mut underrun_bytes: u64 = 0;
-    let mut tick = tokio::time::interval(chunk_interval);
+    let mut tick = tokio::time::interval(packetizer.interval());
     tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // Set once the capture side is done; what is still pending gets sent,
+    // then the stream ends cleanly.
+    let mut capture_ended = false;
+    // Set when the gate opens, until the pre-roll is out.
+    let mut flush_preroll = false;
 
-    let mut stream: Option<TcpStream> = None;
-    loop {
-        if stream.is_none() {
-            params.status.set_state("RECONNECTING");
-            match connect_tcp(&addr, &header).await {
-                Ok(connected) => {
-                    stream = Some(connected);
-                    params.status.set_state("STREAMING");
-                    params.status.set_last_error(None);
-                    backoff.reset();
-                }
-                Err(err) => {
-                    params.status.set_last_error(Some(err.to_string()));
-                    tokio::time::sleep(backoff.next_delay()).await;
-                    continue;
-                }
-            }
-        }
+    let settings = SinkSettings {
+        framing: params.framing,
+        output_rate: params.output_rate,
+        // A sink may queue as much audio as the loop itself buffers.
+        queue: max_pending / packetizer.max_bytes(),
+        socket: params.socket,
+        reconnect_buffer: params.reconnect_buffer,
+    };
+    let mut tasks = JoinSet::new();
+    let sinks: Vec<SinkHandle> = params
+        .ingest
+        .drain(..)
+        .map(|target| sink::spawn(target, settings, params.status.clone(), &mut tasks))
+        .collect();
 
+    loop {
         tokio::select! {
-            maybe_chunk = params.rx.recv() => {
+            maybe_chunk = params.rx.recv(), if !capture_ended => {
                 match maybe_chunk {
                     Some(chunk) => {
-                        let rms_db = rms_db_from_pcm_i16_le(&chunk);
-                        pending.extend(chunk);
+                        if faults::take_device_loss() {
+                            let message = "capture device lost (injected)".to_string();
+                            params.status.set_last_error(Some(message.clone()));
+                            return Err(anyhow::anyhow!(message));
+                        }
+                        if faults::take_drop_chunk() {
+                            params.status.record_dropped_chunk();
+                            continue;
+                        }
+                        if stage.is_active() {
+                            if let Some(confidence) =
+                                track.as_mut().and_then(|track| track.process(&chunk))
+                            {
+                                params.status.set_track_change(Some(confidence));
+                                info!("track change detected (confidence {:.2})", confidence);
+                            }
+                        }
+                        pending_clock.on_chunk(chunk.captured_at_us);
+                        pending.extend(chunk.iter());
                         if pending.len() > max_pending {
                             let overflow = pending.len() - max_pending;
                             for _ in 0..overflow {
                                 pending.pop_front();
                             }
-                            if overrun_since.elapsed() >= Duration::from_secs(5) {
-                                warn!("audio buffer overrun, dropping {} bytes", overflow);
-                                overrun_since = Instant::now();
-                            }
+                            params.status.record_dropped_chunk();
                         }
-                        params.status.set_rms_db(rms_db);
-                        if let Some(rms_db) = rms_db {
-                            let now = Instant::now();
-                            let was_active = gate.active;
-                            if rms_db >= threshold_db {
-                                gate.set_active(now);
-                            } else if gate.should_keep_active(now, hold_duration) {
-                            } else {
-                                gate.set_inactive();
-                            }
-
-                            if gate.active && !was_active {
-                                if let Some(idle_start) = idle_since.take() {
-                                    if now.duration_since(idle_start)
-                                        >= Duration::from_millis(TRACK_GAP_MS)
-                                    {
-                                        params.status.set_track_change();
-                                        info!("track change detected");
-                                    }
+                        let step = stage.process(&chunk, Instant::now(), &vad);
+                        params.status.set_levels(step.levels);
+                        match step.change {
+                            Some(GateChange::Opened { level_db, after_gap }) => {
+                                if after_gap && vad.track_detect == TrackDetectMode::Gap {
+                                    params.status.set_track_change(None);
+                                    info!("track change detected");
                                 }
-                                info!("audio detected, streaming (rms_db={:.1})", rms_db);
-                            } else if !gate.active && was_active {
-                                idle_since = Some(now);
+                                params.status.record_gate(true);
+                                flush_preroll = true;
+                                info!("audio detected, streaming (level_db={:.1})", level_db);
+                            }
+                            Some(GateChange::Closed { level_db }) => {
                                 pending.clear();
-                                info!("silence detected, pausing stream (rms_db={:.1})", rms_db);
+                                params.status.record_gate(false);
+                                info!(
+                                    "silence detected, pausing stream (level_db={:.1})",
+                                    level_db
+                                );
                             }
+                            None => {}
                         }
 
-                        if !gate.active {
+                        if !stage.is_active() {
+                            trim_front(&mut pending, preroll_bytes);
                             params.status.set_state("IDLE");
                             continue;
                         }
@@ -280,248 +828,114 @@ async fn stream_audio_tcp(params: &mut StreamParams) -> Result<()> {
                         // paced writes happen on the interval tick
                     }
                     None => {
-                        return Err(anyhow::anyhow!("audio capture channel closed"));
+                        if !stage.is_active() || pending.is_empty() {
+                            break;
+                        }
+                        capture_ended = true;
                     }
                 }
             }
             _ = tick.tick() => {
-                if !gate.active {
-                    continue;
+                if capture_ended && pending.is_empty() {
+                    break;
                 }
-                if let Some(writer) = stream.as_mut() {
-                    if pending.len() < chunk_bytes {
-                        let missing = chunk_bytes - pending.len();
-                        let mut payload = Vec::with_capacity(chunk_bytes);
-                        while let Some(value) = pending.pop_front() {
-                            payload.push(value);
-                        }
-                        payload.extend(std::iter::repeat_n(0u8, missing));
-                        underrun_bytes += missing as u64;
-                        if let Err(err) = writer.write_all(&payload).await {
-                            params.status.set_last_error(Some(err.to_string()));
-                            stream = None;
-                        } else {
-                            params.status.set_state("STREAMING");
-                            params.status.record_bytes(chunk_bytes);
-                            bytes_since_log += chunk_bytes as u64;
-                        }
-                    } else {
-                        let mut payload = Vec::with_capacity(chunk_bytes);
-                        for _ in 0..chunk_bytes {
-                            if let Some(value) = pending.pop_front() {
-                                payload.push(value);
-                            }
-                        }
-                        if let Err(err) = writer.write_all(&payload).await {
-                            params.status.set_last_error(Some(err.to_string()));
-                            stream = None;
-                        } else {
-                            params.status.set_state("STREAMING");
-                            params.status.record_bytes(chunk_bytes);
-                            bytes_since_log += chunk_bytes as u64;
-                        }
+                let chunk_bytes = packetizer.next_bytes();
+                if !stage.is_active() && !vad.silence_fill {
+                    if let Some(hls) = params.hls.as_ref() {
+                        hls.write(&vec![0u8; chunk_bytes], params.output_rate);
                     }
-                    if last_rate_log.elapsed() >= Duration::from_secs(5) {
-                        let secs = last_rate_log.elapsed().as_secs_f64();
-                        let bytes_per_sec = (bytes_since_log as f64 / secs).round();
-                        let est_rate = bytes_per_sec / 4.0;
-                        info!(
-                            "stream throughput: {} B/s (~{:.0} Hz)",
-                            bytes_per_sec, est_rate
-                        );
-                        bytes_since_log = 0;
-                        last_rate_log = Instant::now();
+                    continue;
+                }
+                // Disconnected targets keep the audio in their reconnect
+                // buffer, so pacing goes on while they are down.
+                let connected = sinks.iter().any(SinkHandle::is_connected);
+                let all_connected = sinks.iter().all(SinkHandle::is_connected);
+                params
+                    .status
+                    .set_buffered_ms(sinks.iter().map(SinkHandle::buffered_ms).max().unwrap_or(0));
+                let captured_at_us =
+                    pending_clock.front_us(if stage.is_active() { pending.len() } else { 0 });
+                let payload = if !stage.is_active() {
+                    vec![0u8; chunk_bytes]
+                } else if pending.len() < chunk_bytes {
+                    let missing = chunk_bytes - pending.len();
+                    let mut buffer = Vec::with_capacity(chunk_bytes);
+                    while let Some(value) = pending.pop_front() {
+                        buffer.push(value);
                     }
-                    if underrun_since.elapsed() >= Duration::from_secs(5) && underrun_bytes > 0 {
-                        warn!(
-                            "audio buffer underrun: {} bytes padded in last {:.1}s",
-                            underrun_bytes,
-                            underrun_since.elapsed().as_secs_f64()
-                        );
-                        underrun_bytes = 0;
-                        underrun_since = Instant::now();
+                    buffer.extend(std::iter::repeat_n(0u8, missing));
+                    underrun_bytes += missing as u64;
+                    buffer
+                } else {
+                    let mut buffer = Vec::with_capacity(chunk_bytes);
+                    for _ in 0..chunk_bytes {
+                        if let Some(value) = pending.pop_front() {
+                            buffer.push(value);
+                        }
                     }
-                }
-            }
-            maybe_err = params.err_rx.recv() => {
-                let message = match maybe_err {
-                    Some(message) => message,
-                    None => "audio capture error channel closed".to_string(),
+                    buffer
                 };
-                params.status.set_last_error(Some(message.clone()));
-                return Err(anyhow::anyhow!(message));
-            }
-            _changed = async {
-                match params.vad_updates.as_mut() {
-                    Some(rx) => rx.changed().await.ok(),
-                    None => None,
+                if let Some(tee) = tee.as_mut() {
+                    tee.write(&payload);
                 }
-            }, if params.vad_updates.is_some() => {
-                if let Some(rx) = params.vad_updates.as_ref() {
-                    let (next_threshold, next_hold) = *rx.borrow();
-                    threshold_db = next_threshold;
-                    hold_duration = next_hold;
+                if let Some(hls) = params.hls.as_ref() {
+                    hls.write(&payload, params.output_rate);
                 }
-            }
-        }
-    }
-}
-
-async fn stream_audio_ws(params: &mut StreamParams) -> Result<()> {
-    let mut backoff = Backoff::new();
-    let url = match &params.ingest {
-        IngestTarget::Ws { url } => url.clone(),
-        IngestTarget::Tcp { .. } => anyhow::bail!("invalid ws ingest"),
-    };
-    let mut gate = VadGate::new();
-    let mut threshold_db = params.threshold_db;
-    let mut hold_duration = params.hold_duration;
-    let mut idle_since: Option<Instant> = None;
-    let mut last_rate_log = Instant::now();
-    let mut bytes_since_log: u64 = 0;
-    let chunk_bytes = chunk_bytes_for_rate(params.output_rate);
-    let chunk_interval = chunk_interval();
-    let max_pending = max_buffer_bytes_for_rate(params.output_rate);
-    let mut pending = VecDeque::with_capacity(max_pending);
-    let mut overrun_since = Instant::now();
-    let mut underrun_since = Instant::now();
-    let mut underrun_bytes: u64 = 0;
-    let mut tick = tokio::time::interval(chunk_interval);
-    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
-
-    let mut stream = None;
-    loop {
-        if stream.is_none() {
-            params.status.set_state("RECONNECTING");
-            match connect_ws(&url).await {
-                Ok(connected) => {
-                    stream = Some(connected);
-                    params.status.set_state("STREAMING");
-                    params.status.set_last_error(None);
-                    backoff.reset();
+                if let Some(monitor) = params.monitor.as_ref() {
+                    monitor.write(&payload, params.output_rate);
                 }
-                Err(err) => {
-                    params.status.set_last_error(Some(err.to_string()));
-                    tokio::time::sleep(backoff.next_delay()).await;
-                    continue;
+                if let Some(recorder) = params.recorder.as_ref() {
+                    recorder.write(&payload, params.output_rate);
                 }
-            }
-        }
-
-        tokio::select! {
-            maybe_chunk = params.rx.recv() => {
-                match maybe_chunk {
-                    Some(chunk) => {
-                        let rms_db = rms_db_from_pcm_i16_le(&chunk);
-                        pending.extend(chunk);
-                        if pending.len() > max_pending {
-                            let overflow = pending.len() - max_pending;
-                            for _ in 0..overflow {
-                                pending.pop_front();
-                            }
-                            if overrun_since.elapsed() >= Duration::from_secs(5) {
-                                warn!("audio buffer overrun, dropping {} bytes", overflow);
-                                overrun_since = Instant::now();
-                            }
-                        }
-                        params.status.set_rms_db(rms_db);
-                        if let Some(rms_db) = rms_db {
-                            let now = Instant::now();
-                            let was_active = gate.active;
-                            if rms_db >= threshold_db {
-                                gate.set_active(now);
-                            } else if gate.should_keep_active(now, hold_duration) {
-                            } else {
-                                gate.set_inactive();
-                            }
-
-                            if gate.active && !was_active {
-                                if let Some(idle_start) = idle_since.take() {
-                                    if now.duration_since(idle_start)
-                                        >= Duration::from_millis(TRACK_GAP_MS)
-                                    {
-                                        params.status.set_track_change();
-                                        info!("track change detected");
-                                    }
-                                }
-                                info!("audio detected, streaming (rms_db={:.1})", rms_db);
-                            } else if !gate.active && was_active {
-                                idle_since = Some(now);
-                                pending.clear();
-                                info!("silence detected, pausing stream (rms_db={:.1})", rms_db);
-                            }
-                        }
-
-                        if !gate.active {
-                            params.status.set_state("IDLE");
-                            continue;
-                        }
-
-                        // paced writes happen on the interval tick
-                    }
-                    None => {
-                        return Err(anyhow::anyhow!("audio capture channel closed"));
+                let paced = Arc::new(Paced {
+                    pcm: payload,
+                    captured_at_us,
+                });
+                for sink in &sinks {
+                    if !sink.offer(&paced) {
+                        params.status.record_dropped_chunk();
                     }
                 }
-            }
-            _ = tick.tick() => {
-                if !gate.active {
-                    continue;
+                // The pre-roll goes out back to back instead of at the
+                // capture pace, where it would stay behind as latency.
+                if flush_preroll && stage.is_active() && pending.len() > chunk_bytes {
+                    tick.reset_immediately();
+                } else {
+                    flush_preroll = false;
                 }
-                if let Some(writer) = stream.as_mut() {
-                    let payload = if pending.len() < chunk_bytes {
-                        let missing = chunk_bytes - pending.len();
-                        let mut buffer = Vec::with_capacity(chunk_bytes);
-                        while let Some(value) = pending.pop_front() {
-                            buffer.push(value);
-                        }
-                        buffer.extend(std::iter::repeat_n(0u8, missing));
-                        underrun_bytes += missing as u64;
-                        buffer
-                    } else {
-                        let mut buffer = Vec::with_capacity(chunk_bytes);
-                        for _ in 0..chunk_bytes {
-                            if let Some(value) = pending.pop_front() {
-                                buffer.push(value);
-                            }
-                        }
-                        buffer
-                    };
-                    if let Err(err) = writer.send(Message::Binary(payload)).await {
-                        params.status.set_last_error(Some(err.to_string()));
-                        stream = None;
-                    } else {
-                        params.status.set_state("STREAMING");
-                        params.status.record_bytes(chunk_bytes);
-                        bytes_since_log += chunk_bytes as u64;
-                    }
-                    if last_rate_log.elapsed() >= Duration::from_secs(5) {
-                        let secs = last_rate_log.elapsed().as_secs_f64();
-                        let bytes_per_sec = (bytes_since_log as f64 / secs).round();
-                        let est_rate = bytes_per_sec / 4.0;
-                        info!(
-                            "stream throughput: {} B/s (~{:.0} Hz)",
-                            bytes_per_sec, est_rate
-                        );
-                        bytes_since_log = 0;
-                        last_rate_log = Instant::now();
-                    }
-                    if underrun_since.elapsed() >= Duration::from_secs(5) && underrun_bytes > 0 {
-                        warn!(
-                            "audio buffer underrun: {} bytes padded in last {:.1}s",
-                            underrun_bytes,
-                            underrun_since.elapsed().as_secs_f64()
-                        );
-                        underrun_bytes = 0;
-                        underrun_since = Instant::now();
-                    }
+                if !all_connected {
+                    params.status.set_state("RECONNECTING");
+                } else if stage.is_active() {
+                    params.status.set_state("STREAMING");
+                }
+                if connected {
+                    params.status.record_bytes(chunk_bytes);
+                    bytes_since_log += chunk_bytes as u64;
+                }
+                if last_rate_log.elapsed() >= Duration::from_secs(5) {
+                    let secs = last_rate_log.elapsed().as_secs_f64();
+                    let bytes_per_sec = (bytes_since_log as f64 / secs).round();
+                    let est_rate = bytes_per_sec / 4.0;
+                    info!(
+                        "stream throughput: {} B/s (~{:.0} Hz)",
+                        bytes_per_sec, est_rate
+                    );
+                    bytes_since_log = 0;
+                    last_rate_log = Instant::now();
+                }
+                if underrun_since.elapsed() >= Duration::from_secs(5) && underrun_bytes > 0 {
+                    warn!(
+                        "audio buffer underrun: {} bytes padded in last {:.1}s",
+                        underrun_bytes,
+                        underrun_since.elapsed().as_secs_f64()
+                    );
+                    underrun_bytes = 0;
+                    underrun_since = Instant::now();
                 }
             }
-            maybe_err = params.err_rx.recv() => {
-                let message = match maybe_err {
-                    Some(message) => message,
-                    None => "audio capture error channel closed".to_string(),
-                };
+            // The error channel closes together with the capture stream; the
+            // end of capture is handled through `rx`.
+            Some(message) = params.err_rx.recv() => {
                 params.status.set_last_error(Some(message.clone()));
                 return Err(anyhow::anyhow!(message));
             }
@@ -532,60 +946,116 @@ async fn stream_audio_ws(params: &mut StreamParams) -> Result<()> {
                 }
             }, if params.vad_updates.is_some() => {
                 if let Some(rx) = params.vad_updates.as_ref() {
-                    let (next_threshold, next_hold) = *rx.borrow();
-                    threshold_db = next_threshold;
-                    hold_duration = next_hold;
+                    vad = *rx.borrow();
+                    stage.set_mode(vad.mode);
+                    if track.is_some() != (vad.track_detect == TrackDetectMode::Profile) {
+                        track = track_detector(vad.track_detect, params.output_rate);
+                    }
+                    preroll_bytes =
+                        preroll_bytes_for_rate(params.output_rate, vad.preroll, max_pending);
                 }
             }
         }
     }
+
+    // Closing the queues lets every sink send what it holds and stop.
+    drop(sinks);
+    while tasks.join_next().await.is_some() {}
+    Ok(())
+}
+
+fn open_tee(path: Option<PathBuf>) -> Option<PipeTee> {
+    match PipeTee::new(path?) {
+        Ok(tee) => Some(tee),
+        Err(err) => {
+            warn!("tee disabled: {:#}", err);
+            None
+        }
+    }
 }
 
-async fn connect_tcp(addr: &str, header: &str) -> Result<TcpStream> {
-    let mut stream = TcpStream::connect(addr)
-        .await
-        .with_context(|| format!("connect to {}", addr))?;
-    stream.set_nodelay(true).context("set TCP nodelay")?;
-    let header_line = format!("{}\n", header);
-    stream
-        .write_all(header_line.as_bytes())
-        .await
-        .context("send input id")?;
-    Ok(stream)
+/// Level meter, VAD and gate, fed one capture chunk at a time. The stream
+/// and the pipeline fixtures both go through it.
+pub(crate) struct GateStage {
+    meter: LevelMeter,
+    detector: VadDetector,
+    gate: VadGate,
+    idle_since: Option<Instant>,
 }
 
-async fn connect_ws(url: &str) -> Result<WsStream> {
-    let (stream, _) = connect_async(url)
-        .await
-        .with_context(|| format!("connect ws {}", url))?;
-    Ok(stream)
+pub(crate) struct GateStep {
+    pub(crate) levels: Option<Levels>,
+    pub(crate) change: Option<GateChange>,
 }
 
-struct Backoff {
-    current: Duration,
+pub(crate) enum GateChange {
+    /// `after_gap` when the gate was closed for at least `TRACK_GAP_MS`.
+    Opened {
+        level_db: f32,
+        after_gap: bool,
+    },
+    Closed {
+        level_db: f32,
+    },
 }
 
-impl Backoff {
-    fn new() -> Self {
+impl GateStage {
+    pub(crate) fn new(mode: VadMode, rate: u32) -> Self {
         Self {
-            current: Duration::from_secs(1),
+            meter: LevelMeter::new(rate),
+            detector: VadDetector::new(mode, rate),
+            gate: VadGate::new(),
+            idle_since: None,
         }
     }
 
-    fn reset(&mut self) {
-        self.current = Duration::from_secs(1);
+    pub(crate) fn is_active(&self) -> bool {
+        self.gate.active
     }
 
-    fn next_delay(&mut self) -> Duration {
-        let delay = self.current;
-        self.current = std::cmp::min(self.current * 2, Duration::from_secs(30));
-        delay
+    pub(crate) fn set_mode(&mut self, mode: VadMode) {
+        self.detector.set_mode(mode);
+    }
+
+    /// Meters `chunk`, captured at `now`, and moves the gate.
+    pub(crate) fn process(&mut self, chunk: &[u8], now: Instant, vad: &VadSettings) -> GateStep {
+        let levels = self.meter.process(chunk);
+        let reading = self.detector.detect(
+            chunk,
+            levels.map(|levels| levels.rms_db),
+            vad.threshold_db(self.gate.active),
+        );
+        let Some(reading) = reading else {
+            return GateStep {
+                levels,
+                change: None,
+            };
+        };
+        let was_active = self.gate.active;
+        self.gate.update(now, reading.active, vad);
+        let change = match (was_active, self.gate.active) {
+            (false, true) => Some(GateChange::Opened {
+                level_db: reading.level_db,
+                after_gap: self.idle_since.take().is_some_and(|idle_start| {
+                    now.duration_since(idle_start) >= Duration::from_millis(TRACK_GAP_MS)
+                }),
+            }),
+            (true, false) => {
+                self.idle_since = Some(now);
+                Some(GateChange::Closed {
+                    level_db: reading.level_db,
+                })
+            }
+            _ => None,
+        };
+        GateStep { levels, change }
     }
 }
 
 struct VadGate {
     active: bool,
     last_active: Option<Instant>,
+    above_since: Option<Instant>,
 }
 
 impl VadGate {
@@ -593,16 +1063,30 @@ impl VadGate {
         Self {
             active: false,
             last_active: None,
+            above_since: None,
         }
     }
 
-    fn set_active(&mut self, now: Instant) {
-        self.active = true;
-        self.last_active = Some(now);
-    }
-
-    fn set_inactive(&mut self) {
-        self.active = false;
+    /// Feeds one VAD decision. The gate opens once activity has lasted for the
+    /// attack time and closes after the hold time without activity.
+    fn update(&mut self, now: Instant, above: bool, vad: &VadSettings) {
+        if !vad.enabled {
+            self.active = true;
+            self.last_active = Some(now);
+            return;
+        }
+        if above {
+            let since = *self.above_since.get_or_insert(now);
+            if self.active || now.duration_since(since) >= vad.attack {
+                self.active = true;
+                self.last_active = Some(now);
+            }
+        } else {
+            self.above_since = None;
+            if self.active && !self.should_keep_active(now, vad.hold) {
+                self.active = false;
+            }
+        }
     }
 
     fn should_keep_active(&self, now: Instant, hold: Duration) -> bool {
@@ -613,37 +1097,60 @@ impl VadGate {
     }
 }
 
-fn rms_db_from_pcm_i16_le(bytes: &[u8]) -> Option<f32> {
-    let mut sum = 0f64;
-    let mut count = 0u64;
-    for chunk in bytes.chunks_exact(2) {
-        let sample = i16::from_le_bytes([chunk[0], chunk[1]]);
-        let normalized = sample as f64 / i16::MAX as f64;
-        sum += normalized * normalized;
-        count += 1;
-    }
-    if count == 0 {
-        return None;
-    }
-    let mean = sum / count as f64;
-    let rms = mean.sqrt();
-    let db = if rms <= 0.0 {
-        -100.0
-    } else {
-        20.0 * rms.log10()
-    };
-    Some(db as f32)
+fn track_detector(mode: TrackDetectMode, rate: u32) -> Option<ProfileDetector> {
+    (mode == TrackDetectMode::Profile).then(|| ProfileDetector::new(rate))
+}
+
+fn max_db(current: Option<f32>, value: f32) -> f32 {
+    current.map_or(value, |current| current.max(value))
 }
 
-fn chunk_bytes_for_rate(rate: u32) -> usize {
-    let chunk_ms = 40u32;
-    let bytes_per_sec = rate.saturating_mul(4);
-    let bytes = bytes_per_sec.saturating_mul(chunk_ms) / 1000;
-    bytes.max(4) as usize
+/// Re-blocks the output into payloads of one packet duration each, sent
+/// once per tick. When a packet isn't a whole number of frames at the
+/// output rate, sizes alternate so the average matches the rate exactly.
+struct Packetizer {
+    rate: u64,
+    packet_ms: u64,
+    /// Frame fractions (in 1/1000) carried over to the next packet.
+    remainder: u64,
 }
 
-fn chunk_interval() -> Duration {
-    Duration::from_millis(40)
+impl Packetizer {
+    fn new(rate: u32, packet: Duration) -> Self {
+        Self {
+            rate: rate.max(1) as u64,
+            packet_ms: (packet.as_millis() as u64).max(1),
+            remainder: 0,
+        }
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_millis(self.packet_ms)
+    }
+
+    /// Largest payload `next_bytes` returns.
+    fn max_bytes(&self) -> usize {
+        (self.rate * self.packet_ms).div_ceil(1000).max(1) as usize * 4
+    }
+
+    fn next_bytes(&mut self) -> usize {
+        let total = self.rate * self.packet_ms + self.remainder;
+        self.remainder = total % 1000;
+        (total / 1000).max(1) as usize * 4
+    }
+}
+
+fn preroll_bytes_for_rate(rate: u32, preroll: Duration, max_pending: usize) -> usize {
+    let frames = (rate as u128 * preroll.as_millis() / 1000) as usize;
+    frames.saturating_mul(4).min(max_pending / 4 * 4)
+}
+
+/// Drops the oldest bytes so that at most `max` remain.
+fn trim_front(pending: &mut VecDeque<u8>, max: usize) {
+    if pending.len() > max {
+        let excess = pending.len() - max;
+        pending.drain(..excess);
+    }
 }
 
 fn max_buffer_bytes_for_rate(rate: u32) -> usize {
@@ -0,0 +1,157 @@
+use anyhow::{Context, Result};
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+pub const DEFAULT_TEE_DIR: &str = "/run/lox-linein-bridge";
+const REOPEN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Path of the tee pipe for an assigned input.
+pub fn pipe_path(dir: &Path, input_id: &str) -> PathBuf {
+    let name = input_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    dir.join(format!("{}.pcm", name))
+}
+
+/// Copies the outgoing PCM into a named pipe without ever blocking the
+/// stream: while no reader is attached, or the reader falls behind, data is
+/// dropped. Only whole payloads are dropped; the tail of one the pipe took
+/// only partly is kept and goes out before the next, so the reader never
+/// loses its place in the frames.
+pub struct PipeTee {
+    /// Names the pipe in logs.
+    label: &'static str,
+    path: PathBuf,
+    file: Option<File>,
+    /// Unwritten end of the last payload.
+    pending: Vec<u8>,
+    last_open: Option<Instant>,
+    dropped: bool,
+}
+
+impl PipeTee {
+    pub fn new(path: PathBuf) -> Result<Self> {
+        Self::named("tee", path)
+    }
+
+    pub fn named(label: &'static str, path: PathBuf) -> Result<Self> {
+        create_fifo(&path)?;
+        info!("{} pipe: {}", label, path.display());
+        Ok(Self {
+            label,
+            path,
+            file: None,
+            pending: Vec::new(),
+            last_open: None,
+            dropped: false,
+        })
+    }
+
+    pub fn write(&mut self, payload: &[u8]) {
+        if self.file.is_none() {
+            if self
+                .last_open
+                .map(|ts| ts.elapsed() < REOPEN_INTERVAL)
+                .unwrap_or(false)
+            {
+                return;
+            }
+            self.last_open = Some(Instant::now());
+            // Opening a FIFO for non-blocking writes fails with ENXIO until a
+            // reader is attached.
+            match OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(&self.path)
+            {
+                Ok(file) => {
+                    info!("{} reader attached: {}", self.label, self.path.display());
+                    self.file = Some(file);
+                    self.pending.clear();
+                    self.dropped = false;
+                }
+                Err(_) => return,
+            }
+        }
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            match self.write_some(&pending) {
+                Some(written) if written == pending.len() => {}
+                Some(written) => {
+                    self.pending = pending[written..].to_vec();
+                    self.note_drop();
+                    return;
+                }
+                None => return,
+            }
+        }
+        match self.write_some(payload) {
+            Some(written) if written == payload.len() => {}
+            Some(0) => self.note_drop(),
+            Some(written) => self.pending = payload[written..].to_vec(),
+            None => {}
+        }
+    }
+
+    /// Bytes the pipe took; `None` once the reader is gone.
+    fn write_some(&mut self, data: &[u8]) -> Option<usize> {
+        let file = self.file.as_mut()?;
+        match file.write(data) {
+            Ok(written) => Some(written),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Some(0),
+            Err(err) if err.kind() == ErrorKind::BrokenPipe => {
+                info!("{} reader detached: {}", self.label, self.path.display());
+                self.file = None;
+                None
+            }
+            Err(err) => {
+                warn!("{} write failed: {}", self.label, err);
+                self.file = None;
+                None
+            }
+        }
+    }
+
+    fn note_drop(&mut self) {
+        if !self.dropped {
+            warn!(
+                "{} reader too slow, dropping audio: {}",
+                self.label,
+                self.path.display()
+            );
+            self.dropped = true;
+        }
+    }
+}
+
+fn create_fifo(path: &Path) -> Result<()> {
+    if let Ok(meta) = std::fs::metadata(path) {
+        if meta.file_type().is_fifo() {
+            return Ok(());
+        }
+        anyhow::bail!("{} exists and is not a named pipe", path.display());
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes()).context("named pipe path")?;
+    let result = unsafe { libc::mkfifo(c_path.as_ptr(), 0o644) };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("create named pipe {}", path.display()));
+    }
+    Ok(())
+}
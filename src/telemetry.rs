@@ -0,0 +1,116 @@
+//! Process and host telemetry for status posts: uptime, CPU use, resident
+//! memory and, where the board exposes it, SoC temperature and the
+//! Raspberry Pi firmware's throttling flags. Everything comes from procfs and
+//! sysfs; values that can't be read are left out.
+
+use std::fs;
+use std::time::Instant;
+
+const THERMAL_ZONE: &str = "/sys/class/thermal/thermal_zone0/temp";
+/// Raspberry Pi firmware flags, as printed by `vcgencmd get_throttled`.
+const PI_THROTTLED: &str = "/sys/devices/platform/soc/soc:firmware/get_throttled";
+/// Frequency capped, throttled, or soft temperature limit active right now.
+const PI_THROTTLED_NOW: u32 = 0b1110;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Telemetry {
+    pub uptime_secs: Option<u64>,
+    /// CPU time of the process over the last sample interval, 100 = one core.
+    pub cpu_percent: Option<f32>,
+    pub rss_bytes: Option<u64>,
+    pub soc_temp_c: Option<f32>,
+    pub throttled: Option<bool>,
+}
+
+/// Keeps the previous CPU reading; CPU use is reported from the second
+/// sample on.
+#[derive(Default)]
+pub struct TelemetrySampler {
+    last_cpu: Option<(Instant, u64)>,
+}
+
+impl TelemetrySampler {
+    pub fn sample(&mut self) -> Telemetry {
+        let ticks_per_sec = clock_ticks();
+        let stat = ProcStat::read();
+        let now = Instant::now();
+        let cpu_percent = match (stat, self.last_cpu, ticks_per_sec) {
+            (Some(stat), Some((at, ticks)), Some(per_sec)) => {
+                let wall = now.duration_since(at).as_secs_f32();
+                let cpu = stat.cpu_ticks.saturating_sub(ticks) as f32 / per_sec as f32;
+                (wall > 0.0).then(|| cpu / wall * 100.0)
+            }
+            _ => None,
+        };
+        if let Some(stat) = stat {
+            self.last_cpu = Some((now, stat.cpu_ticks));
+        }
+        Telemetry {
+            uptime_secs: stat.zip(ticks_per_sec).and_then(|(stat, per_sec)| {
+                let host_uptime: f64 = fs::read_to_string("/proc/uptime")
+                    .ok()?
+                    .split_whitespace()
+                    .next()?
+                    .parse()
+                    .ok()?;
+                let started = stat.start_ticks as f64 / per_sec as f64;
+                Some((host_uptime - started).max(0.0) as u64)
+            }),
+            cpu_percent,
+            rss_bytes: rss_bytes(),
+            soc_temp_c: soc_temp_c(),
+            throttled: throttled(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ProcStat {
+    /// User plus system time.
+    cpu_ticks: u64,
+    /// Start time after boot.
+    start_ticks: u64,
+}
+
+impl ProcStat {
+    fn read() -> Option<Self> {
+        let stat = fs::read_to_string("/proc/self/stat").ok()?;
+        // The command name may contain spaces; fields resume after the ')'.
+        let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+        let field = |n: usize| fields.get(n - 3)?.parse::<u64>().ok();
+        Some(Self {
+            cpu_ticks: field(14)? + field(15)?,
+            start_ticks: field(22)?,
+        })
+    }
+}
+
+fn clock_ticks() -> Option<u64> {
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    (ticks > 0).then_some(ticks as u64)
+}
+
+fn rss_bytes() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kb: u64 = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+fn soc_temp_c() -> Option<f32> {
+    let millidegrees: i64 = fs::read_to_string(THERMAL_ZONE).ok()?.trim().parse().ok()?;
+    Some(millidegrees as f32 / 1000.0)
+}
+
+fn throttled() -> Option<bool> {
+    let value = fs::read_to_string(PI_THROTTLED).ok()?;
+    let value = value.trim();
+    let flags = u32::from_str_radix(value.trim_start_matches("0x"), 16).ok()?;
+    Some(flags & PI_THROTTLED_NOW != 0)
+}
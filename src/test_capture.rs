@@ -0,0 +1,199 @@
+//! `test-capture` command: opens a capture device and shows a live level
+//! meter per channel plus the rate the device actually delivers, to check
+//! wiring and gain before a server is involved.
+
+use crate::audio::{self, CaptureOptions, ResamplerMode, TARGET_CHANNELS};
+use crate::cli::DeviceArgs;
+use crate::i18n::t;
+use crate::limiter::OutputSettings;
+use crate::meter::{amplitude_to_db, power_to_db};
+use crate::simd;
+use anyhow::{Context, Result};
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+const DEFAULT_RATE: u32 = 48_000;
+const DEFAULT_SECONDS: u64 = 10;
+/// Redraw interval on a terminal; piped output gets one line per second.
+const TERMINAL_INTERVAL: Duration = Duration::from_millis(100);
+const PIPED_INTERVAL: Duration = Duration::from_secs(1);
+const BAR_WIDTH: usize = 30;
+/// Left end of the bar.
+const BAR_FLOOR_DB: f32 = -60.0;
+/// Peaks at or above this count as clipping.
+const CLIP_DB: f32 = -0.1;
+
+#[derive(Debug, clap::Args)]
+pub struct TestCaptureOptions {
+    #[command(flatten)]
+    capture: DeviceArgs,
+    #[arg(long, default_value_t = DEFAULT_RATE)]
+    rate: u32,
+    /// How long to show the meter.
+    #[arg(long, default_value_t = DEFAULT_SECONDS)]
+    seconds: u64,
+}
+
+/// RMS and peak of one channel over a redraw interval.
+#[derive(Debug, Clone, Copy, Default)]
+struct ChannelLevels {
+    sum_squares: f64,
+    samples: usize,
+    peak: f32,
+}
+
+impl ChannelLevels {
+    fn rms_db(&self) -> f32 {
+        if self.samples == 0 {
+            return power_to_db(0.0);
+        }
+        power_to_db(self.sum_squares / self.samples as f64)
+    }
+
+    fn peak_db(&self) -> f32 {
+        amplitude_to_db(self.peak)
+    }
+}
+
+pub async fn run_test_capture(options: TestCaptureOptions) -> Result<()> {
+    let device = audio::default_capture_device(options.capture.device)?;
+    let session = audio::start_capture(
+        &device,
+        options.rate,
+        ResamplerMode::SincQuality,
+        CaptureOptions::default(),
+        OutputSettings::default(),
+    )?;
+    let audio::CaptureSession {
+        mut receiver,
+        mut error_receiver,
+        streams,
+        sample_rate,
+        channels,
+        format,
+        observed_rate,
+        ..
+    } = session;
+    let _capture_guard = streams;
+    println!(
+        "{}",
+        t!(
+            "test-capture-opened",
+            device = device.as_str(),
+            rate = sample_rate,
+            channels = channels,
+            format = format.to_string(),
+            seconds = options.seconds,
+        )
+    );
+
+    let terminal = io::stdout().is_terminal();
+    let interval = if terminal {
+        TERMINAL_INTERVAL
+    } else {
+        PIPED_INTERVAL
+    };
+    let channel_count = TARGET_CHANNELS as usize;
+    let mut current = vec![ChannelLevels::default(); channel_count];
+    let mut max_peak = vec![0f32; channel_count];
+    let mut samples = Vec::new();
+    let started = Instant::now();
+    let mut last_draw = Instant::now();
+    let duration = Duration::from_secs(options.seconds);
+    while started.elapsed() < duration {
+        let chunk = tokio::select! {
+            chunk = receiver.recv() => chunk.context("capture stopped")?,
+            Some(err) = error_receiver.recv() => anyhow::bail!("capture failed: {}", err),
+            _ = tokio::time::sleep(Duration::from_secs(5)) => anyhow::bail!("capture stalled"),
+        };
+        samples.clear();
+        simd::i16_le_to_f32(&chunk, &mut samples);
+        for frame in samples.chunks_exact(channel_count) {
+            for (levels, &sample) in current.iter_mut().zip(frame) {
+                levels.sum_squares += f64::from(sample) * f64::from(sample);
+                levels.samples += 1;
+                levels.peak = levels.peak.max(sample.abs());
+            }
+        }
+        if last_draw.elapsed() < interval {
+            continue;
+        }
+        let observed = observed_rate.lock().ok().and_then(|rate| *rate);
+        draw(&current, observed, terminal)?;
+        for (max, levels) in max_peak.iter_mut().zip(&current) {
+            *max = max.max(levels.peak);
+        }
+        current.fill(ChannelLevels::default());
+        last_draw = Instant::now();
+    }
+    if terminal {
+        println!();
+    }
+    for (max, levels) in max_peak.iter_mut().zip(&current) {
+        *max = max.max(levels.peak);
+    }
+    let peaks: Vec<String> = max_peak
+        .iter()
+        .zip(channel_names())
+        .map(|(&peak, name)| format!("{} {:.1} dBFS", name, amplitude_to_db(peak)))
+        .collect();
+    println!("{}", t!("test-capture-peaks", peaks = peaks.join(", ")));
+    if max_peak
+        .iter()
+        .any(|&peak| amplitude_to_db(peak) >= CLIP_DB)
+    {
+        println!("{}", t!("test-capture-clipping"));
+    } else if max_peak
+        .iter()
+        .all(|&peak| amplitude_to_db(peak) < BAR_FLOOR_DB)
+    {
+        println!("{}", t!("test-capture-silent"));
+    }
+    Ok(())
+}
+
+fn channel_names() -> impl Iterator<Item = &'static str> {
+    ["L", "R"].into_iter()
+}
+
+/// One line with a bar, RMS and peak per channel. On a terminal the line
+/// is redrawn in place.
+fn draw(levels: &[ChannelLevels], observed_rate: Option<u32>, terminal: bool) -> Result<()> {
+    let mut line = String::new();
+    for (levels, name) in levels.iter().zip(channel_names()) {
+        line.push_str(&format!(
+            "{} [{}] rms {:>6.1} peak {:>6.1}  ",
+            name,
+            bar(levels.rms_db(), levels.peak_db()),
+            levels.rms_db(),
+            levels.peak_db()
+        ));
+    }
+    match observed_rate {
+        Some(rate) => line.push_str(&format!("{} Hz", rate)),
+        None => line.push_str("-- Hz"),
+    }
+    let mut stdout = io::stdout().lock();
+    if terminal {
+        write!(stdout, "\r{}", line)?;
+    } else {
+        writeln!(stdout, "{}", line)?;
+    }
+    stdout.flush().context("write level meter")
+}
+
+/// `#` up to the RMS, `-` up to the peak, from -60 to 0 dBFS.
+fn bar(rms_db: f32, peak_db: f32) -> String {
+    let cells = |db: f32| {
+        let fraction = ((db - BAR_FLOOR_DB) / -BAR_FLOOR_DB).clamp(0.0, 1.0);
+        (fraction * BAR_WIDTH as f32).round() as usize
+    };
+    let rms = cells(rms_db);
+    let peak = cells(peak_db).max(rms);
+    format!(
+        "{}{}{}",
+        "#".repeat(rms),
+        "-".repeat(peak - rms),
+        " ".repeat(BAR_WIDTH - peak)
+    )
+}
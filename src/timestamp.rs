@@ -6,3 +6,32 @@ pub fn now_rfc3339() -> String {
         .format(&Rfc3339)
         .unwrap_or_else(|_| "1970-01-01T00:00:00Z".to_string())
 }
+
+/// UTC time as `20240131-235959` for file names; sorts chronologically.
+pub fn file_stamp() -> String {
+    let now = OffsetDateTime::now_utc();
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        now.year(),
+        now.month() as u8,
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    )
+}
+
+pub fn now_unix_ms() -> f64 {
+    OffsetDateTime::now_utc().unix_timestamp_nanos() as f64 / 1e6
+}
+
+pub fn now_unix_us() -> u64 {
+    (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1000).max(0) as u64
+}
+
+/// Unix milliseconds of an RFC 3339 timestamp.
+pub fn parse_unix_ms(value: &str) -> Option<f64> {
+    OffsetDateTime::parse(value, &Rfc3339)
+        .ok()
+        .map(|ts| ts.unix_timestamp_nanos() as f64 / 1e6)
+}
@@ -0,0 +1,231 @@
+//! TLS for ingest connections (raw TCP with `ingest_tls` and `wss://`
+//! URLs) and for the server API (`[server_tls]`). Servers are checked
+//! against the built-in web roots, a configured CA bundle, or a pinned
+//! certificate fingerprint for self-signed setups. A client certificate is
+//! presented when configured.
+
+use anyhow::{Context, Result};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+/// A plaintext or TLS connection.
+pub trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+pub type Stream = Box<dyn Io>;
+
+/// PEM values are either inline PEM text or a path on the bridge.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsSettings {
+    /// CA bundle that replaces the built-in roots.
+    pub ca: Option<String>,
+    /// SHA-256 of the server certificate (hex, colons allowed). When set,
+    /// only that certificate is accepted and no chain is checked.
+    pub pin_sha256: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    /// Accepts any server certificate; for lab setups only.
+    pub skip_verify: bool,
+}
+
+/// `[server_tls]` in the config file: TLS for register, status and the
+/// control channel, e.g. behind a reverse proxy with an internal CA.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerTlsConfig {
+    /// CA bundle (path or inline PEM) that replaces the built-in roots.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
+    pub insecure_skip_verify: bool,
+}
+
+impl ServerTlsConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn settings(&self) -> TlsSettings {
+        TlsSettings {
+            ca: self.ca.clone(),
+            pin_sha256: None,
+            client_cert: self.client_cert.clone(),
+            client_key: self.client_key.clone(),
+            skip_verify: self.insecure_skip_verify,
+        }
+    }
+}
+
+/// Runs the TLS handshake on `stream`. `host` is checked against the
+/// server certificate unless a fingerprint is pinned.
+pub async fn connect(stream: TcpStream, host: &str, settings: &TlsSettings) -> Result<Stream> {
+    let config = client_config(settings)?;
+    let name = ServerName::try_from(host.trim_matches(['[', ']']).to_string())
+        .with_context(|| format!("invalid TLS server name {}", host))?;
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(name, stream)
+        .await
+        .with_context(|| format!("TLS handshake with {}", host))?;
+    Ok(Box::new(stream))
+}
+
+pub fn client_config(settings: &TlsSettings) -> Result<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .context("TLS protocol versions")?;
+    let builder = match &settings.pin_sha256 {
+        _ if settings.skip_verify => {
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedVerifier {
+                    fingerprint: None,
+                    provider,
+                }))
+        }
+        Some(pin) => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedVerifier {
+                fingerprint: Some(parse_fingerprint(pin)?),
+                provider,
+            })),
+        None => builder.with_root_certificates(roots(settings.ca.as_deref())?),
+    };
+    match (&settings.client_cert, &settings.client_key) {
+        (Some(cert), Some(key)) => {
+            let certs = read_pem(cert)
+                .and_then(|pem| {
+                    CertificateDer::pem_slice_iter(&pem)
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(anyhow::Error::from)
+                })
+                .context("read client certificate")?;
+            let key = read_pem(key)
+                .and_then(|pem| PrivateKeyDer::from_pem_slice(&pem).map_err(anyhow::Error::from))
+                .context("read client key")?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .context("use client certificate")
+        }
+        (None, None) => Ok(builder.with_no_client_auth()),
+        _ => anyhow::bail!("client certificate and key must be set together"),
+    }
+}
+
+fn roots(ca: Option<&str>) -> Result<RootCertStore> {
+    let Some(ca) = ca else {
+        return Ok(RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        });
+    };
+    let pem = read_pem(ca).context("read CA bundle")?;
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_slice_iter(&pem) {
+        roots
+            .add(cert.context("parse CA bundle")?)
+            .context("add CA certificate")?;
+    }
+    if roots.is_empty() {
+        anyhow::bail!("CA bundle contains no certificates");
+    }
+    Ok(roots)
+}
+
+fn read_pem(value: &str) -> Result<Vec<u8>> {
+    if value.trim_start().starts_with("-----BEGIN") {
+        return Ok(value.as_bytes().to_vec());
+    }
+    std::fs::read(value).with_context(|| format!("read {}", value))
+}
+
+fn parse_fingerprint(value: &str) -> Result<[u8; 32]> {
+    let hex: String = value.chars().filter(|c| *c != ':').collect();
+    let mut fingerprint = [0u8; 32];
+    if hex.len() != 64 {
+        anyhow::bail!("pinned fingerprint must be 32 bytes of hex");
+    }
+    for (byte, pair) in fingerprint.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).unwrap_or_default();
+        *byte = u8::from_str_radix(pair, 16)
+            .with_context(|| format!("invalid fingerprint {}", value))?;
+    }
+    Ok(fingerprint)
+}
+
+/// Accepts exactly one server certificate, identified by its SHA-256, or
+/// any certificate without a fingerprint (`skip_verify`). Signatures are
+/// checked either way.
+#[derive(Debug)]
+struct PinnedVerifier {
+    fingerprint: Option<[u8; 32]>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let Some(fingerprint) = self.fingerprint else {
+            return Ok(ServerCertVerified::assertion());
+        };
+        let digest = ring::digest::digest(&ring::digest::SHA256, end_entity);
+        if digest.as_ref() == fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "server certificate doesn't match the pinned fingerprint".to_string(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
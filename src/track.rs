@@ -0,0 +1,210 @@
+use crate::audio::TARGET_CHANNELS;
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+const FFT_SIZE: usize = 2048;
+/// Log-spaced band edges (Hz) of the spectral profile.
+const BAND_EDGES_HZ: [f32; 9] = [
+    60.0, 150.0, 300.0, 600.0, 1200.0, 2400.0, 4800.0, 9600.0, 16000.0,
+];
+const BANDS: usize = BAND_EDGES_HZ.len() - 1;
+/// Seconds of audio compared against the longer-term reference.
+const RECENT_BLOCKS: usize = 4;
+/// Time constant of the reference profile, in one-second blocks.
+const REFERENCE_BLOCKS: f32 = 20.0;
+/// Profile distance (dB) mapped to zero and full confidence.
+const DISTANCE_MIN_DB: f32 = 3.0;
+const DISTANCE_FULL_DB: f32 = 9.0;
+/// Confidence at which a change is reported.
+const TRIGGER_CONFIDENCE: f32 = 0.6;
+/// Shortest plausible track, in one-second blocks.
+const MIN_TRACK_BLOCKS: u32 = 30;
+const FLOOR_DB: f32 = -120.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackDetectMode {
+    /// A silence gap of at least two seconds starts a new track.
+    Gap,
+    /// Shift of the longer-term level and spectral profile, which also
+    /// catches gapless albums and ignores quiet passages.
+    Profile,
+}
+
+impl TrackDetectMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "gap" | "silence" => Some(Self::Gap),
+            "profile" | "spectral" => Some(Self::Profile),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Gap => "gap",
+            Self::Profile => "profile",
+        }
+    }
+}
+
+type Profile = [f32; BANDS];
+
+/// Detects track changes from one-second spectral profiles. Feed it only
+/// while the gate is open.
+pub struct ProfileDetector {
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    bins_per_hz: f32,
+    input: Vec<f32>,
+    frame: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    frames_per_block: usize,
+    block_sum: [f64; BANDS],
+    block_frames: usize,
+    recent: VecDeque<Profile>,
+    reference: Option<Profile>,
+    blocks_since_change: u32,
+}
+
+impl ProfileDetector {
+    pub fn new(rate: u32) -> Self {
+        let rate = rate.max(1);
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FFT_SIZE);
+        let window = (0..FFT_SIZE)
+            .map(|idx| {
+                let phase = 2.0 * std::f32::consts::PI * idx as f32 / FFT_SIZE as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        Self {
+            frame: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            fft,
+            window,
+            bins_per_hz: FFT_SIZE as f32 / rate as f32,
+            input: Vec::with_capacity(FFT_SIZE * 2),
+            frames_per_block: (rate as usize / FFT_SIZE).max(1),
+            block_sum: [0.0; BANDS],
+            block_frames: 0,
+            recent: VecDeque::with_capacity(RECENT_BLOCKS + 1),
+            reference: None,
+            blocks_since_change: 0,
+        }
+    }
+
+    /// Returns the confidence when this chunk completes a detected change.
+    pub fn process(&mut self, bytes: &[u8]) -> Option<f32> {
+        let frame_bytes = TARGET_CHANNELS as usize * 2;
+        for frame in bytes.chunks_exact(frame_bytes) {
+            let left = i16::from_le_bytes([frame[0], frame[1]]) as f32;
+            let right = i16::from_le_bytes([frame[2], frame[3]]) as f32;
+            self.input.push((left + right) / (2.0 * i16::MAX as f32));
+        }
+        let mut detected = None;
+        while self.input.len() >= FFT_SIZE {
+            for (idx, slot) in self.frame.iter_mut().enumerate() {
+                *slot = self.input[idx] * self.window[idx];
+            }
+            self.input.drain(0..FFT_SIZE);
+            if self
+                .fft
+                .process(&mut self.frame, &mut self.spectrum)
+                .is_err()
+            {
+                continue;
+            }
+            self.accumulate_frame();
+            if self.block_frames >= self.frames_per_block {
+                if let Some(confidence) = self.finish_block() {
+                    detected = Some(confidence);
+                }
+            }
+        }
+        detected
+    }
+
+    fn accumulate_frame(&mut self) {
+        for band in 0..BANDS {
+            let start = (BAND_EDGES_HZ[band] * self.bins_per_hz) as usize;
+            let end =
+                ((BAND_EDGES_HZ[band + 1] * self.bins_per_hz) as usize).min(self.spectrum.len());
+            let power: f32 = self.spectrum[start.min(end)..end]
+                .iter()
+                .map(|bin| bin.norm_sqr())
+                .sum();
+            self.block_sum[band] += power as f64;
+        }
+        self.block_frames += 1;
+    }
+
+    fn finish_block(&mut self) -> Option<f32> {
+        let mut profile = [FLOOR_DB; BANDS];
+        for (band, value) in profile.iter_mut().enumerate() {
+            let power = self.block_sum[band] / self.block_frames as f64;
+            if power > 0.0 {
+                *value = ((10.0 * power.log10()) as f32).max(FLOOR_DB);
+            }
+        }
+        self.block_sum = [0.0; BANDS];
+        self.block_frames = 0;
+        self.blocks_since_change = self.blocks_since_change.saturating_add(1);
+
+        self.recent.push_back(profile);
+        if self.recent.len() <= RECENT_BLOCKS {
+            return None;
+        }
+        // The block leaving the recent window feeds the reference.
+        let aged = self.recent.pop_front()?;
+        let reference = match self.reference.as_mut() {
+            Some(reference) => {
+                let alpha = 1.0 / REFERENCE_BLOCKS;
+                for (value, aged) in reference.iter_mut().zip(aged) {
+                    *value += (aged - *value) * alpha;
+                }
+                *reference
+            }
+            None => {
+                self.reference = Some(aged);
+                return None;
+            }
+        };
+
+        let recent = mean_profile(&self.recent);
+        let confidence = confidence(&reference, &recent);
+        if confidence < TRIGGER_CONFIDENCE || self.blocks_since_change < MIN_TRACK_BLOCKS {
+            return None;
+        }
+        self.reference = Some(recent);
+        self.blocks_since_change = 0;
+        Some(confidence)
+    }
+}
+
+fn mean_profile(profiles: &VecDeque<Profile>) -> Profile {
+    let mut mean = [0f32; BANDS];
+    for profile in profiles {
+        for (acc, value) in mean.iter_mut().zip(profile) {
+            *acc += value;
+        }
+    }
+    for value in mean.iter_mut() {
+        *value /= profiles.len().max(1) as f32;
+    }
+    mean
+}
+
+/// Maps the distance between two profiles to 0..1. Overall level shifts and
+/// spectral shape changes both count, shape at full weight.
+fn confidence(reference: &Profile, recent: &Profile) -> f32 {
+    let diffs = reference
+        .iter()
+        .zip(recent)
+        .map(|(a, b)| b - a)
+        .collect::<Vec<_>>();
+    let level = diffs.iter().sum::<f32>() / BANDS as f32;
+    let shape = diffs.iter().map(|diff| (diff - level).abs()).sum::<f32>() / BANDS as f32;
+    let distance = shape + level.abs() * 0.5;
+    ((distance - DISTANCE_MIN_DB) / (DISTANCE_FULL_DB - DISTANCE_MIN_DB)).clamp(0.0, 1.0)
+}
@@ -0,0 +1,475 @@
+//! `lox-linein-bridge update`: looks up the latest release, downloads the
+//! archive for the architecture this binary was built for, checks it
+//! against the published SHA-256 (and an Ed25519 signature when
+//! `update_public_key` is configured), swaps the binary with a rename and
+//! restarts the service.
+//!
+//! The release URL answers like the GitHub releases API: `tag_name` plus
+//! `assets` with `name` and `browser_download_url`. Mirrors only need to
+//! serve that JSON next to the files.
+//!
+//! The server can also roll out a version itself: `desired_version` with an
+//! artifact URL, checksum and signature in the bridge config. The bridge
+//! only accepts that with `update_public_key` set, since anyone answering
+//! as the server could otherwise run code on it. It then installs it the
+//! same way, reports progress in its status and restarts in place.
+//!
+//! The signature covers the archive, not the version it is offered as, so
+//! the version is read from the unpacked binary itself and must match
+//! before it replaces the running one. An older signed release can't be
+//! passed off as a newer one that way.
+
+use crate::config;
+use crate::hex::{from_hex, to_hex};
+use crate::i18n::t;
+use crate::install;
+use crate::models::{BridgeConfigResponse, UpdateStatus};
+use crate::shutdown::Shutdown;
+use crate::stream::StatusHandle;
+use anyhow::{Context, Result};
+use reqwest::Client;
+use serde::Deserialize;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const DEFAULT_RELEASE_URL: &str =
+    "https://api.github.com/repos/lox-audioserver/lox-linein-bridge/releases/latest";
+/// Target triple of this build; release archives are named after it.
+const TARGET: &str = env!("BUILD_TARGET");
+const BINARY_NAME: &str = "lox-linein-bridge";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// One remote update at a time, even with several bridges in the process.
+static REMOTE_UPDATE_RUNNING: AtomicBool = AtomicBool::new(false);
+/// Installed binary to run on restart; `/proc/self/exe` names the replaced
+/// file by then.
+static RESTART_BINARY: OnceLock<PathBuf> = OnceLock::new();
+
+#[derive(Debug, Default, clap::Args)]
+pub struct UpdateOptions {
+    /// Release URL instead of `update_url` or GitHub.
+    #[arg(long)]
+    url: Option<String>,
+    /// Only reports whether an update is available.
+    #[arg(long)]
+    check: bool,
+    /// Installs even when the release isn't newer.
+    #[arg(long)]
+    force: bool,
+    /// Leaves the running service alone.
+    #[arg(long)]
+    no_restart: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// A downloadable build and how to check it.
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub version: String,
+    pub url: String,
+    /// Hex SHA-256 of the download.
+    pub sha256: String,
+    /// Detached Ed25519 signature over the download, raw or hex.
+    pub signature_url: Option<String>,
+}
+
+pub async fn run_update(options: UpdateOptions) -> Result<()> {
+    // Updating must not create a config and a bridge id on the way.
+    let Some(path) = config::existing_config_path() else {
+        anyhow::bail!("{}", t!("update-no-config"));
+    };
+    let config = config::reload_config(&path)?;
+    let url = options
+        .url
+        .or(config.update_url.clone())
+        .unwrap_or_else(|| DEFAULT_RELEASE_URL.to_string());
+    let client = http_client()?;
+    let artifact = latest_artifact(&client, &url).await?;
+    let current = env!("CARGO_PKG_VERSION");
+    if !options.force && !is_newer(&artifact.version, current) {
+        println!("{}", t!("update-current", version = current));
+        return Ok(());
+    }
+    if options.check {
+        println!(
+            "{}",
+            t!(
+                "update-available",
+                version = artifact.version.as_str(),
+                current = current
+            )
+        );
+        return Ok(());
+    }
+    println!(
+        "{}",
+        t!("update-downloading", version = artifact.version.as_str())
+    );
+    let binary = download_verified(&client, &artifact, config.update_public_key.as_deref()).await?;
+    let path = replace_binary(&binary, &artifact.version)?;
+    println!(
+        "{}",
+        t!(
+            "update-installed",
+            version = artifact.version.as_str(),
+            path = path.display().to_string()
+        )
+    );
+    if !options.no_restart && install::restart_service()? {
+        println!("{}", t!("update-restarted"));
+    }
+    Ok(())
+}
+
+/// Starts the update the server asked for in `response`, unless this
+/// version is already installed or was tried. Progress goes into the status
+/// posts; once installed, the bridge shuts down and starts the new binary.
+pub fn apply_remote(
+    response: &BridgeConfigResponse,
+    status: &StatusHandle,
+    public_key: Option<&str>,
+    shutdown: &Shutdown,
+) {
+    let Some(version) = response.desired_version.as_deref() else {
+        return;
+    };
+    let version = version.trim_start_matches('v');
+    if !is_newer(version, env!("CARGO_PKG_VERSION"))
+        || status
+            .update()
+            .is_some_and(|update| update.version == version)
+        || REMOTE_UPDATE_RUNNING.swap(true, Ordering::SeqCst)
+    {
+        return;
+    }
+    let report = {
+        let status = status.clone();
+        let version = version.to_string();
+        move |state: &str, error: Option<String>| {
+            status.set_update(UpdateStatus {
+                version: version.clone(),
+                state: state.to_string(),
+                error,
+            })
+        }
+    };
+    // The artifact comes from whoever answers as the server, so only a
+    // signature ties it to the publisher; a checksum alone proves nothing.
+    let Some(public_key) = public_key.map(str::to_string) else {
+        warn!(
+            "server asked for version {}, but remote updates need update_public_key",
+            version
+        );
+        report(
+            "failed",
+            Some("remote updates need update_public_key on the bridge".to_string()),
+        );
+        REMOTE_UPDATE_RUNNING.store(false, Ordering::SeqCst);
+        return;
+    };
+    let (Some(url), Some(sha256)) = (
+        response.update_url.clone(),
+        response.update_sha256.as_deref(),
+    ) else {
+        warn!(
+            "server asked for version {} without an artifact URL and checksum",
+            version
+        );
+        report(
+            "failed",
+            Some("update_url and update_sha256 are required".to_string()),
+        );
+        REMOTE_UPDATE_RUNNING.store(false, Ordering::SeqCst);
+        return;
+    };
+    let artifact = Artifact {
+        version: version.to_string(),
+        url,
+        sha256: sha256.trim().to_lowercase(),
+        signature_url: response.update_signature_url.clone(),
+    };
+    let shutdown = shutdown.clone();
+    info!(
+        "server requested version {}, downloading {}",
+        artifact.version, artifact.url
+    );
+    report("downloading", None);
+    tokio::spawn(async move {
+        let installed = async {
+            let client = http_client()?;
+            let binary = download_verified(&client, &artifact, Some(&public_key)).await?;
+            report("installing", None);
+            replace_binary(&binary, &artifact.version)
+        }
+        .await;
+        match installed {
+            Ok(path) => {
+                info!(
+                    "installed version {} to {}, restarting",
+                    artifact.version,
+                    path.display()
+                );
+                report("restarting", None);
+                let _ = RESTART_BINARY.set(path);
+                shutdown.request_restart();
+            }
+            Err(err) => {
+                warn!("update to {} failed: {:#}", artifact.version, err);
+                report("failed", Some(format!("{:#}", err)));
+                REMOTE_UPDATE_RUNNING.store(false, Ordering::SeqCst);
+            }
+        }
+    });
+}
+
+/// Replaces the process with the installed binary and the same arguments.
+/// Only returns on failure.
+pub fn reexec() -> anyhow::Error {
+    use std::os::unix::process::CommandExt;
+    let binary = RESTART_BINARY
+        .get()
+        .cloned()
+        .or_else(|| std::env::args_os().next().map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from(BINARY_NAME));
+    info!("starting {}", binary.display());
+    let err = Command::new(&binary)
+        .args(std::env::args_os().skip(1))
+        .exec();
+    anyhow::Error::new(err).context(format!("restart {}", binary.display()))
+}
+
+pub fn http_client() -> Result<Client> {
+    Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        // GitHub rejects API requests without one.
+        .user_agent(concat!("lox-linein-bridge/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .context("create http client")
+}
+
+/// The archive for this target in the release at `url`, with its checksum.
+async fn latest_artifact(client: &Client, url: &str) -> Result<Artifact> {
+    let release: Release = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("fetch {}", url))?
+        .error_for_status()
+        .with_context(|| format!("fetch {}", url))?
+        .json()
+        .await
+        .context("parse release")?;
+    let asset = release
+        .assets
+        .iter()
+        .find(|asset| asset.name.ends_with(&format!("-{}.tar.gz", TARGET)))
+        .with_context(|| format!("release {} has no build for {}", release.tag_name, TARGET))?;
+    let checksum_url = release
+        .assets
+        .iter()
+        .find(|other| other.name == format!("{}.sha256", asset.name))
+        .map(|other| other.browser_download_url.clone())
+        .with_context(|| {
+            format!(
+                "release {} has no checksum for {}",
+                release.tag_name, asset.name
+            )
+        })?;
+    let checksum = client
+        .get(&checksum_url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("fetch {}", checksum_url))?
+        .text()
+        .await
+        .context("read checksum")?;
+    // `sha256sum` output: the hash, then the file name.
+    let sha256 = checksum
+        .split_whitespace()
+        .next()
+        .context("empty checksum file")?
+        .to_lowercase();
+    Ok(Artifact {
+        version: release.tag_name.trim_start_matches('v').to_string(),
+        url: asset.browser_download_url.clone(),
+        sha256,
+        signature_url: release
+            .assets
+            .iter()
+            .find(|other| other.name == format!("{}.sig", asset.name))
+            .map(|other| other.browser_download_url.clone()),
+    })
+}
+
+/// Downloads the artifact, checks it and returns the binary inside.
+pub async fn download_verified(
+    client: &Client,
+    artifact: &Artifact,
+    public_key: Option<&str>,
+) -> Result<Vec<u8>> {
+    let archive = fetch(client, &artifact.url).await?;
+    let digest = ring::digest::digest(&ring::digest::SHA256, &archive);
+    if to_hex(digest.as_ref()) != artifact.sha256 {
+        anyhow::bail!("checksum mismatch for {}", artifact.url);
+    }
+    if let Some(key) = public_key {
+        let signature_url = artifact
+            .signature_url
+            .as_deref()
+            .context("update_public_key is set, but the release is not signed")?;
+        let signature = fetch(client, signature_url).await?;
+        verify_signature(key, &archive, &signature)?;
+    }
+    if artifact.url.ends_with(".tar.gz") {
+        extract_binary(&archive)
+    } else {
+        Ok(archive)
+    }
+}
+
+async fn fetch(client: &Client, url: &str) -> Result<Vec<u8>> {
+    let body = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("download {}", url))?
+        .bytes()
+        .await
+        .with_context(|| format!("download {}", url))?;
+    Ok(body.to_vec())
+}
+
+fn verify_signature(public_key: &str, data: &[u8], signature: &[u8]) -> Result<()> {
+    let key = from_hex(public_key.trim()).context("update_public_key must be hex")?;
+    // Either 64 raw bytes or their hex text.
+    let signature = match std::str::from_utf8(signature) {
+        Ok(text) if text.trim().len() == 128 => {
+            from_hex(text.trim()).context("invalid signature")?
+        }
+        _ => signature.to_vec(),
+    };
+    ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, key)
+        .verify(data, &signature)
+        .map_err(|_| anyhow::anyhow!("signature check failed"))
+}
+
+/// Unpacks `lox-linein-bridge` from the release tarball with the system
+/// `tar`. The archive goes in through a pipe, so nothing is written to a
+/// shared directory like `/tmp` on the way.
+fn extract_binary(archive: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new("tar")
+        .arg("-xzOf")
+        .arg("-")
+        .arg(BINARY_NAME)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("run tar")?;
+    let mut stdin = child.stdin.take().context("tar stdin")?;
+    let archive = archive.to_vec();
+    // Fed from a thread, so tar can't block on a full stdout pipe meanwhile.
+    let feeder = std::thread::spawn(move || stdin.write_all(&archive));
+    let output = child.wait_with_output().context("run tar")?;
+    // tar stops reading early when the archive is broken; its exit status
+    // says more than the broken pipe.
+    let _ = feeder.join();
+    if !output.status.success() || output.stdout.is_empty() {
+        anyhow::bail!(
+            "unpacking the release failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Writes the new binary next to the running one and renames it over it,
+/// so the swap is atomic and the running process keeps its old file. The
+/// staged binary has to report `version` first.
+pub fn replace_binary(binary: &[u8], version: &str) -> Result<PathBuf> {
+    let current = std::env::current_exe()
+        .and_then(|path| path.canonicalize())
+        .context("locate the running binary")?;
+    let staged = staged_path(&current);
+    fs::write(&staged, binary).with_context(|| format!("write {}", staged.display()))?;
+    fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("chmod {}", staged.display()))?;
+    if let Err(err) = check_version(&staged, version) {
+        let _ = fs::remove_file(&staged);
+        return Err(err);
+    }
+    if let Err(err) = fs::rename(&staged, &current) {
+        let _ = fs::remove_file(&staged);
+        return Err(err).with_context(|| format!("replace {}", current.display()));
+    }
+    Ok(current)
+}
+
+/// Runs `binary --version` and compares its answer with the version the
+/// release claims.
+fn check_version(binary: &Path, expected: &str) -> Result<()> {
+    let output = Command::new(binary)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .with_context(|| format!("run {} --version", binary.display()))?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let actual = text
+        .split_whitespace()
+        .last()
+        .filter(|_| output.status.success())
+        .with_context(|| format!("{} reports no version", binary.display()))?
+        .trim_start_matches('v');
+    if actual != expected.trim_start_matches('v') {
+        anyhow::bail!(
+            "the download is version {}, but was offered as {}",
+            actual,
+            expected
+        );
+    }
+    Ok(())
+}
+
+fn staged_path(current: &Path) -> PathBuf {
+    let mut name = current.file_name().unwrap_or_default().to_os_string();
+    name.push(".new");
+    current.with_file_name(name)
+}
+
+/// Whether `candidate` is a later `major.minor.patch` than `current`.
+/// Pre-release and build suffixes are ignored.
+pub fn is_newer(candidate: &str, current: &str) -> bool {
+    fn parse(version: &str) -> Vec<u64> {
+        version
+            .trim_start_matches('v')
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    }
+    parse(candidate) > parse(current)
+}
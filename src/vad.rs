@@ -0,0 +1,341 @@
+use crate::audio::TARGET_CHANNELS;
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+use std::sync::Arc;
+
+const FFT_SIZE: usize = 1024;
+/// Energy below this frequency (hum, rumble) is ignored by the spectral mode.
+const BAND_LOW_HZ: f32 = 100.0;
+/// Spectral flux ratio at which no stationarity penalty is applied.
+const FLUX_REFERENCE: f32 = 0.1;
+const FLOOR_DB: f32 = -100.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VadMode {
+    /// Broadband RMS of each chunk.
+    Rms,
+    /// Band-limited energy above 100 Hz, attenuated when the spectrum is
+    /// stationary (mains buzz) as measured by spectral flux.
+    Spectral,
+    /// WebRTC-style speech/audio activity decision from sub-band SNR against
+    /// an adaptive noise model. Ignores the dB threshold.
+    WebRtc,
+}
+
+impl VadMode {
+    pub const ALL: [Self; 3] = [Self::Rms, Self::Spectral, Self::WebRtc];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "rms" | "level" => Some(Self::Rms),
+            "spectral" | "spectral-flux" | "flux" | "band" => Some(Self::Spectral),
+            "webrtc" | "speech" => Some(Self::WebRtc),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Rms => "rms",
+            Self::Spectral => "spectral",
+            Self::WebRtc => "webrtc",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VadReading {
+    /// Level used for the decision, in dBFS, for logging and calibration.
+    pub level_db: f32,
+    /// Whether this chunk counts as activity.
+    pub active: bool,
+}
+
+/// Decides per chunk whether the VAD gate should see activity.
+pub struct VadDetector {
+    mode: VadMode,
+    rate: u32,
+    spectral: Option<SpectralState>,
+    webrtc: Option<WebRtcState>,
+}
+
+impl VadDetector {
+    pub fn new(mode: VadMode, rate: u32) -> Self {
+        let mut detector = Self {
+            mode: VadMode::Rms,
+            rate,
+            spectral: None,
+            webrtc: None,
+        };
+        detector.set_mode(mode);
+        detector
+    }
+
+    pub fn set_mode(&mut self, mode: VadMode) {
+        let initialized = match mode {
+            VadMode::Rms => true,
+            VadMode::Spectral => self.spectral.is_some(),
+            VadMode::WebRtc => self.webrtc.is_some(),
+        };
+        if mode == self.mode && initialized {
+            return;
+        }
+        self.mode = mode;
+        self.spectral = (mode == VadMode::Spectral).then(|| SpectralState::new(self.rate));
+        self.webrtc = (mode == VadMode::WebRtc).then(|| WebRtcState::new(self.rate));
+    }
+
+    pub fn detect(
+        &mut self,
+        bytes: &[u8],
+        rms_db: Option<f32>,
+        threshold_db: f32,
+    ) -> Option<VadReading> {
+        let by_threshold = |level_db: f32| VadReading {
+            level_db,
+            active: level_db >= threshold_db,
+        };
+        match self.mode {
+            VadMode::Rms => rms_db.map(by_threshold),
+            VadMode::Spectral => self
+                .spectral
+                .as_mut()
+                .and_then(|spectral| spectral.process(bytes))
+                .map(by_threshold),
+            VadMode::WebRtc => self
+                .webrtc
+                .as_mut()
+                .and_then(|webrtc| webrtc.process(bytes)),
+        }
+    }
+}
+
+/// Pushes interleaved stereo s16le frames into `out` as mono f32.
+fn push_mono(bytes: &[u8], out: &mut Vec<f32>) {
+    let frame_bytes = TARGET_CHANNELS as usize * 2;
+    for frame in bytes.chunks_exact(frame_bytes) {
+        let left = i16::from_le_bytes([frame[0], frame[1]]) as f32;
+        let right = i16::from_le_bytes([frame[2], frame[3]]) as f32;
+        out.push((left + right) / (2.0 * i16::MAX as f32));
+    }
+}
+
+struct SpectralState {
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    window_power: f32,
+    low_bin: usize,
+    input: Vec<f32>,
+    frame: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    prev_magnitudes: Vec<f32>,
+    last_db: Option<f32>,
+}
+
+impl SpectralState {
+    fn new(rate: u32) -> Self {
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FFT_SIZE);
+        let window = (0..FFT_SIZE)
+            .map(|idx| {
+                let phase = 2.0 * std::f32::consts::PI * idx as f32 / FFT_SIZE as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect::<Vec<_>>();
+        let window_power = window.iter().map(|w| w * w).sum();
+        let low_bin = ((BAND_LOW_HZ * FFT_SIZE as f32) / rate.max(1) as f32).ceil() as usize;
+        Self {
+            frame: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            fft,
+            window,
+            window_power,
+            low_bin: low_bin.max(1),
+            input: Vec::with_capacity(FFT_SIZE * 2),
+            prev_magnitudes: Vec::new(),
+            last_db: None,
+        }
+    }
+
+    fn process(&mut self, bytes: &[u8]) -> Option<f32> {
+        push_mono(bytes, &mut self.input);
+        while self.input.len() >= FFT_SIZE {
+            for (idx, slot) in self.frame.iter_mut().enumerate() {
+                *slot = self.input[idx] * self.window[idx];
+            }
+            self.input.drain(0..FFT_SIZE);
+            if self
+                .fft
+                .process(&mut self.frame, &mut self.spectrum)
+                .is_err()
+            {
+                continue;
+            }
+            self.last_db = Some(self.analyze());
+        }
+        self.last_db
+    }
+
+    fn analyze(&mut self) -> f32 {
+        let bins = &self.spectrum[self.low_bin.min(self.spectrum.len())..];
+        let mut power = 0f32;
+        let mut magnitude_sum = 0f32;
+        let mut flux = 0f32;
+        let has_prev = self.prev_magnitudes.len() == bins.len();
+        for (idx, bin) in bins.iter().enumerate() {
+            let magnitude = bin.norm();
+            power += magnitude * magnitude;
+            magnitude_sum += magnitude;
+            if has_prev {
+                flux += (magnitude - self.prev_magnitudes[idx]).max(0.0);
+            }
+        }
+        self.prev_magnitudes.clear();
+        self.prev_magnitudes
+            .extend(bins.iter().map(|bin| bin.norm()));
+
+        // One-sided spectrum back to mean square of the band (Parseval).
+        let mean_square = 2.0 * power / (FFT_SIZE as f32 * self.window_power);
+        if mean_square <= 0.0 || magnitude_sum <= 0.0 {
+            return FLOOR_DB;
+        }
+        let band_db = 10.0 * mean_square.log10();
+        let flux_ratio = if has_prev {
+            flux / magnitude_sum
+        } else {
+            FLUX_REFERENCE
+        };
+        let penalty_db = 10.0 * (flux_ratio / FLUX_REFERENCE).clamp(1e-3, 1.0).log10();
+        (band_db + penalty_db).max(FLOOR_DB)
+    }
+}
+
+/// Sub-bands of the WebRTC VAD (Hz) and their weight in the speech score.
+const WEBRTC_BANDS: [(f32, f32, f32); 6] = [
+    (80.0, 250.0, 0.5),
+    (250.0, 500.0, 1.0),
+    (500.0, 1000.0, 1.0),
+    (1000.0, 2000.0, 1.0),
+    (2000.0, 3000.0, 0.8),
+    (3000.0, 4000.0, 0.5),
+];
+const WEBRTC_FRAME_MS: u32 = 20;
+/// Weighted mean sub-band SNR above which a frame counts as active.
+const WEBRTC_SNR_DB: f32 = 6.0;
+/// Frames below this absolute level are never active (digital silence).
+const WEBRTC_MIN_DB: f32 = -75.0;
+const WEBRTC_HANGOVER_FRAMES: u32 = 8;
+const NOISE_INITIAL_DB: f32 = -70.0;
+
+struct WebRtcState {
+    fft: Arc<dyn RealToComplex<f32>>,
+    frame_len: usize,
+    fft_len: usize,
+    bins_per_hz: f32,
+    input: Vec<f32>,
+    frame: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    noise_db: [f32; WEBRTC_BANDS.len()],
+    hangover: u32,
+    last: Option<VadReading>,
+}
+
+impl WebRtcState {
+    fn new(rate: u32) -> Self {
+        let rate = rate.max(1);
+        let frame_len = (rate * WEBRTC_FRAME_MS / 1000).max(1) as usize;
+        let fft_len = frame_len.next_power_of_two();
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(fft_len);
+        Self {
+            frame: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            fft,
+            frame_len,
+            fft_len,
+            bins_per_hz: fft_len as f32 / rate as f32,
+            input: Vec::with_capacity(frame_len * 2),
+            noise_db: [NOISE_INITIAL_DB; WEBRTC_BANDS.len()],
+            hangover: 0,
+            last: None,
+        }
+    }
+
+    fn process(&mut self, bytes: &[u8]) -> Option<VadReading> {
+        push_mono(bytes, &mut self.input);
+        let mut any_active = false;
+        while self.input.len() >= self.frame_len {
+            self.frame.fill(0.0);
+            self.frame[..self.frame_len].copy_from_slice(&self.input[..self.frame_len]);
+            self.input.drain(0..self.frame_len);
+            if self
+                .fft
+                .process(&mut self.frame, &mut self.spectrum)
+                .is_err()
+            {
+                continue;
+            }
+            let reading = self.analyze();
+            any_active |= reading.active;
+            self.last = Some(reading);
+        }
+        self.last.map(|last| VadReading {
+            active: last.active || any_active,
+            ..last
+        })
+    }
+
+    fn analyze(&mut self) -> VadReading {
+        // One-sided spectrum of the zero-padded frame back to mean square.
+        let scale = 2.0 / (self.frame_len as f32 * self.fft_len as f32);
+        let mut band_db = [FLOOR_DB; WEBRTC_BANDS.len()];
+        let mut total = 0f32;
+        for (idx, (low, high, _)) in WEBRTC_BANDS.iter().enumerate() {
+            let start = (low * self.bins_per_hz) as usize;
+            let end = ((high * self.bins_per_hz) as usize).min(self.spectrum.len());
+            let power = self.spectrum[start.min(end)..end]
+                .iter()
+                .map(|bin| bin.norm_sqr())
+                .sum::<f32>()
+                * scale;
+            total += power;
+            if power > 0.0 {
+                band_db[idx] = (10.0 * power.log10()).max(FLOOR_DB);
+            }
+        }
+        let level_db = if total > 0.0 {
+            (10.0 * total.log10()).max(FLOOR_DB)
+        } else {
+            FLOOR_DB
+        };
+
+        let mut score = 0f32;
+        let mut weights = 0f32;
+        for (idx, (_, _, weight)) in WEBRTC_BANDS.iter().enumerate() {
+            score += weight * (band_db[idx] - self.noise_db[idx]).clamp(0.0, 30.0);
+            weights += weight;
+        }
+        let speech = level_db >= WEBRTC_MIN_DB && score / weights >= WEBRTC_SNR_DB;
+
+        // Noise model: follow drops quickly, rises slowly, and only adapt
+        // upwards on frames that don't look like activity.
+        for (idx, noise) in self.noise_db.iter_mut().enumerate() {
+            let energy = band_db[idx];
+            if energy < *noise {
+                *noise += (energy - *noise) * 0.2;
+            } else if !speech {
+                *noise += (energy - *noise) * 0.05;
+            } else {
+                *noise += (energy - *noise) * 0.002;
+            }
+        }
+
+        if speech {
+            self.hangover = WEBRTC_HANGOVER_FRAMES;
+        } else {
+            self.hangover = self.hangover.saturating_sub(1);
+        }
+        VadReading {
+            level_db,
+            active: speech || self.hangover > 0,
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! `lox-linein-bridge validate-config`: parses the config the way the bridge
+//! would and checks the values it can check without a server, so a typo
+//! shows up before a restart instead of as a backed-up `.invalid` file or a
+//! bridge that quietly ignores a setting. Only the file is checked, not the
+//! `LOX_LINEIN_BRIDGE_*` overrides.
+//!
+//! Errors exit with 1. Warnings are for things that may be fine later, e.g.
+//! a USB device that is unplugged right now.
+
+use crate::audio::ResamplerMode;
+use crate::config::{self, Config};
+use crate::i18n::t;
+use crate::logging::LogTarget;
+use crate::track::TrackDetectMode;
+use crate::vad::VadMode;
+use crate::{audio, dnssd, multicast, net, secrets, snapcast};
+use anyhow::Result;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+const EXIT_INVALID: i32 = 1;
+
+#[derive(Debug, clap::Args)]
+pub struct ValidateConfigOptions {
+    /// Config file to check instead of the one the bridge would load.
+    #[arg(long, value_name = "PATH")]
+    file: Option<PathBuf>,
+}
+
+pub fn run_validate_config(options: ValidateConfigOptions) -> Result<()> {
+    let path = options.file.unwrap_or_else(default_path);
+    println!(
+        "{}",
+        t!("validate-checking", path = path.display().to_string())
+    );
+    if !path.exists() {
+        eprintln!(
+            "{}",
+            t!("validate-missing", path = path.display().to_string())
+        );
+        std::process::exit(EXIT_INVALID);
+    }
+    let (config, unknown) = match config::read_config_file(&path) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{}: {:#}", t!("validate-error"), err);
+            std::process::exit(EXIT_INVALID);
+        }
+    };
+
+    let mut checks = Checks::default();
+    for key in unknown {
+        checks.error(&key, t!("validate-unknown-key"));
+    }
+    check(&config, &mut checks);
+    for (key, err) in secrets::check(&config, &path) {
+        checks.error(key, t!("validate-bad-value", error = format!("{:#}", err)));
+    }
+
+    for problem in &checks.problems {
+        let severity = if problem.error {
+            t!("validate-error")
+        } else {
+            t!("validate-warning")
+        };
+        println!("{}: {}: {}", severity, problem.key, problem.message);
+    }
+    let errors = checks
+        .problems
+        .iter()
+        .filter(|problem| problem.error)
+        .count();
+    let warnings = checks.problems.len() - errors;
+    if checks.problems.is_empty() {
+        println!("{}", t!("validate-ok"));
+    } else {
+        println!(
+            "{}",
+            t!("validate-summary", errors = errors, warnings = warnings)
+        );
+    }
+    if errors > 0 {
+        std::process::exit(EXIT_INVALID);
+    }
+    Ok(())
+}
+
+/// The file `load_or_create_config` would read.
+fn default_path() -> PathBuf {
+    config::existing_config_path().unwrap_or_else(config::preferred_config_path)
+}
+
+struct Problem {
+    error: bool,
+    key: String,
+    message: String,
+}
+
+#[derive(Default)]
+struct Checks {
+    problems: Vec<Problem>,
+}
+
+impl Checks {
+    fn error(&mut self, key: &str, message: String) {
+        self.push(true, key, message);
+    }
+
+    fn warning(&mut self, key: &str, message: String) {
+        self.push(false, key, message);
+    }
+
+    fn push(&mut self, error: bool, key: &str, message: String) {
+        self.problems.push(Problem {
+            error,
+            key: key.to_string(),
+            message,
+        });
+    }
+
+    fn url(&mut self, key: &str, value: &str) {
+        match url::Url::parse(value) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => {}
+            Ok(_) => self.error(key, t!("validate-not-http")),
+            Err(err) => self.error(key, t!("validate-bad-url", error = err.to_string())),
+        }
+    }
+
+    fn choice(&mut self, key: &str, value: &str, valid: bool, choices: &str) {
+        if !valid {
+            self.error(
+                key,
+                t!("validate-bad-choice", value = value, choices = choices),
+            );
+        }
+    }
+
+    /// The bridge clamps these, so it still runs.
+    fn range<T: PartialOrd + Display>(&mut self, key: &str, value: T, min: T, max: T) {
+        if value < min || value > max {
+            self.warning(
+                key,
+                t!(
+                    "validate-out-of-range",
+                    value = value.to_string(),
+                    min = min.to_string(),
+                    max = max.to_string()
+                ),
+            );
+        }
+    }
+
+    fn parsed<T>(&mut self, key: &str, result: Result<T>) {
+        if let Err(err) = result {
+            self.error(key, t!("validate-bad-value", error = format!("{:#}", err)));
+        }
+    }
+
+    /// Inline PEM or a path that exists.
+    fn pem(&mut self, key: &str, value: &str) {
+        if !value.contains("-----BEGIN") && !Path::new(value).exists() {
+            self.error(key, t!("validate-no-file", path = value));
+        }
+    }
+}
+
+fn check(config: &Config, checks: &mut Checks) {
+    if config.bridge_id.trim().is_empty() {
+        checks.error("bridge_id", t!("validate-empty"));
+    }
+    if let Some(url) = &config.server_url {
+        checks.url("server_url", url);
+    }
+    for (index, url) in config.fallback_servers.iter().enumerate() {
+        checks.url(&format!("fallback_servers.{}", index), url);
+    }
+    if let Some(url) = &config.update_url {
+        checks.url("update_url", url);
+    }
+    if let Some(mac) = &config.preferred_server_mac {
+        let digits: String = mac.chars().filter(|c| !matches!(c, ':' | '-')).collect();
+        if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            checks.error("preferred_server_mac", t!("validate-bad-mac"));
+        }
+    }
+    if let Some(server) = &config.dns_sd_server {
+        checks.parsed("dns_sd_server", dnssd::parse_nameserver(server));
+    }
+    checks.parsed(
+        "bind_address",
+        net::Binding::new(None, config.bind_address.as_deref()),
+    );
+    if let Some(interface) = &config.bind_interface {
+        let exists = get_if_addrs::get_if_addrs()
+            .unwrap_or_default()
+            .iter()
+            .any(|iface| iface.name == *interface);
+        if !exists {
+            checks.warning(
+                "bind_interface",
+                t!("validate-no-interface", name = interface.as_str()),
+            );
+        }
+    }
+    let tls = &config.server_tls;
+    for (key, value) in [
+        ("server_tls.ca", &tls.ca),
+        ("server_tls.client_cert", &tls.client_cert),
+        ("server_tls.client_key", &tls.client_key),
+    ] {
+        if let Some(value) = value {
+            checks.pem(key, value);
+        }
+    }
+
+    check_devices(config, checks);
+    if let Some(manual) = &config.sources.manual {
+        if !config
+            .sources
+            .inputs
+            .iter()
+            .any(|input| input.name == *manual)
+        {
+            checks.error(
+                "sources.manual",
+                t!("validate-no-source", name = manual.as_str()),
+            );
+        }
+    }
+    if let Some(snapcast) = &config.snapcast {
+        checks.parsed(
+            "snapcast.target",
+            snapcast::SnapcastTarget::parse(&snapcast.target),
+        );
+    }
+    if let Some(multicast) = &config.multicast {
+        checks.parsed("multicast.group", multicast::parse_group(&multicast.group));
+    }
+    check_overrides(config, checks);
+}
+
+/// Devices may be unplugged right now, so missing ones are warnings.
+fn check_devices(config: &Config, checks: &mut Checks) {
+    let mut wanted = Vec::new();
+    if let Some(device) = &config.capture_device {
+        wanted.push(("capture_device".to_string(), device));
+    }
+    if let Some(device) = &config.overrides.capture_device {
+        wanted.push(("overrides.capture_device".to_string(), device));
+    }
+    for (index, input) in config.sources.inputs.iter().enumerate() {
+        wanted.push((format!("sources.inputs.{}.device", index), &input.device));
+    }
+    if wanted.is_empty() {
+        return;
+    }
+    let Ok(devices) = audio::list_input_device_details() else {
+        return;
+    };
+    for (key, device) in wanted {
+        if !devices
+            .iter()
+            .any(|known| known.id == *device || known.name == *device)
+        {
+            checks.warning(&key, t!("validate-no-device", device = device.as_str()));
+        }
+    }
+}
+
+fn check_overrides(config: &Config, checks: &mut Checks) {
+    let overrides = &config.overrides;
+    if let Some(resampler) = &overrides.ingest_resampler {
+        checks.choice(
+            "overrides.ingest_resampler",
+            resampler,
+            ResamplerMode::parse(resampler).is_some(),
+            "linear, sinc-fast, sinc, fft",
+        );
+        if ResamplerMode::parse(resampler) == Some(ResamplerMode::Fft) {
+            checks.warning("overrides.ingest_resampler", t!("validate-fft-drift"));
+        }
+    }
+    if let Some(mode) = &overrides.vad_mode {
+        checks.choice(
+            "overrides.vad_mode",
+            mode,
+            VadMode::parse(mode).is_some(),
+            "rms, spectral, webrtc",
+        );
+    }
+    if let Some(mode) = &overrides.track_detect {
+        checks.choice(
+            "overrides.track_detect",
+            mode,
+            TrackDetectMode::parse(mode).is_some(),
+            "gap, profile",
+        );
+    }
+    if let Some(target) = &overrides.log_target {
+        checks.choice(
+            "overrides.log_target",
+            target,
+            LogTarget::parse(target).is_some(),
+            "journal, file, both",
+        );
+    }
+    if let Some(level) = &overrides.log_level {
+        checks.choice(
+            "overrides.log_level",
+            level,
+            tracing_subscriber::EnvFilter::try_new(level).is_ok(),
+            "off, error, warn, info, debug, trace",
+        );
+    }
+    if overrides.ingest_sample_rate == Some(0) {
+        checks.error("overrides.ingest_sample_rate", t!("validate-zero"));
+    }
+    if let Some(ms) = overrides.ingest_packet_ms {
+        checks.range("overrides.ingest_packet_ms", ms, 5, 200);
+    }
+    if let Some(secs) = overrides.status_interval_secs {
+        checks.range("overrides.status_interval_secs", secs, 1, 300);
+    }
+    if overrides.capture_period_frames == Some(0) {
+        checks.error("overrides.capture_period_frames", t!("validate-zero"));
+    }
+}
@@ -0,0 +1,42 @@
+use crate::audio::TARGET_CHANNELS;
+
+const HEADER_LEN: u32 = 44;
+const BITS_PER_SAMPLE: u16 = 16;
+/// Largest data chunk the 32-bit size fields can describe; longer audio
+/// has to go into a new file.
+pub const MAX_DATA_LEN: u32 = u32::MAX - HEADER_LEN;
+
+/// Builds a canonical 44-byte PCM s16le WAV header. Without a known length
+/// (live streams) the RIFF and data sizes are set to `0xFFFFFFFF`, which
+/// ffmpeg, sox, and VLC read as "until end of stream".
+pub fn header(rate: u32, data_len: Option<u32>) -> [u8; HEADER_LEN as usize] {
+    header_with_channels(rate, TARGET_CHANNELS, data_len)
+}
+
+/// Same as [`header`] for any channel count.
+pub fn header_with_channels(
+    rate: u32,
+    channels: u16,
+    data_len: Option<u32>,
+) -> [u8; HEADER_LEN as usize] {
+    let block_align = channels * BITS_PER_SAMPLE / 8;
+    let byte_rate = rate * block_align as u32;
+    let data_len = data_len.unwrap_or(u32::MAX);
+    let riff_len = data_len.saturating_add(HEADER_LEN - 8);
+
+    let mut out = [0u8; HEADER_LEN as usize];
+    out[0..4].copy_from_slice(b"RIFF");
+    out[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    out[8..12].copy_from_slice(b"WAVE");
+    out[12..16].copy_from_slice(b"fmt ");
+    out[16..20].copy_from_slice(&16u32.to_le_bytes());
+    out[20..22].copy_from_slice(&1u16.to_le_bytes());
+    out[22..24].copy_from_slice(&channels.to_le_bytes());
+    out[24..28].copy_from_slice(&rate.to_le_bytes());
+    out[28..32].copy_from_slice(&byte_rate.to_le_bytes());
+    out[32..34].copy_from_slice(&block_align.to_le_bytes());
+    out[34..36].copy_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+    out[36..40].copy_from_slice(b"data");
+    out[40..44].copy_from_slice(&data_len.to_le_bytes());
+    out
+}
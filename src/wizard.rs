@@ -0,0 +1,251 @@
+use crate::audio::{self, CaptureOptions, ResamplerMode};
+use crate::config::{self, Config};
+use crate::discovery;
+use crate::i18n::t;
+use crate::install;
+use crate::limiter::OutputSettings;
+use crate::meter::LevelMeter;
+use crate::net;
+use anyhow::{Context, Result};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+const LEVEL_TEST_RATE: u32 = 48_000;
+const LEVEL_TEST_DURATION: Duration = Duration::from_secs(5);
+const LEVEL_PRINT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The wizard only runs when someone can answer it.
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+/// Guided setup for a first start without config or discoverable server.
+/// Returns the written config.
+pub async fn run(mut config: Config) -> Result<Config> {
+    println!();
+    println!("{}", t!("wizard-intro"));
+    println!();
+
+    choose_server(&mut config)?;
+    config.capture_device = choose_device()?;
+    if let Some(device) = config.capture_device.clone() {
+        if confirm(&t!("wizard-test-levels"), true)? {
+            if let Err(err) = test_levels(&device).await {
+                println!(
+                    "{}",
+                    t!("wizard-level-test-failed", error = format!("{:#}", err))
+                );
+            }
+        }
+    }
+
+    let path = config::write_config(&config)?;
+    println!(
+        "{}",
+        t!("wizard-config-written", path = path.display().to_string())
+    );
+
+    if confirm(&t!("wizard-install"), false)? {
+        install::run_install(install::InstallOptions::default()).await?;
+        println!("{}", t!("wizard-installed"));
+        std::process::exit(0);
+    }
+    Ok(config)
+}
+
+fn choose_server(config: &mut Config) -> Result<()> {
+    let binding = net::Binding::new(
+        config.bind_interface.as_deref(),
+        config.bind_address.as_deref(),
+    )
+    .unwrap_or_default();
+    loop {
+        println!("{}", t!("wizard-searching"));
+        let servers =
+            discovery::browse_servers(Duration::from_secs(5), &binding).unwrap_or_default();
+        for (idx, server) in servers.iter().enumerate() {
+            let name = server.txt.get("name").map(String::as_str).unwrap_or("");
+            println!("  {}) {} {}", idx + 1, server.base_url, name);
+        }
+        println!("  m) {}", t!("wizard-server-manual"));
+        println!("  r) {}", t!("wizard-server-rescan"));
+        let answer = prompt(&t!("wizard-server-prompt"))?;
+        match answer.as_str() {
+            "r" | "" => continue,
+            "m" => {
+                let url = prompt(&t!("wizard-server-url-prompt"))?;
+                if url.starts_with("http://") || url.starts_with("https://") {
+                    config.server_url = Some(url);
+                    return Ok(());
+                }
+                println!("{}", t!("wizard-server-url-invalid"));
+            }
+            other => match other
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| servers.get(n.wrapping_sub(1)))
+            {
+                Some(server) => {
+                    prefer_server(config, server);
+                    return Ok(());
+                }
+                None => println!("{}", t!("wizard-unknown-choice", choice = other)),
+            },
+        }
+    }
+}
+
+/// Lets the installer choose when several servers answer on mDNS, so the
+/// bridge doesn't just take whichever ranks first. Returns whether a choice
+/// was stored; with one server or none there is nothing to pick.
+pub fn pick_server(config: &mut Config) -> Result<bool> {
+    let binding = net::Binding::new(
+        config.bind_interface.as_deref(),
+        config.bind_address.as_deref(),
+    )
+    .unwrap_or_default();
+    println!("{}", t!("wizard-searching"));
+    let servers = discovery::browse_servers(Duration::from_secs(5), &binding).unwrap_or_default();
+    if servers.len() < 2 {
+        return Ok(false);
+    }
+    println!("{}", t!("install-servers-found", count = servers.len()));
+    for (idx, server) in servers.iter().enumerate() {
+        let name = server.txt.get("name").map(String::as_str).unwrap_or("");
+        let mac = server.txt.get("mac").map(String::as_str).unwrap_or("");
+        println!("  {}) {} {} {}", idx + 1, server.base_url, name, mac);
+    }
+    loop {
+        let answer = prompt(&t!("wizard-server-prompt"))?;
+        match answer
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| servers.get(n.wrapping_sub(1)))
+        {
+            Some(server) => {
+                prefer_server(config, server);
+                return Ok(true);
+            }
+            None => println!("{}", t!("wizard-unknown-choice", choice = answer.as_str())),
+        }
+    }
+}
+
+/// Stores the server's TXT name and MAC as preferences; a server without
+/// either is pinned by URL instead.
+fn prefer_server(config: &mut Config, server: &discovery::DiscoveredServer) {
+    config.preferred_server_name = server.txt.get("name").cloned();
+    config.preferred_server_mac = server.txt.get("mac").cloned();
+    if config.preferred_server_name.is_none() && config.preferred_server_mac.is_none() {
+        config.server_url = Some(server.base_url.clone());
+    }
+}
+
+fn choose_device() -> Result<Option<String>> {
+    let devices = audio::list_input_device_details()?;
+    if devices.is_empty() {
+        println!("{}", t!("wizard-no-devices"));
+        return Ok(None);
+    }
+    println!("{}", t!("wizard-devices"));
+    for (idx, device) in devices.iter().enumerate() {
+        let monitor = if device.is_monitor {
+            format!(" {}", t!("wizard-device-monitor"))
+        } else {
+            String::new()
+        };
+        println!(
+            "  {}) {} [{} ch]{}",
+            idx + 1,
+            device.name,
+            device.channels,
+            monitor
+        );
+    }
+    println!("  s) {}", t!("wizard-device-skip"));
+    loop {
+        let answer = prompt(&t!("wizard-device-prompt"))?;
+        if answer == "s" {
+            return Ok(None);
+        }
+        match answer
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| devices.get(n.wrapping_sub(1)))
+        {
+            Some(device) => return Ok(Some(device.id.clone())),
+            None => println!("{}", t!("wizard-unknown-choice", choice = answer.as_str())),
+        }
+    }
+}
+
+async fn test_levels(device: &str) -> Result<()> {
+    println!(
+        "{}",
+        t!("wizard-level-test", seconds = LEVEL_TEST_DURATION.as_secs())
+    );
+    let session = audio::start_capture(
+        device,
+        LEVEL_TEST_RATE,
+        ResamplerMode::SincQuality,
+        CaptureOptions::default(),
+        OutputSettings::default(),
+    )?;
+    let audio::CaptureSession {
+        mut receiver,
+        streams,
+        ..
+    } = session;
+    let _capture_guard = streams;
+    let mut meter = LevelMeter::new(LEVEL_TEST_RATE);
+    let started = Instant::now();
+    let mut last_print = Instant::now();
+    let mut peak = f32::MIN;
+    while started.elapsed() < LEVEL_TEST_DURATION {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .context("capture stalled")?
+            .context("capture stopped")?;
+        let Some(levels) = meter.process(&chunk) else {
+            continue;
+        };
+        peak = peak.max(levels.peak_db);
+        if last_print.elapsed() >= LEVEL_PRINT_INTERVAL {
+            println!(
+                "  rms {:>6.1} dBFS  peak {:>6.1} dBFS",
+                levels.rms_short_db, peak
+            );
+            peak = f32::MIN;
+            last_print = Instant::now();
+        }
+    }
+    Ok(())
+}
+
+fn prompt(label: &str) -> Result<String> {
+    print!("{}: ", label);
+    io::stdout().flush().context("flush stdout")?;
+    let mut line = String::new();
+    let read = io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .context("read stdin")?;
+    if read == 0 {
+        anyhow::bail!("setup aborted");
+    }
+    Ok(line.trim().to_string())
+}
+
+fn confirm(question: &str, default: bool) -> Result<bool> {
+    let hint = if default {
+        t!("confirm-hint-yes")
+    } else {
+        t!("confirm-hint-no")
+    };
+    let answer = prompt(&format!("{} {}", question, hint))?;
+    Ok(match answer.to_lowercase().as_str() {
+        "y" | "yes" | "j" | "ja" => true,
+        "n" | "no" | "nein" => false,
+        _ => default,
+    })
+}